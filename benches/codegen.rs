@@ -0,0 +1,71 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+// Numeric hot loops, compiled with and without native loop codegen
+fn programs() -> Vec<(&'static str, &'static str)> {
+    let sum = "\
+T = 0
+FOR I = 1 TO 20000000
+T = T + I * 2
+NEXT I
+PRINT T
+";
+
+    let nested = "\
+S = 0
+FOR I = 1 TO 3000
+FOR J = 1 TO 3000
+S = S + ABS(I - J) / 2
+NEXT J
+NEXT I
+PRINT S
+";
+
+    vec![("sum", sum), ("nested", nested)]
+}
+
+fn compile(dir: &Path, native: bool) {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_lang"));
+    command.args(["compile", "code.bs"]).current_dir(dir);
+    if !native {
+        command.arg("--no-native-loops");
+    }
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "compile failed: {}", String::from_utf8_lossy(&output.stderr));
+}
+
+fn time(dir: &Path) -> (Duration, String) {
+    let mut best = Duration::MAX;
+    let mut stdout = String::new();
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let output = Command::new(dir.join(format!("code{}", std::env::consts::EXE_SUFFIX))).output().unwrap();
+        best = best.min(start.elapsed());
+        stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    }
+    (best, stdout)
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join("lang-bench-codegen");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    for (name, source) in programs() {
+        std::fs::write(dir.join("code.bs"), source).unwrap();
+        compile(&dir, false);
+        let (naive, expected) = time(&dir);
+        compile(&dir, true);
+        let (native, actual) = time(&dir);
+        assert_eq!(expected, actual, "{} output differs between codegens", name);
+        println!(
+            "{:<8} naive {:>8.1} ms  native {:>8.1} ms  ({:.1}x)",
+            name,
+            naive.as_secs_f64() * 1000.0,
+            native.as_secs_f64() * 1000.0,
+            naive.as_secs_f64() / native.as_secs_f64()
+        );
+    }
+}
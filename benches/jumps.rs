@@ -0,0 +1,37 @@
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+const LINES: u32 = 100_000;
+
+// A loop that jumps to the far end of a 100,000 line program and back, so the time goes
+// into finding GOTO targets rather than running statements
+fn program() -> String {
+    let last = LINES * 10;
+    let mut source = String::from("10 I = 0\n20 I = I + 1\n30 IF I > 20000 THEN 60\n40 GOTO ");
+    source.push_str(&format!("{}\n60 END\n", last));
+    for n in 7..LINES {
+        source.push_str(&format!("{} PRINT {}\n", n * 10, n));
+    }
+    source.push_str(&format!("{} GOTO 20\n", last));
+    source
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join("lang-bench-jumps");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("code.bs"), program()).unwrap();
+
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let output = Command::new(env!("CARGO_BIN_EXE_lang"))
+            .args(["--no-config", "--warnings=allow", "code.bs"])
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "failed: {}", String::from_utf8_lossy(&output.stderr));
+        best = best.min(start.elapsed());
+    }
+    println!("{:<16} {:>8.1} ms (best of {})", "far_goto", best.as_secs_f64() * 1000.0, RUNS);
+}
@@ -0,0 +1,56 @@
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+// Variable-heavy programs: tight loops that read and write many variables per step
+fn programs() -> Vec<(&'static str, String)> {
+    let arithmetic = "\
+A = 1
+B = 2
+C = 3
+D = 4
+T = 0
+FOR I = 1 TO 200000
+T = T + A * B - C / D + I
+A = B
+B = C
+C = D
+D = A + 1
+NEXT I
+PRINT T
+".to_string();
+
+    let mut many = String::new();
+    for n in 0..50 {
+        many.push_str(&format!("V{} = {}\n", n, n));
+    }
+    many.push_str("S = 0\nFOR I = 1 TO 20000\n");
+    for n in 0..50 {
+        many.push_str(&format!("S = S + V{}\n", n));
+    }
+    many.push_str("NEXT I\nPRINT S\n");
+
+    vec![("arithmetic", arithmetic), ("many_variables", many)]
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join("lang-bench");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    for (name, source) in programs() {
+        std::fs::write(dir.join("code.bs"), source).unwrap();
+        let mut best = Duration::MAX;
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            let output = Command::new(env!("CARGO_BIN_EXE_lang"))
+                .arg("code.bs")
+                .current_dir(&dir)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{} failed: {}", name, String::from_utf8_lossy(&output.stderr));
+            best = best.min(start.elapsed());
+        }
+        println!("{:<16} {:>8.1} ms (best of {})", name, best.as_secs_f64() * 1000.0, RUNS);
+    }
+}
@@ -0,0 +1,48 @@
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+// A loop that goes round through a chain of GOTOs and an IF-GOTO and adds up as it goes, the
+// statements --vm-opt has quicker forms for
+fn program() -> String {
+    "\
+10 I = 0
+20 T = 0
+30 S$ = \"\"
+40 GOTO 100
+100 GOTO 110
+110 GOTO 120
+120 I = I + 1
+130 T = T + I
+140 IF I = INT(I / 1000) * 1000 THEN 200
+150 IF I < 300000 THEN 100 ELSE 300
+200 S$ = S$ + \"*\"
+210 GOTO 150
+300 PRINT T
+310 PRINT S$
+".to_string()
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join("lang-bench-vm-opt");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("code.bs"), program()).unwrap();
+
+    for (name, flags) in [("interpreted", &[][..]), ("vm_opt", &["--vm-opt"][..])] {
+        let mut best = Duration::MAX;
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            let output = Command::new(env!("CARGO_BIN_EXE_lang"))
+                .args(["--no-config", "--warnings=allow"])
+                .args(flags)
+                .arg("code.bs")
+                .current_dir(&dir)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{} failed: {}", name, String::from_utf8_lossy(&output.stderr));
+            best = best.min(start.elapsed());
+        }
+        println!("{:<16} {:>8.1} ms (best of {})", name, best.as_secs_f64() * 1000.0, RUNS);
+    }
+}
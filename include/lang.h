@@ -0,0 +1,48 @@
+/* Embedding LANG from C and C++. Link against the library
+ * `cargo rustc --release --lib --features ffi --crate-type cdylib` builds: liblang.so,
+ * liblang.dylib or lang.dll in target/release. */
+#ifndef LANG_H
+#define LANG_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct LangInterpreter LangInterpreter;
+
+/* What lang_get_var found */
+#define LANG_UNSET 0
+#define LANG_NUMBER 1
+#define LANG_STRING 2
+
+/* Gets `length` bytes of PRINT's text, which isn't NUL-terminated */
+typedef void (*LangOutputCallback)(const char *text, size_t length, void *user_data);
+
+/* NULL if the interpreter couldn't be made */
+LangInterpreter *lang_new(void);
+void lang_free(LangInterpreter *lang);
+
+/* 0 when the program parsed and passed its checks, -1 otherwise */
+int lang_load_source(LangInterpreter *lang, const char *source);
+
+/* 0 with the exit status, 0 or END's number, in *status, which can be NULL, or -1 when the
+ * program stopped with an error */
+int lang_run(LangInterpreter *lang, int *status);
+
+/* A string stays valid until the next lang_get_var or lang_free */
+int lang_get_var(LangInterpreter *lang, const char *name, double *number, const char **string);
+
+/* NULL sends output back to stdout */
+void lang_set_output_callback(LangInterpreter *lang, LangOutputCallback callback, void *user_data);
+
+/* The message of the last call that returned -1, or NULL. Functions that would have panicked
+ * return -1 with the panic's message here instead. */
+const char *lang_last_error(const LangInterpreter *lang);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,173 @@
+// basic!, which parses a LANG program while the Rust around it compiles, so a syntax error or a
+// GOTO to a missing line is a compile error pointing at the BASIC token:
+//
+//     let program = lang_macros::basic! {
+//         10 FOR I = 1 TO 3
+//         20 PRINT "HI ", I
+//         30 NEXT I
+//     };
+//     lang::Interpreter::new().run(&program)?;
+//
+// The program goes into the binary as the JSON of `Program::to_json`. The text between the braces
+// is read back from the Rust tokens with the original line breaks and spacing, so anything Rust
+// can't tokenize, like a backslash in a string or an apostrophe comment, has to stay out.
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+use lang::LangError;
+
+#[proc_macro]
+pub fn basic(input: TokenStream) -> TokenStream {
+    let mut source = Source::default();
+    source.add_stream(input);
+
+    let program = match lang::parse(&source.text).and_then(|program| lang::check_program(&program).map(|_| program)) {
+        Ok(program) => program,
+        Err(error) => {
+            let mut errors = Vec::new();
+            source.errors(&error, &mut errors);
+            return compile_errors(&errors);
+        },
+    };
+
+    // ::lang::Program::from_json("...").expect("...")
+    let mut output = path(&["lang", "Program", "from_json"]);
+    output.extend([TokenTree::Group(Group::new(
+        Delimiter::Parenthesis,
+        TokenTree::Literal(Literal::string(&program.to_json())).into(),
+    ))]);
+    output.extend([
+        TokenTree::Punct(Punct::new('.', Spacing::Alone)),
+        TokenTree::Ident(Ident::new("expect", Span::call_site())),
+        TokenTree::Group(Group::new(
+            Delimiter::Parenthesis,
+            TokenTree::Literal(Literal::string("basic! program from a different version of lang")).into(),
+        )),
+    ]);
+    output
+}
+
+// The BASIC text laid out as it was written, and where each token went in it
+#[derive(Default)]
+struct Source {
+    text: String,
+    // Line and column in `text`, counting from 1, of each token
+    tokens: Vec<(usize, usize, Span)>,
+    first_line: Option<usize>,
+    line: usize,
+    column: usize,
+}
+
+impl Source {
+    fn add_stream(&mut self, stream: TokenStream) {
+        for tree in stream {
+            match tree {
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::None => ("", ""),
+                    };
+                    self.add(open, group.span_open());
+                    self.add_stream(group.stream());
+                    self.add(close, group.span_close());
+                },
+                other => {
+                    let span = other.span();
+                    let text = span.source_text().unwrap_or_else(|| other.to_string());
+                    self.add(&text, span);
+                },
+            }
+        }
+    }
+
+    fn add(&mut self, text: &str, span: Span) {
+        if text.is_empty() {
+            return;
+        }
+        let first_line = *self.first_line.get_or_insert(span.line());
+        let line = span.line() - first_line + 1;
+        if line > self.line {
+            if self.line > 0 {
+                self.text.push_str(&"\n".repeat(line - self.line));
+            }
+            self.line = line;
+            self.column = 1;
+        }
+        // Columns from the span count from 1, and tokens on one line never go backwards
+        let column = span.column().max(self.column);
+        self.text.push_str(&" ".repeat(column - self.column));
+        self.tokens.push((line, column, span));
+        self.text.push_str(text);
+        self.column = column + text.chars().count();
+    }
+
+    // The last token at or before a position
+    fn span_at(&self, line: usize, column: usize) -> Span {
+        self.tokens.iter()
+            .take_while(|&&(token_line, token_column, _)| (token_line, token_column) <= (line, column))
+            .last()
+            .or(self.tokens.last())
+            .map_or(Span::call_site(), |&(_, _, span)| span)
+    }
+
+    fn errors(&self, error: &LangError, found: &mut Vec<(String, Span)>) {
+        match error {
+            LangError::LexError { line, column, message, .. } | LangError::ParseError { line, column, message, .. } => {
+                found.push((message.clone(), self.span_at(*line, *column)));
+            },
+            LangError::SemanticError { source_line, column, message, .. } | LangError::RuntimeError { source_line, column, message, .. } => {
+                found.push((message.clone(), self.span_at(*source_line, *column)));
+            },
+            LangError::Multiple(errors) => {
+                for error in errors {
+                    self.errors(error, found);
+                }
+            },
+        }
+    }
+}
+
+fn path(segments: &[&str]) -> TokenStream {
+    let mut stream = TokenStream::new();
+    for segment in segments {
+        stream.extend([
+            TokenTree::Punct(Punct::new(':', Spacing::Joint)),
+            TokenTree::Punct(Punct::new(':', Spacing::Alone)),
+            TokenTree::Ident(Ident::new(segment, Span::call_site())),
+        ]);
+    }
+    stream
+}
+
+// { compile_error!("message"); ... } with each call at its token's span, so rustc underlines the
+// BASIC that is wrong
+fn compile_errors(errors: &[(String, Span)]) -> TokenStream {
+    let mut calls = TokenStream::new();
+    for (message, span) in errors {
+        let mut message = Literal::string(message);
+        message.set_span(*span);
+        let mut arguments = Group::new(Delimiter::Parenthesis, TokenTree::Literal(message).into());
+        arguments.set_span(*span);
+        let mut bang = Punct::new('!', Spacing::Alone);
+        bang.set_span(*span);
+        let mut semicolon = Punct::new(';', Spacing::Alone);
+        semicolon.set_span(*span);
+        calls.extend([
+            TokenTree::Ident(Ident::new("compile_error", *span)),
+            TokenTree::Punct(bang),
+            TokenTree::Group(arguments),
+            TokenTree::Punct(semicolon),
+        ]);
+    }
+    // Ending in an empty program, ::lang::parse("").unwrap(), so the errors aren't followed by
+    // one about the block's type
+    calls.extend(path(&["lang", "parse"]));
+    calls.extend([
+        TokenTree::Group(Group::new(Delimiter::Parenthesis, TokenTree::Literal(Literal::string("")).into())),
+        TokenTree::Punct(Punct::new('.', Spacing::Alone)),
+        TokenTree::Ident(Ident::new("unwrap", Span::call_site())),
+        TokenTree::Group(Group::new(Delimiter::Parenthesis, TokenStream::new())),
+    ]);
+    TokenTree::Group(Group::new(Delimiter::Brace, calls)).into()
+}
@@ -0,0 +1,391 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use crate::errors::Code;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Purity {
+    Pure,
+    Impure(String),
+}
+
+// Where compiled code keeps a variable
+#[derive(Debug, Clone, PartialEq)]
+pub enum Storage {
+    Local,
+    Map(String),
+}
+
+const PURE_BUILTINS: &[&str] = &[
+    "ABS", "SQR", "SIN", "COS", "TAN", "INT", "MKI$", "MKS$", "MKD$", "CVI", "CVS", "CVD",
+];
+
+// A DEF FN is pure when its result depends only on its arguments: no RND, no
+// globals, no file state, and only calls to other pure functions
+pub fn function_purity(program: &crate::Program) -> HashMap<String, Purity> {
+    let mut definitions: Vec<(&String, &Vec<String>, &crate::Expression)> = Vec::new();
+    for line in &program.lines {
+        collect_definitions(&line.statement, &mut definitions);
+    }
+
+    let mut purity = HashMap::new();
+    let mut calls: HashMap<&String, Vec<String>> = HashMap::new();
+    for (name, parameters, body) in &definitions {
+        if purity.contains_key(*name) {
+            purity.insert((*name).clone(), Purity::Impure("defined more than once".to_string()));
+            continue;
+        }
+        let mut called = Vec::new();
+        let local = expression_purity(body, parameters, &mut called);
+        purity.insert((*name).clone(), local);
+        calls.insert(name, called);
+    }
+
+    // Impurity spreads to every caller until nothing changes
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (name, called) in &calls {
+            if purity.get(*name) != Some(&Purity::Pure) {
+                continue;
+            }
+            for callee in called {
+                let reason = match purity.get(callee) {
+                    Some(Purity::Pure) => continue,
+                    Some(Purity::Impure(_)) => format!("calls impure {}", callee),
+                    None => format!("calls undefined {}", callee),
+                };
+                purity.insert((*name).clone(), Purity::Impure(reason));
+                changed = true;
+                break;
+            }
+        }
+    }
+    purity
+}
+
+fn collect_definitions<'a>(
+    statement: &'a crate::Statement,
+    definitions: &mut Vec<(&'a String, &'a Vec<String>, &'a crate::Expression)>,
+) {
+    match statement {
+        crate::Statement::DefFn { name, parameters, body } => definitions.push((name, parameters, body)),
+        crate::Statement::If { then_branch, else_branch, .. } => {
+            collect_definitions(then_branch, definitions);
+            if let Some(else_branch) = else_branch {
+                collect_definitions(else_branch, definitions);
+            }
+        },
+        _ => {},
+    }
+}
+
+fn expression_purity(expr: &crate::Expression, parameters: &[String], called: &mut Vec<String>) -> Purity {
+    match expr {
+        crate::Expression::Number(_) | crate::Expression::String(_) => Purity::Pure,
+        crate::Expression::Variable(name, ..) => {
+            if parameters.contains(name) {
+                Purity::Pure
+            } else {
+                Purity::Impure(format!("reads global {}", name))
+            }
+        },
+        crate::Expression::Binary { left, right, .. } => {
+            match expression_purity(left, parameters, called) {
+                Purity::Pure => expression_purity(right, parameters, called),
+                impure => impure,
+            }
+        },
+        crate::Expression::FunctionCall { name, arguments, .. } => {
+            if name.starts_with("FN") {
+                called.push(name.clone());
+            } else if !PURE_BUILTINS.contains(&name.as_str()) {
+                return Purity::Impure(format!("uses {}", name));
+            }
+            for arg in arguments {
+                if let Purity::Impure(reason) = expression_purity(arg, parameters, called) {
+                    return Purity::Impure(reason);
+                }
+            }
+            Purity::Pure
+        },
+    }
+}
+
+// Variables are only ever named statically, so each one can be a plain Rust local unless
+// something reaches it by name at runtime, like a FIELD record buffer
+pub fn variable_storage(program: &crate::Program) -> BTreeMap<String, Storage> {
+    let mut storage: BTreeMap<String, Storage> = crate::stats::variable_names(program)
+        .into_iter()
+        .map(|name| (name, Storage::Local))
+        .collect();
+    for line in &program.lines {
+        collect_escapes(&line.statement, &mut storage);
+    }
+    storage
+}
+
+fn collect_escapes(statement: &crate::Statement, storage: &mut BTreeMap<String, Storage>) {
+    match statement {
+        crate::Statement::Field { fields, .. } => {
+            for (_, name) in fields {
+                storage.insert(name.clone(), Storage::Map("bound to a FIELD record buffer".to_string()));
+            }
+        },
+        crate::Statement::If { then_branch, else_branch, .. } => {
+            collect_escapes(then_branch, storage);
+            if let Some(else_branch) = else_branch {
+                collect_escapes(else_branch, storage);
+            }
+        },
+        _ => {},
+    }
+}
+
+// OPTION EXPLICIT: variables that are read somewhere but never assigned anywhere, which is
+// almost always a typo. Returns the line number and name of each, in program order.
+pub fn undeclared_variables(program: &crate::Program) -> Vec<(u32, String)> {
+    let mut assigned = HashSet::new();
+    for line in &program.lines {
+        collect_assignments(&line.statement, &mut assigned);
+    }
+    let mut undeclared = Vec::new();
+    for line in &program.lines {
+        let names: BTreeSet<String> = crate::stats::statement_variables(&line.statement).into_iter().collect();
+        for name in names {
+            if !assigned.contains(&name) {
+                undeclared.push((line.number, name));
+            }
+        }
+    }
+    undeclared
+}
+
+pub(crate) fn collect_assignments(statement: &crate::Statement, assigned: &mut HashSet<String>) {
+    match statement {
+        crate::Statement::Let { variable, .. }
+        | crate::Statement::Input { variable }
+        | crate::Statement::Receive { variable, .. }
+        | crate::Statement::SerialIn { variable, .. } => {
+            assigned.insert(variable.clone());
+        },
+        crate::Statement::For { loop_data } => {
+            assigned.insert(loop_data.variable.clone());
+        },
+        crate::Statement::Task { id_variable: Some(variable), .. } => {
+            assigned.insert(variable.clone());
+        },
+        crate::Statement::Field { fields, .. } => {
+            assigned.extend(fields.iter().map(|(_, name)| name.clone()));
+        },
+        // A program that was chained to gets these from the one before
+        crate::Statement::Common { variables } => {
+            assigned.extend(variables.iter().cloned());
+        },
+        // Parameters only exist inside the function body
+        crate::Statement::DefFn { parameters, .. } => {
+            assigned.extend(parameters.iter().cloned());
+        },
+        crate::Statement::If { then_branch, else_branch, .. } => {
+            collect_assignments(then_branch, assigned);
+            if let Some(else_branch) = else_branch {
+                collect_assignments(else_branch, assigned);
+            }
+        },
+        _ => {},
+    }
+}
+
+// Mistakes that show without running the program: GOTO or TASK to a line that doesn't exist,
+// NEXT with no FOR before it, and variables read where no way through the program has assigned
+// them yet. Returns the line index, where on the line, the code and the message for each, in program order.
+pub fn semantic_errors(program: &crate::Program) -> Vec<(usize, Option<crate::Position>, Code, String)> {
+    let lines = &program.lines;
+    let index = program.line_index();
+    let mut errors = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let (targets, _) = jumps(&line.statement);
+        for target in targets {
+            if !index.contains_key(&target) {
+                errors.push((i, None, Code::UndefinedLine, format!("Line {} doesn't exist", target)));
+            }
+        }
+        if let crate::Statement::Next { variable } = &line.statement {
+            if !lines[..i].iter().any(|line| is_for(&line.statement, variable)) {
+                errors.push((i, None, Code::NextWithoutFor, format!("NEXT {} without FOR", variable)));
+            }
+        }
+    }
+
+    let assigned = assigned_on_entry(program);
+    let mut reported = HashSet::new();
+    for (i, line) in lines.iter().enumerate() {
+        let Some(assigned) = &assigned[i] else {
+            continue;
+        };
+        let mut reads = Vec::new();
+        statement_reads(&line.statement, &mut reads);
+        for (name, position) in reads {
+            if !assigned.contains(&name) && reported.insert(name.clone()) {
+                errors.push((i, position, Code::UndefinedVariable, format!("{} is read before it is assigned", name)));
+            }
+        }
+    }
+    errors.sort_by_key(|(i, ..)| *i);
+    errors
+}
+
+fn is_for(statement: &crate::Statement, variable: &str) -> bool {
+    matches!(statement, crate::Statement::For { loop_data } if loop_data.variable == variable)
+}
+
+// The lines a statement can jump to, and whether it can carry on to the next line
+fn jumps(statement: &crate::Statement) -> (Vec<u32>, bool) {
+    match statement {
+        crate::Statement::Goto(target) => (vec![*target], false),
+        crate::Statement::Task { target, .. } => (vec![*target], true),
+        crate::Statement::End { .. } | crate::Statement::EndTask => (Vec::new(), false),
+        crate::Statement::If { then_branch, else_branch, .. } => {
+            let (mut targets, then_falls) = jumps(then_branch);
+            let else_falls = match else_branch {
+                Some(else_branch) => {
+                    let (else_targets, falls) = jumps(else_branch);
+                    targets.extend(else_targets);
+                    falls
+                },
+                None => true,
+            };
+            (targets, then_falls || else_falls)
+        },
+        _ => (Vec::new(), true),
+    }
+}
+
+// The lines each line can go on to. Tasks share variables with the code that started them, so
+// a task's first line counts as following every line, which is as early as it could run.
+pub(crate) fn successors(program: &crate::Program) -> Vec<Vec<usize>> {
+    let lines = &program.lines;
+    let index = program.line_index();
+    let mut successors: Vec<Vec<usize>> = Vec::with_capacity(lines.len());
+    let mut task_starts = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let (targets, falls) = jumps(&line.statement);
+        let mut next: Vec<usize> = targets.iter().filter_map(|target| index.get(target).copied()).collect();
+        if falls && i + 1 < lines.len() {
+            next.push(i + 1);
+        }
+        if let crate::Statement::Next { variable } = &line.statement {
+            next.extend((0..i).filter(|&j| is_for(&lines[j].statement, variable)).map(|j| j + 1));
+        }
+        if let crate::Statement::Task { target, .. } = &line.statement {
+            task_starts.extend(index.get(target).copied());
+        }
+        successors.push(next);
+    }
+    for next in &mut successors {
+        next.extend(task_starts.iter().copied());
+    }
+    successors
+}
+
+// Which lines some way through the program from its first line gets to
+pub(crate) fn reachable(program: &crate::Program) -> Vec<bool> {
+    let mut reached = vec![false; program.lines.len()];
+    if program.lines.is_empty() {
+        return reached;
+    }
+    let successors = successors(program);
+    let mut pending = vec![0];
+    while let Some(i) = pending.pop() {
+        if !std::mem::replace(&mut reached[i], true) {
+            pending.extend(successors[i].iter().copied());
+        }
+    }
+    reached
+}
+
+// For each line, the variables some way of reaching it has assigned, or None when nothing reaches it
+fn assigned_on_entry(program: &crate::Program) -> Vec<Option<HashSet<String>>> {
+    let lines = &program.lines;
+    let successors = successors(program);
+    let mut entry: Vec<Option<HashSet<String>>> = vec![None; lines.len()];
+    if lines.is_empty() {
+        return entry;
+    }
+    entry[0] = Some(HashSet::new());
+    let mut pending = vec![0];
+    while let Some(i) = pending.pop() {
+        let mut exit = entry[i].clone().unwrap_or_default();
+        if !matches!(lines[i].statement, crate::Statement::DefFn { .. }) {
+            collect_assignments(&lines[i].statement, &mut exit);
+        }
+        for &j in &successors[i] {
+            let first = entry[j].is_none();
+            let known = entry[j].get_or_insert_with(HashSet::new);
+            let before = known.len();
+            known.extend(exit.iter().cloned());
+            if first || known.len() > before {
+                pending.push(j);
+            }
+        }
+    }
+    entry
+}
+
+// The variables a statement reads, with where each is read when the expression says.
+// A DEF FN body only runs when the function is called, so it reads nothing here.
+pub(crate) fn statement_reads(statement: &crate::Statement, reads: &mut Vec<(String, Option<crate::Position>)>) {
+    match statement {
+        crate::Statement::Let { expression, .. } => expression_reads(expression, reads),
+        crate::Statement::If { condition, then_branch, else_branch } => {
+            expression_reads(condition, reads);
+            statement_reads(then_branch, reads);
+            if let Some(else_branch) = else_branch {
+                statement_reads(else_branch, reads);
+            }
+        },
+        crate::Statement::For { loop_data } => {
+            expression_reads(&loop_data.start, reads);
+            expression_reads(&loop_data.end, reads);
+            expression_reads(&loop_data.step, reads);
+        },
+        crate::Statement::Print { expressions, .. } => {
+            for expression in expressions {
+                expression_reads(expression, reads);
+            }
+        },
+        crate::Statement::Input { .. } | crate::Statement::Next { .. } | crate::Statement::Task { .. }
+        | crate::Statement::DefFn { .. } => {},
+        crate::Statement::Field { file_number, fields } => {
+            expression_reads(file_number, reads);
+            for (width, _) in fields {
+                expression_reads(width, reads);
+            }
+        },
+        crate::Statement::Lset { expression, .. } | crate::Statement::Rset { expression, .. } => {
+            expression_reads(expression, reads)
+        },
+        crate::Statement::Receive { channel, .. } => expression_reads(channel, reads),
+        crate::Statement::SerialIn { file_number, .. } => expression_reads(file_number, reads),
+        crate::Statement::Common { .. } => {},
+        // Everything else only reads
+        other => {
+            let names: BTreeSet<String> = crate::stats::statement_variables(other).into_iter().collect();
+            reads.extend(names.into_iter().map(|name| (name, None)));
+        },
+    }
+}
+
+pub(crate) fn expression_reads(expression: &crate::Expression, reads: &mut Vec<(String, Option<crate::Position>)>) {
+    match expression {
+        crate::Expression::Number(_) | crate::Expression::String(_) => {},
+        crate::Expression::Variable(name, _, position) => reads.push((name.clone(), Some(*position))),
+        crate::Expression::Binary { left, right, .. } => {
+            expression_reads(left, reads);
+            expression_reads(right, reads);
+        },
+        crate::Expression::FunctionCall { arguments, .. } => {
+            for argument in arguments {
+                expression_reads(argument, reads);
+            }
+        },
+    }
+}
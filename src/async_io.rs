@@ -0,0 +1,86 @@
+// Running a program on an async runtime, for hosts like chat bots and web servers that run many
+// programs at once without a thread each. Built with `--features async`. It brings no runtime of
+// its own, so tokio or any other executor can drive it. The program runs a statement at a time
+// through Interpreter::step: PRINT's text is handed to AsyncIo::write after each statement,
+// INPUT awaits AsyncIo::read_line, and SLEEP awaits AsyncIo::sleep instead of blocking the
+// thread. Random-access files, SHELL and sound still block while their statement runs.
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::io::BufferIo;
+use crate::{Interpreter, LangError, Program, StepResult};
+
+// How many statements run between chances for other tasks on the executor to run
+const STATEMENTS_PER_YIELD: usize = 1000;
+
+pub trait AsyncIo: Send {
+    // PRINT's text and INPUT's prompts, in the order the program wrote them
+    fn write(&mut self, text: &str) -> impl Future<Output = Result<(), String>> + Send;
+
+    // A line for INPUT without its newline, or None when there is no more input
+    fn read_line(&mut self) -> impl Future<Output = Result<Option<String>, String>> + Send;
+
+    fn sleep(&mut self, duration: Duration) -> impl Future<Output = ()> + Send;
+}
+
+impl Interpreter {
+    // Like run, with the program's input and output going through `io`. The interpreter's own
+    // io backend is put back when the run ends.
+    pub async fn run_async<A: AsyncIo>(&mut self, program: &Program, io: &mut A) -> Result<(), LangError> {
+        let buffer = BufferIo::new(Vec::new());
+        let output = buffer.output();
+        let previous = std::mem::replace(&mut self.io, Box::new(buffer));
+        self.async_sleep = Some(None);
+        let result = self.drive(program, io, &output).await;
+        self.async_sleep = None;
+        self.io = previous;
+        result
+    }
+
+    async fn drive<A: AsyncIo>(&mut self, program: &Program, io: &mut A, output: &Arc<Mutex<String>>) -> Result<(), LangError> {
+        self.load(program);
+        let mut statements = 0;
+        loop {
+            let step = self.step();
+            let text = std::mem::take(&mut *output.lock().unwrap());
+            if !text.is_empty() {
+                io.write(&text).await.map_err(|e| self.runtime_error(e))?;
+            }
+            if let Some(duration) = self.async_sleep.as_mut().and_then(Option::take) {
+                io.sleep(duration).await;
+            }
+            match step {
+                StepResult::Ran => {},
+                StepResult::NeedsInput => match io.read_line().await.map_err(|e| self.runtime_error(e))? {
+                    Some(line) => self.provide_input(&line),
+                    None => return Err(self.runtime_error("INPUT reached the end of the input".to_string())),
+                },
+                StepResult::Finished => return Ok(()),
+                StepResult::Error(e) => return Err(e),
+            }
+            statements += 1;
+            if statements % STATEMENTS_PER_YIELD == 0 {
+                YieldNow(false).await;
+            }
+        }
+    }
+}
+
+// Pending once, so a program that never waits on its io still lets other tasks have a turn
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        context.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use crate::io::IoBackend;
+use crate::errors::{Code, Error};
+
+// SOUND durations are in clock ticks, 18.2 to the second
+pub const TICKS_PER_SECOND: f64 = 18.2;
+const BEEP_FREQUENCY: f64 = 800.0;
+const BEEP_SECONDS: f64 = 0.25;
+
+// Where BEEP, SOUND and PLAY send their notes. Send so an interpreter can be moved to another thread.
+pub trait Audio: Send {
+    fn tone(&mut self, frequency: f64, seconds: f64) -> Result<(), String>;
+
+    // `io` is where the program's output goes, for a backend that beeps with the bell character
+    fn beep(&mut self, _io: &mut dyn IoBackend) -> Result<(), String> {
+        self.tone(BEEP_FREQUENCY, BEEP_SECONDS)
+    }
+
+    fn rest(&mut self, seconds: f64) -> Result<(), String> {
+        std::thread::sleep(Duration::from_secs_f64(seconds));
+        Ok(())
+    }
+
+    // Called once when the program ends normally
+    fn finish(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// The default backend: BEEP rings the terminal bell and tones keep their timing but make no sound
+pub struct Bell;
+
+impl Audio for Bell {
+    fn tone(&mut self, _frequency: f64, seconds: f64) -> Result<(), String> {
+        std::thread::sleep(Duration::from_secs_f64(seconds));
+        Ok(())
+    }
+
+    fn beep(&mut self, io: &mut dyn IoBackend) -> Result<(), String> {
+        io.write("\x07").and_then(|_| io.flush()).map_err(|e| e.message)
+    }
+}
+
+// Used under buffered I/O, where nothing should be heard or waited for
+pub struct Silent;
+
+impl Audio for Silent {
+    fn tone(&mut self, _frequency: f64, _seconds: f64) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn beep(&mut self, _io: &mut dyn IoBackend) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn rest(&mut self, _seconds: f64) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Sine tones through the sound card, available when built with `--features sound`
+#[cfg(feature = "sound")]
+pub struct Speaker {
+    sink: rodio::Sink,
+    // Dropped with the speaker, which lets the stream's thread end
+    _stream: std::sync::mpsc::Sender<()>,
+}
+
+#[cfg(feature = "sound")]
+impl Speaker {
+    // The output stream can't leave the thread that opened it, so it gets a thread of its own and
+    // the speaker, which only holds the sink, can move between threads with its interpreter
+    pub fn open() -> Result<Self, String> {
+        let (opened, handle) = std::sync::mpsc::channel();
+        let (stream, closed) = std::sync::mpsc::channel::<()>();
+        std::thread::spawn(move || match rodio::OutputStream::try_default() {
+            Ok((_stream, handle)) => {
+                let _ = opened.send(Ok(handle));
+                let _ = closed.recv();
+            },
+            Err(e) => {
+                let _ = opened.send(Err(format!("Error opening audio device: {}", e)));
+            },
+        });
+        let handle = handle.recv().map_err(|_| "Error opening audio device".to_string())??;
+        let sink = rodio::Sink::try_new(&handle)
+            .map_err(|e| format!("Error opening audio device: {}", e))?;
+        Ok(Speaker { sink, _stream: stream })
+    }
+}
+
+#[cfg(feature = "sound")]
+impl Audio for Speaker {
+    fn tone(&mut self, frequency: f64, seconds: f64) -> Result<(), String> {
+        use rodio::Source;
+        let wave = rodio::source::SineWave::new(frequency as f32)
+            .take_duration(Duration::from_secs_f64(seconds))
+            .amplify(0.2);
+        self.sink.append(wave);
+        self.sink.sleep_until_end();
+        Ok(())
+    }
+}
+
+// Falls back to the bell when there is no sound card to open
+#[cfg(feature = "sound")]
+pub fn default_backend() -> Box<dyn Audio> {
+    match Speaker::open() {
+        Ok(speaker) => Box::new(speaker),
+        Err(_) => Box::new(Bell),
+    }
+}
+
+#[cfg(not(feature = "sound"))]
+pub fn default_backend() -> Box<dyn Audio> {
+    Box::new(Bell)
+}
+
+pub fn check_sound(frequency: f64, ticks: f64) -> Result<f64, Error> {
+    if !(37.0..=32767.0).contains(&frequency) {
+        return Err(Error::new(Code::OutOfRange, format!("SOUND frequency {} out of range 37-32767", frequency)));
+    }
+    if !(0.0..=65535.0).contains(&ticks) {
+        return Err(Error::new(Code::OutOfRange, format!("SOUND duration {} out of range 0-65535", ticks)));
+    }
+    Ok(ticks / TICKS_PER_SECOND)
+}
@@ -0,0 +1,69 @@
+// MKI$, MKS$ and MKD$ pack a number into a 2, 4 or 8 byte string and CVI, CVS and CVD unpack
+// it again, for records in random-access files. Each byte is one character from U+0000 to
+// U+00FF, the same way FIELD buffers are read and written. Singles and doubles are IEEE
+// little-endian like QBasic, not the Microsoft Binary Format of GW-BASIC.
+shared! {
+    pub fn mki(n: f64) -> Result<String, String> {
+        let n = n.round();
+        if !(-32768.0..=32767.0).contains(&n) {
+            return Err(format!("Overflow in MKI$: {}", n));
+        }
+        Ok((n as i16).to_le_bytes().iter().map(|&b| b as char).collect())
+    }
+
+    pub fn mks(n: f64) -> Result<String, String> {
+        Ok((n as f32).to_le_bytes().iter().map(|&b| b as char).collect())
+    }
+
+    pub fn mkd(n: f64) -> Result<String, String> {
+        Ok(n.to_le_bytes().iter().map(|&b| b as char).collect())
+    }
+
+    pub fn cvi(text: &str) -> Result<f64, String> {
+        Ok(i16::from_le_bytes(unpack(text, "CVI")?) as f64)
+    }
+
+    pub fn cvs(text: &str) -> Result<f64, String> {
+        Ok(f32::from_le_bytes(unpack(text, "CVS")?) as f64)
+    }
+
+    pub fn cvd(text: &str) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(unpack(text, "CVD")?))
+    }
+
+    // The first N characters as bytes; anything after them is ignored, as a FIELD may be wider
+    fn unpack<const N: usize>(text: &str, name: &str) -> Result<[u8; N], String> {
+        let mut bytes = [0; N];
+        let mut chars = text.chars();
+        for byte in bytes.iter_mut() {
+            let c = chars.next().ok_or_else(|| format!("{} needs a string of at least {} bytes", name, N))?;
+            *byte = u8::try_from(c).map_err(|_| format!("{} needs a packed string, not {:?}", name, c))?;
+        }
+        Ok(bytes)
+    }
+
+    // Compiled programs stop with the message where the interpreter would report an error
+    #[allow(dead_code)]
+    pub fn or_exit<T>(result: Result<T, String>) -> T {
+        result.unwrap_or_else(|message| {
+            eprintln!("{}", message);
+            std::process::exit(1)
+        })
+    }
+}
+
+// Helpers emitted into compiled programs that use MKI$, CVI and the rest
+pub fn rust_helpers() -> String {
+    format!("\n{}\n", RUST_SOURCE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unpack_takes_only_packed_characters() {
+        assert_eq!(cvi(&mki(-2.0).unwrap()).unwrap(), -2.0);
+        assert_eq!(cvi("\u{100}\0").unwrap_err(), "CVI needs a packed string, not '\u{100}'");
+    }
+}
@@ -0,0 +1,94 @@
+// Turning generated code into a program. Rust goes into a Cargo project, where Cargo keeps what
+// it built between runs; C goes to the C compiler. Either way only the program itself is left
+// where it was asked for.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::compiler::Target;
+
+// How hard the compiler optimizes, and flags handed straight to rustc
+#[derive(Default)]
+pub struct Options {
+    // 0 to 3, s or z from -O0 to -Oz; without one, Rust builds at Cargo's release level and C at 2
+    pub opt_level: Option<String>,
+    pub rustc_args: Vec<String>,
+}
+
+// Where intermediate files go without --target-dir: a directory in the system's temp dir for
+// each directory programs are compiled from, so two of them don't trample each other's builds
+pub fn default_dir() -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    std::env::current_dir().unwrap_or_default().hash(&mut hasher);
+    std::env::temp_dir().join(format!("lang-build-{:016x}", hasher.finish()))
+}
+
+// Builds the program in a Cargo project in `project` and copies it to `output`
+pub fn rust(
+    source: &str,
+    manifest: &str,
+    target: &Target,
+    project: &Path,
+    output: &str,
+    options: &Options,
+) -> Result<(), String> {
+    let source_path = project.join(target.source_path());
+    std::fs::create_dir_all(project.join("src"))
+        .map_err(|e| format!("Error creating {}: {}", project.display(), e))?;
+    // A program built for the other target leaves its source behind, which Cargo would build too
+    for stale in ["src/main.rs", "src/lib.rs"] {
+        let stale = project.join(stale);
+        if stale != source_path && stale.exists() {
+            std::fs::remove_file(&stale).map_err(|e| format!("Error removing {}: {}", stale.display(), e))?;
+        }
+    }
+    let mut manifest = manifest.to_string();
+    match options.opt_level.as_deref() {
+        Some(level @ ("s" | "z")) => manifest.push_str(&format!("\n[profile.release]\nopt-level = \"{}\"\n", level)),
+        Some(level) => manifest.push_str(&format!("\n[profile.release]\nopt-level = {}\n", level)),
+        None => {},
+    }
+    std::fs::write(project.join("Cargo.toml"), manifest)
+        .map_err(|e| format!("Error writing Cargo.toml: {}", e))?;
+    std::fs::write(&source_path, source)
+        .map_err(|e| format!("Error writing Rust code: {}", e))?;
+
+    // cargo rustc rather than cargo build, so the extra flags reach only the program's own crate
+    let result = Command::new("cargo")
+        .args(["rustc", "--release", "--quiet", "--manifest-path"])
+        .arg(project.join("Cargo.toml"))
+        .args(target.cargo_args())
+        .arg("--")
+        .args(&options.rustc_args)
+        .output()
+        .map_err(|e| format!("Failed to run cargo: {}", e))?;
+    if !result.status.success() {
+        return Err(format!("Compilation failed: {}", String::from_utf8_lossy(&result.stderr)));
+    }
+
+    let built = project.join(target.artifact());
+    std::fs::copy(&built, output)
+        .map_err(|e| format!("Error copying {} to {}: {}", built.display(), output, e))?;
+    Ok(())
+}
+
+// Builds with the C compiler in $CC, as make uses, or whatever cc is, from a file in `dir`
+pub fn c(source: &str, dir: &Path, output: &str, options: &Options) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("Error creating {}: {}", dir.display(), e))?;
+    let source_path = dir.join("code.c");
+    std::fs::write(&source_path, source)
+        .map_err(|e| format!("Error writing C code: {}", e))?;
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let result = Command::new(&cc)
+        .arg(format!("-O{}", options.opt_level.as_deref().unwrap_or("2")))
+        .arg("-std=c99")
+        .arg(&source_path)
+        .args(["-o", output, "-lm"])
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}; set CC to your C compiler", cc, e))?;
+    if !result.status.success() {
+        return Err(format!("Compilation failed: {}", String::from_utf8_lossy(&result.stderr)));
+    }
+    Ok(())
+}
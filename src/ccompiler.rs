@@ -0,0 +1,310 @@
+// --emit c: programs written out as C99 instead of Rust, for systems with a C compiler but no Rust
+// toolchain. Every variable is a double and every line a label, so GOTO and IF ... THEN are plain
+// C jumps. Each NEXT belongs to the FOR it closes in the source, and like the interpreter it
+// works out the STEP and the end again before going round.
+use std::collections::BTreeSet;
+
+const HELPERS: &str = r#"
+static void print_number(double n) {
+    char scientific[32], digits[20];
+    int precision, exponent, count = 0, point, i;
+    const char *c;
+    if (n != n) {
+        fputs(" NaN", stdout);
+        return;
+    }
+    if (n == 0.0) {
+        fputs(" 0", stdout);
+        return;
+    }
+    if (isinf(n)) {
+        fputs(n < 0.0 ? "-inf" : " inf", stdout);
+        return;
+    }
+    /* The fewest digits that read back as the same number, written out without an exponent
+       the way the interpreter prints it */
+    for (precision = 0; precision < 17; precision++) {
+        snprintf(scientific, sizeof scientific, "%.*e", precision, n);
+        if (strtod(scientific, NULL) == n) {
+            break;
+        }
+    }
+    for (c = scientific; *c != 'e'; c++) {
+        if (isdigit((unsigned char)*c)) {
+            digits[count++] = *c;
+        }
+    }
+    exponent = atoi(c + 1);
+    while (count > 1 && digits[count - 1] == '0') {
+        count--;
+    }
+    putchar(n < 0.0 ? '-' : ' ');
+    point = exponent + 1;
+    if (point <= 0) {
+        fputs("0.", stdout);
+        for (i = 0; i < -point; i++) {
+            putchar('0');
+        }
+        fwrite(digits, 1, count, stdout);
+    } else if (point >= count) {
+        fwrite(digits, 1, count, stdout);
+        for (i = count; i < point; i++) {
+            putchar('0');
+        }
+    } else {
+        fwrite(digits, 1, point, stdout);
+        putchar('.');
+        fwrite(digits + point, 1, count - point, stdout);
+    }
+}
+
+static double input_number(const char *name) {
+    char line[256];
+    char *end;
+    double n;
+    printf("Enter %s: ", name);
+    fflush(stdout);
+    if (!fgets(line, sizeof line, stdin)) {
+        line[0] = '\0';
+    }
+    n = strtod(line, &end);
+    while (isspace((unsigned char)*end)) {
+        end++;
+    }
+    if (end == line || *end != '\0') {
+        fprintf(stderr, "Invalid number input\n");
+        exit(1);
+    }
+    return n;
+}
+"#;
+
+// A FOR whose NEXT hasn't been reached yet
+struct OpenLoop {
+    label: usize,
+    variable: String,
+    end: String,
+    step: String,
+}
+
+#[derive(Default)]
+pub struct CCompiler {
+    indent_level: usize,
+    loops: Vec<OpenLoop>,
+    loop_count: usize,
+    uses_rnd: bool,
+}
+
+impl CCompiler {
+    pub fn new() -> Self {
+        CCompiler::default()
+    }
+
+    // The C source, or what in the program has no C translation yet
+    pub fn compile_program(&mut self, program: &crate::Program) -> Result<String, String> {
+        let mut targets = BTreeSet::new();
+        for line in &program.lines {
+            jump_targets(&line.statement, &mut targets);
+        }
+        let mut variables = crate::stats::variable_names(program).into_iter().collect::<Vec<_>>();
+        variables.sort();
+
+        self.indent_level = 1;
+        let mut body = String::new();
+        for line in &program.lines {
+            if targets.contains(&line.number) {
+                body.push_str(&format!("line_{}: ;\n", line.number));
+            }
+            let code = self.compile_statement(&line.statement, true)
+                .map_err(|e| format!("Line {} can't be compiled to C: {}", line.number, e))?;
+            body.push_str(&code);
+        }
+        if let Some(open) = self.loops.last() {
+            return Err(format!("FOR {} has no NEXT, which C needs to close the loop", open.variable));
+        }
+
+        let mut output = String::from(
+            "#include <ctype.h>\n#include <math.h>\n#include <stdio.h>\n#include <stdlib.h>\n#include <time.h>\n",
+        );
+        output.push_str(HELPERS);
+        output.push_str("\nint main(void) {\n");
+        for name in &variables {
+            if name.ends_with('$') {
+                return Err(format!("{} is a string variable, and only numbers can be compiled to C", name));
+            }
+            output.push_str(&format!("    double {} = 0.0;\n", variable_name(name)));
+        }
+        if self.uses_rnd {
+            output.push_str("    srand((unsigned)time(NULL));\n");
+        }
+        output.push_str(&body);
+        output.push_str("    return 0;\n}\n");
+        Ok(output)
+    }
+
+    fn indent(&self) -> String {
+        "    ".repeat(self.indent_level)
+    }
+
+    // `top_level` is false inside an IF, where a FOR or NEXT would leave its loop half open
+    fn compile_statement(&mut self, statement: &crate::Statement, top_level: bool) -> Result<String, String> {
+        let indent = self.indent();
+        let mut output = String::new();
+        match statement {
+            crate::Statement::Let { variable, expression, .. } => {
+                output.push_str(&format!("{}{} = {};\n", indent, variable_name(variable), self.compile_expression(expression)?));
+            },
+            crate::Statement::Print { expressions, semicolon } => {
+                for (i, expr) in expressions.iter().enumerate() {
+                    if i > 0 {
+                        output.push_str(&format!("{}putchar(' ');\n", indent));
+                    }
+                    match expr {
+                        crate::Expression::String(text) => {
+                            output.push_str(&format!("{}fputs({}, stdout);\n", indent, c_string(text)));
+                        },
+                        expr => {
+                            output.push_str(&format!("{}print_number({});\n", indent, self.compile_expression(expr)?));
+                        },
+                    }
+                }
+                if !semicolon {
+                    output.push_str(&format!("{}putchar('\\n');\n", indent));
+                }
+            },
+            crate::Statement::Input { variable } => {
+                output.push_str(&format!("{}{} = input_number({});\n", indent, variable_name(variable), c_string(variable)));
+            },
+            crate::Statement::If { condition, then_branch, else_branch } => {
+                output.push_str(&format!("{}if (({}) != 0.0) {{\n", indent, self.compile_expression(condition)?));
+                self.indent_level += 1;
+                output.push_str(&self.compile_statement(then_branch, false)?);
+                if let Some(else_branch) = else_branch {
+                    output.push_str(&format!("{}}} else {{\n", indent));
+                    output.push_str(&self.compile_statement(else_branch, false)?);
+                }
+                self.indent_level -= 1;
+                output.push_str(&format!("{}}}\n", indent));
+            },
+            crate::Statement::For { .. } | crate::Statement::Next { .. } if !top_level => {
+                return Err("FOR and NEXT have to be on lines of their own".to_string());
+            },
+            crate::Statement::For { loop_data } => {
+                let var = variable_name(&loop_data.variable);
+                output.push_str(&format!("{}{} = {};\n", indent, var, self.compile_expression(&loop_data.start)?));
+                let label = self.loop_count;
+                self.loop_count += 1;
+                output.push_str(&format!("for_{}: ;\n", label));
+                let end = self.compile_expression(&loop_data.end)?;
+                let step = self.compile_expression(&loop_data.step)?;
+                self.loops.push(OpenLoop { label, variable: loop_data.variable.clone(), end, step });
+            },
+            crate::Statement::Next { variable } => {
+                let open = match self.loops.pop() {
+                    Some(open) if open.variable == *variable => open,
+                    Some(open) => return Err(format!("NEXT {} doesn't match FOR {}", variable, open.variable)),
+                    None => return Err("NEXT without FOR".to_string()),
+                };
+                let var = variable_name(variable);
+                output.push_str(&format!("{}{{\n", indent));
+                output.push_str(&format!("{}    double step = {};\n", indent, open.step));
+                output.push_str(&format!("{}    double next = {} + step;\n", indent, var));
+                output.push_str(&format!("{}    double end = {};\n", indent, open.end));
+                output.push_str(&format!("{}    if ((step > 0.0 && next <= end) || (step < 0.0 && next >= end)) {{\n", indent));
+                output.push_str(&format!("{}        {} = next;\n", indent, var));
+                output.push_str(&format!("{}        goto for_{};\n", indent, open.label));
+                output.push_str(&format!("{}    }}\n", indent));
+                output.push_str(&format!("{}}}\n", indent));
+            },
+            crate::Statement::Goto(target) => output.push_str(&format!("{}goto line_{};\n", indent, target)),
+            crate::Statement::End { code: None } => output.push_str(&format!("{}return 0;\n", indent)),
+            crate::Statement::End { code: Some(code) } => {
+                output.push_str(&format!("{}return (int)({});\n", indent, self.compile_expression(code)?));
+            },
+            crate::Statement::Cls => output.push_str(&format!("{}fputs(\"\\x1b[2J\\x1b[H\", stdout);\n", indent)),
+            crate::Statement::Rem(_) | crate::Statement::OptionExplicit => {},
+            other => return Err(format!("{} isn't supported", crate::stats::keyword(other))),
+        }
+        Ok(output)
+    }
+
+    fn compile_expression(&mut self, expr: &crate::Expression) -> Result<String, String> {
+        Ok(match expr {
+            crate::Expression::Number(n) => format!("{:?}", n),
+            crate::Expression::String(_) => return Err("strings can only be printed".to_string()),
+            crate::Expression::Variable(name, ..) => variable_name(name),
+            crate::Expression::Binary { left, operator, right, .. } => {
+                let left = self.compile_expression(left)?;
+                let right = self.compile_expression(right)?;
+                let comparison = match operator {
+                    crate::Token::Plus => return Ok(format!("({} + {})", left, right)),
+                    crate::Token::Minus => return Ok(format!("({} - {})", left, right)),
+                    crate::Token::Multiply => return Ok(format!("({} * {})", left, right)),
+                    crate::Token::Divide => return Ok(format!("({} / {})", left, right)),
+                    crate::Token::Power => return Ok(format!("pow({}, {})", left, right)),
+                    crate::Token::LessThan => "<",
+                    crate::Token::GreaterThan => ">",
+                    crate::Token::Equals => "==",
+                    crate::Token::LessOrEqual => "<=",
+                    crate::Token::GreaterOrEqual => ">=",
+                    crate::Token::NotEqual => "!=",
+                    other => return Err(format!("the operator {:?} isn't supported", other)),
+                };
+                format!("({} {} {} ? 1.0 : 0.0)", left, comparison, right)
+            },
+            crate::Expression::FunctionCall { name, arguments, .. } => {
+                let function = match name.as_str() {
+                    "ABS" => "fabs",
+                    "SQR" => "sqrt",
+                    "SIN" => "sin",
+                    "COS" => "cos",
+                    "TAN" => "tan",
+                    "INT" => "floor",
+                    "RND" => {
+                        self.uses_rnd = true;
+                        return Ok("(rand() / (RAND_MAX + 1.0))".to_string());
+                    },
+                    _ => return Err(format!("{} isn't supported", name)),
+                };
+                let argument = arguments.first().ok_or_else(|| format!("{} needs an argument", name))?;
+                format!("{}({})", function, self.compile_expression(argument)?)
+            },
+        })
+    }
+}
+
+fn variable_name(name: &str) -> String {
+    format!("v_{}", name.to_lowercase())
+}
+
+// A C string literal, with anything unusual as an octal escape so it can't run into the next character
+fn c_string(text: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in text.bytes() {
+        match byte {
+            b'"' => literal.push_str("\\\""),
+            b'\\' => literal.push_str("\\\\"),
+            // Or it could start a trigraph
+            b'?' => literal.push_str("\\?"),
+            b' '..=b'~' => literal.push(byte as char),
+            _ => literal.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+fn jump_targets(statement: &crate::Statement, targets: &mut BTreeSet<u32>) {
+    match statement {
+        crate::Statement::Goto(target) => {
+            targets.insert(*target);
+        },
+        crate::Statement::If { then_branch, else_branch, .. } => {
+            jump_targets(then_branch, targets);
+            if let Some(else_branch) = else_branch {
+                jump_targets(else_branch, targets);
+            }
+        },
+        _ => {},
+    }
+}
@@ -0,0 +1,157 @@
+// CHECKPOINT, and SIGTERM or SIGHUP under --checkpoint: save where a long run has got to and stop,
+// so --resume can carry on later. A snapshot holds the variables, DEF types, DEF FN functions,
+// open FOR loops and the line to continue from. Open files, graphics, sprites and tasks are not
+// saved, so a program can't checkpoint while it has files open or tasks running.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+
+use crate::{store, Value, VarType};
+use crate::errors::{Code, Error};
+
+pub const DEFAULT_FILE: &str = "checkpoint.snap";
+const HEADER: &str = "LANG checkpoint 1";
+
+static REQUESTED: LazyLock<Arc<AtomicBool>> = LazyLock::new(|| Arc::new(AtomicBool::new(false)));
+
+// From now on SIGTERM and SIGHUP ask for a checkpoint before the next statement instead of killing the process
+#[cfg(unix)]
+pub fn watch_signals() -> Result<(), String> {
+    for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGHUP] {
+        signal_hook::flag::register(signal, Arc::clone(&REQUESTED))
+            .map_err(|e| format!("Error watching for signals: {}", e))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn watch_signals() -> Result<(), String> {
+    Err("--checkpoint signals are only supported on Unix; use the CHECKPOINT statement".to_string())
+}
+
+pub(crate) fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+// The flag itself, for JIT-compiled code that checks it without calling back
+#[cfg(feature = "jit")]
+pub(crate) fn requested_flag() -> *const u8 {
+    REQUESTED.as_ptr() as *const u8
+}
+
+pub(crate) struct Snapshot {
+    // Identifies the program, so a snapshot isn't resumed against different code
+    pub fingerprint: u64,
+    // Index of the line to continue from
+    pub line: usize,
+    pub steps: usize,
+    pub var_types: [VarType; 26],
+    pub variables: Vec<(String, Value)>,
+    // Line indexes of the open FOR statements, outermost first
+    pub loops: Vec<usize>,
+    // Each function with the index of the line whose DEF FN defined it
+    pub functions: Vec<(String, usize)>,
+}
+
+// FNV-1a over the line numbers, which is stable across builds unlike the std hasher
+pub(crate) fn fingerprint(numbers: impl Iterator<Item = u32>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for number in numbers {
+        for byte in number.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+impl Snapshot {
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let mut text = format!("{}\nprogram {}\nline {}\nsteps {}\n", HEADER, self.fingerprint, self.line, self.steps);
+        let types: String = self.var_types.iter().map(|var_type| match var_type {
+            VarType::Integer => 'I',
+            VarType::Single => 'S',
+            VarType::Double => 'D',
+            VarType::String => '$',
+        }).collect();
+        text.push_str(&format!("types {}\n", types));
+        for (name, value) in &self.variables {
+            let value = match value {
+                Value::Number(n) => n.to_string(),
+                Value::String(s) => store::quote(s),
+            };
+            text.push_str(&format!("var {} {}\n", name, value));
+        }
+        for line in &self.loops {
+            text.push_str(&format!("for {}\n", line));
+        }
+        for (name, line) in &self.functions {
+            text.push_str(&format!("fn {} {}\n", name, line));
+        }
+        // Through a temporary file, so a signal arriving twice can't leave half a snapshot
+        let temporary = format!("{}.tmp", path);
+        std::fs::write(&temporary, text)
+            .and_then(|_| std::fs::rename(&temporary, path))
+            .map_err(|e| Error::new(Code::FileError, format!("Error writing checkpoint {}: {}", path, e)))
+    }
+
+    pub fn load(path: &str) -> Result<Snapshot, Error> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::new(Code::FileError, format!("Error reading checkpoint {}: {}", path, e)))?;
+        Ok(Snapshot::parse(&text).map_err(|e| format!("Bad checkpoint {}: {}", path, e))?)
+    }
+
+    fn parse(text: &str) -> Result<Snapshot, String> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err("not a LANG checkpoint".to_string());
+        }
+        let mut snapshot = Snapshot {
+            fingerprint: 0,
+            line: 0,
+            steps: 0,
+            var_types: [VarType::Double; 26],
+            variables: Vec::new(),
+            loops: Vec::new(),
+            functions: Vec::new(),
+        };
+        for line in lines {
+            let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "program" => snapshot.fingerprint = number(rest)?,
+                "line" => snapshot.line = number(rest)?,
+                "steps" => snapshot.steps = number(rest)?,
+                "types" => {
+                    for (slot, c) in snapshot.var_types.iter_mut().zip(rest.chars()) {
+                        *slot = match c {
+                            'I' => VarType::Integer,
+                            'S' => VarType::Single,
+                            'D' => VarType::Double,
+                            '$' => VarType::String,
+                            _ => return Err(format!("bad type {}", c)),
+                        };
+                    }
+                },
+                "var" => {
+                    let (name, value) = rest.split_once(' ').ok_or("bad variable")?;
+                    let value = if value.starts_with('"') {
+                        Value::String(store::string(&mut value.chars().peekable())?)
+                    } else {
+                        Value::Number(number(value)?)
+                    };
+                    snapshot.variables.push((name.to_string(), value));
+                },
+                "for" => snapshot.loops.push(number(rest)?),
+                "fn" => {
+                    let (name, line) = rest.split_once(' ').ok_or("bad function")?;
+                    snapshot.functions.push((name.to_string(), number(line)?));
+                },
+                _ => return Err(format!("unknown entry {}", key)),
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+fn number<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("bad number {}", text))
+}
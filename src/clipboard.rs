@@ -0,0 +1,52 @@
+// The desktop clipboard for CLIPGET$ and CLIPSET, available when built with `--features clipboard`
+
+#[cfg(feature = "clipboard")]
+pub struct Clipboard {
+    // Opened by the first CLIPSET and kept for the rest of the run, since on X11 the
+    // text is only on the clipboard while the program that set it is still holding it
+    owner: Option<arboard::Clipboard>,
+}
+
+#[cfg(feature = "clipboard")]
+impl Clipboard {
+    pub fn new() -> Self {
+        Clipboard { owner: None }
+    }
+
+    // An empty clipboard, or one holding something other than text, reads as ""
+    pub fn get(&self) -> Result<String, String> {
+        match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text()) {
+            Ok(text) => Ok(text),
+            Err(arboard::Error::ContentNotAvailable) => Ok(String::new()),
+            Err(e) => Err(format!("Error reading clipboard: {}", e)),
+        }
+    }
+
+    pub fn set(&mut self, text: &str) -> Result<(), String> {
+        let owner = match &mut self.owner {
+            Some(owner) => owner,
+            None => self.owner.insert(
+                arboard::Clipboard::new().map_err(|e| format!("Error opening clipboard: {}", e))?,
+            ),
+        };
+        owner.set_text(text).map_err(|e| format!("Error writing clipboard: {}", e))
+    }
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub struct Clipboard;
+
+#[cfg(not(feature = "clipboard"))]
+impl Clipboard {
+    pub fn new() -> Self {
+        Clipboard
+    }
+
+    pub fn get(&self) -> Result<String, String> {
+        Err("CLIPGET$ needs a build with `--features clipboard`".to_string())
+    }
+
+    pub fn set(&mut self, _text: &str) -> Result<(), String> {
+        Err("CLIPSET needs a build with `--features clipboard`".to_string())
+    }
+}
@@ -1,8 +1,181 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::analyzer::Storage;
+
+const NATIVE_FUNCTIONS: &[&str] = &["ABS", "SQR", "SIN", "COS", "TAN", "INT"];
+
+// What the generated Rust is built into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Native,
+    // A WebAssembly module for a web page, which provides PRINT and INPUT
+    Wasm,
+    // An executable for another platform, named by its rustc target triple
+    Triple(String),
+}
+
+impl Target {
+    pub fn parse(text: &str) -> Result<Target, String> {
+        match text {
+            "native" => Ok(Target::Native),
+            "wasm" => Ok(Target::Wasm),
+            triple if triple.contains('-') => Ok(Target::Triple(triple.to_string())),
+            _ => Err(format!("--target must be native, wasm or a target triple like aarch64-unknown-linux-gnu, not {}", text)),
+        }
+    }
+
+    // Where the program goes in the generated Cargo project
+    pub fn source_path(&self) -> &'static str {
+        match self {
+            Target::Wasm => "src/lib.rs",
+            _ => "src/main.rs",
+        }
+    }
+
+    fn triple(&self) -> Option<&str> {
+        match self {
+            Target::Native => None,
+            Target::Wasm => Some("wasm32-unknown-unknown"),
+            Target::Triple(triple) => Some(triple),
+        }
+    }
+
+    pub fn cargo_args(&self) -> Vec<String> {
+        match self.triple() {
+            Some(triple) => vec!["--target".to_string(), triple.to_string()],
+            None => Vec::new(),
+        }
+    }
+
+    // What cargo build leaves in the project, and the file it's copied to
+    pub fn artifact(&self) -> String {
+        match self.triple() {
+            Some(triple) => format!("target/{}/release/{}", triple, self.default_output()),
+            None => format!("target/release/{}", self.default_output()),
+        }
+    }
+
+    // The file built without -o: `code` with whatever the target's executables end in
+    pub fn default_output(&self) -> String {
+        let extension = match self.triple() {
+            None => std::env::consts::EXE_SUFFIX,
+            Some(triple) if triple.starts_with("wasm") => ".wasm",
+            Some(triple) if triple.contains("windows") => ".exe",
+            Some(_) => "",
+        };
+        format!("code{}", extension)
+    }
+}
+
+// Put ahead of main in a wasm build. The page imports lang.print(pointer, length), which shows
+// UTF-8 text from the module's memory, and lang.input(pointer, capacity), which writes a line
+// there and returns its length; the module exports run. Defining print! and println! here
+// sends everything the rest of the program prints to the page.
+const WASM_PRELUDE: &str = r#"
+#[link(wasm_import_module = "lang")]
+extern "C" {
+    #[link_name = "print"]
+    fn host_print(text: *const u8, len: usize);
+    #[link_name = "input"]
+    fn host_input(buffer: *mut u8, capacity: usize) -> usize;
+}
+
+fn write_host(text: &str) {
+    unsafe { host_print(text.as_ptr(), text.len()) }
+}
+
+fn read_host_line() -> String {
+    let mut buffer = vec![0u8; 1024];
+    let len = unsafe { host_input(buffer.as_mut_ptr(), buffer.len()) };
+    buffer.truncate(len);
+    String::from_utf8_lossy(&buffer).into_owned()
+}
+
+macro_rules! print {
+    ($($arg:tt)*) => { write_host(&format!($($arg)*)) };
+}
+
+macro_rules! println {
+    () => { write_host("\n") };
+}
+
+#[no_mangle]
+pub extern "C" fn run() {
+    main()
+}
+"#;
+
+// RND without the rand crate, so programs build offline and for wasm: xorshift64*, seeded
+// from the clock the first time it's called
+const RND_HELPER: &str = r#"
+fn basic_rnd() -> f64 {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static STATE: AtomicU64 = AtomicU64::new(0);
+    let mut x = STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        x = rnd_seed().wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    }
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    STATE.store(x, Ordering::Relaxed);
+    // The top 53 bits of the output, as a fraction in [0, 1)
+    (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn rnd_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+// A web page's module has no clock to read, so it gets the same numbers every run
+#[cfg(target_arch = "wasm32")]
+fn rnd_seed() -> u64 {
+    1
+}
+"#;
+
+// Where GOTO and NEXT go in a program compiled as a state machine
+struct Jumps {
+    // Line number to index in the program
+    lines: HashMap<u32, usize>,
+    // The index and FOR each NEXT closes, by the NEXT's line index, or why it closes none
+    loops: BTreeMap<usize, Result<(usize, crate::ForLoop), String>>,
+    // Index of the line being compiled
+    current: usize,
+}
 
 pub struct Compiler {
     temp_vars: usize,
     indent_level: usize,
+    uses_color: bool,
+    uses_numbers: bool,
+    uses_binary: bool,
+    uses_rnd: bool,
+    pub native_loops: bool,
+    pub target: Target,
+    // --readable: comments with the BASIC each block came from, and BASIC variable names
+    pub readable: bool,
+    // The program's source, for those comments
+    pub source: String,
+    storage: BTreeMap<String, Storage>,
+    // Line numbers left out of the last compiled program because nothing can reach them
+    removed: Vec<u32>,
+    // Map variables hoisted into Rust locals while compiling a native loop
+    locals: Option<BTreeSet<String>>,
+    // Set while compiling a program that jumps
+    jumps: Option<Jumps>,
+    // FORs compiled to a Rust loop whose NEXT hasn't been reached yet
+    open_loops: Vec<crate::ForLoop>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Compiler {
@@ -10,6 +183,19 @@ impl Compiler {
         Compiler {
             temp_vars: 0,
             indent_level: 0,
+            uses_color: false,
+            uses_numbers: false,
+            uses_binary: false,
+            uses_rnd: false,
+            native_loops: true,
+            target: Target::Native,
+            readable: false,
+            source: String::new(),
+            storage: BTreeMap::new(),
+            removed: Vec::new(),
+            locals: None,
+            jumps: None,
+            open_loops: Vec::new(),
         }
     }
 
@@ -22,35 +208,324 @@ impl Compiler {
         format!("temp_{}", self.temp_vars)
     }
 
-    pub fn compile_program(&mut self, program: &crate::Program) -> String {
+    pub fn compile_program(&mut self, program: &crate::Program) -> Result<String, String> {
         let mut output = String::new();
         
         // Add necessary imports and main function
         output.push_str("use std::io::{self, Write};\n\n");
+        if self.target == Target::Wasm {
+            output.push_str(WASM_PRELUDE.trim_start());
+            output.push('\n');
+        }
         output.push_str("fn main() {\n");
         self.indent_level += 1;
         
-        // Add variables hashmap
-        output.push_str(&self.indent());
-        output.push_str("let mut variables: HashMap<String, f64> = HashMap::new();\n");
+        // Variables live in typed locals, f64 or String; the maps are only created for the
+        // ones that need them
+        self.storage = crate::analyzer::variable_storage(program);
+        let in_map = |strings: bool| {
+            self.storage.iter().any(|(name, storage)| matches!(storage, Storage::Map(_)) && name.ends_with('$') == strings)
+        };
+        let uses_map = in_map(false) || in_map(true);
+        if in_map(false) {
+            output.push_str(&self.indent());
+            output.push_str("let mut variables: HashMap<String, f64> = HashMap::new();\n");
+        }
+        if in_map(true) {
+            output.push_str(&self.indent());
+            output.push_str("let mut strings: HashMap<String, String> = HashMap::new();\n");
+        }
+        for (name, storage) in &self.storage {
+            if *storage == Storage::Local {
+                output.push_str(&self.indent());
+                if name.ends_with('$') {
+                    output.push_str(&format!("let mut {}: String = String::new();\n", self.local_name(name)));
+                } else {
+                    output.push_str(&format!("let mut {}: f64 = 0.0;\n", self.local_name(name)));
+                }
+            }
+        }
         
-        // Compile each statement
-        for line in &program.lines {
-            output.push_str(&self.compile_statement(&line.statement));
+        let reached = crate::analyzer::reachable(program);
+        self.removed.clear();
+        self.open_loops.clear();
+        if program.lines.iter().any(|line| jumps(&line.statement, false)) {
+            output.push_str(&self.compile_state_machine(program, &reached)?);
+        } else {
+            // Compile each statement that can run. FOR and NEXT are kept even where nothing
+            // reaches them, because they open and close a Rust block.
+            let mut lines = Vec::new();
+            for (line, reached) in program.lines.iter().zip(reached) {
+                match &line.statement {
+                    crate::Statement::Rem(_) => {},
+                    crate::Statement::For { .. } | crate::Statement::Next { .. } => lines.push(line),
+                    _ if reached => lines.push(line),
+                    _ => self.removed.push(line.number),
+                }
+            }
+            output.push_str(&self.compile_statements(&lines)?);
+            if let Some(open) = self.open_loops.last() {
+                return Err(format!("FOR {} has no NEXT, which the Rust loop needs to close it", open.variable));
+            }
         }
         
         self.indent_level -= 1;
         output.push_str("}\n");
+
+        if self.uses_color {
+            output.push_str(&crate::screen::rust_helpers());
+        }
+        if self.uses_numbers {
+            output.push_str(&crate::numbers::rust_helpers());
+        }
+        if self.uses_binary {
+            output.push_str(&crate::binary::rust_helpers());
+        }
+        if self.uses_rnd {
+            output.push_str(RND_HELPER);
+        }
         
-        format!(
-            r#"use std::collections::HashMap;
-{}
-"#,
-            output
-        )
+        if uses_map {
+            Ok(format!("use std::collections::HashMap;\n{}\n", output))
+        } else {
+            Ok(format!("{}\n", output))
+        }
+    }
+
+    // Cargo.toml for the last compiled program. The empty [workspace] keeps a project written
+    // inside someone else's workspace from being taken for one of its members.
+    pub fn cargo_manifest(&self) -> String {
+        let mut manifest = String::from("[package]\nname = \"code\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n");
+        if self.target == Target::Wasm {
+            manifest.push_str("[lib]\ncrate-type = [\"cdylib\"]\n\n");
+        }
+        manifest.push_str("[workspace]\n");
+        manifest
+    }
+
+    // --explain-codegen: where each variable of the last compiled program lives, and why
+    pub fn explain(&self) -> String {
+        let mut report = String::from("Variable storage:\n");
+        for (name, storage) in &self.storage {
+            match storage {
+                Storage::Local if name.ends_with('$') => report.push_str(&format!("  {:<8} local String\n", name)),
+                Storage::Local => report.push_str(&format!("  {:<8} local f64\n", name)),
+                Storage::Map(reason) => report.push_str(&format!("  {:<8} map ({})\n", name, reason)),
+            }
+        }
+        if !self.removed.is_empty() {
+            let numbers: Vec<String> = self.removed.iter().map(|number| number.to_string()).collect();
+            report.push_str(&format!("Left out because nothing reaches them: lines {}\n", numbers.join(", ")));
+        }
+        report
     }
 
-    fn compile_statement(&mut self, statement: &crate::Statement) -> String {
+    // A program with GOTO can't be nested Rust blocks, so it becomes a loop over the index of
+    // the line to run next. GOTO sets the index; each NEXT belongs to the FOR it closes in the
+    // source and, like the interpreter, works out the STEP and the end again before going round.
+    fn compile_state_machine(&mut self, program: &crate::Program, reached: &[bool]) -> Result<String, String> {
+        let mut jumps = Jumps {
+            lines: program.line_index(),
+            loops: BTreeMap::new(),
+            current: 0,
+        };
+        let mut open = Vec::new();
+        for (i, line) in program.lines.iter().enumerate() {
+            pair_loops(&line.statement, i, &mut open, &mut jumps.loops);
+        }
+        self.jumps = Some(jumps);
+
+        let mut output = String::new();
+        output.push_str(&self.indent());
+        output.push_str("let mut line: usize = 0;\n");
+        output.push_str(&self.indent());
+        output.push_str(&format!("'program: while line < {} {{\n", program.lines.len()));
+        self.indent_level += 1;
+        output.push_str(&self.indent());
+        output.push_str("match line {\n");
+        self.indent_level += 1;
+        for (i, (line, reached)) in program.lines.iter().zip(reached).enumerate() {
+            match &line.statement {
+                crate::Statement::Rem(_) => continue,
+                _ if !reached => {
+                    self.removed.push(line.number);
+                    continue;
+                },
+                _ => {},
+            }
+            if let Some(jumps) = &mut self.jumps {
+                jumps.current = i;
+            }
+            output.push_str(&self.comment(line));
+            output.push_str(&self.indent());
+            output.push_str(&format!("{} => {{\n", i));
+            self.indent_level += 1;
+            output.push_str(&self.compile_statement(&line.statement).map_err(|e| format!("Line {}: {}", line.number, e))?);
+            self.indent_level -= 1;
+            output.push_str(&self.indent());
+            output.push_str("},\n");
+        }
+        output.push_str(&self.indent());
+        output.push_str("_ => {},\n");
+        self.indent_level -= 1;
+        output.push_str(&self.indent());
+        output.push_str("}\n");
+        output.push_str(&self.indent());
+        output.push_str("line += 1;\n");
+        self.indent_level -= 1;
+        output.push_str(&self.indent());
+        output.push_str("}\n");
+        self.jumps = None;
+        Ok(output)
+    }
+
+    // What NEXT does before going round again: work out the STEP and the end afresh, and
+    // open an `if` that steps the loop variable when it's still in range
+    fn go_round(&mut self, loop_data: &crate::ForLoop) -> Result<String, String> {
+        let step = self.compile_expression(&loop_data.step)?;
+        let end = self.compile_expression(&loop_data.end)?;
+        let current = self.read(&loop_data.variable);
+        let indent = self.indent();
+        let mut output = String::new();
+        output.push_str(&format!("{}let step: f64 = {};\n", indent, step));
+        output.push_str(&format!("{}let next = {} + step;\n", indent, current));
+        output.push_str(&format!("{}let end: f64 = {};\n", indent, end));
+        output.push_str(&format!("{}if (step > 0.0 && next <= end) || (step < 0.0 && next >= end) {{\n", indent));
+        output.push_str(&format!("{}    {}", indent, self.assign(&loop_data.variable, "next")));
+        Ok(output)
+    }
+
+    // Prompts for a variable and reads a line into `input`, which is empty at the end of input
+    fn read_line(&self, variable: &str) -> String {
+        let indent = self.indent();
+        let mut output = format!("{}print!(\"Enter {}: \");\n", indent, variable);
+        if self.target == Target::Wasm {
+            output.push_str(&format!("{}let input = read_host_line();\n", indent));
+        } else {
+            output.push_str(&format!("{}io::stdout().flush().unwrap();\n", indent));
+            output.push_str(&format!("{}let mut input = String::new();\n", indent));
+            output.push_str(&format!("{}io::stdin().read_line(&mut input).unwrap_or(0);\n", indent));
+        }
+        output
+    }
+
+    // A jump to line index `target`, or the error the interpreter would stop with
+    fn jump(&self, target: Result<usize, String>) -> String {
+        match target {
+            Ok(index) => format!("{0}line = {1};\n{0}continue 'program;\n", self.indent(), index),
+            Err(message) => format!(
+                "{0}eprintln!(\"{1}\");\n{0}std::process::exit(1);\n",
+                self.indent(), message.escape_default()
+            ),
+        }
+    }
+
+    fn compile_statements(&mut self, lines: &[&crate::Line]) -> Result<String, String> {
+        let statements: Vec<&crate::Statement> = lines.iter().map(|line| &line.statement).collect();
+        let mut output = String::new();
+        let mut i = 0;
+        while i < lines.len() {
+            output.push_str(&self.comment(lines[i]));
+            if let crate::Statement::For { loop_data } = statements[i] {
+                if let Some(next) = matching_next(&statements, i) {
+                    if self.native_loops && native_loop(loop_data, &statements[i + 1..next]) {
+                        output.push_str(&self.compile_native_loop(loop_data, &lines[i + 1..next])?);
+                        i = next + 1;
+                        continue;
+                    }
+                }
+            }
+            let code = self.compile_statement(statements[i])
+                .map_err(|e| format!("Line {}: {}", lines[i].number, e))?;
+            output.push_str(&code);
+            i += 1;
+        }
+        Ok(output)
+    }
+
+    // --readable: the BASIC line a block of Rust came from, as a comment above it
+    fn comment(&self, line: &crate::Line) -> String {
+        if !self.readable {
+            return String::new();
+        }
+        let text = match self.source.lines().nth(line.position.0.saturating_sub(1)) {
+            Some(text) if !text.trim().is_empty() => text.trim().to_string(),
+            _ => format!("{} {}", line.number, crate::stats::keyword(&line.statement)),
+        };
+        format!("{}// {}\n", self.indent(), text)
+    }
+
+    // A numeric FOR loop with a constant step becomes a counted Rust `for` over f64 locals,
+    // so the hot path never touches the variables map
+    fn compile_native_loop(&mut self, loop_data: &crate::ForLoop, body: &[&crate::Line]) -> Result<String, String> {
+        let mut output = String::new();
+        let outermost = self.locals.is_none();
+        if outermost {
+            let mut names = BTreeSet::new();
+            let statements: Vec<&crate::Statement> = body.iter().map(|line| &line.statement).collect();
+            loop_variables(loop_data, &statements, &mut names);
+            names.retain(|name| !self.is_local(name));
+            if !names.is_empty() {
+                output.push_str(&self.indent());
+                output.push_str("{\n");
+                self.indent_level += 1;
+            }
+            for name in &names {
+                output.push_str(&self.indent());
+                output.push_str(&format!(
+                    "let mut {} = variables.get(\"{}\").copied().unwrap_or(0.0);\n",
+                    self.local_name(name), name
+                ));
+            }
+            self.locals = Some(names);
+        }
+
+        let var = self.local_name(&loop_data.variable);
+        let step = match loop_data.step {
+            crate::Expression::Number(n) => format!("{:?}", n),
+            _ => unreachable!("native loops have a constant step"),
+        };
+        let start = self.next_temp();
+        let count = self.next_temp();
+        let start_expr = self.compile_expression(&loop_data.start)?;
+        let end_expr = self.compile_expression(&loop_data.end)?;
+        output.push_str(&self.indent());
+        output.push_str(&format!("let {}: f64 = {};\n", start, start_expr));
+        // At least once: the body runs before NEXT first checks the end
+        output.push_str(&self.indent());
+        output.push_str(&format!(
+            "let {} = ((({}) - {}) / {}).floor().max(0.0) as i64 + 1;\n",
+            count, end_expr, start, step
+        ));
+        output.push_str(&self.indent());
+        output.push_str(&format!("for n in 0..{} {{\n", count));
+        self.indent_level += 1;
+        output.push_str(&self.indent());
+        output.push_str(&format!("{} = {} + n as f64 * {};\n", var, start, step));
+        output.push_str(&self.compile_statements(body)?);
+        self.indent_level -= 1;
+        output.push_str(&self.indent());
+        output.push_str("}\n");
+        // The loop variable keeps its last value, as in the interpreter
+        output.push_str(&self.indent());
+        output.push_str(&format!("{} = {} + ({} - 1) as f64 * {};\n", var, start, count, step));
+
+        if outermost {
+            let names = self.locals.take().unwrap_or_default();
+            for name in &names {
+                output.push_str(&self.indent());
+                output.push_str(&format!("variables.insert(\"{}\".to_string(), {});\n", name, self.local_name(name)));
+            }
+            if !names.is_empty() {
+                self.indent_level -= 1;
+                output.push_str(&self.indent());
+                output.push_str("}\n");
+            }
+        }
+        Ok(output)
+    }
+
+    fn compile_statement(&mut self, statement: &crate::Statement) -> Result<String, String> {
         let mut output = String::new();
         match statement {
             crate::Statement::Print { expressions, semicolon } => {
@@ -60,108 +535,255 @@ impl Compiler {
                         output.push_str("print!(\" \");\n");
                     }
                     output.push_str(&self.indent());
-                    output.push_str(&format!("print!(\"{{}}\", {});\n", self.compile_expression(expr)));
+                    if is_string(expr) {
+                        output.push_str(&format!("print!(\"{{}}\", {});\n", self.compile_expression(expr)?));
+                    } else {
+                        self.uses_numbers = true;
+                        output.push_str(&format!("print!(\"{{}}\", format_number({}));\n", self.compile_expression(expr)?));
+                    }
                 }
                 if !semicolon {
                     output.push_str(&self.indent());
                     output.push_str("println!();\n");
                 }
             },
-            crate::Statement::Let { variable, expression } => {
+            crate::Statement::Let { variable, expression, .. } => {
+                let mut value = self.compile_expression(expression)?;
+                if variable.ends_with('$') {
+                    value = format!("({}).to_string()", value);
+                }
                 output.push_str(&self.indent());
-                output.push_str(&format!(
-                    "variables.insert(\"{}\".to_string(), {});\n",
-                    variable,
-                    self.compile_expression(expression)
-                ));
+                output.push_str(&self.assign(variable, &value));
             },
+            crate::Statement::Input { variable } if variable.ends_with('$') => {
+                output.push_str(&self.read_line(variable));
+                output.push_str(&self.indent());
+                output.push_str(&self.assign(variable, "input.trim_end_matches(['\\r', '\\n']).to_string()"));
+            },
+            // A number that doesn't parse asks again instead of stopping the program, until
+            // the input runs out
             crate::Statement::Input { variable } => {
                 output.push_str(&self.indent());
-                output.push_str(&format!("print!(\"Enter {}: \");\n", variable));
+                output.push_str("let value = loop {\n");
+                self.indent_level += 1;
+                output.push_str(&self.read_line(variable));
+                let indent = self.indent();
+                if self.target != Target::Wasm {
+                    output.push_str(&format!("{}if input.is_empty() {{\n", indent));
+                    output.push_str(&format!("{}    eprintln!(\"Invalid number input\");\n", indent));
+                    output.push_str(&format!("{}    std::process::exit(1);\n", indent));
+                    output.push_str(&format!("{}}}\n", indent));
+                }
+                output.push_str(&format!("{}match input.trim().parse::<f64>() {{\n", indent));
+                output.push_str(&format!("{}    Ok(n) => break n,\n", indent));
+                output.push_str(&format!("{}    Err(_) => println!(\"?Redo from start\"),\n", indent));
+                output.push_str(&format!("{}}}\n", indent));
+                self.indent_level -= 1;
                 output.push_str(&self.indent());
-                output.push_str("io::stdout().flush().unwrap();\n");
+                output.push_str("};\n");
                 output.push_str(&self.indent());
-                output.push_str("let mut input = String::new();\n");
+                output.push_str(&self.assign(variable, "value"));
+            },
+            crate::Statement::For { loop_data } if self.jumps.is_some() => {
+                let start = self.compile_expression(&loop_data.start)?;
                 output.push_str(&self.indent());
-                output.push_str("io::stdin().read_line(&mut input).unwrap();\n");
+                output.push_str(&self.assign(&loop_data.variable, &start));
+            },
+            crate::Statement::Next { .. } if self.jumps.is_some() => {
+                let closes = self.jumps.as_ref().and_then(|jumps| jumps.loops.get(&jumps.current)).cloned();
+                let (start, loop_data) = match closes {
+                    Some(Ok(closes)) => closes,
+                    Some(Err(message)) => return Ok(self.jump(Err(message))),
+                    None => return Ok(self.jump(Err("NEXT without FOR".to_string()))),
+                };
+                output.push_str(&self.go_round(&loop_data)?);
+                self.indent_level += 1;
+                output.push_str(&self.jump(Ok(start + 1)));
+                self.indent_level -= 1;
                 output.push_str(&self.indent());
-                output.push_str(&format!(
-                    "variables.insert(\"{0}\".to_string(), input.trim().parse::<f64>().unwrap());\n",
-                    variable
-                ));
+                output.push_str("}\n");
             },
+            // The body runs once before NEXT checks the end, as in the interpreter
             crate::Statement::For { loop_data } => {
-                let start = self.compile_expression(&loop_data.start);
-                let end = self.compile_expression(&loop_data.end);
-                let step = self.compile_expression(&loop_data.step);
-                let var = &loop_data.variable;
-                
+                let start = self.compile_expression(&loop_data.start)?;
                 output.push_str(&self.indent());
-                output.push_str(&format!(
-                    "let mut {} = {};\n",
-                    var, start
-                ));
+                output.push_str(&self.assign(&loop_data.variable, &start));
                 output.push_str(&self.indent());
-                output.push_str(&format!(
-                    "while {} <= {} {{\n",
-                    var, end
-                ));
-                
+                output.push_str("loop {\n");
                 self.indent_level += 1;
+                self.open_loops.push(loop_data.clone());
+            },
+            crate::Statement::Next { .. } => {
+                let loop_data = self.open_loops.pop().ok_or("NEXT without FOR")?;
+                output.push_str(&self.go_round(&loop_data)?);
                 output.push_str(&self.indent());
-                output.push_str(&format!(
-                    "variables.insert(\"{}\".to_string(), {});\n",
-                    var, var
-                ));
+                output.push_str("} else {\n");
+                output.push_str(&self.indent());
+                output.push_str("    break;\n");
+                output.push_str(&self.indent());
+                output.push_str("}\n");
+                self.indent_level -= 1;
+                output.push_str(&self.indent());
+                output.push_str("}\n");
             },
-            crate::Statement::Next { variable } => {
+            // A condition known at compile time keeps only the branch that runs
+            crate::Statement::If { condition, then_branch, else_branch } if constant(condition).is_some() => {
+                if constant(condition) != Some(0.0) {
+                    return self.compile_statement(then_branch);
+                }
+                if let Some(else_branch) = else_branch {
+                    return self.compile_statement(else_branch);
+                }
+            },
+            crate::Statement::If { condition, then_branch, else_branch } => {
                 output.push_str(&self.indent());
-                output.push_str(&format!(
-                    "{} += 1.0;\n",
-                    variable
-                ));
+                output.push_str(&format!("if ({}) != 0.0 {{\n", self.compile_expression(condition)?));
+                self.indent_level += 1;
+                output.push_str(&self.compile_statement(then_branch)?);
+                if let Some(else_branch) = else_branch {
+                    self.indent_level -= 1;
+                    output.push_str(&self.indent());
+                    output.push_str("} else {\n");
+                    self.indent_level += 1;
+                    output.push_str(&self.compile_statement(else_branch)?);
+                }
                 self.indent_level -= 1;
                 output.push_str(&self.indent());
                 output.push_str("}\n");
             },
-            crate::Statement::End => {
+            crate::Statement::Goto(target) => {
+                let index = self.jumps.as_ref().and_then(|jumps| jumps.lines.get(target).copied());
+                return Ok(self.jump(index.ok_or_else(|| format!("Undefined line number {}", target))));
+            },
+            crate::Statement::End { code: Some(code) } if self.target != Target::Wasm => {
+                let code = self.compile_expression(code)?;
+                output.push_str(&self.indent());
+                output.push_str("io::stdout().flush().unwrap();\n");
+                output.push_str(&self.indent());
+                output.push_str(&format!("std::process::exit(({}) as i32);\n", code));
+            },
+            // A wasm module has no process to exit, so it only stops
+            crate::Statement::End { .. } => {
                 output.push_str(&self.indent());
                 output.push_str("return;\n");
             },
-            _ => panic!("Statement not implemented for compilation"),
+            crate::Statement::Cls => {
+                output.push_str(&self.indent());
+                output.push_str("print!(\"\\x1b[2J\\x1b[H\");\n");
+            },
+            crate::Statement::Locate { row, col } => {
+                // Clamped like COLOR, to the positions the interpreter accepts
+                let mut position = |e: &crate::Expression| {
+                    Ok::<_, String>(format!("(({}) as u32).clamp(1, {})", self.compile_expression(e)?, crate::screen::MAX_POSITION))
+                };
+                let row = row.as_ref().map(&mut position).transpose()?;
+                let col = col.as_ref().map(&mut position).transpose()?;
+                let line = match (row, col) {
+                    (Some(row), Some(col)) => format!("print!(\"\\x1b[{{}};{{}}H\", {}, {});\n", row, col),
+                    (Some(row), None) => format!("print!(\"\\x1b[{{}}d\", {});\n", row),
+                    (None, Some(col)) => format!("print!(\"\\x1b[{{}}G\", {});\n", col),
+                    (None, None) => return Ok(output),
+                };
+                output.push_str(&self.indent());
+                output.push_str(&line);
+            },
+            crate::Statement::Color { foreground, background } => {
+                self.uses_color = true;
+                let codes: Vec<String> = [(foreground, 30), (background, 40)].into_iter()
+                    .filter_map(|(expr, base)| expr.as_ref().map(|e| {
+                        Ok(format!("basic_color({}, {})", self.compile_expression(e)?, base))
+                    }))
+                    .collect::<Result<_, String>>()?;
+                if codes.is_empty() {
+                    return Ok(output);
+                }
+                let placeholders = vec!["{}"; codes.len()].join(";");
+                output.push_str(&self.indent());
+                output.push_str(&format!("print!(\"\\x1b[{}m\", {});\n", placeholders, codes.join(", ")));
+            },
+            crate::Statement::OptionExplicit | crate::Statement::Rem(_) | crate::Statement::Common { .. } => {},
+            statement => return Err(format!("{} can't be compiled", crate::stats::keyword(statement))),
         }
-        output
+        Ok(output)
     }
 
-    fn compile_expression(&mut self, expr: &crate::Expression) -> String {
-        match expr {
+    // A statement storing `value` in a variable, wherever it lives
+    fn assign(&self, name: &str, value: &str) -> String {
+        if self.is_local(name) {
+            format!("{} = {};\n", self.local_name(name), value)
+        } else {
+            format!("{}.insert(\"{}\".to_string(), {});\n", map_name(name), name, value)
+        }
+    }
+
+    // v_x and v_name_str, or with --readable the BASIC name itself where Rust allows it
+    fn local_name(&self, name: &str) -> String {
+        if !self.readable {
+            return local_name(name);
+        }
+        let readable = name.to_lowercase().replace('$', "_str");
+        if RESERVED.contains(&readable.as_str()) || readable.starts_with("temp_") {
+            format!("{}_", readable)
+        } else {
+            readable
+        }
+    }
+
+    fn read(&self, name: &str) -> String {
+        if self.is_local(name) {
+            self.local_name(name)
+        } else if name.ends_with('$') {
+            format!("strings.get(\"{}\").cloned().unwrap_or_default()", name)
+        } else {
+            format!("variables.get(\"{}\").copied().unwrap_or(0.0)", name)
+        }
+    }
+
+    fn is_local(&self, name: &str) -> bool {
+        self.storage.get(name) == Some(&Storage::Local)
+            || self.locals.as_ref().is_some_and(|locals| locals.contains(name))
+    }
+
+    fn compile_expression(&mut self, expr: &crate::Expression) -> Result<String, String> {
+        Ok(match expr {
             crate::Expression::Number(n) => format!("{:.1}", n),
-            crate::Expression::String(s) => format!("\"{}\"", s),
-            crate::Expression::Variable(name) => {
-                format!("*variables.get(\"{}\").unwrap()", name)
+            // Debug formatting escapes quotes, backslashes and control characters the way a Rust
+            // literal needs them
+            crate::Expression::String(s) => format!("{:?}", s),
+            crate::Expression::Variable(name, ..) => self.read(name),
+            crate::Expression::Binary { left, operator, right, .. } if is_string(left) || is_string(right) => {
+                let both = is_string(left) && is_string(right);
+                let left = self.compile_expression(left)?;
+                let right = self.compile_expression(right)?;
+                match operator {
+                    crate::Token::Plus if both => format!("format!(\"{{}}{{}}\", {}, {})", left, right),
+                    crate::Token::Equals if both => format!("if {} == {} {{ 1.0 }} else {{ 0.0 }}", left, right),
+                    crate::Token::NotEqual if both => format!("if {} != {} {{ 1.0 }} else {{ 0.0 }}", left, right),
+                    _ => "{ eprintln!(\"Invalid operation or type mismatch\"); std::process::exit(1) }".to_string(),
+                }
             },
-            crate::Expression::Binary { left, operator, right } => {
-                let left = self.compile_expression(left);
-                let right = self.compile_expression(right);
+            crate::Expression::Binary { left, operator, right, .. } => {
+                let left = self.compile_expression(left)?;
+                let right = self.compile_expression(right)?;
                 match operator {
                     crate::Token::Plus => format!("({} + {})", left, right),
                     crate::Token::Minus => format!("({} - {})", left, right),
                     crate::Token::Multiply => format!("({} * {})", left, right),
                     crate::Token::Divide => format!("({} / {})", left, right),
-                    crate::Token::Power => format!("({}).powf({})", left, right),
+                    crate::Token::Power => format!("f64::powf({}, {})", left, right),
                     crate::Token::LessThan => format!("if {} < {} {{ 1.0 }} else {{ 0.0 }}", left, right),
                     crate::Token::GreaterThan => format!("if {} > {} {{ 1.0 }} else {{ 0.0 }}", left, right),
                     crate::Token::Equals => format!("if {} == {} {{ 1.0 }} else {{ 0.0 }}", left, right),
                     crate::Token::LessOrEqual => format!("if {} <= {} {{ 1.0 }} else {{ 0.0 }}", left, right),
                     crate::Token::GreaterOrEqual => format!("if {} >= {} {{ 1.0 }} else {{ 0.0 }}", left, right),
                     crate::Token::NotEqual => format!("if {} != {} {{ 1.0 }} else {{ 0.0 }}", left, right),
-                    _ => panic!("Operator not implemented for compilation"),
+                    _ => return Err(format!("{} can't be compiled", expr)),
                 }
             },
-            crate::Expression::FunctionCall { name, arguments } => {
+            crate::Expression::FunctionCall { name, arguments, .. } => {
                 let args: Vec<String> = arguments.iter()
                     .map(|arg| self.compile_expression(arg))
-                    .collect();
+                    .collect::<Result<_, _>>()?;
                 match name.as_str() {
                     "ABS" => format!("({}).abs()", args[0]),
                     "SQR" => format!("({}).sqrt()", args[0]),
@@ -169,10 +791,224 @@ impl Compiler {
                     "COS" => format!("({}).cos()", args[0]),
                     "TAN" => format!("({}).tan()", args[0]),
                     "INT" => format!("({}).floor()", args[0]),
-                    "RND" => "rand::random::<f64>()".to_string(),
-                    _ => panic!("Function not implemented for compilation"),
+                    "RND" => {
+                        self.uses_rnd = true;
+                        "basic_rnd()".to_string()
+                    },
+                    "MKI$" | "MKS$" | "MKD$" | "CVI" | "CVS" | "CVD" => {
+                        self.uses_binary = true;
+                        let function = name.trim_end_matches('$').to_lowercase();
+                        if name.ends_with('$') {
+                            format!("or_exit({}({}))", function, args[0])
+                        } else {
+                            format!("or_exit({}(&{}))", function, args[0])
+                        }
+                    },
+                    _ => return Err(format!("{} can't be compiled", name)),
+                }
+            },
+        })
+    }
+}
+
+// Whether PRINT should show an expression as text rather than as a number
+fn is_string(expr: &crate::Expression) -> bool {
+    match expr {
+        crate::Expression::String(_) => true,
+        crate::Expression::Variable(name, ..) | crate::Expression::FunctionCall { name, .. } => name.ends_with('$'),
+        crate::Expression::Binary { left, operator: crate::Token::Plus, right, .. } => is_string(left) && is_string(right),
+        _ => false,
+    }
+}
+
+// The value of an expression made only of numbers, or None when it depends on the program
+// running. Division by zero is left for runtime, where it is an error.
+fn constant(expr: &crate::Expression) -> Option<f64> {
+    match expr {
+        crate::Expression::Number(n) => Some(*n),
+        crate::Expression::Binary { left, operator, right, .. } => {
+            let (l, r) = (constant(left)?, constant(right)?);
+            let truth = |condition: bool| if condition { 1.0 } else { 0.0 };
+            match operator {
+                crate::Token::Plus => Some(l + r),
+                crate::Token::Minus => Some(l - r),
+                crate::Token::Multiply => Some(l * r),
+                crate::Token::Divide if r != 0.0 => Some(l / r),
+                crate::Token::Power => Some(l.powf(r)),
+                crate::Token::LessThan => Some(truth(l < r)),
+                crate::Token::GreaterThan => Some(truth(l > r)),
+                crate::Token::Equals => Some(truth(l == r)),
+                crate::Token::LessOrEqual => Some(truth(l <= r)),
+                crate::Token::GreaterOrEqual => Some(truth(l >= r)),
+                crate::Token::NotEqual => Some(truth(l != r)),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+// Whether a statement needs the program compiled as a state machine: it jumps, or it opens or
+// closes a loop inside an IF, where a Rust block can't
+fn jumps(statement: &crate::Statement, in_if: bool) -> bool {
+    match statement {
+        crate::Statement::Goto(_) => true,
+        crate::Statement::For { .. } | crate::Statement::Next { .. } => in_if,
+        crate::Statement::If { then_branch, else_branch, .. } => {
+            jumps(then_branch, true) || else_branch.as_ref().is_some_and(|branch| jumps(branch, true))
+        },
+        _ => false,
+    }
+}
+
+// Pairs each NEXT with the FOR still open above it in the source
+fn pair_loops(
+    statement: &crate::Statement,
+    index: usize,
+    open: &mut Vec<(usize, crate::ForLoop)>,
+    loops: &mut BTreeMap<usize, Result<(usize, crate::ForLoop), String>>,
+) {
+    match statement {
+        crate::Statement::For { loop_data } => open.push((index, loop_data.clone())),
+        crate::Statement::Next { variable } => {
+            let closes = match open.pop() {
+                Some((start, loop_data)) if loop_data.variable == *variable => Ok((start, loop_data)),
+                Some((_, loop_data)) => Err(format!("NEXT {} doesn't match FOR {}", variable, loop_data.variable)),
+                None => Err("NEXT without FOR".to_string()),
+            };
+            loops.insert(index, closes);
+        },
+        crate::Statement::If { then_branch, else_branch, .. } => {
+            pair_loops(then_branch, index, open, loops);
+            if let Some(else_branch) = else_branch {
+                pair_loops(else_branch, index, open, loops);
+            }
+        },
+        _ => {},
+    }
+}
+
+fn map_name(name: &str) -> &'static str {
+    if name.ends_with('$') { "strings" } else { "variables" }
+}
+
+// Rust keywords and the names generated code uses for itself, which a readable variable name
+// must not take
+const RESERVED: &[&str] = &[
+    "as", "async", "await", "basic_color", "basic_rnd", "break", "const", "continue", "crate",
+    "cvd", "cvi", "cvs", "dyn", "else", "end", "enum", "extern", "false", "fn", "for",
+    "format_number", "host_input", "host_print", "if", "impl", "in", "input", "io", "let", "line",
+    "loop", "main", "match", "mkd", "mki", "mks", "mod", "move", "mut", "n", "next", "or_exit",
+    "pub", "read_host_line", "ref", "return", "rnd_seed", "run", "self", "static", "step",
+    "strings", "struct", "super", "trait", "true", "type", "unsafe", "use", "value", "variables",
+    "where", "while", "write_host", "yield",
+];
+
+fn local_name(name: &str) -> String {
+    format!("v_{}", name.to_lowercase().replace('$', "_str"))
+}
+
+// Index of the NEXT closing the FOR at `start`, if the loops nest properly
+fn matching_next(statements: &[&crate::Statement], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, statement) in statements.iter().enumerate().skip(start + 1) {
+        match statement {
+            crate::Statement::For { .. } => depth += 1,
+            crate::Statement::Next { variable } if depth == 0 => {
+                let crate::Statement::For { loop_data } = statements[start] else { return None };
+                return (*variable == loop_data.variable).then_some(i);
+            },
+            crate::Statement::Next { .. } => depth -= 1,
+            _ => {},
+        }
+    }
+    None
+}
+
+// A loop can run natively when it is straight-line numeric code with a constant step,
+// and nothing in the body changes the loop variable or the end bound
+fn native_loop(loop_data: &crate::ForLoop, body: &[&crate::Statement]) -> bool {
+    if loop_data.variable.ends_with('$') || !numeric(&loop_data.start) || !numeric(&loop_data.end) {
+        return false;
+    }
+    if !matches!(loop_data.step, crate::Expression::Number(n) if n != 0.0) {
+        return false;
+    }
+
+    let mut assigned = BTreeSet::new();
+    for (i, statement) in body.iter().enumerate() {
+        let ok = match statement {
+            crate::Statement::Let { variable, expression, .. } => {
+                assigned.insert(variable.clone());
+                !variable.ends_with('$') && numeric(expression)
+            },
+            crate::Statement::Print { expressions, .. } => {
+                expressions.iter().all(|e| matches!(e, crate::Expression::String(_)) || numeric(e))
+            },
+            crate::Statement::For { loop_data } => {
+                assigned.insert(loop_data.variable.clone());
+                matching_next(body, i).is_some_and(|next| native_loop(loop_data, &body[i + 1..next]))
+            },
+            crate::Statement::Next { .. } | crate::Statement::Rem(_) => true,
+            _ => false,
+        };
+        if !ok {
+            return false;
+        }
+    }
+
+    let mut bound = BTreeSet::new();
+    expression_variables(&loop_data.end, &mut bound);
+    !assigned.contains(&loop_data.variable) && bound.is_disjoint(&assigned)
+}
+
+fn numeric(expr: &crate::Expression) -> bool {
+    match expr {
+        crate::Expression::Number(_) => true,
+        crate::Expression::String(_) => false,
+        crate::Expression::Variable(name, ..) => !name.ends_with('$'),
+        crate::Expression::Binary { left, right, .. } => numeric(left) && numeric(right),
+        crate::Expression::FunctionCall { name, arguments, .. } => {
+            NATIVE_FUNCTIONS.contains(&name.as_str()) && arguments.len() == 1 && numeric(&arguments[0])
+        },
+    }
+}
+
+fn loop_variables(loop_data: &crate::ForLoop, body: &[&crate::Statement], names: &mut BTreeSet<String>) {
+    names.insert(loop_data.variable.clone());
+    expression_variables(&loop_data.start, names);
+    expression_variables(&loop_data.end, names);
+    for statement in body {
+        match statement {
+            crate::Statement::Let { variable, expression, .. } => {
+                names.insert(variable.clone());
+                expression_variables(expression, names);
+            },
+            crate::Statement::Print { expressions, .. } => {
+                for expr in expressions {
+                    expression_variables(expr, names);
                 }
             },
+            crate::Statement::For { loop_data } => loop_variables(loop_data, &[], names),
+            _ => {},
         }
     }
 }
+
+fn expression_variables(expr: &crate::Expression, names: &mut BTreeSet<String>) {
+    match expr {
+        crate::Expression::Variable(name, ..) => {
+            names.insert(name.clone());
+        },
+        crate::Expression::Binary { left, right, .. } => {
+            expression_variables(left, names);
+            expression_variables(right, names);
+        },
+        crate::Expression::FunctionCall { arguments, .. } => {
+            for arg in arguments {
+                expression_variables(arg, names);
+            }
+        },
+        crate::Expression::Number(_) | crate::Expression::String(_) => {},
+    }
+}
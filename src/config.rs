@@ -0,0 +1,156 @@
+// Default options from ~/.langrc, or from the file $LANGRC names, so they don't have to be
+// given on every run. Each line is `name = value` and # starts a comment:
+//
+//     sandbox = true
+//     max_steps = 1000000
+//     timeout = 2.5
+//     warnings = deny
+//
+// Flags on the command line win over the file.
+use std::time::Duration;
+
+use crate::diagnostics::Level;
+
+pub struct Config {
+    pub sandbox: bool,
+    pub strict: bool,
+    pub checked_math: bool,
+    pub memoize: bool,
+    pub profile: bool,
+    pub window: bool,
+    pub native_loops: bool,
+    pub jit: bool,
+    pub vm_opt: bool,
+    pub max_steps: Option<usize>,
+    pub timeout: Option<Duration>,
+    pub max_memory: Option<usize>,
+    pub seed: Option<u64>,
+    pub warnings: Level,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            sandbox: false,
+            strict: false,
+            checked_math: false,
+            memoize: false,
+            profile: false,
+            window: false,
+            native_loops: true,
+            jit: false,
+            vm_opt: false,
+            max_steps: None,
+            timeout: None,
+            max_memory: None,
+            seed: None,
+            warnings: Level::Warn,
+        }
+    }
+}
+
+impl Config {
+    // A missing file is the same as an empty one; a broken one is an error rather than being half applied
+    pub fn load() -> Result<Config, String> {
+        let Some(path) = path() else {
+            return Ok(Config::default());
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format!("Error reading {}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line.split_once('=')
+                .ok_or_else(|| format!("line {}: expected name = value", index + 1))?;
+            config.set(name.trim(), value.trim())
+                .map_err(|e| format!("line {}: {}", index + 1, e))?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let flag = match name {
+            "sandbox" => &mut self.sandbox,
+            "strict" => &mut self.strict,
+            "checked_math" => &mut self.checked_math,
+            "memoize" => &mut self.memoize,
+            "profile" => &mut self.profile,
+            "window" => &mut self.window,
+            "native_loops" => &mut self.native_loops,
+            "jit" => &mut self.jit,
+            "vm_opt" => &mut self.vm_opt,
+            "max_steps" => {
+                let steps = value.parse().map_err(|_| format!("max_steps must be a whole number, not {}", value))?;
+                self.max_steps = Some(steps);
+                return Ok(());
+            },
+            "timeout" => {
+                self.timeout = Some(parse_seconds(value)?);
+                return Ok(());
+            },
+            "max_memory" => {
+                let bytes = value.parse().map_err(|_| format!("max_memory must be a whole number of bytes, not {}", value))?;
+                self.max_memory = Some(bytes);
+                return Ok(());
+            },
+            "seed" => {
+                let seed = value.parse().map_err(|_| format!("seed must be a whole number, not {}", value))?;
+                self.seed = Some(seed);
+                return Ok(());
+            },
+            "warnings" => {
+                self.warnings = Level::parse(value)?;
+                return Ok(());
+            },
+            _ => return Err(format!("unknown setting {}", name)),
+        };
+        *flag = match value {
+            "true" => true,
+            "false" => false,
+            _ => return Err(format!("{} must be true or false, not {}", name, value)),
+        };
+        Ok(())
+    }
+
+    pub fn configure(&self, interpreter: &mut crate::Interpreter) {
+        if self.checked_math {
+            interpreter.check_math_problems();
+        }
+        interpreter.memoize = self.memoize;
+        interpreter.profile = self.profile;
+        interpreter.show_window = self.window;
+        interpreter.sandbox = self.sandbox;
+        interpreter.max_steps = self.max_steps;
+        interpreter.timeout = self.timeout;
+        interpreter.max_memory = self.max_memory;
+        if let Some(seed) = self.seed {
+            interpreter.seed(seed);
+        }
+        interpreter.jit = self.jit;
+        interpreter.vm_opt = self.vm_opt;
+    }
+}
+
+// A timeout in seconds, which can have a fraction, like 0.5
+pub fn parse_seconds(text: &str) -> Result<Duration, String> {
+    text.parse().ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("timeout must be a number of seconds, not {}", text))
+}
+
+fn path() -> Option<std::path::PathBuf> {
+    if let Some(path) = std::env::var_os("LANGRC") {
+        return Some(path.into());
+    }
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(std::path::Path::new(&home).join(".langrc"))
+}
@@ -0,0 +1,46 @@
+// --coverage: which lines of a program ran and how often, from the counts the interpreter keeps
+// as it runs. `listing` marks up the source the way gcov does, and `lcov` writes a tracefile for
+// genhtml or an editor's coverage gutter.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::Interpreter;
+
+// How often the statements on each line of the source file ran, by line in the file
+fn counts(interpreter: &Interpreter) -> BTreeMap<usize, usize> {
+    let mut counts = BTreeMap::new();
+    for (line, stats) in interpreter.program.lines.iter().zip(&interpreter.line_stats) {
+        *counts.entry(line.position.0).or_default() += stats.runs;
+    }
+    counts
+}
+
+// Every line of the source with how often it ran in front: ##### for a statement that never
+// ran and - for a line without one, followed by how many of the lines ran
+pub fn listing(interpreter: &Interpreter, source: &str) -> String {
+    let counts = counts(interpreter);
+    let mut listing = String::new();
+    for (index, text) in source.lines().enumerate() {
+        let count = match counts.get(&(index + 1)) {
+            Some(0) => "#####".to_string(),
+            Some(runs) => runs.to_string(),
+            None => "-".to_string(),
+        };
+        let _ = writeln!(listing, "{:>9} | {}", count, text.trim_end());
+    }
+    let ran = counts.values().filter(|&&runs| runs > 0).count();
+    let share = if counts.is_empty() { 100.0 } else { 100.0 * ran as f64 / counts.len() as f64 };
+    let _ = writeln!(listing, "{} of {} lines ran ({:.1}%)", ran, counts.len(), share);
+    listing
+}
+
+pub fn lcov(interpreter: &Interpreter, file_name: &str) -> String {
+    let counts = counts(interpreter);
+    let mut lcov = format!("TN:\nSF:{}\n", file_name);
+    for (line, runs) in &counts {
+        let _ = writeln!(lcov, "DA:{},{}", line, runs);
+    }
+    let ran = counts.values().filter(|&&runs| runs > 0).count();
+    let _ = writeln!(lcov, "LF:{}\nLH:{}\nend_of_record", counts.len(), ran);
+    lcov
+}
@@ -0,0 +1,98 @@
+// Reports for panics inside LANG itself. The command line runs each phase under `catch`, so a
+// bug prints what a bug report needs instead of a bare Rust panic message.
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::fmt::Write;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::Position;
+
+// How many of the last statements run go in a report
+pub(crate) const RECENT_STATEMENTS: usize = 5;
+
+thread_local! {
+    // Where the parser has got to, so a crash while parsing can point at it
+    static PARSER_POSITION: Cell<Option<Position>> = const { Cell::new(None) };
+    static PANIC_LOCATION: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+pub(crate) fn note_parser_position(position: Position) {
+    PARSER_POSITION.set(Some(position));
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+pub struct CrashReport {
+    pub phase: String,
+    pub message: String,
+    // Where in LANG's own source the panic happened
+    pub location: String,
+    // The BASIC line running at the time, when the program had started
+    pub line: Option<u32>,
+    // Source of the statements run just before the crash, oldest first
+    pub recent: Vec<String>,
+    // The source line the crash was nearest to, and the column to point at
+    pub near: Option<(String, usize)>,
+}
+
+// Runs one phase, turning a panic into a report instead of letting it unwind out of main
+pub fn catch<T>(phase: &str, source: &str, f: impl FnOnce() -> T) -> Result<T, Box<CrashReport>> {
+    PARSER_POSITION.set(None);
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|info| {
+        let location = info.location().map(|location| location.to_string()).unwrap_or_default();
+        PANIC_LOCATION.set(location);
+    }));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    panic::set_hook(default_hook);
+
+    result.map_err(|payload| Box::new(CrashReport {
+        phase: phase.to_string(),
+        message: panic_message(payload.as_ref()),
+        location: PANIC_LOCATION.take(),
+        line: None,
+        recent: Vec::new(),
+        near: PARSER_POSITION.get().map(|(line, column)| (source_line(source, line), column)),
+    }))
+}
+
+pub(crate) fn source_line(source: &str, line: usize) -> String {
+    let text = source.lines().nth(line.saturating_sub(1)).unwrap_or_default().trim_end();
+    text.chars().take(80).collect()
+}
+
+impl CrashReport {
+    pub fn render(&self) -> String {
+        let mut report = String::new();
+        let _ = writeln!(report, "LANG crashed while it was in the {} phase. This is a bug in LANG, not in your", self.phase);
+        let _ = writeln!(report, "program; please report it with everything below.");
+        let _ = writeln!(report);
+        let _ = writeln!(report, "version: {}", env!("CARGO_PKG_VERSION"));
+        let _ = writeln!(report, "phase:   {}", self.phase);
+        let _ = writeln!(report, "panic:   {}", self.message);
+        let _ = writeln!(report, "at:      {}", self.location);
+        if let Some(line) = self.line {
+            let _ = writeln!(report, "line:    {}", line);
+        }
+        if !self.recent.is_empty() {
+            let _ = writeln!(report, "last statements run:");
+            for text in &self.recent {
+                let _ = writeln!(report, "  {}", text);
+            }
+        }
+        if let Some((text, column)) = &self.near {
+            let _ = writeln!(report, "near:");
+            let _ = writeln!(report, "  {}", text);
+            let _ = writeln!(report, "  {}^", " ".repeat(column.saturating_sub(1)));
+        }
+        report
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.render()).map_err(|e| format!("Error writing crash report {}: {}", path, e))
+    }
+}
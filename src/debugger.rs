@@ -0,0 +1,269 @@
+// `lang debug`: runs a program a statement at a time through Interpreter::step. Breakpoints go
+// on BASIC line numbers; step runs one statement, next runs a whole FOR loop as one step and
+// continue runs to the next breakpoint. While the program is stopped, print evaluates any
+// expression, set changes a variable and stack shows the FOR loops that are open. LANG has no
+// GOSUB, so those loops are the only stack there is.
+use std::collections::BTreeSet;
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use crate::{numbers, Interpreter, Program, Statement, StepResult, Value};
+
+const HELP: &str = "\
+break N      stop before line N runs (b)
+delete N     remove the breakpoint on line N, or every one without N (d)
+step         run one statement (s)
+next         run one statement, or a whole FOR loop when stopped at its FOR (n)
+continue     run to the next breakpoint or the end (c)
+print EXPR   show the value of an expression, such as print X * 2 (p)
+set X = EXPR change a variable
+vars         show every variable
+stack        show the FOR loops that are open
+list         show the lines around where the program is stopped (l)
+quit         stop debugging (q)";
+
+struct Debugger<'a> {
+    interpreter: &'a mut Interpreter,
+    reader: DefaultEditor,
+    source: &'a str,
+    file_name: &'a str,
+    breakpoints: BTreeSet<u32>,
+    // False once the program has finished or stopped with an error; it can still be looked at
+    running: bool,
+}
+
+pub fn run(interpreter: &mut Interpreter, program: &Program, source: &str, file_name: &str) -> Result<(), String> {
+    // A breakpoint on a GOTO that --vm-opt jumps past would never be reached
+    interpreter.vm_opt = false;
+    interpreter.load(program);
+    let reader = DefaultEditor::new().map_err(|e| format!("Failed to start the line editor: {}", e))?;
+    let mut debugger = Debugger { interpreter, reader, source, file_name, breakpoints: BTreeSet::new(), running: true };
+    println!("Debugging {}. Type help for the commands.", file_name);
+    debugger.show_position();
+    loop {
+        let input = match debugger.reader.readline("(debug) ") {
+            Ok(input) => input,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(format!("Failed to read input: {}", e)),
+        };
+        let input = input.trim();
+        if input.is_empty() {
+            continue;
+        }
+        let _ = debugger.reader.add_history_entry(input);
+        let (command, argument) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+        let argument = argument.trim();
+        let result = match command.to_lowercase().as_str() {
+            "break" | "b" => debugger.add_breakpoint(argument),
+            "delete" | "d" => debugger.delete_breakpoint(argument),
+            "step" | "s" => debugger.step(),
+            "next" | "n" => debugger.next(),
+            "continue" | "c" => debugger.resume(),
+            "print" | "p" => debugger.print(argument),
+            "set" => debugger.set(argument),
+            "vars" => {
+                debugger.show_variables();
+                Ok(())
+            },
+            "stack" => {
+                debugger.show_stack();
+                Ok(())
+            },
+            "list" | "l" => {
+                debugger.list();
+                Ok(())
+            },
+            "help" | "h" => {
+                println!("{}", HELP);
+                Ok(())
+            },
+            "quit" | "q" => return Ok(()),
+            _ => Err(format!("Unknown command {}; type help for the commands", command)),
+        };
+        if let Err(e) = result {
+            println!("{}", e);
+        }
+    }
+}
+
+impl Debugger<'_> {
+    fn add_breakpoint(&mut self, argument: &str) -> Result<(), String> {
+        let number = line_number(argument)?;
+        if !self.interpreter.line_index.contains_key(&number) {
+            return Err(format!("There is no line {}", number));
+        }
+        self.breakpoints.insert(number);
+        println!("Breakpoint on line {}", number);
+        Ok(())
+    }
+
+    fn delete_breakpoint(&mut self, argument: &str) -> Result<(), String> {
+        if argument.is_empty() {
+            self.breakpoints.clear();
+            return Ok(());
+        }
+        let number = line_number(argument)?;
+        if !self.breakpoints.remove(&number) {
+            return Err(format!("There is no breakpoint on line {}", number));
+        }
+        Ok(())
+    }
+
+    // One statement, and false once the program has stopped
+    fn run_statement(&mut self) -> Result<bool, String> {
+        if !self.running {
+            return Err("The program has stopped; quit and start it again to rerun it".to_string());
+        }
+        loop {
+            match self.interpreter.step() {
+                StepResult::Ran => return Ok(true),
+                // The program's prompt is already showing
+                StepResult::NeedsInput => match self.reader.readline("") {
+                    Ok(line) => self.interpreter.provide_input(&line),
+                    Err(ReadlineError::Interrupted | ReadlineError::Eof) => return Err("INPUT got no line".to_string()),
+                    Err(e) => return Err(format!("Failed to read input: {}", e)),
+                },
+                StepResult::Finished => {
+                    self.running = false;
+                    match self.interpreter.end_status() {
+                        0 => println!("The program finished"),
+                        status => println!("The program finished with END {}", status),
+                    }
+                    return Ok(false);
+                },
+                StepResult::Error(e) => {
+                    self.running = false;
+                    println!("{}", e.render(self.source, self.file_name, false).trim_end());
+                    println!("The program stopped with this error; its variables can still be looked at");
+                    return Ok(false);
+                },
+            }
+        }
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        if self.run_statement()? {
+            self.show_position();
+        }
+        Ok(())
+    }
+
+    // At a FOR, runs until that loop is over, unless a breakpoint inside it comes first
+    fn next(&mut self) -> Result<(), String> {
+        let at_for = matches!(self.current().map(|index| &self.interpreter.program.lines[index].statement), Some(Statement::For { .. }));
+        let depth = self.interpreter.loop_stack.len();
+        if !self.run_statement()? {
+            return Ok(());
+        }
+        while at_for && self.interpreter.loop_stack.len() > depth && !self.at_breakpoint() {
+            if !self.run_statement()? {
+                return Ok(());
+            }
+        }
+        self.show_position();
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), String> {
+        loop {
+            if !self.run_statement()? {
+                return Ok(());
+            }
+            if self.at_breakpoint() {
+                self.show_position();
+                return Ok(());
+            }
+        }
+    }
+
+    // The index of the line about to run, unless the program is past its last line
+    fn current(&self) -> Option<usize> {
+        let index = self.interpreter.current_line;
+        (index < self.interpreter.program.lines.len()).then_some(index)
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        self.interpreter.line_number().is_some_and(|number| self.breakpoints.contains(&number))
+    }
+
+    fn show_position(&self) {
+        match self.current() {
+            Some(index) => {
+                let line = &self.interpreter.program.lines[index];
+                println!("Stopped before line {}: {}", line.number, crate::crash::source_line(self.source, line.position.0));
+            },
+            None => println!("Stopped after the last line"),
+        }
+    }
+
+    // print X, A$ shows each expression; they are read the way PRINT reads them
+    fn print(&mut self, argument: &str) -> Result<(), String> {
+        let program = crate::parse(&format!("PRINT {}", argument)).map_err(|e| e.to_string())?;
+        let Some(Statement::Print { expressions, .. }) = program.lines.first().map(|line| &line.statement) else {
+            return Err("Usage: print EXPR".to_string());
+        };
+        if expressions.is_empty() {
+            return Err("Usage: print EXPR".to_string());
+        }
+        for expression in expressions {
+            println!("{} = {}", expression, show(&self.interpreter.evaluate_expression(expression).map_err(|e| e.to_string())?));
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, argument: &str) -> Result<(), String> {
+        let program = crate::parse(&format!("LET {}", argument)).map_err(|e| e.to_string())?;
+        match program.lines.first().map(|line| &line.statement) {
+            Some(statement @ Statement::Let { .. }) if program.lines.len() == 1 => {
+                self.interpreter.execute_statement(statement).map_err(|e| e.to_string())
+            },
+            _ => Err("Usage: set X = EXPR".to_string()),
+        }
+    }
+
+    fn show_variables(&self) {
+        let variables = self.interpreter.variables();
+        if variables.is_empty() {
+            println!("No variables have been assigned yet");
+        }
+        for (name, value) in variables {
+            println!("{} = {}", name, show(&value));
+        }
+    }
+
+    // Innermost loop first, the way a stack trace reads
+    fn show_stack(&self) {
+        if self.interpreter.loop_stack.is_empty() {
+            println!("No FOR loops are open");
+        }
+        for (&index, loop_data) in self.interpreter.loop_stack.iter().zip(&self.interpreter.loops).rev() {
+            let line = &self.interpreter.program.lines[index];
+            let value = self.interpreter.variables.get(&loop_data.variable).map(show).unwrap_or_default();
+            println!("line {}: {}, where {} = {}", line.number, crate::unparse::statement(&line.statement), loop_data.variable, value);
+        }
+    }
+
+    // A few lines either side of where the program is, with => at it and * at breakpoints
+    fn list(&self) {
+        let lines = &self.interpreter.program.lines;
+        let here = self.current().unwrap_or(lines.len());
+        for (index, line) in lines.iter().enumerate().take(here + 6).skip(here.saturating_sub(5)) {
+            let marker = if index == here { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&line.number) { "*" } else { " " };
+            println!("{}{} {}", marker, breakpoint, crate::crash::source_line(self.source, line.position.0));
+        }
+    }
+}
+
+fn line_number(argument: &str) -> Result<u32, String> {
+    argument.parse().map_err(|_| format!("{} isn't a line number", argument))
+}
+
+fn show(value: &Value) -> String {
+    match value {
+        Value::Number(n) => numbers::format_number(*n).trim().to_string(),
+        Value::String(s) => format!("{:?}", s),
+    }
+}
@@ -0,0 +1,169 @@
+// Warnings about things that are probably mistakes but don't stop a program from running:
+// variables that are assigned and never read, lines nothing can reach, and a FOR inside a
+// loop over the same variable. The command line prints them before running, and
+// --warnings=deny turns them into errors.
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::analyzer;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    pub fn parse(text: &str) -> Result<Level, String> {
+        match text {
+            "allow" => Ok(Level::Allow),
+            "warn" => Ok(Level::Warn),
+            "deny" => Ok(Level::Deny),
+            _ => Err(format!("warnings must be allow, warn or deny, not {}", text)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub line: u32,
+    // The line of the source it's on, counting from 1
+    pub source_line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Warning at line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+// Every warning for a program, in program order
+pub fn warnings(program: &crate::Program) -> Vec<Warning> {
+    let mut found: Vec<(usize, Option<crate::Position>, String)> = Vec::new();
+    unreachable_lines(program, &mut found);
+    unused_variables(program, &mut found);
+    shadowed_loops(program, &mut found);
+    found.sort_by_key(|(index, ..)| *index);
+    found.into_iter()
+        .map(|(index, position, message)| {
+            let line = &program.lines[index];
+            let (source_line, column) = position.unwrap_or(line.position);
+            Warning { line: line.number, source_line, column, message }
+        })
+        .collect()
+}
+
+// One warning at the start of each stretch of lines nothing can get to, unless it's only REMs
+fn unreachable_lines(program: &crate::Program, found: &mut Vec<(usize, Option<crate::Position>, String)>) {
+    let lines = &program.lines;
+    if lines.is_empty() {
+        return;
+    }
+    let reached = analyzer::reachable(program);
+    let mut i = 0;
+    while i < lines.len() {
+        if reached[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < lines.len() && !reached[i] {
+            i += 1;
+        }
+        let stretch = &lines[start..i];
+        if stretch.iter().all(|line| matches!(line.statement, crate::Statement::Rem(_))) {
+            continue;
+        }
+        let after = match start.checked_sub(1).map(|before| &lines[before].statement) {
+            Some(crate::Statement::End { .. }) => "it follows an END and ",
+            Some(crate::Statement::Goto(_)) => "it follows a GOTO and ",
+            _ => "",
+        };
+        let rest = match stretch.len() {
+            1 => String::new(),
+            2 => ", nor does the line after it".to_string(),
+            n => format!(", nor do the {} lines after it", n - 1),
+        };
+        let message = format!("Never runs{}: {}nothing jumps here", rest, after);
+        found.push((start, None, message));
+    }
+}
+
+// Loop variables are read by their NEXT, FIELD variables by PUT and DEF FN parameters only
+// inside the function, so none of those count
+fn unused_variables(program: &crate::Program, found: &mut Vec<(usize, Option<crate::Position>, String)>) {
+    let mut read = HashSet::new();
+    let mut first_assigned: Vec<(String, usize)> = Vec::new();
+    let mut exempt = HashSet::new();
+    for (i, line) in program.lines.iter().enumerate() {
+        let mut reads = Vec::new();
+        analyzer::statement_reads(&line.statement, &mut reads);
+        match &line.statement {
+            crate::Statement::DefFn { body, .. } => {
+                analyzer::expression_reads(body, &mut reads);
+                read.extend(reads.into_iter().map(|(name, _)| name));
+                continue;
+            },
+            crate::Statement::For { loop_data } => {
+                exempt.insert(loop_data.variable.clone());
+            },
+            crate::Statement::Field { fields, .. } => {
+                exempt.extend(fields.iter().map(|(_, name)| name.clone()));
+            },
+            // Kept for the program CHAIN runs next, which reads them
+            crate::Statement::Common { variables } => {
+                exempt.extend(variables.iter().cloned());
+            },
+            _ => {},
+        }
+        read.extend(reads.into_iter().map(|(name, _)| name));
+        let mut assigned = HashSet::new();
+        analyzer::collect_assignments(&line.statement, &mut assigned);
+        let mut assigned: Vec<String> = assigned.into_iter().collect();
+        assigned.sort();
+        for name in assigned {
+            if !first_assigned.iter().any(|(known, _)| *known == name) {
+                first_assigned.push((name, i));
+            }
+        }
+    }
+    for (name, i) in first_assigned {
+        if !read.contains(&name) && !exempt.contains(&name) {
+            found.push((i, None, format!("{} is assigned but never read", name)));
+        }
+    }
+}
+
+fn shadowed_loops(program: &crate::Program, found: &mut Vec<(usize, Option<crate::Position>, String)>) {
+    let mut open: Vec<&String> = Vec::new();
+    for (i, line) in program.lines.iter().enumerate() {
+        match &line.statement {
+            crate::Statement::For { loop_data } => {
+                if open.contains(&&loop_data.variable) {
+                    found.push((i, None, format!(
+                        "FOR {} is inside another FOR {} loop and changes its variable",
+                        loop_data.variable, loop_data.variable,
+                    )));
+                }
+                open.push(&loop_data.variable);
+            },
+            crate::Statement::Next { variable } => {
+                if let Some(depth) = open.iter().rposition(|name| *name == variable) {
+                    open.truncate(depth);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+impl Warning {
+    // Like LangError::render, pointing at the line in the source
+    pub fn render(&self, source: &str, file: &str, color: bool) -> String {
+        let line = crate::error::shown_line(source, self.source_line);
+        crate::error::snippet("warning", &self.message, None, source, file, line.map(|line| (line, self.column)), color)
+    }
+}
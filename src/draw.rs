@@ -0,0 +1,174 @@
+// The GW-BASIC graphics macro language used by DRAW, e.g. "C4 U10 R10 D10 L10"
+use crate::graphics::{Framebuffer, LineShape};
+use crate::errors::{Code, Error};
+
+// Settings carry over from one DRAW statement to the next, as in GW-BASIC.
+// The pen always starts from the screen's last point.
+pub struct Pen {
+    color: u8,
+    // S: a unit is scale/4 pixels
+    scale: f64,
+    // A and TA: degrees counterclockwise
+    angle: f64,
+}
+
+impl Pen {
+    pub fn new() -> Self {
+        Pen {
+            color: crate::graphics::DEFAULT_COLOR,
+            scale: 4.0,
+            angle: 0.0,
+        }
+    }
+
+    pub fn draw(&mut self, screen: &mut Framebuffer, source: &str) -> Result<(), Error> {
+        let chars: Vec<char> = source.to_uppercase().chars().filter(|c| !c.is_whitespace()).collect();
+        let mut pos = 0;
+        // Prefixes for the next move: B moves without drawing, N returns to where it started
+        let mut blank = false;
+        let mut no_update = false;
+
+        while pos < chars.len() {
+            let command = chars[pos];
+            pos += 1;
+            match command {
+                'B' => {
+                    blank = true;
+                    continue;
+                },
+                'N' => {
+                    no_update = true;
+                    continue;
+                },
+                'U' | 'D' | 'L' | 'R' | 'E' | 'F' | 'G' | 'H' => {
+                    let n = coordinate(number(&chars, &mut pos)?.unwrap_or(1.0), "distance")?;
+                    let (dx, dy) = match command {
+                        'U' => (0.0, -1.0),
+                        'D' => (0.0, 1.0),
+                        'L' => (-1.0, 0.0),
+                        'R' => (1.0, 0.0),
+                        'E' => (1.0, -1.0),
+                        'F' => (1.0, 1.0),
+                        'G' => (-1.0, 1.0),
+                        _ => (-1.0, -1.0),
+                    };
+                    let to = self.offset(screen.last_point(), dx * n, dy * n);
+                    self.move_to(screen, to, blank, no_update);
+                },
+                'M' => {
+                    let relative = matches!(chars.get(pos), Some('+') | Some('-'));
+                    let x = coordinate(number(&chars, &mut pos)?.ok_or("Expected x after M in DRAW")?, "x")?;
+                    if chars.get(pos) != Some(&',') {
+                        return Err("Expected ',' after x in DRAW M".into());
+                    }
+                    pos += 1;
+                    let y = coordinate(number(&chars, &mut pos)?.ok_or("Expected y after M in DRAW")?, "y")?;
+                    let to = if relative {
+                        self.offset(screen.last_point(), x, y)
+                    } else {
+                        (x, y)
+                    };
+                    self.move_to(screen, to, blank, no_update);
+                },
+                'A' => {
+                    let n = number(&chars, &mut pos)?.ok_or("Expected a number after A")?;
+                    self.angle = check(n, 0.0, 3.0, "angle")? * 90.0;
+                },
+                'T' => {
+                    if chars.get(pos) != Some(&'A') {
+                        return Err("Expected TA in DRAW".into());
+                    }
+                    pos += 1;
+                    let n = number(&chars, &mut pos)?.ok_or("Expected a number after TA")?;
+                    self.angle = check(n, -360.0, 360.0, "turn angle")?;
+                },
+                'C' => {
+                    let n = number(&chars, &mut pos)?.ok_or("Expected a number after C")?;
+                    self.color = crate::graphics::color_index(n)?;
+                },
+                'S' => {
+                    let n = number(&chars, &mut pos)?.ok_or("Expected a number after S")?;
+                    self.scale = check(n, 1.0, 255.0, "scale")?;
+                },
+                'P' => {
+                    let color = number(&chars, &mut pos)?.ok_or("Expected a color after P")?;
+                    if chars.get(pos) != Some(&',') {
+                        return Err("Expected ',' and border color after P in DRAW".into());
+                    }
+                    pos += 1;
+                    let border = number(&chars, &mut pos)?.ok_or("Expected a border color after P")?;
+                    let at = screen.last_point();
+                    screen.paint(at, crate::graphics::color_index(color)?, crate::graphics::color_index(border)?);
+                },
+                ';' => {},
+                other => return Err(format!("Unknown DRAW command '{}'", other).into()),
+            }
+            blank = false;
+            no_update = false;
+        }
+        Ok(())
+    }
+
+    // Relative moves are scaled and turned; screen y grows downward, so counterclockwise flips the sine
+    fn offset(&self, (x, y): (f64, f64), dx: f64, dy: f64) -> (f64, f64) {
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        let size = self.scale / 4.0;
+        (x + (dx * cos + dy * sin) * size, y + (dy * cos - dx * sin) * size)
+    }
+
+    // The line is clipped to the screen, so one that runs far off it costs no more than one on it
+    fn move_to(&self, screen: &mut Framebuffer, to: (f64, f64), blank: bool, no_update: bool) {
+        let from = screen.last_point();
+        if !blank {
+            screen.line(from, to, self.color, LineShape::Line);
+        }
+        screen.move_to(if no_update { from } else { to });
+    }
+}
+
+// An optionally signed whole number
+fn number(chars: &[char], pos: &mut usize) -> Result<Option<f64>, Error> {
+    let start = *pos;
+    if matches!(chars.get(*pos), Some('+') | Some('-')) {
+        *pos += 1;
+    }
+    let digits = *pos;
+    while *pos < chars.len() && chars[*pos].is_ascii_digit() {
+        *pos += 1;
+    }
+    if *pos == digits {
+        if digits > start {
+            return Err("Expected a number after sign in DRAW".into());
+        }
+        return Ok(None);
+    }
+    let text: String = chars[start..*pos].iter().collect();
+    text.parse().map(Some).map_err(|_| Error::from(format!("Bad number {} in DRAW", text)))
+}
+
+// Distances and points are 16-bit numbers in GW-BASIC
+fn coordinate(n: f64, what: &str) -> Result<f64, Error> {
+    check(n, -32768.0, 32767.0, what)
+}
+
+fn check(n: f64, min: f64, max: f64, what: &str) -> Result<f64, Error> {
+    if n < min || n > max {
+        return Err(Error::new(Code::OutOfRange, format!("DRAW {} {} out of range {} to {}", what, n, min, max)));
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_are_bounded_and_clipped() {
+        let mut screen = Framebuffer::new(100.0, 100.0).unwrap();
+        let mut pen = Pen::new();
+        assert_eq!(pen.draw(&mut screen, "S255 U99999999999"), Err(Error::new(Code::OutOfRange, "DRAW distance 99999999999 out of range -32768 to 32767")));
+        assert_eq!(pen.draw(&mut screen, "M5000,-5"), Ok(()));
+        pen.draw(&mut screen, "BM50,50 S255 NU32767 ND32767").unwrap();
+        assert!((0..100).all(|y| screen.pixel(50, y) == Some(crate::graphics::DEFAULT_COLOR)));
+    }
+}
@@ -0,0 +1,129 @@
+// The program being typed into the REPL, kept by line number. Every command that changes it
+// goes into a journal as one step, so UNDO puts back what the last command replaced or
+// deleted and REDO applies it again.
+use std::collections::BTreeMap;
+
+// One line before and after a change; None means the line wasn't there
+struct Change {
+    number: u32,
+    before: Option<String>,
+    after: Option<String>,
+}
+
+#[derive(Default)]
+pub struct Editor {
+    lines: BTreeMap<u32, String>,
+    undo: Vec<Vec<Change>>,
+    redo: Vec<Vec<Change>>,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Editor::default()
+    }
+
+    // Sets or, with None, deletes each line, as a single step for UNDO. Lines that end up the
+    // same as they were aren't recorded.
+    pub fn edit(&mut self, edits: impl IntoIterator<Item = (u32, Option<String>)>) {
+        let mut step = Vec::new();
+        for (number, text) in edits {
+            let before = match &text {
+                Some(text) => self.lines.insert(number, text.clone()),
+                None => self.lines.remove(&number),
+            };
+            if before != text {
+                step.push(Change { number, before, after: text });
+            }
+        }
+        if !step.is_empty() {
+            self.undo.push(step);
+            self.redo.clear();
+        }
+    }
+
+    pub fn set_line(&mut self, number: u32, text: &str) {
+        self.edit([(number, Some(text.to_string()))]);
+    }
+
+    pub fn delete_line(&mut self, number: u32) {
+        self.edit([(number, None)]);
+    }
+
+    // DELETE first-last; NEW is every line
+    pub fn delete_lines(&mut self, first: u32, last: u32) {
+        let numbers: Vec<u32> = self.lines.range(first..=last).map(|(&number, _)| number).collect();
+        self.edit(numbers.into_iter().map(|number| (number, None)));
+    }
+
+    // LOAD: the program in `source` replaces this one, as one step that UNDO takes back
+    pub fn load(&mut self, source: &str) -> Result<(), String> {
+        let mut lines = BTreeMap::new();
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let digits = line.find(|c: char| !c.is_ascii_digit()).unwrap_or(line.len());
+            let number = line[..digits].parse::<u32>()
+                .map_err(|_| format!("Line {} of the file has no line number", i + 1))?;
+            lines.insert(number, line[digits..].trim().to_string());
+        }
+        let mut edits: Vec<(u32, Option<String>)> = self.lines.keys()
+            .filter(|number| !lines.contains_key(number))
+            .map(|&number| (number, None))
+            .collect();
+        edits.extend(lines.into_iter().map(|(number, text)| (number, Some(text))));
+        self.edit(edits);
+        Ok(())
+    }
+
+    // What was undone, or None when there is nothing left to undo
+    pub fn undo(&mut self) -> Option<String> {
+        let step = self.undo.pop()?;
+        for change in step.iter().rev() {
+            self.put(change.number, change.before.clone());
+        }
+        let summary = summary(&step);
+        self.redo.push(step);
+        Some(summary)
+    }
+
+    pub fn redo(&mut self) -> Option<String> {
+        let step = self.redo.pop()?;
+        for change in &step {
+            self.put(change.number, change.after.clone());
+        }
+        let summary = summary(&step);
+        self.undo.push(step);
+        Some(summary)
+    }
+
+    fn put(&mut self, number: u32, text: Option<String>) {
+        match text {
+            Some(text) => self.lines.insert(number, text),
+            None => self.lines.remove(&number),
+        };
+    }
+
+    // The program as source, one numbered line each, ready for lang::parse
+    pub fn source(&self) -> String {
+        self.listing(0, u32::MAX)
+    }
+
+    // LIST first-last
+    pub fn listing(&self, first: u32, last: u32) -> String {
+        self.lines.range(first..=last).map(|(number, text)| format!("{} {}\n", number, text)).collect()
+    }
+}
+
+// Describes a step as it was first made, so undoing an added line says "added line 10"
+fn summary(step: &[Change]) -> String {
+    let described: Vec<String> = step.iter().map(|change| {
+        match (&change.before, &change.after) {
+            (None, Some(_)) => format!("added line {}", change.number),
+            (Some(_), None) => format!("deleted line {}", change.number),
+            _ => format!("changed line {}", change.number),
+        }
+    }).collect();
+    described.join(", ")
+}
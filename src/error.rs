@@ -0,0 +1,186 @@
+// What went wrong with a program and where. Lex and parse errors point at a line and column
+// of the source file; semantic and runtime errors name the BASIC line that was running and the column of
+// the expression or statement that failed, and keep the source line that is on for `render`,
+// which shows one against the source it came from. Each has its code from the catalog in errors.rs.
+use std::fmt;
+use crate::errors::Code;
+
+const MAX_FRAMES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LangError {
+    // Text that can't be split into tokens, like an unterminated string
+    LexError { line: usize, column: usize, code: Code, message: String },
+    // Tokens that don't make a statement
+    ParseError { line: usize, column: usize, code: Code, message: String },
+    // Found by looking over the whole program before it runs, like a GOTO to a missing line
+    SemanticError { line: u32, source_line: usize, column: usize, code: Code, message: String },
+    // `stack` is the FOR loops that were open, innermost first: each one's line number, statement
+    // and how many times over it was open in a row
+    RuntimeError { line: u32, source_line: usize, column: usize, code: Code, message: String, stack: Vec<(u32, String, usize)> },
+    // Every lex and parse error in a file, in order, when there is more than one
+    Multiple(Vec<LangError>),
+}
+
+impl LangError {
+    pub fn message(&self) -> &str {
+        match self {
+            LangError::LexError { message, .. }
+            | LangError::ParseError { message, .. }
+            | LangError::SemanticError { message, .. }
+            | LangError::RuntimeError { message, .. } => message,
+            LangError::Multiple(errors) => errors.first().map_or("", |e| e.message()),
+        }
+    }
+
+    // The error's code from the catalog in errors.rs, like E001, for `lang explain`
+    pub fn code(&self) -> &'static str {
+        self.kind().entry().code
+    }
+
+    // The code itself, for matching on; a Multiple has the code of its first error
+    pub fn kind(&self) -> Code {
+        match self {
+            LangError::LexError { code, .. }
+            | LangError::ParseError { code, .. }
+            | LangError::SemanticError { code, .. }
+            | LangError::RuntimeError { code, .. } => *code,
+            LangError::Multiple(errors) => errors.first().map_or(Code::Syntax, |e| e.kind()),
+        }
+    }
+}
+
+impl fmt::Display for LangError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LangError::LexError { line, column, message, .. } | LangError::ParseError { line, column, message, .. } => {
+                write!(f, "Syntax error at line {}, column {}: {}", line, column, message)
+            },
+            LangError::SemanticError { line, column, message, .. } => write!(f, "Error at line {}, column {}: {}", line, column, message),
+            LangError::RuntimeError { line, column, message, stack, .. } => {
+                write!(f, "Error at line {}, column {}: {}", line, column, message)?;
+                for frame in frames(stack) {
+                    write!(f, "\n    {}", frame)?;
+                }
+                Ok(())
+            },
+            LangError::Multiple(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", errors.join("\n"))
+            },
+        }
+    }
+}
+
+impl std::error::Error for LangError {}
+
+impl LangError {
+    // The message with the source line it points at, a caret under the column and a hint when
+    // there is one, for printing to a terminal. With `color` the labels use ANSI colors.
+    pub fn render(&self, source: &str, file: &str, color: bool) -> String {
+        let severity = format!("error[{}]", self.code());
+        let help = self.kind().entry().help;
+        match self {
+            LangError::LexError { line, column, message, .. } | LangError::ParseError { line, column, message, .. } => {
+                snippet(&severity, message, help, source, file, Some((*line, *column)), color)
+            },
+            LangError::SemanticError { line, source_line, column, .. } => {
+                self.render_at_line(*line, *source_line, *column, source, file, color)
+            },
+            LangError::RuntimeError { line, source_line, column, stack, .. } => {
+                let mut out = self.render_at_line(*line, *source_line, *column, source, file, color);
+                // Lined up with the snippet's gutter, as the help line is
+                let gutter = " ".repeat(shown_line(source, *source_line).map_or(0, |line| line.to_string().len()));
+                let arrow = if color { "\x1b[34m=\x1b[0m" } else { "=" };
+                for frame in frames(stack) {
+                    out.push_str(&format!("{} {} {}\n", gutter, arrow, frame));
+                }
+                out
+            },
+            LangError::Multiple(errors) => {
+                let rendered: Vec<String> = errors.iter().map(|e| e.render(source, file, color)).collect();
+                rendered.join("\n")
+            },
+        }
+    }
+
+    fn render_at_line(&self, line: u32, source_line: usize, column: usize, source: &str, file: &str, color: bool) -> String {
+        let severity = format!("error[{}]", self.code());
+        let message = self.message();
+        let help = self.kind().entry().help;
+        match shown_line(source, source_line) {
+            Some(source_line) => snippet(&severity, message, help, source, file, Some((source_line, column)), color),
+            // No source to show, as for a program loaded from an AST
+            None => {
+                let message = format!("{} at line {}, column {}", message, line, column);
+                snippet(&severity, &message, help, source, file, None, color)
+            },
+        }
+    }
+}
+
+// The lines of a runtime error's loop trace, up to MAX_FRAMES of them
+fn frames(stack: &[(u32, String, usize)]) -> Vec<String> {
+    let mut frames: Vec<String> = stack.iter().take(MAX_FRAMES)
+        .map(|(number, statement, times)| match times {
+            1 => format!("in {} at line {}", statement, number),
+            times => format!("in {} at line {}, {} times over", statement, number, times),
+        })
+        .collect();
+    if stack.len() > MAX_FRAMES {
+        frames.push(format!("in {} more loops outside those", stack.len() - MAX_FRAMES));
+    }
+    frames
+}
+
+// The source line, counting from 1, when the source has it
+pub(crate) fn shown_line(source: &str, source_line: usize) -> Option<usize> {
+    (source_line > 0 && source.lines().nth(source_line - 1).is_some()).then_some(source_line)
+}
+
+pub(crate) fn snippet(
+    severity: &str,
+    message: &str,
+    help: Option<&str>,
+    source: &str,
+    file: &str,
+    at: Option<(usize, usize)>,
+    color: bool,
+) -> String {
+    let paint = |code: &str, text: &str| if color { format!("\x1b[{}m{}\x1b[0m", code, text) } else { text.to_string() };
+    let label_color = if severity.starts_with("error") { "1;31" } else { "1;33" };
+    let mut out = format!("{}: {}\n", paint(label_color, severity), paint("1", message));
+    let Some((line, column)) = at else {
+        return out;
+    };
+    let text = source.lines().nth(line - 1).unwrap_or_default().trim_end();
+    let gutter = " ".repeat(line.to_string().len());
+    out.push_str(&format!("{}{} {}:{}:{}\n", gutter, paint("34", "-->"), file, line, column));
+    out.push_str(&format!("{} {}\n", gutter, paint("34", "|")));
+    out.push_str(&format!("{} {} {}\n", paint("34", &line.to_string()), paint("34", "|"), text));
+    let pad = " ".repeat(column.saturating_sub(1));
+    out.push_str(&format!("{} {} {}{}\n", gutter, paint("34", "|"), pad, paint(label_color, "^")));
+    if let Some(help) = help {
+        out.push_str(&format!("{} {} {}\n", gutter, paint("34", "="), paint("1", &format!("help: {}", help))));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_loops_are_shown_once() {
+        let stack = vec![(20, "FOR J = 1 TO 2".to_string(), 3), (10, "FOR I = 1 TO 2".to_string(), 1)];
+        assert_eq!(frames(&stack), ["in FOR J = 1 TO 2 at line 20, 3 times over", "in FOR I = 1 TO 2 at line 10"]);
+    }
+
+    #[test]
+    fn long_traces_stop_after_ten_loops() {
+        let stack: Vec<(u32, String, usize)> = (0..25).map(|n| (n, format!("FOR A{} = 1 TO 2", n), 1)).collect();
+        let shown = frames(&stack);
+        assert_eq!(shown.len(), MAX_FRAMES + 1);
+        assert_eq!(shown[MAX_FRAMES], "in 15 more loops outside those");
+    }
+}
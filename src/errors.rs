@@ -0,0 +1,299 @@
+// The catalog of error codes. Every error LANG reports has one, shown as error[E001] and
+// explained at length by `lang explain E001`. The code is given where the error is made, along
+// with its message: the lexer and parser give theirs to LangError directly, and the interpreter
+// passes an Error up to the statement that was running. Codes never change meaning once given
+// out; a new kind of error gets the next free number.
+use std::fmt;
+
+// One variant per entry of CODES, in the same order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    NextWithoutFor = 1,
+    NextMismatch,
+    UnexpectedCharacter,
+    UnterminatedString,
+    Syntax,
+    UndefinedLine,
+    UndefinedVariable,
+    TypeMismatch,
+    DivisionByZero,
+    Overflow,
+    IllegalFunctionCall,
+    UnknownFunction,
+    OutOfRange,
+    BadFileNumber,
+    FileError,
+    Sandbox,
+    Limit,
+    TooDeep,
+    BadExitStatus,
+    Runtime,
+}
+
+impl Code {
+    pub fn entry(self) -> &'static ErrorCode {
+        &CODES[self as usize - 1]
+    }
+}
+
+pub struct ErrorCode {
+    pub code: &'static str,
+    pub title: &'static str,
+    // A line shown under the error's snippet, for the mistakes people make most
+    pub help: Option<&'static str>,
+    pub explanation: &'static str,
+    pub example: &'static str,
+}
+
+// An error from running a program, before it is placed at a line. Plain text gets the catch-all
+// code, so messages from hosts and backends need nothing more.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub code: Code,
+    pub message: String,
+}
+
+impl Error {
+    pub fn new(code: Code, message: impl Into<String>) -> Self {
+        Error { code, message: message.into() }
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::new(Code::Runtime, message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::new(Code::Runtime, message)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+pub const CODES: &[ErrorCode] = &[
+    ErrorCode {
+        code: "E001",
+        title: "NEXT without FOR",
+        help: Some("each NEXT closes the most recent FOR over the same variable"),
+        explanation: "A NEXT was reached with no FOR loop open for it to close. Every NEXT has to come after the FOR it ends, and a GOTO that jumps past a FOR into its loop leaves the loop unopened.",
+        example: "10 PRINT \"Hello\"\n20 NEXT I",
+    },
+    ErrorCode {
+        code: "E002",
+        title: "NEXT doesn't match FOR",
+        help: Some("each NEXT closes the most recent FOR over the same variable"),
+        explanation: "NEXT names a different variable from the FOR loop it would close. Loops have to be closed in the opposite order to the one they were opened in, innermost first.",
+        example: "10 FOR I = 1 TO 3\n20 FOR J = 1 TO 3\n30 NEXT I\n40 NEXT J",
+    },
+    ErrorCode {
+        code: "E003",
+        title: "Unexpected character",
+        help: Some("outside double quotes a line can only have names, numbers and operators"),
+        explanation: "The line has a character LANG doesn't know what to do with. Outside double quotes a line can only have names, numbers, operators, commas, semicolons and parentheses.",
+        example: "10 PRINT 5 @ 2",
+    },
+    ErrorCode {
+        code: "E004",
+        title: "Unterminated string",
+        help: Some("strings end with a closing double quote on the same line"),
+        explanation: "A string was started with a double quote but the line ended before the closing one. Strings can't go over more than one line.",
+        example: "10 PRINT \"Hello",
+    },
+    ErrorCode {
+        code: "E005",
+        title: "Syntax error",
+        help: None,
+        explanation: "The words on the line don't make a statement. The message says what LANG expected to find where the caret points, such as TO in a FOR statement or = in a LET.",
+        example: "10 FOR I = 1 10",
+    },
+    ErrorCode {
+        code: "E006",
+        title: "Undefined line number",
+        help: Some("GOTO, THEN and TASK need the number of a line in the program"),
+        explanation: "A GOTO, THEN or TASK names a line number that no line in the program has. Check the number, or renumber the program with `lang renum`, which fixes every jump.",
+        example: "10 GOTO 100\n20 END",
+    },
+    ErrorCode {
+        code: "E007",
+        title: "Undefined variable",
+        help: Some("a variable has to be given a value with LET, INPUT or FOR before it is read"),
+        explanation: "A variable was read before anything gave it a value. Give it one with LET, INPUT or FOR first. With --strict or OPTION EXPLICIT this is checked before the program runs.",
+        example: "10 PRINT TOTAL\n20 LET TOTAL = 5",
+    },
+    ErrorCode {
+        code: "E008",
+        title: "Type mismatch",
+        help: None,
+        explanation: "A string was used where a number has to go, or a number where a string has to go. Names ending in $ hold strings, and other names hold numbers.",
+        example: "10 LET A$ = 5",
+    },
+    ErrorCode {
+        code: "E009",
+        title: "Division by zero",
+        help: Some("check the divisor with IF before dividing"),
+        explanation: "The right-hand side of a / or MOD was zero. Check the divisor with IF before dividing.",
+        example: "10 LET D = 0\n20 PRINT 10 / D",
+    },
+    ErrorCode {
+        code: "E010",
+        title: "Overflow",
+        help: None,
+        explanation: "A number was too big for where it was going, such as more than 32767 for an integer variable made with DEFINT or a % name.",
+        example: "10 DEFINT N\n20 LET N = 40000",
+    },
+    ErrorCode {
+        code: "E011",
+        title: "Illegal function call",
+        help: None,
+        explanation: "A function or statement was given the wrong number of arguments, or a value it can't work with, such as the square root of a negative number.",
+        example: "10 LET X = -4\n20 PRINT SQR(X)",
+    },
+    ErrorCode {
+        code: "E012",
+        title: "Unknown function",
+        help: None,
+        explanation: "The program calls a function that is neither built in nor defined with DEF FN. Check its spelling, and that its DEF FN runs before the call.",
+        example: "10 PRINT FNSQ(3)",
+    },
+    ErrorCode {
+        code: "E013",
+        title: "Out of range",
+        help: None,
+        explanation: "A number used to pick something, such as an argument for ARGS$, a sprite or a pin, is past the ones there are.",
+        example: "10 PRINT ARGS$(5)",
+    },
+    ErrorCode {
+        code: "E014",
+        title: "Bad file number",
+        help: None,
+        explanation: "A file statement used a file number that no OPEN has opened or that was already closed, or an OPEN used a number that is already open.",
+        example: "10 GET #1, 1",
+    },
+    ErrorCode {
+        code: "E015",
+        title: "File error",
+        help: None,
+        explanation: "A file couldn't be opened, read or written. The message has the reason the system gave, such as a missing file or a missing permission.",
+        example: "10 OPEN \"/no/such/dir/data.dat\" FOR RANDOM AS #1 LEN = 32",
+    },
+    ErrorCode {
+        code: "E016",
+        title: "Disabled in sandbox mode",
+        help: None,
+        explanation: "With --sandbox, statements and functions that reach outside the program, such as SHELL, KILL and ENVIRON$, are turned off.",
+        example: "10 SHELL \"ls\"",
+    },
+    ErrorCode {
+        code: "E017",
+        title: "Limit exceeded",
+        help: None,
+        explanation: "The program went past a limit it was run with: --max-steps, --timeout or --max-memory. A program that never ends usually has a GOTO or loop that nothing stops.",
+        example: "10 GOTO 10",
+    },
+    ErrorCode {
+        code: "E018",
+        title: "Nested too deeply",
+        help: None,
+        explanation: "DEF FN functions called each other too many times deep, usually because a function calls itself with nothing to stop it.",
+        example: "10 DEF FNF(X) = FNF(X + 1)\n20 PRINT FNF(1)",
+    },
+    ErrorCode {
+        code: "E019",
+        title: "Bad exit status",
+        help: None,
+        explanation: "END was given an exit status outside 0 to 255, which is all an operating system keeps.",
+        example: "10 END 300",
+    },
+    ErrorCode {
+        code: "E020",
+        title: "Runtime error",
+        help: None,
+        explanation: "Something went wrong while the program ran that has no code of its own. The message says what happened.",
+        example: "10 PLAY \"Z\"",
+    },
+];
+
+pub fn find(code: &str) -> Option<&'static ErrorCode> {
+    CODES.iter().find(|entry| entry.code.eq_ignore_ascii_case(code))
+}
+
+// What `lang explain` prints for a code
+pub fn explain(code: &str) -> Result<String, String> {
+    let entry = find(code).ok_or_else(|| format!("{} isn't an error code; `lang explain` lists them", code))?;
+    let example: Vec<String> = entry.example.lines().map(|line| format!("    {}", line)).collect();
+    Ok(format!("{}: {}\n\n{}\n\nFor example:\n\n{}\n", entry.code, entry.title, entry.explanation, example.join("\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The code of the first error `source` stops with, run with a step limit
+    fn code_of(source: &str, sandbox: bool) -> Option<&'static str> {
+        let program = match crate::parse(source).and_then(|program| crate::check_program(&program).map(|_| program)) {
+            Ok(program) => program,
+            Err(e) => return Some(e.code()),
+        };
+        let mut interpreter = crate::Interpreter::new();
+        interpreter.sandbox = sandbox;
+        interpreter.max_steps = Some(10_000);
+        interpreter.run_captured(&program, Vec::new()).error.map(|e| e.code())
+    }
+
+    #[test]
+    fn every_example_gives_its_own_code() {
+        for entry in CODES {
+            // Only the sandbox turns SHELL down, and it turns OPEN down too
+            let sandbox = entry.code == "E016";
+            assert_eq!(code_of(entry.example, sandbox), Some(entry.code), "{}", entry.example);
+        }
+    }
+
+    #[test]
+    fn codes_are_numbered_in_order() {
+        for (i, entry) in CODES.iter().enumerate() {
+            assert_eq!(entry.code, format!("E{:03}", i + 1));
+        }
+    }
+
+    #[test]
+    fn each_code_is_its_own_entry() {
+        assert_eq!(Code::NextWithoutFor.entry().code, "E001");
+        assert_eq!(Code::DivisionByZero.entry().title, "Division by zero");
+        assert_eq!(Code::Runtime.entry().code, "E020");
+        assert_eq!(Error::from("Something nobody wrote").code, Code::Runtime);
+    }
+
+    #[test]
+    fn codes_come_from_where_the_error_is_made_not_its_message() {
+        // The file's name isn't a division, nor a hint about one
+        let source = "10 OPEN \"nodir/Division by zero\" FOR RANDOM AS #1 LEN=20";
+        assert_eq!(code_of(source, false), Some("E015"));
+        let error = crate::Interpreter::new().run_captured(&crate::parse(source).unwrap(), Vec::new()).error.unwrap();
+        assert!(!error.render(source, "test.bas", false).contains("help:"));
+        assert_eq!(code_of("10 PRINT \"Division by zero\" + 1", false), Some("E008"));
+    }
+
+    #[test]
+    fn find_ignores_case() {
+        assert_eq!(find("e009").map(|entry| entry.title), Some("Division by zero"));
+        assert_eq!(find("E009").map(|entry| entry.title), Some("Division by zero"));
+        assert!(find("E999").is_none());
+        assert!(find("").is_none());
+    }
+
+    #[test]
+    fn explain_shows_the_example_indented() {
+        let text = explain("E004").unwrap();
+        assert!(text.starts_with("E004: Unterminated string\n\n"));
+        assert!(text.ends_with("For example:\n\n    10 PRINT \"Hello\n"));
+        assert_eq!(explain("E100"), Err("E100 isn't an error code; `lang explain` lists them".to_string()));
+    }
+}
@@ -0,0 +1,183 @@
+// A C interface for embedding LANG in programs that aren't written in Rust, built into
+// liblang.so, lang.dll or liblang.dylib by `cargo rustc --lib --features ffi --crate-type cdylib`.
+// include/lang.h declares it. Every pointer given to these functions must be valid, strings
+// must be UTF-8 and end in a NUL, and one LangInterpreter must not be used from two threads at
+// once. A panic can't unwind into C, so each function catches them and fails instead.
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::errors::Error;
+use crate::io::{IoBackend, StdIo};
+use crate::{Interpreter, Program, Value};
+
+pub const LANG_UNSET: c_int = 0;
+pub const LANG_NUMBER: c_int = 1;
+pub const LANG_STRING: c_int = 2;
+
+pub type OutputCallback = extern "C" fn(text: *const c_char, length: usize, user_data: *mut c_void);
+
+pub struct LangInterpreter {
+    interpreter: Interpreter,
+    program: Option<Program>,
+    // What lang_last_error and lang_get_var hand out, kept until the next call that replaces it
+    error: Option<CString>,
+    string: Option<CString>,
+}
+
+impl LangInterpreter {
+    fn fail(&mut self, message: String) -> c_int {
+        self.error = Some(CString::new(message.replace('\0', " ")).unwrap_or_default());
+        -1
+    }
+}
+
+// Runs `f` on the interpreter behind `lang`, turning a NULL into -1 and a panic into -1 with
+// lang_last_error saying what happened
+unsafe fn guarded(lang: *mut LangInterpreter, f: impl FnOnce(&mut LangInterpreter) -> c_int) -> c_int {
+    let Some(lang) = lang.as_mut() else { return -1 };
+    match panic::catch_unwind(AssertUnwindSafe(|| f(lang))) {
+        Ok(result) => result,
+        Err(payload) => lang.fail(format!("LANG crashed: {}", crate::crash::panic_message(payload.as_ref()))),
+    }
+}
+
+// PRINT goes to the host's callback; INPUT still reads stdin
+struct CallbackIo {
+    callback: OutputCallback,
+    user_data: *mut c_void,
+}
+
+// The host promises user_data can go wherever the interpreter goes
+unsafe impl Send for CallbackIo {}
+
+impl IoBackend for CallbackIo {
+    fn write(&mut self, text: &str) -> Result<(), Error> {
+        // Text can hold a NUL, so the callback gets a length and no terminator
+        (self.callback)(text.as_ptr().cast(), text.len(), self.user_data);
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<String, Error> {
+        StdIo.read_line()
+    }
+}
+
+unsafe fn text<'a>(pointer: *const c_char) -> Result<&'a str, String> {
+    if pointer.is_null() {
+        return Err("A string argument was NULL".to_string());
+    }
+    CStr::from_ptr(pointer).to_str().map_err(|_| "A string argument isn't UTF-8".to_string())
+}
+
+// NULL if the interpreter couldn't be made
+#[no_mangle]
+pub extern "C" fn lang_new() -> *mut LangInterpreter {
+    panic::catch_unwind(|| {
+        Box::into_raw(Box::new(LangInterpreter {
+            interpreter: Interpreter::new(),
+            program: None,
+            error: None,
+            string: None,
+        }))
+    }).unwrap_or(std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn lang_free(lang: *mut LangInterpreter) {
+    if !lang.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(lang))));
+    }
+}
+
+// Parses and checks a program for lang_run. 0 when it loaded, -1 with lang_last_error set when not.
+#[no_mangle]
+pub unsafe extern "C" fn lang_load_source(lang: *mut LangInterpreter, source: *const c_char) -> c_int {
+    guarded(lang, |lang| {
+        let source = match text(source) {
+            Ok(source) => source,
+            Err(e) => return lang.fail(e),
+        };
+        match crate::parse(source).and_then(|program| crate::check_program(&program).map(|_| program)) {
+            Ok(program) => {
+                lang.program = Some(program);
+                0
+            },
+            Err(e) => lang.fail(e.to_string()),
+        }
+    })
+}
+
+// Runs the loaded program. 0 with the exit status `lang` would give, 0 or END's number, in
+// *status, which can be NULL, or -1 with lang_last_error set when there is no program or it
+// stopped with an error. Variables keep their values afterwards for lang_get_var.
+#[no_mangle]
+pub unsafe extern "C" fn lang_run(lang: *mut LangInterpreter, status: *mut c_int) -> c_int {
+    guarded(lang, |lang| {
+        let Some(program) = lang.program.take() else {
+            return lang.fail("No program is loaded; call lang_load_source first".to_string());
+        };
+        let result = lang.interpreter.run(&program);
+        lang.program = Some(program);
+        match result {
+            Ok(()) => {
+                if let Some(status) = status.as_mut() {
+                    *status = lang.interpreter.end_status();
+                }
+                0
+            },
+            Err(e) => lang.fail(e.to_string()),
+        }
+    })
+}
+
+// LANG_NUMBER with the value in *number, LANG_STRING with it in *string, or LANG_UNSET when the
+// variable has no value. Either pointer can be NULL. The string lasts until the next
+// lang_get_var or lang_free.
+#[no_mangle]
+pub unsafe extern "C" fn lang_get_var(lang: *mut LangInterpreter, name: *const c_char, number: *mut f64, string: *mut *const c_char) -> c_int {
+    let found = guarded(lang, |lang| {
+        let Ok(name) = text(name) else { return LANG_UNSET };
+        match lang.interpreter.get_var(name) {
+            Some(Value::Number(value)) => {
+                if let Some(number) = number.as_mut() {
+                    *number = value;
+                }
+                LANG_NUMBER
+            },
+            Some(Value::String(value)) => {
+                let value = lang.string.insert(CString::new(value.replace('\0', " ")).unwrap_or_default());
+                if let Some(string) = string.as_mut() {
+                    *string = value.as_ptr();
+                }
+                LANG_STRING
+            },
+            None => LANG_UNSET,
+        }
+    });
+    // guarded's -1 for a NULL or a panic reads as no value
+    found.max(LANG_UNSET)
+}
+
+// Sends PRINT's text and INPUT's prompts to `callback` instead of stdout, with `user_data`
+// passed back on every call. NULL puts stdout back.
+#[no_mangle]
+pub unsafe extern "C" fn lang_set_output_callback(lang: *mut LangInterpreter, callback: Option<OutputCallback>, user_data: *mut c_void) {
+    guarded(lang, |lang| {
+        lang.interpreter.io = match callback {
+            Some(callback) => Box::new(CallbackIo { callback, user_data }),
+            None => Box::new(StdIo),
+        };
+        0
+    });
+}
+
+// The message of the last call that failed, or NULL. It lasts until the next failure or lang_free.
+#[no_mangle]
+pub unsafe extern "C" fn lang_last_error(lang: *const LangInterpreter) -> *const c_char {
+    match lang.as_ref().and_then(|lang| lang.error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
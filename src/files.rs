@@ -0,0 +1,257 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use crate::errors::{Code, Error};
+
+pub const DEFAULT_RECORD_LENGTH: usize = 128;
+// GW-BASIC's limits on LEN and on record numbers
+const MAX_RECORD_LENGTH: usize = 32767;
+const MAX_RECORD: u64 = 16_777_215;
+
+// A web page has no file system for a program to reach
+const NO_FILES: &str = "Files aren't available in the browser";
+
+pub struct Field {
+    variable: String,
+    offset: usize,
+    width: usize,
+}
+
+// A file opened FOR RANDOM: fixed-length records moved through a shared buffer
+pub struct RandomFile {
+    file: File,
+    record_length: usize,
+    buffer: Vec<u8>,
+    fields: Vec<Field>,
+    last_record: u64,
+}
+
+impl RandomFile {
+    pub fn open(path: &str, record_length: usize) -> Result<Self, Error> {
+        if record_length == 0 || record_length > MAX_RECORD_LENGTH {
+            return Err(Error::new(Code::OutOfRange, format!("Record length must be between 1 and {}", MAX_RECORD_LENGTH)));
+        }
+        if cfg!(target_arch = "wasm32") {
+            return Err(Error::new(Code::FileError, NO_FILES));
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| Error::new(Code::FileError, format!("Error opening {}: {}", path, e)))?;
+        Ok(RandomFile {
+            file,
+            record_length,
+            buffer: vec![b' '; record_length],
+            fields: Vec::new(),
+            last_record: 0,
+        })
+    }
+
+    pub fn record_length(&self) -> usize {
+        self.record_length
+    }
+
+    pub fn set_fields(&mut self, fields: Vec<(usize, String)>) -> Result<(), Error> {
+        match fields.iter().try_fold(0usize, |total, (width, _)| total.checked_add(*width)) {
+            Some(total) if total <= self.record_length => {},
+            Some(total) => return Err(Error::new(Code::OutOfRange, format!("FIELD of {} bytes overflows record length {}", total, self.record_length))),
+            None => return Err(Error::new(Code::OutOfRange, format!("FIELD overflows record length {}", self.record_length))),
+        }
+        let mut offset = 0;
+        self.fields = fields.into_iter()
+            .map(|(width, variable)| {
+                let field = Field { variable, offset, width };
+                offset += width;
+                field
+            })
+            .collect();
+        Ok(())
+    }
+
+    pub fn has_field(&self, variable: &str) -> bool {
+        self.fields.iter().any(|f| f.variable == variable)
+    }
+
+    pub fn field_values(&self) -> Vec<(String, String)> {
+        self.fields.iter()
+            .map(|f| {
+                let bytes = &self.buffer[f.offset..f.offset + f.width];
+                (f.variable.clone(), latin1(bytes))
+            })
+            .collect()
+    }
+
+    // LSET/RSET into the record buffer, returning the justified field contents
+    pub fn write_field(&mut self, variable: &str, value: &str, right: bool) -> Option<String> {
+        let field = self.fields.iter().find(|f| f.variable == variable)?;
+        let justified = justify(value, field.width, right);
+        self.buffer[field.offset..field.offset + field.width].copy_from_slice(&justified);
+        Some(latin1(&justified))
+    }
+
+    pub fn get(&mut self, record: Option<f64>) -> Result<(), Error> {
+        let record = self.record_number(record)?;
+        self.file.seek(SeekFrom::Start(self.offset(record)?))
+            .map_err(|e| Error::new(Code::FileError, format!("Error seeking record {}: {}", record, e)))?;
+
+        // Reading past the end of the file leaves the rest of the record blank
+        self.buffer.fill(b' ');
+        let mut read = 0;
+        while read < self.record_length {
+            match self.file.read(&mut self.buffer[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) => return Err(Error::new(Code::FileError, format!("Error reading record {}: {}", record, e))),
+            }
+        }
+        self.last_record = record;
+        Ok(())
+    }
+
+    pub fn put(&mut self, record: Option<f64>) -> Result<(), Error> {
+        let record = self.record_number(record)?;
+        let offset = self.offset(record)?;
+        self.file.seek(SeekFrom::Start(offset))
+            .and_then(|_| self.file.write_all(&self.buffer))
+            .map_err(|e| Error::new(Code::FileError, format!("Error writing record {}: {}", record, e)))?;
+        self.last_record = record;
+        Ok(())
+    }
+
+    pub fn length(&self) -> Result<u64, Error> {
+        self.file.metadata()
+            .map(|m| m.len())
+            .map_err(|e| Error::new(Code::FileError, format!("Error reading file length: {}", e)))
+    }
+
+    pub fn location(&self) -> u64 {
+        self.last_record
+    }
+
+    fn record_number(&self, record: Option<f64>) -> Result<u64, Error> {
+        match record {
+            None if self.last_record < MAX_RECORD => Ok(self.last_record + 1),
+            None => Err(Error::new(Code::OutOfRange, format!("Bad record number {}", self.last_record + 1))),
+            Some(n) if (1.0..=MAX_RECORD as f64).contains(&n) => Ok(n as u64),
+            Some(n) => Err(Error::new(Code::OutOfRange, format!("Bad record number {}", n))),
+        }
+    }
+
+    fn offset(&self, record: u64) -> Result<u64, Error> {
+        record.checked_sub(1)
+            .and_then(|index| index.checked_mul(self.record_length as u64))
+            .ok_or_else(|| Error::new(Code::OutOfRange, format!("Bad record number {}", record)))
+    }
+}
+
+// Record buffers hold one byte per character, like GW-BASIC, so strings from MKI$ and friends
+// come back out of a file unchanged. Characters past U+00FF can't be stored and become ?.
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+// Pads or truncates to exactly `width` bytes, like LSET/RSET
+pub fn justify(value: &str, width: usize, right: bool) -> Vec<u8> {
+    let mut bytes: Vec<u8> = value.chars().map(|c| u8::try_from(c).unwrap_or(b'?')).take(width).collect();
+    let padding = vec![b' '; width - bytes.len()];
+    if right {
+        [padding, bytes].concat()
+    } else {
+        bytes.extend(padding);
+        bytes
+    }
+}
+
+// The source of a program CHAIN runs
+pub fn read_program(path: &std::path::Path) -> Result<String, Error> {
+    if cfg!(target_arch = "wasm32") {
+        return Err(Error::new(Code::FileError, NO_FILES));
+    }
+    std::fs::read_to_string(path).map_err(|e| Error::new(Code::FileError, format!("Error reading {}: {}", path.display(), e)))
+}
+
+pub fn kill(path: &str) -> Result<(), Error> {
+    if cfg!(target_arch = "wasm32") {
+        return Err(Error::new(Code::FileError, NO_FILES));
+    }
+    std::fs::remove_file(path).map_err(|e| Error::new(Code::FileError, format!("Error deleting {}: {}", path, e)))
+}
+
+pub fn rename(from: &str, to: &str) -> Result<(), Error> {
+    if cfg!(target_arch = "wasm32") {
+        return Err(Error::new(Code::FileError, NO_FILES));
+    }
+    if std::path::Path::new(to).exists() {
+        return Err(Error::new(Code::FileError, format!("Error renaming {}: {} already exists", from, to)));
+    }
+    std::fs::rename(from, to).map_err(|e| Error::new(Code::FileError, format!("Error renaming {}: {}", from, e)))
+}
+
+// FILES lists a directory, optionally filtered by a * and ? wildcard pattern
+pub fn list(pattern: Option<&str>) -> Result<Vec<String>, Error> {
+    if cfg!(target_arch = "wasm32") {
+        return Err(Error::new(Code::FileError, NO_FILES));
+    }
+    let (directory, wildcard) = match pattern {
+        Some(pattern) if std::path::Path::new(pattern).is_dir() => (pattern, "*"),
+        Some(pattern) => match pattern.rfind(['/', '\\']) {
+            Some(i) => (&pattern[..=i], &pattern[i + 1..]),
+            None => (".", pattern),
+        },
+        None => (".", "*"),
+    };
+
+    let entries = std::fs::read_dir(directory)
+        .map_err(|e| Error::new(Code::FileError, format!("Error listing {}: {}", directory, e)))?;
+    let mut names: Vec<String> = entries.flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| wildcard_match(&wildcard.to_uppercase(), &name.to_uppercase()))
+        .collect();
+    if names.is_empty() {
+        return Err(Error::new(Code::FileError, format!("File not found: {}", pattern.unwrap_or(directory))));
+    }
+    names.sort();
+    Ok(names)
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_have_to_fit_in_the_record() {
+        let path = std::env::temp_dir().join(format!("lang-fields-{}.dat", std::process::id()));
+        let mut file = RandomFile::open(path.to_str().unwrap(), 20).unwrap();
+        let fields = |widths: &[usize]| widths.iter().map(|&width| (width, "A$".to_string())).collect();
+        assert_eq!(file.set_fields(fields(&[usize::MAX, usize::MAX])), Err(Error::new(Code::OutOfRange, "FIELD overflows record length 20")));
+        assert_eq!(file.set_fields(fields(&[10, 15])), Err(Error::new(Code::OutOfRange, "FIELD of 25 bytes overflows record length 20")));
+        assert_eq!(file.set_fields(fields(&[10, 10])), Ok(()));
+        drop(file);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::panic::{self, AssertUnwindSafe};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const MAX_STEPS: usize = 10_000;
+const OUTPUT_LIMIT: usize = 64 * 1024;
+const MAX_LINES: usize = 60;
+const MAX_EXPR_DEPTH: usize = 3;
+const CORPUS_DIR: &str = "fuzz/corpus";
+const CRASH_DIR: &str = "fuzz/crashes";
+
+const VARIABLES: &[&str] = &["A", "B", "C", "N", "X", "Y"];
+const LOOP_VARIABLES: &[&str] = &["I", "J", "K"];
+const FUNCTIONS: &[&str] = &["ABS", "SQR", "SIN", "COS", "TAN", "INT", "RND"];
+const OPERATORS: &[&str] = &["+", "-", "*", "/", "^", "<", ">", "=", "<=", ">=", "<>"];
+
+pub enum Outcome {
+    Finished(String),
+    Error(String),
+    Crash(String),
+}
+
+// Runs a program with a step cap and buffered IO, turning panics into Outcome::Crash
+pub fn run_guarded(source: &str, inputs: Vec<String>) -> Outcome {
+    let program = match crate::parse(source) {
+        Ok(program) => program,
+        Err(e) => return Outcome::Error(e.to_string()),
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut interpreter = crate::Interpreter::new();
+        interpreter.max_steps = Some(MAX_STEPS);
+        let io = crate::io::BufferIo::new(inputs).with_output_limit(OUTPUT_LIMIT);
+        let output = io.output();
+        interpreter.io = Box::new(io);
+        interpreter.audio = Box::new(crate::audio::Silent);
+        let result = interpreter.execute_program(program);
+        let output = std::mem::take(&mut *output.lock().unwrap());
+        (result, output)
+    }));
+
+    match result {
+        Ok((Ok(()), output)) => Outcome::Finished(output),
+        Ok((Err(e), _)) => Outcome::Error(e.to_string()),
+        Err(payload) => Outcome::Crash(crate::crash::panic_message(payload.as_ref())),
+    }
+}
+
+// parse, to_source and parse again has to give the same program, which shows as to_source
+// writing the same text both times
+fn round_trip(source: &str) -> Result<(), String> {
+    let Ok(program) = crate::parse(source) else {
+        return Ok(());
+    };
+    let unparsed = crate::to_source(&program);
+    let reparsed = crate::parse(&unparsed)
+        .map_err(|e| format!("to_source wrote a program that doesn't parse: {}", e))?;
+    if crate::to_source(&reparsed) != unparsed {
+        return Err("to_source wrote a different program from its own output".to_string());
+    }
+    Ok(())
+}
+
+type PanicHook = Box<dyn Fn(&panic::PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+// Silences the panic hook until it's dropped, so the hook comes back however run returns
+struct QuietPanics(Option<PanicHook>);
+
+impl QuietPanics {
+    fn new() -> Self {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        QuietPanics(Some(hook))
+    }
+}
+
+impl Drop for QuietPanics {
+    fn drop(&mut self) {
+        if let Some(hook) = self.0.take() {
+            panic::set_hook(hook);
+        }
+    }
+}
+
+pub struct Fuzzer {
+    rng: StdRng,
+    corpus: Vec<Vec<String>>,
+    seen: HashSet<String>,
+    crashes: usize,
+}
+
+impl Fuzzer {
+    pub fn new(seed: u64) -> Self {
+        Fuzzer {
+            rng: StdRng::seed_from_u64(seed),
+            corpus: Vec::new(),
+            seen: HashSet::new(),
+            crashes: 0,
+        }
+    }
+
+    pub fn run(&mut self, iterations: usize) -> Result<(), String> {
+        std::fs::create_dir_all(CORPUS_DIR)
+            .map_err(|e| format!("Error creating corpus directory: {}", e))?;
+
+        // Panics are reported through the crash files, not the default hook
+        let _quiet = QuietPanics::new();
+        self.load_corpus()?;
+        for _ in 0..iterations {
+            let lines = if self.corpus.is_empty() || self.rng.gen_bool(0.2) {
+                self.generate_program()
+            } else {
+                self.mutate_program()
+            };
+            self.execute(lines)?;
+        }
+
+        println!("Fuzzing finished: {} iterations, {} corpus entries, {} crashes",
+            iterations, self.corpus.len(), self.crashes);
+        if self.crashes > 0 {
+            return Err(format!("Found {} crashing or unparsable programs, see {}", self.crashes, CRASH_DIR));
+        }
+        Ok(())
+    }
+
+    fn load_corpus(&mut self) -> Result<(), String> {
+        let entries = std::fs::read_dir(CORPUS_DIR)
+            .map_err(|e| format!("Error reading corpus directory: {}", e))?;
+        for entry in entries.flatten() {
+            if let Ok(contents) = std::fs::read_to_string(entry.path()) {
+                let lines: Vec<String> = contents.lines().map(|l| l.to_string()).collect();
+                self.execute(lines)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, lines: Vec<String>) -> Result<(), String> {
+        let source = lines.join("\n");
+        let inputs: Vec<String> = (0..8)
+            .map(|_| self.rng.gen_range(0..100).to_string())
+            .collect();
+
+        if let Err(message) = round_trip(&source) {
+            let path = self.save(CRASH_DIR, "unparse-", &source)?;
+            println!("Round trip: {} ({})", message, path);
+            self.crashes += 1;
+        }
+
+        match run_guarded(&source, inputs) {
+            Outcome::Crash(message) => {
+                let path = self.save(CRASH_DIR, "crash-", &source)?;
+                println!("Crash: {} ({})", message, path);
+                self.crashes += 1;
+            },
+            outcome => {
+                let outcome = outcome_class(&outcome);
+                let mut new_keyword = false;
+                for keyword in keywords(&lines) {
+                    new_keyword |= self.seen.insert(format!("{}|{}", keyword, outcome));
+                }
+                if new_keyword {
+                    self.save(CORPUS_DIR, "", &source)?;
+                    self.corpus.push(lines);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn save(&self, dir: &str, prefix: &str, source: &str) -> Result<String, String> {
+        let mut hasher = DefaultHasher::new();
+        source.hash(&mut hasher);
+        let path = format!("{}/{}{:016x}.bs", dir, prefix, hasher.finish());
+        std::fs::create_dir_all(dir)
+            .and_then(|_| std::fs::write(&path, source))
+            .map_err(|e| format!("Error writing {}: {}", path, e))?;
+        Ok(path)
+    }
+
+    fn mutate_program(&mut self) -> Vec<String> {
+        let mut lines = self.corpus[self.rng.gen_range(0..self.corpus.len())].clone();
+        match self.rng.gen_range(0..4) {
+            0 if !lines.is_empty() => {
+                let index = self.rng.gen_range(0..lines.len());
+                lines.remove(index);
+            },
+            1 if !lines.is_empty() => {
+                let index = self.rng.gen_range(0..lines.len());
+                lines[index] = self.generate_simple_statement();
+            },
+            2 => {
+                let other = &self.corpus[self.rng.gen_range(0..self.corpus.len())];
+                let start = self.rng.gen_range(0..=other.len());
+                let tail: Vec<String> = other[start..].to_vec();
+                let cut = self.rng.gen_range(0..=lines.len());
+                lines.truncate(cut);
+                lines.extend(tail);
+            },
+            _ => {
+                let index = self.rng.gen_range(0..=lines.len());
+                let mut block = Vec::new();
+                self.generate_statement(&mut block, 0);
+                for (offset, line) in block.into_iter().enumerate() {
+                    lines.insert(index + offset, line);
+                }
+            },
+        }
+        lines.truncate(MAX_LINES);
+        lines
+    }
+
+    fn generate_program(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if self.rng.gen_bool(0.7) {
+            for var in VARIABLES {
+                let value = self.generate_expression(1);
+                lines.push(format!("LET {} = {}", var, value));
+            }
+        }
+        let count = self.rng.gen_range(1..12);
+        for _ in 0..count {
+            self.generate_statement(&mut lines, 0);
+        }
+        if self.rng.gen_bool(0.5) {
+            lines.push("END".to_string());
+        }
+        lines.truncate(MAX_LINES);
+        lines
+    }
+
+    fn generate_statement(&mut self, lines: &mut Vec<String>, loop_depth: usize) {
+        if loop_depth < LOOP_VARIABLES.len() && self.rng.gen_bool(0.2) {
+            let var = LOOP_VARIABLES[loop_depth];
+            let start = self.generate_expression(1);
+            let end = self.generate_expression(1);
+            if self.rng.gen_bool(0.3) {
+                let step = self.generate_expression(1);
+                lines.push(format!("FOR {} = {} TO {} STEP {}", var, start, end, step));
+            } else {
+                lines.push(format!("FOR {} = {} TO {}", var, start, end));
+            }
+            let body = self.rng.gen_range(1..4);
+            for _ in 0..body {
+                self.generate_statement(lines, loop_depth + 1);
+            }
+            lines.push(format!("NEXT {}", var));
+        } else {
+            let statement = self.generate_simple_statement();
+            lines.push(statement);
+        }
+    }
+
+    fn generate_simple_statement(&mut self) -> String {
+        match self.rng.gen_range(0..7) {
+            0 => format!("LET {} = {}", self.pick(VARIABLES), self.generate_expression(0)),
+            1 => format!("{} = {}", self.pick(VARIABLES), self.generate_expression(0)),
+            2 => format!("INPUT {}", self.pick(VARIABLES)),
+            3 => {
+                let condition = self.generate_expression(0);
+                let then_branch = self.generate_branch();
+                if self.rng.gen_bool(0.5) {
+                    let else_branch = self.generate_branch();
+                    format!("IF {} THEN {} ELSE {}", condition, then_branch, else_branch)
+                } else {
+                    format!("IF {} THEN {}", condition, then_branch)
+                }
+            },
+            4 => format!("PRINT \"{}\"", self.pick(VARIABLES)),
+            _ => {
+                let count = self.rng.gen_range(1..4);
+                let expressions: Vec<String> = (0..count)
+                    .map(|_| self.generate_expression(0))
+                    .collect();
+                let semicolon = if self.rng.gen_bool(0.2) { ";" } else { "" };
+                format!("PRINT {}{}", expressions.join(", "), semicolon)
+            },
+        }
+    }
+
+    fn generate_branch(&mut self) -> String {
+        match self.rng.gen_range(0..3) {
+            0 => format!("PRINT {}", self.generate_expression(1)),
+            1 => format!("LET {} = {}", self.pick(VARIABLES), self.generate_expression(1)),
+            _ => "END".to_string(),
+        }
+    }
+
+    fn generate_expression(&mut self, depth: usize) -> String {
+        if depth >= MAX_EXPR_DEPTH {
+            return self.generate_atom();
+        }
+        match self.rng.gen_range(0..5) {
+            0 | 1 => self.generate_atom(),
+            2 => {
+                let function = self.pick(FUNCTIONS);
+                let argument = self.generate_expression(depth + 1);
+                format!("{}({})", function, argument)
+            },
+            3 => format!("({})", self.generate_expression(depth + 1)),
+            _ => {
+                let left = self.generate_expression(depth + 1);
+                let operator = self.pick(OPERATORS);
+                let right = self.generate_expression(depth + 1);
+                format!("{} {} {}", left, operator, right)
+            },
+        }
+    }
+
+    fn generate_atom(&mut self) -> String {
+        match self.rng.gen_range(0..3) {
+            0 => self.rng.gen_range(0..1000).to_string(),
+            1 => format!("{:.2}", self.rng.gen_range(0.0..100.0)),
+            _ => {
+                let pool = if self.rng.gen_bool(0.8) { VARIABLES } else { LOOP_VARIABLES };
+                self.pick(pool).to_string()
+            },
+        }
+    }
+
+    fn pick(&mut self, pool: &[&'static str]) -> &'static str {
+        pool[self.rng.gen_range(0..pool.len())]
+    }
+}
+
+// Which statement keywords and builtins appear in a program's text. This isn't coverage: a
+// keyword counts when it's written, whether or not it ran.
+fn keywords(lines: &[String]) -> Vec<&str> {
+    let mut found: Vec<&str> = Vec::new();
+    for line in lines {
+        if let Some(keyword) = line.split_whitespace().next() {
+            let keyword = if line.contains(" = ") && !line.starts_with("LET") && !line.starts_with("FOR")
+                && !line.starts_with("IF") && !line.starts_with("PRINT") {
+                "ASSIGN"
+            } else {
+                keyword
+            };
+            found.push(keyword);
+        }
+        for function in FUNCTIONS {
+            if line.contains(&format!("{}(", function)) {
+                found.push(function);
+            }
+        }
+    }
+    found.sort();
+    found.dedup();
+    found
+}
+
+fn outcome_class(outcome: &Outcome) -> String {
+    match outcome {
+        Outcome::Finished(_) => "ok".to_string(),
+        Outcome::Crash(_) => "crash".to_string(),
+        // Drop the line index and any variable names so similar errors group together
+        Outcome::Error(e) => e.split(": ").nth(1).unwrap_or(e)
+            .split_whitespace()
+            .take(3)
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_programs_round_trip_without_crashing() {
+        for seed in 0..20 {
+            let mut fuzzer = Fuzzer::new(seed);
+            for _ in 0..10 {
+                let lines = fuzzer.generate_program();
+                let source = lines.join("\n");
+                assert_eq!(round_trip(&source), Ok(()), "{}", source);
+                if let Outcome::Crash(message) = run_guarded(&source, vec!["1".to_string(); 8]) {
+                    panic!("{} crashed: {}", source, message);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn run_guarded_reports_output_and_errors() {
+        assert!(matches!(run_guarded("PRINT 1 + 2", Vec::new()), Outcome::Finished(output) if output == " 3\n"));
+        assert!(matches!(run_guarded("10 GOTO 10", Vec::new()), Outcome::Error(e) if e.contains("Step limit")));
+        assert!(matches!(run_guarded("PRINT (", Vec::new()), Outcome::Error(_)));
+    }
+
+    #[test]
+    fn keywords_are_the_words_a_program_uses() {
+        let lines = ["LET A = 1", "X = SQR(A) + ABS(A)", "PRINT X", "PRINT A"].map(String::from);
+        assert_eq!(keywords(&lines), ["ABS", "ASSIGN", "LET", "PRINT", "SQR"]);
+    }
+
+    #[test]
+    fn outcomes_group_errors_by_their_message() {
+        assert_eq!(outcome_class(&Outcome::Finished(String::new())), "ok");
+        let first = outcome_class(&Outcome::Error("Error at line 10, column 4: Division by zero".to_string()));
+        let second = outcome_class(&Outcome::Error("Error at line 30, column 9: Division by zero".to_string()));
+        assert_eq!(first, second);
+    }
+}
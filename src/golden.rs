@@ -0,0 +1,97 @@
+// `lang test DIR`: golden-output tests. Every .bs file in the directory is run with the lines of
+// the .input file next to it, if there is one, and what it prints has to match its .expected
+// file. A run that stops with an error has the error as its last line, so errors can be expected
+// too. --bless writes each program's output to its .expected file instead of comparing.
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::Interpreter;
+
+pub fn run(dir: &str, config: &Config, bless: bool) -> Result<(), String> {
+    let mut programs: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| format!("Error reading {}: {}", dir, e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "bs"))
+        .collect();
+    if programs.is_empty() {
+        return Err(format!("There are no .bs files in {}", dir));
+    }
+    programs.sort();
+
+    let mut failed = 0;
+    for program in &programs {
+        let output = run_one(program, config)?;
+        let expected_path = program.with_extension("expected");
+        if bless {
+            std::fs::write(&expected_path, &output).map_err(|e| format!("Error writing {}: {}", expected_path.display(), e))?;
+            println!("{} ... blessed", program.display());
+            continue;
+        }
+        let problem = match std::fs::read_to_string(&expected_path) {
+            Ok(expected) => difference(&expected.replace("\r\n", "\n"), &output),
+            Err(_) => Some(format!("there is no {}; --bless writes it", expected_path.display())),
+        };
+        match problem {
+            None => println!("{} ... ok", program.display()),
+            Some(problem) => {
+                println!("{} ... FAILED\n    {}", program.display(), problem);
+                failed += 1;
+            },
+        }
+    }
+    if bless {
+        return Ok(());
+    }
+    println!("{} passed, {} failed", programs.len() - failed, failed);
+    match failed {
+        0 => Ok(()),
+        1 => Err(format!("1 of {} tests failed", programs.len())),
+        failed => Err(format!("{} of {} tests failed", failed, programs.len())),
+    }
+}
+
+// What the program printed, and the error that stopped it if one did
+fn run_one(path: &Path, config: &Config) -> Result<String, String> {
+    let source = std::fs::read_to_string(path).map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+    let program = match crate::parse(&source) {
+        Ok(program) => program,
+        Err(e) => return Ok(format!("{}\n", e)),
+    };
+    let input = match std::fs::read_to_string(path.with_extension("input")) {
+        Ok(input) => input.lines().map(|line| format!("{}\n", line)).collect(),
+        Err(_) => Vec::new(),
+    };
+    let mut interpreter = Interpreter::new();
+    config.configure(&mut interpreter);
+    interpreter.arguments[0] = path.display().to_string();
+    let run = interpreter.run_captured(&program, input);
+    let mut output = run.stdout();
+    if let Some(error) = run.error {
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        output.push_str(&format!("{}\n", error));
+    }
+    Ok(output)
+}
+
+// The first line where the output isn't what was expected
+fn difference(expected: &str, actual: &str) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+    let mut expected_lines = expected.split_inclusive('\n');
+    let mut actual_lines = actual.split_inclusive('\n');
+    let mut number = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(expected), Some(actual)) if expected == actual => number += 1,
+            (Some(expected), Some(actual)) => {
+                return Some(format!("line {}: expected {:?}, got {:?}", number, expected, actual));
+            },
+            (Some(expected), None) => return Some(format!("line {}: expected {:?}, but the output ended", number, expected)),
+            (None, Some(actual)) => return Some(format!("line {}: expected the output to end, got {:?}", number, actual)),
+            (None, None) => return None,
+        }
+    }
+}
@@ -0,0 +1,45 @@
+// The --golf dialect for writing programs in as few characters as possible. Each of these
+// characters is a whole keyword, so `10&I=1 TO 3` and `20?I` need no spaces around it.
+// Everything else, like implicit LET and lower case keywords, is plain LANG. `lang expand`
+// turns a golfed program back into the readable kind.
+pub const ALIASES: &[(char, &str)] = &[
+    ('?', "PRINT"),
+    ('@', "INPUT"),
+    ('&', "FOR"),
+    ('!', "NEXT"),
+    ('%', "IF"),
+    ('|', "THEN"),
+    ('~', "GOTO"),
+    ('\\', "END"),
+];
+
+pub fn alias(c: char) -> Option<&'static str> {
+    ALIASES.iter().find(|(alias, _)| *alias == c).map(|(_, word)| *word)
+}
+
+// Spells out every alias outside strings, with a space either side where it needs one
+pub fn expand(source: &str) -> String {
+    let mut expanded = String::new();
+    let mut in_string = false;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            in_string = !in_string;
+        } else if c == '\n' {
+            in_string = false;
+        }
+        match alias(c).filter(|_| !in_string) {
+            Some(word) => {
+                if !expanded.is_empty() && !expanded.ends_with([' ', '\n']) {
+                    expanded.push(' ');
+                }
+                expanded.push_str(word);
+                if chars.peek().is_some_and(|next| !matches!(next, ' ' | '\n' | '\r')) {
+                    expanded.push(' ');
+                }
+            },
+            None => expanded.push(c),
+        }
+    }
+    expanded
+}
@@ -0,0 +1,374 @@
+use std::collections::VecDeque;
+use crate::errors::{Code, Error};
+
+// The 16-color CGA/EGA palette used by GW-BASIC
+pub const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00), (0x00, 0x00, 0xAA), (0x00, 0xAA, 0x00), (0x00, 0xAA, 0xAA),
+    (0xAA, 0x00, 0x00), (0xAA, 0x00, 0xAA), (0xAA, 0x55, 0x00), (0xAA, 0xAA, 0xAA),
+    (0x55, 0x55, 0x55), (0x55, 0x55, 0xFF), (0x55, 0xFF, 0x55), (0x55, 0xFF, 0xFF),
+    (0xFF, 0x55, 0x55), (0xFF, 0x55, 0xFF), (0xFF, 0xFF, 0x55), (0xFF, 0xFF, 0xFF),
+];
+
+pub const DEFAULT_COLOR: u8 = 15;
+pub const DEFAULT_IMAGE: &str = "screen.png";
+pub const NO_SCREEN: &str = "No graphics screen; use SCREEN width, height first";
+const MAX_DIMENSION: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LineShape {
+    Line,
+    Box,
+    FilledBox,
+}
+
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    last_point: (f64, f64),
+    unsaved: bool,
+}
+
+impl Framebuffer {
+    pub fn new(width: f64, height: f64) -> Result<Self, Error> {
+        let in_range = |n: f64| n >= 1.0 && n <= MAX_DIMENSION as f64;
+        if !in_range(width) || !in_range(height) {
+            return Err(Error::new(Code::OutOfRange, format!("SCREEN size must be between 1 and {}", MAX_DIMENSION)));
+        }
+        let (width, height) = (width as usize, height as usize);
+        Ok(Framebuffer {
+            width,
+            height,
+            pixels: vec![0; width * height],
+            last_point: (width as f64 / 2.0, height as f64 / 2.0),
+            unsaved: false,
+        })
+    }
+
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn last_point(&self) -> (f64, f64) {
+        self.last_point
+    }
+
+    pub fn unsaved(&self) -> bool {
+        self.unsaved
+    }
+
+    // Moves the graphics cursor without drawing, for DRAW's B and N prefixes
+    pub fn move_to(&mut self, point: (f64, f64)) {
+        self.last_point = point;
+    }
+
+    pub fn pixel(&self, x: i64, y: i64) -> Option<u8> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+        Some(self.pixels[y as usize * self.width + x as usize])
+    }
+
+    // Off-screen pixels are clipped, as on real hardware
+    fn plot(&mut self, x: i64, y: i64, color: u8) {
+        if self.pixel(x, y).is_some() {
+            self.pixels[y as usize * self.width + x as usize] = color;
+        }
+    }
+
+    pub fn pset(&mut self, (x, y): (f64, f64), color: u8) {
+        self.plot(x.round() as i64, y.round() as i64, color);
+        self.last_point = (x, y);
+        self.unsaved = true;
+    }
+
+    pub fn line(&mut self, from: (f64, f64), to: (f64, f64), color: u8, shape: LineShape) {
+        let (x1, y1) = (from.0.round() as i64, from.1.round() as i64);
+        let (x2, y2) = (to.0.round() as i64, to.1.round() as i64);
+        match shape {
+            LineShape::Line => self.bresenham(x1, y1, x2, y2, color),
+            LineShape::Box => {
+                self.bresenham(x1, y1, x2, y1, color);
+                self.bresenham(x2, y1, x2, y2, color);
+                self.bresenham(x2, y2, x1, y2, color);
+                self.bresenham(x1, y2, x1, y1, color);
+            },
+            // Only the part of the box on the screen is filled
+            LineShape::FilledBox => {
+                let (left, right) = (x1.min(x2).max(0), x1.max(x2).min(self.width as i64 - 1));
+                let (top, bottom) = (y1.min(y2).max(0), y1.max(y2).min(self.height as i64 - 1));
+                for y in top..=bottom {
+                    for x in left..=right {
+                        self.plot(x, y, color);
+                    }
+                }
+            },
+        }
+        self.last_point = to;
+        self.unsaved = true;
+    }
+
+    fn bresenham(&mut self, x1: i64, y1: i64, x2: i64, y2: i64, color: u8) {
+        // Clipped first, so the loop only walks pixels on the screen
+        let Some(((mut x, mut y), (x2, y2))) = self.clip((x1, y1), (x2, y2)) else { return };
+        let dx = (x2 - x).abs();
+        let dy = -(y2 - y).abs();
+        let sx = if x < x2 { 1 } else { -1 };
+        let sy = if y < y2 { 1 } else { -1 };
+        let mut error = dx + dy;
+        loop {
+            self.plot(x, y, color);
+            if x == x2 && y == y2 {
+                break;
+            }
+            let e2 = 2 * error;
+            if e2 >= dy {
+                error += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    // The part of the line from `from` to `to` that is on the screen, or None when none of it
+    // is (Cohen-Sutherland). Done in floating point, which can't overflow however far off the
+    // screen the ends are.
+    fn clip(&self, from: (i64, i64), to: (i64, i64)) -> Option<((i64, i64), (i64, i64))> {
+        let (right, bottom) = ((self.width - 1) as f64, (self.height - 1) as f64);
+        let outside = |(x, y): (f64, f64)| [x < 0.0, x > right, y < 0.0, y > bottom];
+        let (mut a, mut b) = ((from.0 as f64, from.1 as f64), (to.0 as f64, to.1 as f64));
+        // Each pass moves an end onto an edge, and a line through a corner can take a few
+        for _ in 0..8 {
+            let (outside_a, outside_b) = (outside(a), outside(b));
+            if (0..4).any(|edge| outside_a[edge] && outside_b[edge]) {
+                return None;
+            }
+            let (end, edges) = match (outside_a.contains(&true), outside_b.contains(&true)) {
+                (true, _) => (&mut a, outside_a),
+                (false, true) => (&mut b, outside_b),
+                (false, false) => break,
+            };
+            let ((x1, y1), (x2, y2)) = (from, to);
+            let (x1, y1, x2, y2) = (x1 as f64, y1 as f64, x2 as f64, y2 as f64);
+            let at_x = |x: f64| (x, y1 + (y2 - y1) * (x - x1) / (x2 - x1));
+            let at_y = |y: f64| (x1 + (x2 - x1) * (y - y1) / (y2 - y1), y);
+            *end = match edges {
+                [true, ..] => at_x(0.0),
+                [_, true, ..] => at_x(right),
+                [_, _, true, _] => at_y(0.0),
+                _ => at_y(bottom),
+            };
+        }
+        Some(((a.0.round() as i64, a.1.round() as i64), (b.0.round() as i64, b.1.round() as i64)))
+    }
+
+    // Each row of the screen the circle crosses gets the two points where it does, and so does
+    // each column, which leaves no gaps however steep the outline gets. Only the screen's rows
+    // and columns are looked at, so a circle that can't touch the screen draws nothing and a
+    // huge one takes no longer than a small one. i128 holds the square of any radius.
+    pub fn circle(&mut self, center: (f64, f64), radius: f64, color: u8) {
+        let (cx, cy) = (center.0.round() as i64 as i128, center.1.round() as i64 as i128);
+        let r = radius.round().abs() as i64 as i128;
+        // How far the circle is from the center along one axis, `offset` along the other
+        let reach = |offset: i128| {
+            let square = r * r - offset * offset;
+            let root = square.isqrt();
+            if square - root * root > root { root + 1 } else { root }
+        };
+        for y in (cy - r).max(0)..=(cy + r).min(self.height as i128 - 1) {
+            let dx = reach(y - cy);
+            self.plot_wide(cx - dx, y, color);
+            self.plot_wide(cx + dx, y, color);
+        }
+        for x in (cx - r).max(0)..=(cx + r).min(self.width as i128 - 1) {
+            let dy = reach(x - cx);
+            self.plot_wide(x, cy - dy, color);
+            self.plot_wide(x, cy + dy, color);
+        }
+        self.last_point = center;
+        self.unsaved = true;
+    }
+
+    fn plot_wide(&mut self, x: i128, y: i128, color: u8) {
+        if let (Ok(x), Ok(y)) = (i64::try_from(x), i64::try_from(y)) {
+            self.plot(x, y, color);
+        }
+    }
+
+    // Flood fill outward from a point until pixels of the border color
+    pub fn paint(&mut self, start: (f64, f64), color: u8, border: u8) {
+        let start = (start.0.round() as i64, start.1.round() as i64);
+        let mut queue = VecDeque::from([start]);
+        while let Some((x, y)) = queue.pop_front() {
+            match self.pixel(x, y) {
+                Some(existing) if existing != border && existing != color => {
+                    self.plot(x, y, color);
+                    queue.extend([(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)]);
+                },
+                _ => {},
+            }
+        }
+        self.last_point = (start.0 as f64, start.1 as f64);
+        self.unsaved = true;
+    }
+
+    pub fn save_png(&mut self, path: &str) -> Result<(), Error> {
+        std::fs::write(path, self.encode_png())
+            .map_err(|e| Error::new(Code::FileError, format!("Error saving image {}: {}", path, e)))?;
+        self.unsaved = false;
+        Ok(())
+    }
+
+    // An 8-bit indexed PNG; the image data is stored uncompressed to avoid a zlib dependency
+    fn encode_png(&self) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend((self.width as u32).to_be_bytes());
+        header.extend((self.height as u32).to_be_bytes());
+        header.extend([8, 3, 0, 0, 0]);
+
+        let palette: Vec<u8> = PALETTE.iter().flat_map(|&(r, g, b)| [r, g, b]).collect();
+
+        let mut scanlines = Vec::with_capacity((self.width + 1) * self.height);
+        for row in self.pixels.chunks(self.width) {
+            scanlines.push(0);
+            scanlines.extend_from_slice(row);
+        }
+
+        let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"PLTE", &palette);
+        write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+pub fn color_index(color: f64) -> Result<u8, Error> {
+    if !(0.0..16.0).contains(&color) {
+        return Err(Error::new(Code::OutOfRange, format!("Color {} out of range 0-15", color)));
+    }
+    Ok(color as u8)
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend([1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(if blocks.peek().is_none() { 1 } else { 0 });
+        let len = block.len() as u16;
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drawn(screen: &Framebuffer) -> usize {
+        screen.pixels().iter().filter(|&&pixel| pixel != 0).count()
+    }
+
+    #[test]
+    fn lines_far_off_the_screen_draw_their_visible_part() {
+        let mut screen = Framebuffer::new(100.0, 100.0).unwrap();
+        screen.line((-1e300, 0.0), (1e300, 0.0), 1, LineShape::Line);
+        assert_eq!(drawn(&screen), 100);
+        screen.line((0.0, 0.0), (1e18, 1e18), 2, LineShape::Line);
+        assert_eq!(screen.pixel(99, 99), Some(2));
+        screen.line((-50.0, 50.0), (150.0, 50.0), 3, LineShape::Line);
+        assert!((0..100).all(|x| screen.pixel(x, 50) == Some(3)));
+        screen.line((200.0, 0.0), (300.0, 99.0), 4, LineShape::Line);
+        assert!(!screen.pixels().contains(&4));
+    }
+
+    #[test]
+    fn boxes_only_fill_the_screen() {
+        let mut screen = Framebuffer::new(100.0, 100.0).unwrap();
+        screen.line((0.0, 0.0), (1e5, 1e5), 1, LineShape::FilledBox);
+        assert_eq!(drawn(&screen), 100 * 100);
+        let mut screen = Framebuffer::new(100.0, 100.0).unwrap();
+        screen.line((-1e18, 10.0), (1e18, 20.0), 1, LineShape::Box);
+        assert_eq!(drawn(&screen), 200);
+    }
+
+    #[test]
+    fn circles_that_miss_the_screen_draw_nothing() {
+        let mut screen = Framebuffer::new(100.0, 100.0).unwrap();
+        screen.circle((50.0, 50.0), 1e12, 1);
+        screen.circle((-20.0, -20.0), 5.0, 1);
+        assert_eq!(drawn(&screen), 0);
+        // Grazing the screen, so it's as good as a straight line there
+        screen.circle((-1e12 + 10.0, 50.0), 1e12, 1);
+        assert!((40..60).all(|y| screen.pixel(10, y) == Some(1)));
+        screen.circle((1e300, 0.0), 1e300, 2);
+        assert_eq!(screen.pixel(0, 0), Some(2));
+    }
+
+    #[test]
+    fn circles_match_the_midpoint_algorithm() {
+        let mut screen = Framebuffer::new(11.0, 11.0).unwrap();
+        screen.circle((5.0, 5.0), 3.0, 1);
+        let rows: Vec<String> = screen.pixels().chunks(11)
+            .map(|row| row.iter().map(|&pixel| if pixel == 0 { '.' } else { '#' }).collect())
+            .collect();
+        assert_eq!(rows[2..9], [
+            "....###....",
+            "...#...#...",
+            "..#.....#..",
+            "..#.....#..",
+            "..#.....#..",
+            "...#...#...",
+            "....###....",
+        ]);
+    }
+}
@@ -0,0 +1,152 @@
+// Serial ports and GPIO pins for OPENSERIAL, SERIALOUT, SERIALIN, OUTPIN and INPIN,
+// available when built with `--features hardware`
+use crate::errors::{Code, Error};
+#[cfg(feature = "hardware")]
+use std::collections::HashMap;
+
+// Pins are line offsets on the first GPIO chip, which is the header on a Raspberry Pi
+#[cfg(all(feature = "hardware", target_os = "linux"))]
+const GPIO_CHIP: &str = "/dev/gpiochip0";
+#[cfg(all(feature = "hardware", target_os = "linux"))]
+const CONSUMER: &str = "lang";
+
+#[cfg(feature = "hardware")]
+#[derive(Default)]
+pub struct Hardware {
+    // Serial ports share the file number space with OPEN
+    ports: HashMap<u32, Box<dyn serialport::SerialPort>>,
+    // Output pins stay claimed, and keep their level, until the program ends
+    #[cfg(target_os = "linux")]
+    pins: HashMap<u32, gpio_cdev::LineHandle>,
+}
+
+#[cfg(feature = "hardware")]
+impl Hardware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_open(&self, number: u32) -> bool {
+        self.ports.contains_key(&number)
+    }
+
+    pub fn open_serial(&mut self, number: u32, device: &str, baud: u32) -> Result<(), Error> {
+        let port = serialport::new(device, baud)
+            .timeout(std::time::Duration::from_millis(100))
+            .open()
+            .map_err(|e| Error::new(Code::FileError, format!("Error opening {}: {}", device, e)))?;
+        self.ports.insert(number, port);
+        Ok(())
+    }
+
+    pub fn close(&mut self, number: u32) {
+        self.ports.remove(&number);
+    }
+
+    pub fn close_all(&mut self) {
+        self.ports.clear();
+    }
+
+    pub fn serial_write(&mut self, number: u32, text: &str) -> Result<(), Error> {
+        use std::io::Write;
+        let port = self.port(number)?;
+        port.write_all(text.as_bytes())
+            .and_then(|_| port.flush())
+            .map_err(|e| Error::new(Code::FileError, format!("Error writing to serial #{}: {}", number, e)))
+    }
+
+    // Whatever has arrived so far, or "" if nothing has; SERIALIN never waits for more
+    pub fn serial_read(&mut self, number: u32) -> Result<String, Error> {
+        use std::io::Read;
+        let port = self.port(number)?;
+        let error = |e: &dyn std::fmt::Display| Error::new(Code::FileError, format!("Error reading from serial #{}: {}", number, e));
+        let available = port.bytes_to_read().map_err(|e| error(&e))?;
+        let mut buffer = vec![0; available as usize];
+        port.read_exact(&mut buffer).map_err(|e| error(&e))?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+
+    fn port(&mut self, number: u32) -> Result<&mut Box<dyn serialport::SerialPort>, Error> {
+        self.ports.get_mut(&number)
+            .ok_or_else(|| Error::new(Code::BadFileNumber, format!("Serial #{} not open", number)))
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn output_pin(&mut self, pin: u32, high: bool) -> Result<(), String> {
+        let error = |e: gpio_cdev::Error| format!("Error setting GPIO pin {}: {}", pin, e);
+        if let Some(handle) = self.pins.get(&pin) {
+            return handle.set_value(high as u8).map_err(error);
+        }
+        let handle = gpio_cdev::Chip::new(GPIO_CHIP)
+            .and_then(|mut chip| chip.get_line(pin))
+            .and_then(|line| line.request(gpio_cdev::LineRequestFlags::OUTPUT, high as u8, CONSUMER))
+            .map_err(error)?;
+        self.pins.insert(pin, handle);
+        Ok(())
+    }
+
+    // A pin this program drives reads back its own level; any other is read as an input
+    #[cfg(target_os = "linux")]
+    pub fn input_pin(&self, pin: u32) -> Result<bool, String> {
+        let error = |e: gpio_cdev::Error| format!("Error reading GPIO pin {}: {}", pin, e);
+        let value = match self.pins.get(&pin) {
+            Some(handle) => handle.get_value(),
+            None => gpio_cdev::Chip::new(GPIO_CHIP)
+                .and_then(|mut chip| chip.get_line(pin))
+                .and_then(|line| line.request(gpio_cdev::LineRequestFlags::INPUT, 0, CONSUMER))
+                .and_then(|handle| handle.get_value()),
+        };
+        value.map(|v| v != 0).map_err(error)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn output_pin(&mut self, _pin: u32, _high: bool) -> Result<(), String> {
+        Err("OUTPIN needs Linux GPIO".to_string())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn input_pin(&self, _pin: u32) -> Result<bool, String> {
+        Err("INPIN needs Linux GPIO".to_string())
+    }
+}
+
+#[cfg(not(feature = "hardware"))]
+pub struct Hardware;
+
+#[cfg(not(feature = "hardware"))]
+const UNAVAILABLE: &str = "needs a build with `--features hardware`";
+
+#[cfg(not(feature = "hardware"))]
+impl Hardware {
+    pub fn new() -> Self {
+        Hardware
+    }
+
+    pub fn is_open(&self, _number: u32) -> bool {
+        false
+    }
+
+    pub fn open_serial(&mut self, _number: u32, _device: &str, _baud: u32) -> Result<(), Error> {
+        Err(format!("OPENSERIAL {}", UNAVAILABLE).into())
+    }
+
+    pub fn close(&mut self, _number: u32) {}
+
+    pub fn close_all(&mut self) {}
+
+    pub fn serial_write(&mut self, number: u32, _text: &str) -> Result<(), Error> {
+        Err(Error::new(Code::BadFileNumber, format!("Serial #{} not open", number)))
+    }
+
+    pub fn serial_read(&mut self, number: u32) -> Result<String, Error> {
+        Err(Error::new(Code::BadFileNumber, format!("Serial #{} not open", number)))
+    }
+
+    pub fn output_pin(&mut self, _pin: u32, _high: bool) -> Result<(), String> {
+        Err(format!("OUTPIN {}", UNAVAILABLE))
+    }
+
+    pub fn input_pin(&self, _pin: u32) -> Result<bool, String> {
+        Err(format!("INPIN {}", UNAVAILABLE))
+    }
+}
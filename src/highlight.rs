@@ -0,0 +1,156 @@
+// `lang highlight`: a program listed with its keywords, names, numbers and strings in color,
+// either for a terminal or as an HTML page. The colors come from the same tokenizer the
+// parser uses, so the listing shows a line the way LANG reads it, and a line it can't read is
+// marked as an error.
+use crate::Token;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    Plain,
+    Keyword,
+    Function,
+    Variable,
+    Number,
+    String,
+    LineNumber,
+    Error,
+}
+
+impl Class {
+    fn ansi(self) -> &'static str {
+        match self {
+            Class::Plain => "",
+            Class::Keyword => "\x1b[1;34m",
+            Class::Function => "\x1b[36m",
+            Class::Variable => "\x1b[33m",
+            Class::Number => "\x1b[35m",
+            Class::String => "\x1b[32m",
+            Class::LineNumber => "\x1b[90m",
+            Class::Error => "\x1b[4;31m",
+        }
+    }
+
+    fn css(self) -> &'static str {
+        match self {
+            Class::Plain => "",
+            Class::Keyword => "k",
+            Class::Function => "f",
+            Class::Variable => "v",
+            Class::Number => "n",
+            Class::String => "s",
+            Class::LineNumber => "l",
+            Class::Error => "e",
+        }
+    }
+}
+
+const STYLE: &str = "pre.lang { background: #fafafa; padding: 1em; }
+pre.lang .k { color: #0033b3; font-weight: bold; }
+pre.lang .f { color: #00627a; }
+pre.lang .v { color: #871094; }
+pre.lang .n { color: #1750eb; }
+pre.lang .s { color: #067d17; }
+pre.lang .l { color: #8c8c8c; }
+pre.lang .e { color: #c00; text-decoration: underline wavy; }
+";
+
+// For a terminal, with ANSI colors
+pub fn ansi(source: &str) -> String {
+    spans(source).into_iter()
+        .map(|(class, text)| match class {
+            Class::Plain => text.to_string(),
+            class => format!("{}{}\x1b[0m", class.ansi(), text),
+        })
+        .collect()
+}
+
+// A page of its own, titled `title`, with the styles in it
+pub fn html(source: &str, title: &str) -> String {
+    let listing: String = spans(source).into_iter()
+        .map(|(class, text)| match class {
+            Class::Plain => escape(text),
+            class => format!("<span class=\"{}\">{}</span>", class.css(), escape(text)),
+        })
+        .collect();
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>\n<pre class=\"lang\">{}</pre>\n</body>\n</html>\n",
+        escape(title), STYLE, listing
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// The whole source cut into pieces, each with how to show it. A token runs from where it starts
+// up to the next one, less the spaces in between, which are plain.
+fn spans(source: &str) -> Vec<(Class, &str)> {
+    let lexed = crate::tokenize(source, false);
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(source.match_indices('\n').map(|(offset, _)| offset + 1))
+        .collect();
+    let offset = |(line, column): crate::Position| {
+        let start = line_starts.get(line - 1).copied().unwrap_or(source.len());
+        source[start..].char_indices().nth(column - 1).map_or(source.len(), |(offset, _)| start + offset)
+    };
+    let error_lines: Vec<usize> = lexed.errors.iter()
+        .filter_map(|error| match error {
+            crate::LangError::LexError { line, .. } => Some(*line),
+            _ => None,
+        })
+        .collect();
+
+    let mut spans = Vec::new();
+    let mut done = 0;
+    for (i, token) in lexed.tokens.iter().enumerate() {
+        let start = offset(lexed.positions[i]);
+        if start < done || matches!(token, Token::EOF) {
+            continue;
+        }
+        // Text no token covers: spaces, or a line the lexer threw away
+        if start > done {
+            spans.extend(gap(&source[done..start], &line_starts, done, &error_lines));
+        }
+        let next = lexed.positions.get(i + 1).map_or(source.len(), |&position| offset(position)).max(start);
+        let text = match token {
+            Token::EOL => &source[start..(start + 1).min(source.len())],
+            _ => source[start..next].trim_end(),
+        };
+        let next_token = lexed.tokens.get(i + 1);
+        spans.push((class(token, next_token), text));
+        done = start + text.len();
+    }
+    if done < source.len() {
+        spans.extend(gap(&source[done..], &line_starts, done, &error_lines));
+    }
+    spans
+}
+
+fn gap<'a>(text: &'a str, line_starts: &[usize], offset: usize, error_lines: &[usize]) -> Vec<(Class, &'a str)> {
+    let line = line_starts.partition_point(|&start| start <= offset);
+    if !error_lines.contains(&line) || text.trim().is_empty() {
+        return vec![(Class::Plain, text)];
+    }
+    // Only up to the end of the line is the error; the newline starts the next one
+    match text.find('\n') {
+        Some(end) => vec![(Class::Error, &text[..end]), (Class::Plain, &text[end..])],
+        None => vec![(Class::Error, text)],
+    }
+}
+
+fn class(token: &Token, next: Option<&Token>) -> Class {
+    match token {
+        Token::LineNumber(_) => Class::LineNumber,
+        Token::Number(_) => Class::Number,
+        Token::String(_) => Class::String,
+        Token::Identifier(name) if next == Some(&Token::LParen) || matches!(name.as_str(), "RC" | "ARGC" | "COMMAND$") => {
+            Class::Function
+        },
+        Token::Identifier(_) => Class::Variable,
+        Token::Plus | Token::Minus | Token::Multiply | Token::Divide | Token::Power | Token::Equals
+        | Token::LessThan | Token::GreaterThan | Token::LessOrEqual | Token::GreaterOrEqual | Token::NotEqual
+        | Token::LParen | Token::RParen | Token::Comma | Token::Semicolon | Token::Colon | Token::Hash
+        | Token::EOL | Token::EOF => Class::Plain,
+        _ => Class::Keyword,
+    }
+}
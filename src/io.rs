@@ -0,0 +1,168 @@
+// Where PRINT writes and INPUT reads. The command line uses the terminal, or files with
+// --stdout and --stdin; fuzzing, minimizing and host applications use a buffer instead.
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::errors::{Code, Error};
+
+// Send so an interpreter, backend and all, can be moved to another thread
+pub trait IoBackend: Send {
+    fn write(&mut self, text: &str) -> Result<(), Error>;
+
+    // INPUT's prompt, which goes wherever PRINT does unless the backend splits them
+    fn prompt(&mut self, text: &str) -> Result<(), Error> {
+        self.write(text)
+    }
+
+    // One line including its newline, or "" at the end of input
+    fn read_line(&mut self) -> Result<String, Error>;
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // False when nobody is watching, so SLEEP doesn't wait and SHELL output is captured like PRINT
+    fn interactive(&self) -> bool {
+        true
+    }
+}
+
+// The default backend: stdout and stdin
+pub struct StdIo;
+
+impl IoBackend for StdIo {
+    fn write(&mut self, text: &str) -> Result<(), Error> {
+        std::io::stdout().write_all(text.as_bytes()).map_err(output_error)
+    }
+
+    fn read_line(&mut self) -> Result<String, Error> {
+        let mut input = String::new();
+        std::io::stdin().lock().read_line(&mut input)
+            .map_err(|e| Error::new(Code::FileError, format!("Failed to read input: {}", e)))?;
+        Ok(input)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        std::io::stdout().flush().map_err(output_error)
+    }
+}
+
+// Whatever reads the output going away, as when it's piped into `head`, ends the program
+// quietly the way it ends other command line tools
+fn output_error(e: std::io::Error) -> Error {
+    if e.kind() == std::io::ErrorKind::BrokenPipe {
+        std::process::exit(0);
+    }
+    Error::new(Code::FileError, format!("Error writing output: {}", e))
+}
+
+// --stdout and --stdin: PRINT goes to one file and INPUT reads from another, with the terminal
+// for whichever isn't given. INPUT's prompt stays on the terminal either way.
+pub struct FileIo {
+    output: Option<(String, std::io::BufWriter<std::fs::File>)>,
+    input: Option<(String, std::io::BufReader<std::fs::File>)>,
+}
+
+impl FileIo {
+    pub fn new(output: Option<&str>, input: Option<&str>) -> Result<Self, String> {
+        let output = match output {
+            Some(path) => {
+                let file = std::fs::File::create(path).map_err(|e| format!("Error creating {}: {}", path, e))?;
+                Some((path.to_string(), std::io::BufWriter::new(file)))
+            },
+            None => None,
+        };
+        let input = match input {
+            Some(path) => {
+                let file = std::fs::File::open(path).map_err(|e| format!("Error opening {}: {}", path, e))?;
+                Some((path.to_string(), std::io::BufReader::new(file)))
+            },
+            None => None,
+        };
+        Ok(FileIo { output, input })
+    }
+
+    pub fn create(path: &str) -> Result<Self, String> {
+        Self::new(Some(path), None)
+    }
+}
+
+impl IoBackend for FileIo {
+    fn write(&mut self, text: &str) -> Result<(), Error> {
+        match &mut self.output {
+            Some((path, file)) => file.write_all(text.as_bytes()).map_err(|e| Error::new(Code::FileError, format!("Error writing {}: {}", path, e))),
+            None => StdIo.write(text),
+        }
+    }
+
+    fn prompt(&mut self, text: &str) -> Result<(), Error> {
+        StdIo.write(text)?;
+        StdIo.flush()
+    }
+
+    fn read_line(&mut self) -> Result<String, Error> {
+        match &mut self.input {
+            Some((path, file)) => {
+                let mut line = String::new();
+                file.read_line(&mut line).map_err(|e| Error::new(Code::FileError, format!("Error reading {}: {}", path, e)))?;
+                Ok(line)
+            },
+            None => StdIo.read_line(),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.output {
+            Some((path, file)) => file.flush().map_err(|e| Error::new(Code::FileError, format!("Error writing {}: {}", path, e))),
+            None => StdIo.flush(),
+        }
+    }
+}
+
+// Input lines given up front and output collected into a string. Keep the handle from
+// `output()` to read what the program printed after the backend moves into the Interpreter.
+pub struct BufferIo {
+    input: VecDeque<String>,
+    output: Arc<Mutex<String>>,
+    output_limit: Option<usize>,
+}
+
+impl BufferIo {
+    pub fn new(input: Vec<String>) -> Self {
+        BufferIo {
+            input: input.into(),
+            output: Arc::new(Mutex::new(String::new())),
+            output_limit: None,
+        }
+    }
+
+    // Past this many bytes PRINT fails with "Output limit exceeded"
+    pub fn with_output_limit(mut self, limit: usize) -> Self {
+        self.output_limit = Some(limit);
+        self
+    }
+
+    pub fn output(&self) -> Arc<Mutex<String>> {
+        Arc::clone(&self.output)
+    }
+}
+
+impl IoBackend for BufferIo {
+    fn write(&mut self, text: &str) -> Result<(), Error> {
+        let mut output = self.output.lock().unwrap();
+        if self.output_limit.is_some_and(|limit| output.len() + text.len() > limit) {
+            return Err(Error::new(Code::Limit, "Output limit exceeded"));
+        }
+        output.push_str(text);
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<String, Error> {
+        self.input.pop_front().ok_or_else(|| Error::from("No input available"))
+    }
+
+    fn interactive(&self) -> bool {
+        false
+    }
+}
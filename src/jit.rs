@@ -0,0 +1,802 @@
+// --jit: numeric programs compiled to machine code with Cranelift as they load, so hot loops run
+// at native speed without a rustc build. LET, PRINT, INPUT, IF, GOTO, FOR/NEXT and END over
+// numeric variables are compiled, with + - * / ^, comparisons, ABS, INT, SQR, SIN, COS and TAN.
+// A program that uses anything else runs in the interpreter instead, and
+// `Interpreter::jit_fallback` says why. Errors, the step limit and checkpoint signals behave as
+// they do in the interpreter. Available when built with `--features jit`.
+use crate::{stats, Expression, Interpreter, LangError, Program, Statement, Token, VarType};
+
+#[cfg_attr(not(feature = "jit"), allow(dead_code))]
+pub(crate) enum Outcome {
+    // Finished, stopped at END or failed the way the interpreter would have
+    Done,
+    // Stopped before this line index for the interpreter to carry on from, as a checkpoint signal asks
+    Continue(usize),
+    // The reason the program runs in the interpreter
+    Unsupported(String),
+}
+
+#[cfg(feature = "jit")]
+pub(crate) fn run(interpreter: &mut Interpreter) -> Result<Outcome, LangError> {
+    let program = std::sync::Arc::clone(&interpreter.program);
+    if let Some(reason) = unsupported(interpreter, &program) {
+        return Ok(Outcome::Unsupported(reason));
+    }
+    native::run(interpreter, &program)
+}
+
+#[cfg(not(feature = "jit"))]
+pub(crate) fn run(_interpreter: &mut Interpreter) -> Result<Outcome, LangError> {
+    Ok(Outcome::Unsupported("--jit needs a build with `--features jit`".to_string()))
+}
+
+// Why a program can't be compiled, naming the first line that stops it
+#[cfg_attr(not(feature = "jit"), allow(dead_code))]
+fn unsupported(interpreter: &Interpreter, program: &Program) -> Option<String> {
+    if interpreter.math_diagnostics.is_some() {
+        return Some("--checked-math watches every operation".to_string());
+    }
+    if interpreter.coverage {
+        return Some("--coverage counts every line".to_string());
+    }
+    if interpreter.profile {
+        return Some("--profile times every line".to_string());
+    }
+    if interpreter.timeout.is_some() {
+        return Some("--timeout checks the clock before every statement".to_string());
+    }
+    if !interpreter.loops.is_empty() {
+        return Some("FOR loops are still open from an earlier run".to_string());
+    }
+    program.lines.iter().find_map(|line| {
+        unsupported_statement(interpreter, &line.statement).map(|reason| format!("line {} {}", line.number, reason))
+    })
+}
+
+fn unsupported_statement(interpreter: &Interpreter, statement: &Statement) -> Option<String> {
+    match statement {
+        Statement::Let { variable, expression, .. } => {
+            unsupported_variable(interpreter, variable).or_else(|| unsupported_expression(interpreter, expression))
+        },
+        // String literals are the only strings PRINT can have
+        Statement::Print { expressions, .. } => expressions.iter().find_map(|expr| match expr {
+            Expression::String(_) => None,
+            expr => unsupported_expression(interpreter, expr),
+        }),
+        Statement::If { condition, then_branch, else_branch } => unsupported_expression(interpreter, condition)
+            .or_else(|| unsupported_statement(interpreter, then_branch))
+            .or_else(|| else_branch.as_deref().and_then(|branch| unsupported_statement(interpreter, branch))),
+        Statement::Input { variable } => unsupported_variable(interpreter, variable),
+        Statement::For { loop_data } => unsupported_variable(interpreter, &loop_data.variable)
+            .or_else(|| unsupported_expression(interpreter, &loop_data.start))
+            .or_else(|| unsupported_expression(interpreter, &loop_data.end))
+            .or_else(|| unsupported_expression(interpreter, &loop_data.step)),
+        Statement::Next { .. } | Statement::End { code: None } | Statement::OptionExplicit => None,
+        Statement::End { code: Some(_) } => Some("ends with an exit status".to_string()),
+        // A missing line is the interpreter's error to report when the GOTO runs
+        Statement::Goto(target) if !interpreter.line_index.contains_key(target) => {
+            Some(format!("goes to line {}, which doesn't exist", target))
+        },
+        Statement::Goto(_) => None,
+        other => Some(format!("uses {}", stats::keyword(other))),
+    }
+}
+
+fn unsupported_variable(interpreter: &Interpreter, variable: &str) -> Option<String> {
+    match interpreter.var_type(variable) {
+        VarType::Single | VarType::Double => None,
+        VarType::Integer => Some(format!("uses the integer variable {}", variable)),
+        VarType::String => Some(format!("uses the string variable {}", variable)),
+    }
+}
+
+fn unsupported_expression(interpreter: &Interpreter, expr: &Expression) -> Option<String> {
+    match expr {
+        Expression::Number(_) => None,
+        Expression::String(_) => Some("uses a string".to_string()),
+        Expression::Variable(name, ..) => unsupported_variable(interpreter, name),
+        Expression::Binary { left, operator, right, .. } => match operator {
+            Token::Plus | Token::Minus | Token::Multiply | Token::Divide | Token::Power
+            | Token::LessThan | Token::GreaterThan | Token::Equals
+            | Token::LessOrEqual | Token::GreaterOrEqual | Token::NotEqual => {
+                unsupported_expression(interpreter, left).or_else(|| unsupported_expression(interpreter, right))
+            },
+            _ => Some(format!("uses the operator {:?}", operator)),
+        },
+        Expression::FunctionCall { name, arguments, .. } => match (name.as_str(), arguments.as_slice()) {
+            ("ABS" | "INT" | "SQR" | "SIN" | "COS" | "TAN", [argument]) => unsupported_expression(interpreter, argument),
+            _ => Some(format!("calls {}", name)),
+        },
+    }
+}
+
+#[cfg(feature = "jit")]
+mod native {
+    use std::collections::HashMap;
+
+    use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+    use cranelift_codegen::ir::{types, AbiParam, Block, FuncRef, InstBuilder, MemFlags, StackSlotData, StackSlotKind, Type, Value};
+    use cranelift_codegen::settings::{self, Configurable};
+    use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Switch, Variable};
+    use cranelift_jit::{JITBuilder, JITModule};
+    use cranelift_module::{FuncId, Linkage, Module};
+
+    use super::Outcome;
+    use crate::errors::{Code, Error};
+    use crate::{checkpoint, numbers, output, Expression, ForLoop, Interpreter, LangError, Position, Program, Statement, Token};
+
+    // What the compiled program returns when it doesn't hand a line index back to the interpreter
+    const FINISHED: i64 = -1;
+    const ENDED: i64 = -2;
+    const FAILED: i64 = -3;
+
+    // The program's entry point: context, variable values, which variables are assigned, the step
+    // count and the checkpoint flag
+    type Entry = extern "C" fn(*mut Context, *mut f64, *mut u8, *mut u64, *const u8) -> i64;
+
+    // A place where the compiled code calls back into Rust or fails, with what the error says
+    struct Site {
+        line: usize,
+        position: Option<Position>,
+        kind: SiteKind,
+    }
+
+    enum SiteKind {
+        Error(Error),
+        StepLimit(usize),
+        // NEXT of this variable when the loop on top is over another one
+        Mismatch(String),
+        // PRINT, whose errors come from the IO backend
+        Output,
+        // INPUT of this variable
+        Input(String),
+    }
+
+    // What the compiled code refers to by number
+    #[derive(Default)]
+    struct Tables {
+        names: Vec<String>,
+        // Every FOR with the index of its line
+        loops: Vec<(usize, ForLoop)>,
+        texts: Vec<String>,
+        sites: Vec<Site>,
+    }
+
+    // Handed to every callback
+    struct Context<'a> {
+        interpreter: &'a mut Interpreter,
+        tables: &'a Tables,
+        // Indexes into `tables.loops`, innermost last
+        loops: Vec<usize>,
+        error: Option<(usize, Error)>,
+    }
+
+    impl Context<'_> {
+        fn report(&mut self, site: i64, result: Result<(), Error>) -> i8 {
+            match result {
+                Ok(()) => 0,
+                Err(error) => {
+                    self.error = Some((site as usize, error));
+                    1
+                },
+            }
+        }
+    }
+
+    pub(super) fn run(interpreter: &mut Interpreter, program: &Program) -> Result<Outcome, LangError> {
+        let mut tables = Tables::default();
+        let mut names: Vec<String> = crate::stats::variable_names(program).into_iter().collect();
+        names.sort();
+        tables.names = names;
+        for (index, line) in program.lines.iter().enumerate() {
+            collect_loops(&line.statement, index, &mut tables.loops);
+        }
+        let (module, entry) = match compile(interpreter, program, &mut tables) {
+            Ok(compiled) => compiled,
+            Err(e) => return Ok(Outcome::Unsupported(format!("Cranelift couldn't compile it: {}", e))),
+        };
+        // Safety: `compile` built the function with exactly this signature
+        let entry: Entry = unsafe { std::mem::transmute::<*const u8, Entry>(entry) };
+
+        let mut values = Vec::with_capacity(tables.names.len());
+        let mut assigned = Vec::with_capacity(tables.names.len());
+        for name in &tables.names {
+            match interpreter.variables.get(name) {
+                Some(crate::Value::Number(n)) => {
+                    values.push(*n);
+                    assigned.push(1);
+                },
+                _ => {
+                    values.push(0.0);
+                    assigned.push(0);
+                },
+            }
+        }
+        let mut steps = interpreter.steps as u64;
+
+        let mut context = Context { interpreter, tables: &tables, loops: Vec::new(), error: None };
+        let code = entry(&mut context, values.as_mut_ptr(), assigned.as_mut_ptr(), &mut steps, checkpoint::requested_flag());
+        let Context { interpreter, loops, error, .. } = context;
+        // Safety: nothing compiled is called again
+        unsafe { module.free_memory() };
+
+        // Leave everything as the interpreter would have, for whatever runs or looks next
+        interpreter.steps = steps as usize;
+        for ((name, value), assigned) in tables.names.iter().zip(values).zip(assigned) {
+            if assigned != 0 {
+                interpreter.variables.insert(name, crate::Value::Number(value));
+            }
+        }
+        for id in loops {
+            let (line, loop_data) = &tables.loops[id];
+            interpreter.loops.push(loop_data.clone());
+            interpreter.loop_stack.push(*line);
+        }
+
+        match code {
+            FINISHED => Ok(Outcome::Done),
+            ENDED => {
+                interpreter.exit = output::ExitReason::End;
+                Ok(Outcome::Done)
+            },
+            FAILED => {
+                let (site, error) = error.unwrap_or_else(|| (0, Error::from("")));
+                let site = &tables.sites[site];
+                if let SiteKind::StepLimit(_) = site.kind {
+                    interpreter.exit = output::ExitReason::StepLimit;
+                }
+                interpreter.current_line = site.line;
+                interpreter.error_position.set(site.position);
+                Err(interpreter.runtime_error(error))
+            },
+            line => Ok(Outcome::Continue(line as usize)),
+        }
+    }
+
+    fn collect_loops(statement: &Statement, line: usize, loops: &mut Vec<(usize, ForLoop)>) {
+        match statement {
+            Statement::For { loop_data } => loops.push((line, loop_data.clone())),
+            Statement::If { then_branch, else_branch, .. } => {
+                collect_loops(then_branch, line, loops);
+                if let Some(else_branch) = else_branch {
+                    collect_loops(else_branch, line, loops);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // The callbacks the compiled code imports, with their parameter and return types
+    fn callbacks(pointer: Type) -> Vec<(&'static str, *const u8, Vec<Type>, Vec<Type>)> {
+        let (int, float, flag) = (types::I64, types::F64, types::I8);
+        vec![
+            ("lang_jit_print_number", jit_print_number as *const u8, vec![pointer, int, float], vec![flag]),
+            ("lang_jit_print_text", jit_print_text as *const u8, vec![pointer, int, int], vec![flag]),
+            ("lang_jit_flush", jit_flush as *const u8, vec![pointer, int], vec![flag]),
+            ("lang_jit_input", jit_input as *const u8, vec![pointer, int, pointer], vec![flag]),
+            ("lang_jit_error", jit_error as *const u8, vec![pointer, int, int], vec![]),
+            ("lang_jit_push_loop", jit_push_loop as *const u8, vec![pointer, int], vec![]),
+            ("lang_jit_top_loop", jit_top_loop as *const u8, vec![pointer], vec![int]),
+            ("lang_jit_pop_loop", jit_pop_loop as *const u8, vec![pointer], vec![]),
+            ("lang_jit_pow", jit_pow as *const u8, vec![float, float], vec![float]),
+            ("lang_jit_sin", jit_sin as *const u8, vec![float], vec![float]),
+            ("lang_jit_cos", jit_cos as *const u8, vec![float], vec![float]),
+            ("lang_jit_tan", jit_tan as *const u8, vec![float], vec![float]),
+        ]
+    }
+
+    fn compile(interpreter: &Interpreter, program: &Program, tables: &mut Tables) -> Result<(JITModule, *const u8), String> {
+        let mut flags = settings::builder();
+        for (name, value) in [("opt_level", "speed"), ("use_colocated_libcalls", "false"), ("is_pic", "false")] {
+            flags.set(name, value).map_err(|e| e.to_string())?;
+        }
+        let isa = cranelift_native::builder()?
+            .finish(settings::Flags::new(flags))
+            .map_err(|e| e.to_string())?;
+        let pointer = isa.pointer_type();
+        let callbacks = callbacks(pointer);
+
+        let mut builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+        for (name, function, ..) in &callbacks {
+            builder.symbol(*name, *function);
+        }
+        let mut module = JITModule::new(builder);
+
+        let mut imports: HashMap<&str, FuncId> = HashMap::new();
+        for (name, _, params, returns) in &callbacks {
+            let mut signature = module.make_signature();
+            signature.params.extend(params.iter().map(|&ty| AbiParam::new(ty)));
+            signature.returns.extend(returns.iter().map(|&ty| AbiParam::new(ty)));
+            let id = module.declare_function(name, Linkage::Import, &signature).map_err(|e| e.to_string())?;
+            imports.insert(name, id);
+        }
+
+        let mut signature = module.make_signature();
+        signature.params.extend([pointer; 5].map(AbiParam::new));
+        signature.returns.push(AbiParam::new(types::I64));
+        let main = module.declare_function("lang_jit_program", Linkage::Export, &signature).map_err(|e| e.to_string())?;
+
+        let mut context = module.make_context();
+        context.func.signature = signature;
+        let mut function_context = FunctionBuilderContext::new();
+        {
+            let mut b = FunctionBuilder::new(&mut context.func, &mut function_context);
+            let functions = imports.iter()
+                .map(|(name, &id)| (*name, module.declare_func_in_func(id, b.func)))
+                .collect();
+            Translator::new(&mut b, interpreter, program, tables, functions, pointer).translate();
+            b.seal_all_blocks();
+            b.finalize();
+        }
+        module.define_function(main, &mut context).map_err(|e| format!("{:?}", e))?;
+        module.clear_context(&mut context);
+        module.finalize_definitions().map_err(|e| e.to_string())?;
+        let entry = module.get_finalized_function(main);
+        Ok((module, entry))
+    }
+
+    struct Translator<'a, 'b> {
+        b: &'a mut FunctionBuilder<'b>,
+        interpreter: &'a Interpreter,
+        program: &'a Program,
+        tables: &'a mut Tables,
+        functions: HashMap<&'static str, FuncRef>,
+        pointer: Type,
+        variables: HashMap<String, usize>,
+        // The first block of each line, and one more that finishes the program
+        blocks: Vec<Block>,
+        // Stores the variables back and returns its parameter
+        exit: Block,
+        parameters: Vec<Value>,
+        // One constant 1 for every assigned flag, so flags merged from different paths fold together
+        one: Value,
+        // FOR statements translated so far, which is the next one's index in `tables.loops`
+        loops_seen: usize,
+    }
+
+    impl<'a, 'b> Translator<'a, 'b> {
+        fn new(
+            b: &'a mut FunctionBuilder<'b>,
+            interpreter: &'a Interpreter,
+            program: &'a Program,
+            tables: &'a mut Tables,
+            functions: HashMap<&'static str, FuncRef>,
+            pointer: Type,
+        ) -> Self {
+            let variables = tables.names.iter().enumerate().map(|(index, name)| (name.clone(), index)).collect();
+            let entry = b.create_block();
+            b.append_block_params_for_function_params(entry);
+            b.switch_to_block(entry);
+            let parameters = b.block_params(entry).to_vec();
+            let one = b.ins().iconst(types::I8, 1);
+            let blocks = (0..=program.lines.len()).map(|_| b.create_block()).collect();
+            let exit = b.create_block();
+            b.append_block_param(exit, types::I64);
+            Translator { b, interpreter, program, tables, functions, pointer, variables, blocks, exit, parameters, one, loops_seen: 0 }
+        }
+
+        fn value_variable(index: usize) -> Variable {
+            Variable::from_u32(2 * index as u32)
+        }
+
+        fn assigned_variable(index: usize) -> Variable {
+            Variable::from_u32(2 * index as u32 + 1)
+        }
+
+        fn steps_variable(&self) -> Variable {
+            Variable::from_u32(2 * self.tables.names.len() as u32)
+        }
+
+        fn translate(mut self) {
+            let (values, assigned, steps) = (self.parameters[1], self.parameters[2], self.parameters[3]);
+            let flags = MemFlags::trusted();
+            for index in 0..self.tables.names.len() {
+                let (value_variable, assigned_variable) = (Self::value_variable(index), Self::assigned_variable(index));
+                self.b.declare_var(value_variable, types::F64);
+                self.b.declare_var(assigned_variable, types::I8);
+                let value = self.b.ins().load(types::F64, flags, values, 8 * index as i32);
+                self.b.def_var(value_variable, value);
+                let flag = self.b.ins().load(types::I8, flags, assigned, index as i32);
+                self.b.def_var(assigned_variable, flag);
+            }
+            let steps_variable = self.steps_variable();
+            self.b.declare_var(steps_variable, types::I64);
+            let count = self.b.ins().load(types::I64, flags, steps, 0);
+            self.b.def_var(steps_variable, count);
+            self.b.ins().jump(self.blocks[0], &[]);
+
+            let program = self.program;
+            for (index, line) in program.lines.iter().enumerate() {
+                self.b.switch_to_block(self.blocks[index]);
+                self.count_step(index);
+                self.statement(&line.statement, index, self.blocks[index + 1]);
+            }
+
+            self.b.switch_to_block(self.blocks[program.lines.len()]);
+            self.exit_with(FINISHED);
+
+            self.b.switch_to_block(self.exit);
+            let code = self.b.block_params(self.exit)[0];
+            for index in 0..self.tables.names.len() {
+                let value = self.b.use_var(Self::value_variable(index));
+                self.b.ins().store(flags, value, values, 8 * index as i32);
+                let flag = self.b.use_var(Self::assigned_variable(index));
+                self.b.ins().store(flags, flag, assigned, index as i32);
+            }
+            let count = self.b.use_var(steps_variable);
+            self.b.ins().store(flags, count, steps, 0);
+            self.b.ins().return_(&[code]);
+        }
+
+        // What before_statement does: stop for a checkpoint signal, then count the step against the limit
+        fn count_step(&mut self, line: usize) {
+            let requested = self.b.ins().atomic_load(types::I8, MemFlags::trusted(), self.parameters[4]);
+            let stop = self.b.create_block();
+            let carry_on = self.b.create_block();
+            self.b.ins().brif(requested, stop, &[], carry_on, &[]);
+            self.b.switch_to_block(stop);
+            self.exit_with(line as i64);
+            self.b.switch_to_block(carry_on);
+
+            let steps_variable = self.steps_variable();
+            let steps = self.b.use_var(steps_variable);
+            if let Some(max_steps) = self.interpreter.max_steps {
+                let over = self.b.ins().icmp_imm(IntCC::UnsignedGreaterThanOrEqual, steps, max_steps as i64);
+                let site = self.site(line, None, SiteKind::StepLimit(max_steps));
+                self.fail_if(over, site, None);
+            }
+            let steps = self.b.ins().iadd_imm(steps, 1);
+            self.b.def_var(steps_variable, steps);
+        }
+
+        fn site(&mut self, line: usize, position: Option<Position>, kind: SiteKind) -> i64 {
+            self.tables.sites.push(Site { line, position, kind });
+            self.tables.sites.len() as i64 - 1
+        }
+
+        fn exit_with(&mut self, code: i64) {
+            let code = self.b.ins().iconst(types::I64, code);
+            self.b.ins().jump(self.exit, &[code]);
+        }
+
+        fn call(&mut self, name: &str, arguments: &[Value]) -> Option<Value> {
+            let call = self.b.ins().call(self.functions[name], arguments);
+            self.b.inst_results(call).first().copied()
+        }
+
+        // Reports the site's error and leaves when `failed` isn't zero, carrying on in a new block otherwise
+        fn fail_if(&mut self, failed: Value, site: i64, detail: Option<Value>) {
+            let fail = self.b.create_block();
+            let carry_on = self.b.create_block();
+            self.b.set_cold_block(fail);
+            self.b.ins().brif(failed, fail, &[], carry_on, &[]);
+            self.b.switch_to_block(fail);
+            self.fail(site, detail);
+            self.b.switch_to_block(carry_on);
+        }
+
+        fn fail(&mut self, site: i64, detail: Option<Value>) {
+            let site = self.b.ins().iconst(types::I64, site);
+            let detail = detail.unwrap_or_else(|| self.b.ins().iconst(types::I64, 0));
+            self.call("lang_jit_error", &[self.parameters[0], site, detail]);
+            self.exit_with(FAILED);
+        }
+
+        // Calls a callback that returns nonzero when it failed, leaving with its error
+        fn call_checked(&mut self, name: &str, arguments: &[Value]) {
+            let failed = self.call(name, arguments).expect("checked callbacks return a flag");
+            let fail = self.b.create_block();
+            let carry_on = self.b.create_block();
+            self.b.set_cold_block(fail);
+            self.b.ins().brif(failed, fail, &[], carry_on, &[]);
+            self.b.switch_to_block(fail);
+            self.exit_with(FAILED);
+            self.b.switch_to_block(carry_on);
+        }
+
+        fn assign(&mut self, variable: &str, value: Value) {
+            let index = self.variables[variable];
+            self.b.def_var(Self::value_variable(index), value);
+            self.b.def_var(Self::assigned_variable(index), self.one);
+        }
+
+        fn text(&mut self, text: &str) -> Value {
+            let index = match self.tables.texts.iter().position(|t| t == text) {
+                Some(index) => index,
+                None => {
+                    self.tables.texts.push(text.to_string());
+                    self.tables.texts.len() - 1
+                },
+            };
+            self.b.ins().iconst(types::I64, index as i64)
+        }
+
+        // Ends the current block, going on to `next` unless the statement jumps or stops
+        fn statement(&mut self, statement: &Statement, line: usize, next: Block) {
+            let context = self.parameters[0];
+            match statement {
+                Statement::Let { variable, expression, .. } => {
+                    let value = self.expression(expression, line);
+                    self.assign(variable, value);
+                    self.b.ins().jump(next, &[]);
+                },
+                Statement::Print { expressions, semicolon } => {
+                    let site = self.site(line, None, SiteKind::Output);
+                    let site = self.b.ins().iconst(types::I64, site);
+                    for (i, expr) in expressions.iter().enumerate() {
+                        if i > 0 {
+                            let space = self.text(" ");
+                            self.call_checked("lang_jit_print_text", &[context, site, space]);
+                        }
+                        match expr {
+                            Expression::String(text) => {
+                                let text = self.text(text);
+                                self.call_checked("lang_jit_print_text", &[context, site, text]);
+                            },
+                            expr => {
+                                let value = self.expression(expr, line);
+                                self.call_checked("lang_jit_print_number", &[context, site, value]);
+                            },
+                        }
+                    }
+                    if !semicolon {
+                        let newline = self.text("\n");
+                        self.call_checked("lang_jit_print_text", &[context, site, newline]);
+                    }
+                    self.call_checked("lang_jit_flush", &[context, site]);
+                    self.b.ins().jump(next, &[]);
+                },
+                Statement::If { condition, then_branch, else_branch } => {
+                    let condition = self.expression(condition, line);
+                    let zero = self.b.ins().f64const(0.0);
+                    let taken = self.b.ins().fcmp(FloatCC::NotEqual, condition, zero);
+                    let then_block = self.b.create_block();
+                    let else_block = self.b.create_block();
+                    self.b.ins().brif(taken, then_block, &[], else_block, &[]);
+                    self.b.switch_to_block(then_block);
+                    self.statement(then_branch, line, next);
+                    self.b.switch_to_block(else_block);
+                    match else_branch {
+                        Some(else_branch) => self.statement(else_branch, line, next),
+                        None => {
+                            self.b.ins().jump(next, &[]);
+                        },
+                    }
+                },
+                Statement::Input { variable } => {
+                    let site = self.site(line, None, SiteKind::Input(variable.clone()));
+                    let site = self.b.ins().iconst(types::I64, site);
+                    let slot = self.b.create_sized_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 8, 3));
+                    let address = self.b.ins().stack_addr(self.pointer, slot, 0);
+                    self.call_checked("lang_jit_input", &[context, site, address]);
+                    let value = self.b.ins().stack_load(types::F64, slot, 0);
+                    self.assign(variable, value);
+                    self.b.ins().jump(next, &[]);
+                },
+                Statement::For { loop_data } => {
+                    // The bounds are all worked out, in order, before the variable is set
+                    let start = self.expression(&loop_data.start, line);
+                    self.expression(&loop_data.end, line);
+                    self.expression(&loop_data.step, line);
+                    self.assign(&loop_data.variable, start);
+                    // Statements are translated in the order collect_loops found them
+                    let id = self.b.ins().iconst(types::I64, self.loops_seen as i64);
+                    self.loops_seen += 1;
+                    self.call("lang_jit_push_loop", &[context, id]);
+                    self.b.ins().jump(next, &[]);
+                },
+                Statement::Next { variable } => self.next(variable, line, next),
+                Statement::End { .. } => self.exit_with(ENDED),
+                Statement::Goto(target) => {
+                    let target = self.interpreter.line_index[target];
+                    self.b.ins().jump(self.blocks[target], &[]);
+                },
+                Statement::OptionExplicit => {
+                    self.b.ins().jump(next, &[]);
+                },
+                _ => unreachable!("the JIT was given a statement `unsupported` turns down"),
+            }
+        }
+
+        // NEXT as execute_next does it, against the loop on top of the run's loop stack
+        fn next(&mut self, variable: &str, line: usize, next: Block) {
+            let context = self.parameters[0];
+            let top = self.call("lang_jit_top_loop", &[context]).expect("the top loop is returned");
+            let empty = self.b.ins().icmp_imm(IntCC::SignedLessThan, top, 0);
+            let site = self.site(line, None, SiteKind::Error(Error::new(Code::NextWithoutFor, "NEXT without FOR")));
+            self.fail_if(empty, site, None);
+
+            let mismatch = self.b.create_block();
+            let mut switch = Switch::new();
+            let candidates: Vec<(usize, usize, ForLoop)> = self.tables.loops.iter().enumerate()
+                .filter(|(_, (_, loop_data))| loop_data.variable == variable)
+                .map(|(id, (for_line, loop_data))| (id, *for_line, loop_data.clone()))
+                .collect();
+            let mut bodies = Vec::new();
+            for (id, for_line, loop_data) in candidates {
+                let block = self.b.create_block();
+                switch.set_entry(id as u128, block);
+                bodies.push((block, for_line, loop_data));
+            }
+            switch.emit(self.b, top, mismatch);
+
+            self.b.switch_to_block(mismatch);
+            self.b.set_cold_block(mismatch);
+            let site = self.site(line, None, SiteKind::Mismatch(variable.to_string()));
+            self.fail(site, Some(top));
+
+            for (block, for_line, loop_data) in bodies {
+                self.b.switch_to_block(block);
+                let index = self.variables[variable];
+                let current = self.b.use_var(Self::value_variable(index));
+                let step = self.expression(&loop_data.step, line);
+                let next_value = self.b.ins().fadd(current, step);
+                let end = self.expression(&loop_data.end, line);
+                let zero = self.b.ins().f64const(0.0);
+                let rising = self.b.ins().fcmp(FloatCC::GreaterThan, step, zero);
+                let within_rising = self.b.ins().fcmp(FloatCC::LessThanOrEqual, next_value, end);
+                let falling = self.b.ins().fcmp(FloatCC::LessThan, step, zero);
+                let within_falling = self.b.ins().fcmp(FloatCC::GreaterThanOrEqual, next_value, end);
+                let up = self.b.ins().band(rising, within_rising);
+                let down = self.b.ins().band(falling, within_falling);
+                let again = self.b.ins().bor(up, down);
+
+                let repeat = self.b.create_block();
+                let done = self.b.create_block();
+                self.b.ins().brif(again, repeat, &[], done, &[]);
+                self.b.switch_to_block(repeat);
+                self.assign(variable, next_value);
+                self.b.ins().jump(self.blocks[for_line + 1], &[]);
+                self.b.switch_to_block(done);
+                self.call("lang_jit_pop_loop", &[context]);
+                self.b.ins().jump(next, &[]);
+            }
+        }
+
+        fn expression(&mut self, expr: &Expression, line: usize) -> Value {
+            match expr {
+                Expression::Number(n) => self.b.ins().f64const(*n),
+                Expression::Variable(name, _, position) => {
+                    let index = self.variables[name];
+                    let assigned = self.b.use_var(Self::assigned_variable(index));
+                    let unassigned = self.b.ins().icmp_imm(IntCC::Equal, assigned, 0);
+                    let site = self.site(line, Some(*position), SiteKind::Error(Error::new(Code::UndefinedVariable, format!("Undefined variable: {}", name))));
+                    self.fail_if(unassigned, site, None);
+                    self.b.use_var(Self::value_variable(index))
+                },
+                Expression::Binary { left, operator, right, position } => {
+                    let left = self.expression(left, line);
+                    let right = self.expression(right, line);
+                    let condition = match operator {
+                        Token::Plus => return self.b.ins().fadd(left, right),
+                        Token::Minus => return self.b.ins().fsub(left, right),
+                        Token::Multiply => return self.b.ins().fmul(left, right),
+                        Token::Divide => {
+                            let zero = self.b.ins().f64const(0.0);
+                            let by_zero = self.b.ins().fcmp(FloatCC::Equal, right, zero);
+                            let site = self.site(line, Some(*position), SiteKind::Error(Error::new(Code::DivisionByZero, "Division by zero")));
+                            self.fail_if(by_zero, site, None);
+                            return self.b.ins().fdiv(left, right);
+                        },
+                        Token::Power => return self.call("lang_jit_pow", &[left, right]).expect("pow returns a number"),
+                        Token::LessThan => FloatCC::LessThan,
+                        Token::GreaterThan => FloatCC::GreaterThan,
+                        Token::Equals => FloatCC::Equal,
+                        Token::LessOrEqual => FloatCC::LessThanOrEqual,
+                        Token::GreaterOrEqual => FloatCC::GreaterThanOrEqual,
+                        Token::NotEqual => FloatCC::NotEqual,
+                        _ => unreachable!("the JIT was given an operator `unsupported` turns down"),
+                    };
+                    let result = self.b.ins().fcmp(condition, left, right);
+                    let (one, zero) = (self.b.ins().f64const(1.0), self.b.ins().f64const(0.0));
+                    self.b.ins().select(result, one, zero)
+                },
+                Expression::FunctionCall { name, arguments, position } => {
+                    let argument = self.expression(&arguments[0], line);
+                    match name.as_str() {
+                        "ABS" => self.b.ins().fabs(argument),
+                        "INT" => self.b.ins().floor(argument),
+                        "SQR" => {
+                            let zero = self.b.ins().f64const(0.0);
+                            let negative = self.b.ins().fcmp(FloatCC::LessThan, argument, zero);
+                            let error = Error::new(Code::IllegalFunctionCall, "Cannot take square root of negative number");
+                            let site = self.site(line, Some(*position), SiteKind::Error(error));
+                            self.fail_if(negative, site, None);
+                            self.b.ins().sqrt(argument)
+                        },
+                        "SIN" => self.call("lang_jit_sin", &[argument]).expect("sin returns a number"),
+                        "COS" => self.call("lang_jit_cos", &[argument]).expect("cos returns a number"),
+                        _ => self.call("lang_jit_tan", &[argument]).expect("tan returns a number"),
+                    }
+                },
+                Expression::String(_) => unreachable!("the JIT was given a string `unsupported` turns down"),
+            }
+        }
+    }
+
+    // The callbacks. Each one gets the Context the program was started with.
+
+    extern "C" fn jit_print_number(context: *mut Context, site: i64, n: f64) -> i8 {
+        // Safety: the pointer is the Context `run` passed in, alive for the whole call
+        let context = unsafe { &mut *context };
+        let result = context.interpreter.write_output(&numbers::format_number(n));
+        context.report(site, result)
+    }
+
+    extern "C" fn jit_print_text(context: *mut Context, site: i64, text: i64) -> i8 {
+        let context = unsafe { &mut *context };
+        let result = context.interpreter.write_output(&context.tables.texts[text as usize]);
+        context.report(site, result)
+    }
+
+    extern "C" fn jit_flush(context: *mut Context, site: i64) -> i8 {
+        let context = unsafe { &mut *context };
+        let result = context.interpreter.io.flush();
+        context.report(site, result)
+    }
+
+    extern "C" fn jit_input(context: *mut Context, site: i64, out: *mut f64) -> i8 {
+        let context = unsafe { &mut *context };
+        let SiteKind::Input(variable) = &context.tables.sites[site as usize].kind else {
+            unreachable!("INPUT calls back with its own site");
+        };
+        let interpreter = &mut *context.interpreter;
+        let result = interpreter.io.prompt(&format!("Enter {}: ", variable))
+            .and_then(|_| interpreter.io.flush())
+            .and_then(|_| interpreter.read_input())
+            .and_then(|input| input.trim().parse::<f64>().map_err(|_| Error::new(Code::TypeMismatch, "Invalid number input")))
+            .map(|n| unsafe { *out = n });
+        context.report(site, result)
+    }
+
+    extern "C" fn jit_error(context: *mut Context, site: i64, detail: i64) {
+        let context = unsafe { &mut *context };
+        let error = match &context.tables.sites[site as usize].kind {
+            SiteKind::Error(error) => error.clone(),
+            SiteKind::StepLimit(max_steps) => Error::new(Code::Limit, format!("Step limit of {} exceeded", max_steps)),
+            SiteKind::Mismatch(variable) => {
+                let open = &context.tables.loops[detail as usize].1.variable;
+                Error::new(Code::NextMismatch, format!("NEXT {} doesn't match FOR {}", variable, open))
+            },
+            SiteKind::Output | SiteKind::Input(_) => unreachable!("IO sites report their own errors"),
+        };
+        context.error = Some((site as usize, error));
+    }
+
+    // Closes a loop already open on the variable first, as execute_for does
+    extern "C" fn jit_push_loop(context: *mut Context, id: i64) {
+        let context = unsafe { &mut *context };
+        let loops = &context.tables.loops;
+        let variable = &loops[id as usize].1.variable;
+        if let Some(open) = context.loops.iter().rposition(|&open| loops[open].1.variable == *variable) {
+            context.loops.truncate(open);
+        }
+        context.loops.push(id as usize);
+    }
+
+    extern "C" fn jit_top_loop(context: *mut Context) -> i64 {
+        let context = unsafe { &mut *context };
+        context.loops.last().map_or(-1, |&id| id as i64)
+    }
+
+    extern "C" fn jit_pop_loop(context: *mut Context) {
+        let context = unsafe { &mut *context };
+        context.loops.pop();
+    }
+
+    extern "C" fn jit_pow(base: f64, exponent: f64) -> f64 {
+        base.powf(exponent)
+    }
+
+    extern "C" fn jit_sin(n: f64) -> f64 {
+        n.sin()
+    }
+
+    extern "C" fn jit_cos(n: f64) -> f64 {
+        n.cos()
+    }
+
+    extern "C" fn jit_tan(n: f64) -> f64 {
+        n.tan()
+    }
+}
@@ -0,0 +1,3484 @@
+// LANG, a BASIC inspired by MS BASIC. `parse` turns source into a Program, which an
+// Interpreter runs or the compiler turns into Rust.
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+// std's clock panics in a browser, so the wasm build reads the page's
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+pub use error::LangError;
+use errors::{Code, Error};
+
+// Items that run in the interpreter and whose source is also pasted into compiled programs as
+// RUST_SOURCE, so both behave the same
+macro_rules! shared {
+    ($($item:item)*) => {
+        $($item)*
+
+        pub const RUST_SOURCE: &str = stringify!($($item)*);
+    };
+}
+
+mod analyzer;
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod audio;
+mod binary;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod build;
+pub mod ccompiler;
+pub mod checkpoint;
+mod clipboard;
+pub mod compiler;
+pub mod crash;
+pub mod config;
+pub mod coverage;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod debugger;
+pub mod diagnostics;
+mod draw;
+pub mod editor;
+mod error;
+pub mod errors;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod files;
+pub mod golf;
+pub mod fuzz;
+pub mod golden;
+mod graphics;
+mod hardware;
+pub mod highlight;
+pub mod io;
+mod jit;
+pub mod lsp;
+pub mod midi;
+pub mod minimize;
+mod mml;
+mod numbers;
+pub mod output;
+#[cfg(feature = "python")]
+mod python;
+pub mod renum;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod repl;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod serve;
+mod screen;
+mod sprites;
+pub mod stats;
+mod statements;
+mod store;
+mod system;
+mod tasks;
+mod unparse;
+mod vm_opt;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+mod window;
+
+pub fn parse(source: &str) -> Result<Program, LangError> {
+    Parser::new(tokenize(source, false)).parse_program()
+}
+
+// The program written out as source, in the layout `lang fmt` uses. Parsing it gives back the
+// same program, so tools can change the AST and save the result as BASIC.
+pub fn to_source(program: &Program) -> String {
+    unparse::program(program)
+}
+
+// `lang fmt`: the source in its canonical form
+pub fn format(source: &str) -> Result<String, LangError> {
+    Ok(to_source(&parse(source)?))
+}
+
+// The --golf dialect, where `?` is PRINT, `@` is INPUT and so on
+pub fn parse_golf(source: &str) -> Result<Program, LangError> {
+    Parser::new(tokenize(source, true)).parse_program()
+}
+
+// OPTION EXPLICIT, or --strict: every variable that is read has to be assigned somewhere
+pub fn check_declarations(program: &Program, strict: bool) -> Result<(), String> {
+    let explicit = program.lines.iter().any(|line| matches!(line.statement, Statement::OptionExplicit));
+    if !strict && !explicit {
+        return Ok(());
+    }
+    let undeclared = analyzer::undeclared_variables(program);
+    if undeclared.is_empty() {
+        return Ok(());
+    }
+    let errors: Vec<String> = undeclared.iter()
+        .map(|(line, name)| format!("Line {}: {} is used but never assigned", line, name))
+        .collect();
+    Err(format!("OPTION EXPLICIT: {}", errors.join("; ")))
+}
+
+// Looks over the whole program before it runs, for mistakes that would otherwise only show
+// when execution got there
+pub fn check_program(program: &Program) -> Result<(), LangError> {
+    let mut errors: Vec<LangError> = analyzer::semantic_errors(program).into_iter()
+        .map(|(index, position, code, message)| {
+            let line = &program.lines[index];
+            let (source_line, column) = position.unwrap_or(line.position);
+            LangError::SemanticError { line: line.number, source_line, column, code, message }
+        })
+        .collect();
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(LangError::Multiple(errors)),
+    }
+}
+
+#[allow(dead_code, clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum Token {
+    DefType(VarType),
+    // Numbers and Identifiers
+    Number(f64),
+    Identifier(String),
+    
+    // Operators
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Power,
+    Equals,
+    LessThan,
+    GreaterThan,
+    LessOrEqual,
+    GreaterOrEqual,
+    NotEqual,
+    
+    // Brackets and Separators
+    LParen,
+    RParen,
+    Comma,
+    Semicolon,
+    Colon,
+    Hash,
+    
+    // Keywords
+    Let,
+    Print,
+    Input,
+    If,
+    Then,
+    Else,
+    For,
+    To,
+    Step,
+    Next,
+    Goto,
+    Gosub,
+    Return,
+    Rem,
+    End,
+    Stop,
+    Dim,
+    Read,
+    Data,
+    Restore,
+    Open,
+    Close,
+    Field,
+    Get,
+    Put,
+    Lset,
+    Rset,
+    As,
+    Kill,
+    Name,
+    Files,
+    Chain,
+    Common,
+    Def,
+    Checkpoint,
+    Cls,
+    Locate,
+    Color,
+    Screen,
+    Pset,
+    Line,
+    Circle,
+    Paint,
+    SaveImage,
+    Sleep,
+    Beep,
+    Sound,
+    Play,
+    Draw,
+    Sprite,
+    Option,
+    StoreSet,
+    ClipSet,
+    Task,
+    Await,
+    Send,
+    Receive,
+    Shell,
+    OpenSerial,
+    SerialOut,
+    SerialIn,
+    OutPin,
+    
+    // Built-in Functions
+    Abs,
+    Rnd,
+    Int,
+    Sqr,
+    Sin,
+    Cos,
+    Tan,
+    Log,
+    Exp,
+    Len,
+    Mid,
+    Left,
+    Right,
+    
+    // Special
+    LineNumber(u32),
+    String(String),
+    EOL,
+    EOF,
+}
+
+// Inline cache for a variable reference or assignment: the store generation and slot it last
+// resolved to. Shared between clones of the program, which may be run by other interpreters on
+// other threads, so both halves are packed into one atomic and are never seen apart.
+#[derive(Debug, Clone, Default)]
+struct VarCache(Arc<AtomicU64>);
+
+const SLOT_BITS: u32 = 24;
+
+impl VarCache {
+    fn get(&self) -> Option<(u64, usize)> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            packed => Some((packed >> SLOT_BITS, (packed & ((1 << SLOT_BITS) - 1)) as usize)),
+        }
+    }
+
+    // A slot or generation too big to pack just isn't cached
+    fn set(&self, generation: u64, slot: usize) {
+        if generation < 1 << (64 - SLOT_BITS) && slot < 1 << SLOT_BITS {
+            self.0.store(generation << SLOT_BITS | slot as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Expression {
+    Number(f64),
+    String(String),
+    // Nodes that can fail at runtime keep where they are in the source, for error messages
+    // The cache is only good for one run, so it isn't saved with the AST
+    Variable(String, #[serde(skip)] VarCache, Position),
+    Binary {
+        left: Box<Expression>,
+        operator: Token,
+        right: Box<Expression>,
+        position: Position,
+    },
+    FunctionCall {
+        name: String,
+        arguments: Vec<Expression>,
+        position: Position,
+    },
+}
+
+impl Expression {
+    fn position(&self) -> Option<Position> {
+        match self {
+            Expression::Number(_) | Expression::String(_) => None,
+            Expression::Variable(_, _, position)
+            | Expression::Binary { position, .. }
+            | Expression::FunctionCall { position, .. } => Some(*position),
+        }
+    }
+
+    // What `0 - x` negates, when it's written as `-x`. A number stays `0 - 5`, since `-5`
+    // would read back as the number -5.
+    fn negated(&self) -> Option<&Expression> {
+        match self {
+            Expression::Binary { left, operator: Token::Minus, right, .. }
+                if matches!(**left, Expression::Number(n) if n == 0.0) && !matches!(**right, Expression::Number(_)) => Some(right),
+            _ => None,
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            // Negative numbers and negations are written with a unary minus, which binds looser than ^
+            Expression::Number(n) if *n < 0.0 => 3,
+            expr if expr.negated().is_some() => 3,
+            Expression::Binary { operator, .. } => match operator {
+                Token::Plus | Token::Minus => 2,
+                Token::Multiply | Token::Divide => 3,
+                Token::Power => 4,
+                _ => 1,
+            },
+            _ => 5,
+        }
+    }
+}
+
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expression::Number(n) => write!(f, "{}", n),
+            Expression::String(s) => write!(f, "\"{}\"", s),
+            Expression::Variable(name, ..) => write!(f, "{}", name),
+            expr if expr.negated().is_some() => match expr.negated() {
+                Some(operand) if operand.precedence() < 4 => write!(f, "-({})", operand),
+                Some(operand) => write!(f, "-{}", operand),
+                None => Ok(()),
+            },
+            Expression::Binary { left, operator, right, .. } => {
+                let symbol = match operator {
+                    Token::Plus => "+",
+                    Token::Minus => "-",
+                    Token::Multiply => "*",
+                    Token::Divide => "/",
+                    Token::Power => "^",
+                    Token::Equals => "=",
+                    Token::LessThan => "<",
+                    Token::GreaterThan => ">",
+                    Token::LessOrEqual => "<=",
+                    Token::GreaterOrEqual => ">=",
+                    Token::NotEqual => "<>",
+                    _ => "?",
+                };
+                // Operators are left-associative, so only the right side needs parens at equal precedence
+                if left.precedence() < self.precedence() {
+                    write!(f, "({})", left)?;
+                } else {
+                    write!(f, "{}", left)?;
+                }
+                write!(f, " {} ", symbol)?;
+                if right.precedence() <= self.precedence() {
+                    write!(f, "({})", right)
+                } else {
+                    write!(f, "{}", right)
+                }
+            },
+            Expression::FunctionCall { name, arguments, .. } => {
+                let arguments: Vec<String> = arguments.iter().map(|a| a.to_string()).collect();
+                write!(f, "{}({})", name, arguments.join(", "))
+            },
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ForLoop {
+    variable: String,
+    #[serde(skip)]
+    cache: VarCache,
+    start: Expression,
+    end: Expression,
+    step: Expression,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Statement {
+    Let {
+        variable: String,
+        #[serde(skip)]
+        cache: VarCache,
+        expression: Expression,
+    },
+    Print {
+        expressions: Vec<Expression>,
+        semicolon: bool,
+    },
+    If {
+        condition: Expression,
+        then_branch: Box<Statement>,
+        else_branch: Option<Box<Statement>>,
+    },
+    Input {
+        variable: String,
+    },
+    For {
+        loop_data: ForLoop,
+    },
+    Next {
+        variable: String,
+    },
+    // END 3 stops the program with exit status 3
+    End {
+        code: Option<Expression>,
+    },
+    Goto(u32),
+    Rem(String),
+    Open {
+        filename: Expression,
+        mode: FileMode,
+        file_number: Expression,
+        record_length: Option<Expression>,
+    },
+    Close {
+        file_numbers: Vec<Expression>,
+    },
+    Field {
+        file_number: Expression,
+        fields: Vec<(Expression, String)>,
+    },
+    Lset {
+        variable: String,
+        expression: Expression,
+    },
+    Rset {
+        variable: String,
+        expression: Expression,
+    },
+    Get {
+        file_number: Expression,
+        record: Option<Expression>,
+    },
+    Put {
+        file_number: Expression,
+        record: Option<Expression>,
+    },
+    Kill {
+        path: Expression,
+    },
+    Name {
+        from: Expression,
+        to: Expression,
+    },
+    Files {
+        pattern: Option<Expression>,
+    },
+    Chain {
+        path: Expression,
+    },
+    // The variables that keep their values through a CHAIN
+    Common {
+        variables: Vec<String>,
+    },
+    DefFn {
+        name: String,
+        parameters: Vec<String>,
+        body: Expression,
+    },
+    Checkpoint,
+    Cls,
+    Locate {
+        row: Option<Expression>,
+        col: Option<Expression>,
+    },
+    Color {
+        foreground: Option<Expression>,
+        background: Option<Expression>,
+    },
+    Screen {
+        width: Expression,
+        height: Expression,
+    },
+    Pset {
+        point: Point,
+        color: Option<Expression>,
+    },
+    DrawLine {
+        from: Option<Point>,
+        to: Point,
+        color: Option<Expression>,
+        shape: graphics::LineShape,
+    },
+    Circle {
+        center: Point,
+        radius: Expression,
+        color: Option<Expression>,
+    },
+    Paint {
+        point: Point,
+        color: Option<Expression>,
+        border: Option<Expression>,
+    },
+    SaveImage {
+        path: Expression,
+    },
+    Sleep {
+        seconds: Option<Expression>,
+    },
+    Beep,
+    Sound {
+        frequency: Expression,
+        duration: Expression,
+    },
+    Play {
+        music: Expression,
+    },
+    Draw {
+        commands: Expression,
+    },
+    SpriteLoad {
+        number: Expression,
+        rows: Vec<Expression>,
+    },
+    SpriteMove {
+        number: Expression,
+        row: Expression,
+        col: Expression,
+    },
+    SpriteHide {
+        number: Expression,
+    },
+    Task {
+        target: u32,
+        id_variable: Option<String>,
+    },
+    EndTask,
+    Await {
+        task: Option<Expression>,
+    },
+    Send {
+        channel: Expression,
+        value: Expression,
+    },
+    Receive {
+        channel: Expression,
+        variable: String,
+    },
+    Shell {
+        command: Expression,
+    },
+    OpenSerial {
+        device: Expression,
+        baud: Expression,
+        file_number: Expression,
+    },
+    SerialOut {
+        file_number: Expression,
+        value: Expression,
+    },
+    SerialIn {
+        file_number: Expression,
+        variable: String,
+    },
+    OutPin {
+        pin: Expression,
+        value: Expression,
+    },
+    DefType {
+        var_type: VarType,
+        letters: Vec<(char, char)>,
+    },
+    OptionExplicit,
+    StoreSet {
+        key: Expression,
+        value: Expression,
+    },
+    ClipSet {
+        text: Expression,
+    },
+}
+
+type Point = (Expression, Expression);
+
+// The type DEFINT, DEFSNG, DEFDBL or DEFSTR gives variables by their first letter
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum VarType {
+    Integer,
+    Single,
+    Double,
+    String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum FileMode {
+    Random,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Line {
+    number: u32,
+    // Where the statement starts in the source file
+    position: Position,
+    statement: Statement,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Program {
+    lines: Vec<Line>,
+}
+
+impl Program {
+    fn new() -> Self {
+        Program {
+            lines: Vec::new(),
+        }
+    }
+
+    // The AST as JSON, for --ast and tools that want the parsed program rather than the source
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("the AST always serializes")
+    }
+
+    pub fn from_json(json: &str) -> Result<Program, String> {
+        serde_json::from_str(json).map_err(|e| format!("Bad AST: {}", e))
+    }
+
+    // Where each line number is in the program, so a jump is found without searching the lines.
+    // In reverse so a repeated number, like the 0 of unnumbered lines, finds its first line.
+    fn line_index(&self) -> HashMap<u32, usize> {
+        self.lines.iter().enumerate().rev().map(|(index, line)| (line.number, index)).collect()
+    }
+}
+
+// Line and column in the source, counting from 1
+type Position = (usize, usize);
+
+// The tokens of a source file, each with the position it starts at
+struct Lexed {
+    tokens: Vec<Token>,
+    positions: Vec<Position>,
+    errors: Vec<LangError>,
+}
+
+// A line with a lex error is dropped after the error is noted, so lexing and then parsing
+// carry on and report every bad line at once
+fn keyword(word: &str) -> Option<Token> {
+    Some(match word {
+        "LET" => Token::Let,
+        "PRINT" => Token::Print,
+        "IF" => Token::If,
+        "THEN" => Token::Then,
+        "ELSE" => Token::Else,
+        "FOR" => Token::For,
+        "TO" => Token::To,
+        "STEP" => Token::Step,
+        "NEXT" => Token::Next,
+        "END" => Token::End,
+        "INPUT" => Token::Input,
+        "GOTO" => Token::Goto,
+        "OPEN" => Token::Open,
+        "CLOSE" => Token::Close,
+        "FIELD" => Token::Field,
+        "GET" => Token::Get,
+        "PUT" => Token::Put,
+        "LSET" => Token::Lset,
+        "RSET" => Token::Rset,
+        "AS" => Token::As,
+        "KILL" => Token::Kill,
+        "NAME" => Token::Name,
+        "FILES" => Token::Files,
+        "CHAIN" => Token::Chain,
+        "COMMON" => Token::Common,
+        "DEF" => Token::Def,
+        "DEFINT" => Token::DefType(VarType::Integer),
+        "DEFSNG" => Token::DefType(VarType::Single),
+        "DEFDBL" => Token::DefType(VarType::Double),
+        "DEFSTR" => Token::DefType(VarType::String),
+        "CHECKPOINT" => Token::Checkpoint,
+        "CLS" => Token::Cls,
+        "LOCATE" => Token::Locate,
+        "COLOR" => Token::Color,
+        "SCREEN" => Token::Screen,
+        "PSET" => Token::Pset,
+        "LINE" => Token::Line,
+        "CIRCLE" => Token::Circle,
+        "PAINT" => Token::Paint,
+        "SAVEIMAGE" => Token::SaveImage,
+        "SLEEP" => Token::Sleep,
+        "BEEP" => Token::Beep,
+        "SOUND" => Token::Sound,
+        "PLAY" => Token::Play,
+        "DRAW" => Token::Draw,
+        "SPRITE" => Token::Sprite,
+        "OPTION" => Token::Option,
+        "STORESET" => Token::StoreSet,
+        "CLIPSET" => Token::ClipSet,
+        "TASK" => Token::Task,
+        "AWAIT" => Token::Await,
+        "SEND" => Token::Send,
+        "RECEIVE" => Token::Receive,
+        "SHELL" => Token::Shell,
+        "OPENSERIAL" => Token::OpenSerial,
+        "SERIALOUT" => Token::SerialOut,
+        "SERIALIN" => Token::SerialIn,
+        "OUTPIN" => Token::OutPin,
+        _ => return None,
+    })
+}
+
+// With `golf`, the one-character keywords in golf::ALIASES are read as the keywords they stand for
+fn tokenize(input: &str, golf: bool) -> Lexed {
+    let mut tokens = Vec::new();
+    let mut positions = Vec::new();
+    let mut errors = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    let mut line = 1;
+    let mut line_start = 0;
+    // Where the current line's tokens begin
+    let mut line_tokens = 0;
+
+    while let Some(&(offset, c)) = chars.peek() {
+        let position = (line, input[line_start..offset].chars().count() + 1);
+        let mut error = None;
+        match c {
+            ' ' | '\t' | '\r' => {
+                chars.next();
+            }
+            '\n' => {
+                tokens.push(Token::EOL);
+                chars.next();
+                line += 1;
+                line_start = offset + 1;
+                line_tokens = tokens.len();
+            }
+            '0'..='9' => {
+                let mut number = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_ascii_digit() || c == '.' {
+                        number.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                // A whole number at the start of a line is a line number
+                let at_line_start = matches!(tokens.last(), None | Some(Token::EOL));
+                if let (true, Ok(n)) = (at_line_start, number.parse::<u32>()) {
+                    tokens.push(Token::LineNumber(n));
+                } else if let Ok(n) = number.parse::<f64>() {
+                    tokens.push(Token::Number(n));
+                } else {
+                    error = Some((errors::Code::Syntax, format!("Bad number {}", number)));
+                }
+            }
+            'A'..='Z' | 'a'..='z' | '_' => {
+                let mut ident = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' {
+                        ident.push(c.to_ascii_uppercase());
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                // String variables end in $
+                if let Some(&(_, '$')) = chars.peek() {
+                    ident.push('$');
+                    chars.next();
+                }
+                tokens.push(keyword(&ident).unwrap_or(Token::Identifier(ident)));
+            }
+            '"' => {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next_if(|&(_, c)| c != '\n') {
+                        Some((_, '"')) => break,
+                        Some((_, c)) => string.push(c),
+                        None => {
+                            error = Some((errors::Code::UnterminatedString, "Unterminated string".to_string()));
+                            break;
+                        },
+                    }
+                }
+                tokens.push(Token::String(string));
+            }
+            '+' => {
+                tokens.push(Token::Plus);
+                chars.next();
+            }
+            '-' => {
+                tokens.push(Token::Minus);
+                chars.next();
+            }
+            '*' => {
+                tokens.push(Token::Multiply);
+                chars.next();
+            }
+            '/' => {
+                tokens.push(Token::Divide);
+                chars.next();
+            }
+            '^' => {
+                tokens.push(Token::Power);
+                chars.next();
+            }
+            '=' => {
+                tokens.push(Token::Equals);
+                chars.next();
+            }
+            '<' => {
+                chars.next();
+                if let Some(&(_, '=')) = chars.peek() {
+                    tokens.push(Token::LessOrEqual);
+                    chars.next();
+                } else if let Some(&(_, '>')) = chars.peek() {
+                    tokens.push(Token::NotEqual);
+                    chars.next();
+                } else {
+                    tokens.push(Token::LessThan);
+                }
+            }
+            '>' => {
+                chars.next();
+                if let Some(&(_, '=')) = chars.peek() {
+                    tokens.push(Token::GreaterOrEqual);
+                    chars.next();
+                } else {
+                    tokens.push(Token::GreaterThan);
+                }
+            }
+            '(' => {
+                tokens.push(Token::LParen);
+                chars.next();
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                chars.next();
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                chars.next();
+            }
+            ';' => {
+                tokens.push(Token::Semicolon);
+                chars.next();
+            }
+            ':' => {
+                tokens.push(Token::Colon);
+                chars.next();
+            }
+            '#' => {
+                tokens.push(Token::Hash);
+                chars.next();
+            }
+            _ => {
+                chars.next();
+                match golf::alias(c).filter(|_| golf) {
+                    Some(word) => tokens.extend(keyword(word)),
+                    None => error = Some((errors::Code::UnexpectedCharacter, format!("Unexpected character '{}'", c))),
+                }
+            },
+        }
+        if let Some((code, message)) = error {
+            errors.push(LangError::LexError { line: position.0, column: position.1, code, message });
+            tokens.truncate(line_tokens);
+            while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+        }
+        positions.resize(tokens.len(), position);
+    }
+
+    // If the last token isn't EOL, add one
+    if !tokens.is_empty() && !matches!(tokens.last(), Some(Token::EOL)) {
+        tokens.push(Token::EOL);
+    }
+    tokens.push(Token::EOF);
+    positions.resize(tokens.len(), (line, input[line_start..].chars().count() + 1));
+    Lexed { tokens, positions, errors }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    positions: Vec<Position>,
+    current: usize,
+    errors: Vec<LangError>,
+}
+
+impl Parser {
+    fn new(lexed: Lexed) -> Self {
+        Parser {
+            tokens: lexed.tokens,
+            positions: lexed.positions,
+            current: 0,
+            errors: lexed.errors,
+        }
+    }
+
+    // Points at the next token, the one that didn't fit
+    fn error(&self, message: impl Into<String>) -> LangError {
+        self.error_at(self.current, message)
+    }
+
+    // Points at the token just consumed, for checks made after advancing past it
+    fn error_at_previous(&self, message: impl Into<String>) -> LangError {
+        self.error_at(self.current.saturating_sub(1), message)
+    }
+
+    fn previous_position(&self) -> Position {
+        self.positions.get(self.current.saturating_sub(1)).copied().unwrap_or((1, 1))
+    }
+
+    fn error_at(&self, index: usize, message: impl Into<String>) -> LangError {
+        let (line, column) = self.positions.get(index).or(self.positions.last()).copied().unwrap_or((1, 1));
+        LangError::ParseError { line, column, code: errors::Code::Syntax, message: message.into() }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.current)
+    }
+
+    fn advance(&mut self) -> Option<&Token> {
+        if let Some(&position) = self.positions.get(self.current) {
+            crash::note_parser_position(position);
+        }
+        if self.current < self.tokens.len() {
+            self.current += 1;
+        }
+        self.tokens.get(self.current - 1)
+    }
+
+    fn match_token(&mut self, expected: &[Token]) -> bool {
+        if let Some(token) = self.peek() {
+            if expected.contains(token) {
+                self.advance();
+                return true;
+            }
+        }
+        false
+    }
+
+    fn parse_program(&mut self) -> Result<Program, LangError> {
+        let mut program = Program::new();
+        let mut line_number = 0;
+
+        while let Some(token) = self.peek() {
+            match token {
+                Token::EOL => {
+                    self.advance();
+                },
+                Token::EOF => {
+                    break;
+                },
+                _ => {
+                    let number = match self.peek() {
+                        Some(&Token::LineNumber(n)) => {
+                            self.advance();
+                            n
+                        },
+                        _ => line_number,
+                    };
+                    let position = self.positions[self.current];
+                    match self.parse_statement() {
+                        Ok(statement) => program.lines.push(Line { number, position, statement }),
+                        Err(e) => {
+                            self.errors.push(e);
+                            self.synchronize();
+                        },
+                    }
+                    line_number += 1;
+
+                    // Consume any EOL after the statement
+                    if let Some(Token::EOL) = self.peek() {
+                        self.advance();
+                    }
+                }
+            }
+        }
+
+        // Lex errors were found first, so they need putting back in order among the parse errors
+        let mut errors = std::mem::take(&mut self.errors);
+        errors.sort_by_key(|e| match e {
+            LangError::LexError { line, column, .. } | LangError::ParseError { line, column, .. } => (*line, *column),
+            _ => (0, 0),
+        });
+        match errors.len() {
+            0 => Ok(program),
+            1 => Err(errors.remove(0)),
+            _ => Err(LangError::Multiple(errors)),
+        }
+    }
+
+    // After a syntax error, skip the rest of the line and carry on with the next one
+    fn synchronize(&mut self) {
+        while !matches!(self.peek(), Some(Token::EOL) | Some(Token::EOF) | None) {
+            self.advance();
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement, LangError> {
+        let token = self.peek().cloned();
+        Ok(match token {
+            Some(Token::Let) => {
+                self.advance();
+                self.parse_let()?
+            },
+            Some(Token::Print) => {
+                self.advance();
+                self.parse_print()?
+            },
+            Some(Token::If) => {
+                self.advance();
+                self.parse_if()?
+            },
+            Some(Token::For) => {
+                self.advance();
+                self.parse_for()?
+            },
+            Some(Token::Input) => {
+                self.advance();
+                if let Some(Token::Identifier(name)) = self.advance().cloned() {
+                    Statement::Input {
+                        variable: name,
+                    }
+                } else {
+                    return Err(self.error_at_previous("Expected variable name after INPUT"))
+                }
+            },
+            Some(Token::Next) => {
+                self.advance();
+                if let Some(Token::Identifier(name)) = self.advance().cloned() {
+                    Statement::Next {
+                        variable: name,
+                    }
+                } else {
+                    return Err(self.error_at_previous("Expected variable name after NEXT"))
+                }
+            },
+            Some(Token::End) => {
+                self.advance();
+                if self.match_token(&[Token::Task]) {
+                    Statement::EndTask
+                } else {
+                    let code = match self.peek() {
+                        Some(Token::EOL) | Some(Token::Else) | None => None,
+                        _ => Some(self.parse_expression()?),
+                    };
+                    Statement::End { code }
+                }
+            },
+            Some(Token::Task) => {
+                self.advance();
+                let target = match self.advance() {
+                    Some(&Token::Number(n)) => n as u32,
+                    _ => return Err(self.error_at_previous("Expected line number after TASK")),
+                };
+                let id_variable = if self.match_token(&[Token::Comma]) {
+                    match self.advance() {
+                        Some(Token::Identifier(name)) if !name.ends_with('$') => Some(name.clone()),
+                        _ => return Err(self.error_at_previous("Expected numeric variable for the task id in TASK")),
+                    }
+                } else {
+                    None
+                };
+                Statement::Task { target, id_variable }
+            },
+            Some(Token::Send) => {
+                self.advance();
+                let channel = self.parse_expression()?;
+                if !self.match_token(&[Token::Comma]) {
+                    return Err(self.error("Expected ',' after channel in SEND"));
+                }
+                Statement::Send {
+                    channel,
+                    value: self.parse_expression()?,
+                }
+            },
+            Some(Token::Receive) => {
+                self.advance();
+                let channel = self.parse_expression()?;
+                if !self.match_token(&[Token::Comma]) {
+                    return Err(self.error("Expected ',' after channel in RECEIVE"));
+                }
+                match self.advance() {
+                    Some(Token::Identifier(name)) => Statement::Receive {
+                        channel,
+                        variable: name.clone(),
+                    },
+                    _ => return Err(self.error_at_previous("Expected variable name in RECEIVE")),
+                }
+            },
+            Some(Token::Shell) => {
+                self.advance();
+                Statement::Shell {
+                    command: self.parse_expression()?,
+                }
+            },
+            Some(Token::OpenSerial) => {
+                self.advance();
+                let device = self.parse_expression()?;
+                if !self.match_token(&[Token::Comma]) {
+                    return Err(self.error("Expected ',' after device in OPENSERIAL"));
+                }
+                let baud = self.parse_expression()?;
+                if !self.match_token(&[Token::As]) {
+                    return Err(self.error("Expected AS in OPENSERIAL"));
+                }
+                Statement::OpenSerial {
+                    device,
+                    baud,
+                    file_number: self.parse_file_number()?,
+                }
+            },
+            Some(Token::SerialOut) => {
+                self.advance();
+                let file_number = self.parse_file_number()?;
+                if !self.match_token(&[Token::Comma]) {
+                    return Err(self.error("Expected ',' after serial number in SERIALOUT"));
+                }
+                Statement::SerialOut {
+                    file_number,
+                    value: self.parse_expression()?,
+                }
+            },
+            Some(Token::SerialIn) => {
+                self.advance();
+                let file_number = self.parse_file_number()?;
+                if !self.match_token(&[Token::Comma]) {
+                    return Err(self.error("Expected ',' after serial number in SERIALIN"));
+                }
+                match self.advance() {
+                    Some(Token::Identifier(name)) => Statement::SerialIn {
+                        file_number,
+                        variable: name.clone(),
+                    },
+                    _ => return Err(self.error_at_previous("Expected variable name in SERIALIN")),
+                }
+            },
+            Some(Token::OutPin) => {
+                self.advance();
+                let pin = self.parse_expression()?;
+                if !self.match_token(&[Token::Comma]) {
+                    return Err(self.error("Expected ',' after pin in OUTPIN"));
+                }
+                Statement::OutPin {
+                    pin,
+                    value: self.parse_expression()?,
+                }
+            },
+            Some(Token::Await) => {
+                self.advance();
+                let task = match self.peek() {
+                    Some(Token::EOL) | Some(Token::Else) | None => None,
+                    _ => Some(self.parse_expression()?),
+                };
+                Statement::Await { task }
+            },
+            Some(Token::Goto) => {
+                self.advance();
+                match self.advance() {
+                    Some(&Token::Number(n)) => Statement::Goto(n as u32),
+                    _ => return Err(self.error_at_previous("Expected line number after GOTO")),
+                }
+            },
+            Some(Token::Open) => {
+                self.advance();
+                self.parse_open()?
+            },
+            Some(Token::Close) => {
+                self.advance();
+                let mut file_numbers = Vec::new();
+                while !matches!(self.peek(), Some(Token::EOL) | Some(Token::Else) | None) {
+                    file_numbers.push(self.parse_file_number()?);
+                    if !self.match_token(&[Token::Comma]) {
+                        break;
+                    }
+                }
+                Statement::Close { file_numbers }
+            },
+            Some(Token::Field) => {
+                self.advance();
+                self.parse_field()?
+            },
+            Some(Token::Lset) | Some(Token::Rset) => {
+                self.advance();
+                let variable = match self.advance() {
+                    Some(Token::Identifier(name)) => name.clone(),
+                    _ => return Err(self.error_at_previous("Expected variable name after LSET/RSET")),
+                };
+                if !self.match_token(&[Token::Equals]) {
+                    return Err(self.error("Expected '=' after variable name in LSET/RSET"));
+                }
+                let expression = self.parse_expression()?;
+                if token == Some(Token::Lset) {
+                    Statement::Lset { variable, expression }
+                } else {
+                    Statement::Rset { variable, expression }
+                }
+            },
+            Some(Token::Get) | Some(Token::Put) => {
+                self.advance();
+                let file_number = self.parse_file_number()?;
+                let record = if self.match_token(&[Token::Comma]) {
+                    Some(self.parse_expression()?)
+                } else {
+                    None
+                };
+                if token == Some(Token::Get) {
+                    Statement::Get { file_number, record }
+                } else {
+                    Statement::Put { file_number, record }
+                }
+            },
+            Some(Token::Def) => {
+                self.advance();
+                self.parse_def()?
+            },
+            Some(Token::DefType(var_type)) => {
+                self.advance();
+                self.parse_def_type(var_type)?
+            },
+            Some(Token::Checkpoint) => {
+                self.advance();
+                Statement::Checkpoint
+            },
+            Some(Token::Cls) => {
+                self.advance();
+                Statement::Cls
+            },
+            Some(Token::Locate) => {
+                self.advance();
+                let (row, col) = self.parse_optional_pair()?;
+                Statement::Locate { row, col }
+            },
+            Some(Token::Color) => {
+                self.advance();
+                let (foreground, background) = self.parse_optional_pair()?;
+                Statement::Color { foreground, background }
+            },
+            Some(Token::Screen) => {
+                self.advance();
+                let width = self.parse_expression()?;
+                if !self.match_token(&[Token::Comma]) {
+                    return Err(self.error("Expected ',' in SCREEN"));
+                }
+                Statement::Screen {
+                    width,
+                    height: self.parse_expression()?,
+                }
+            },
+            Some(Token::Pset) => {
+                self.advance();
+                let point = self.parse_point()?;
+                let color = self.parse_optional_argument()?;
+                Statement::Pset { point, color }
+            },
+            Some(Token::Line) => {
+                self.advance();
+                self.parse_line()?
+            },
+            Some(Token::Circle) => {
+                self.advance();
+                let center = self.parse_point()?;
+                if !self.match_token(&[Token::Comma]) {
+                    return Err(self.error("Expected ',' and radius in CIRCLE"));
+                }
+                let radius = self.parse_expression()?;
+                let color = self.parse_optional_argument()?;
+                Statement::Circle { center, radius, color }
+            },
+            Some(Token::Paint) => {
+                self.advance();
+                let point = self.parse_point()?;
+                let (color, border) = if self.match_token(&[Token::Comma]) {
+                    self.parse_optional_pair()?
+                } else {
+                    (None, None)
+                };
+                Statement::Paint { point, color, border }
+            },
+            Some(Token::Sleep) => {
+                self.advance();
+                let seconds = match self.peek() {
+                    Some(Token::EOL) | Some(Token::Else) | None => None,
+                    _ => Some(self.parse_expression()?),
+                };
+                Statement::Sleep { seconds }
+            },
+            Some(Token::Beep) => {
+                self.advance();
+                Statement::Beep
+            },
+            Some(Token::Sound) => {
+                self.advance();
+                let frequency = self.parse_expression()?;
+                if !self.match_token(&[Token::Comma]) {
+                    return Err(self.error("Expected ',' in SOUND"));
+                }
+                Statement::Sound {
+                    frequency,
+                    duration: self.parse_expression()?,
+                }
+            },
+            Some(Token::Play) => {
+                self.advance();
+                Statement::Play {
+                    music: self.parse_expression()?,
+                }
+            },
+            Some(Token::Draw) => {
+                self.advance();
+                Statement::Draw {
+                    commands: self.parse_expression()?,
+                }
+            },
+            Some(Token::Sprite) => {
+                self.advance();
+                self.parse_sprite()?
+            },
+            Some(Token::StoreSet) => {
+                self.advance();
+                let key = self.parse_expression()?;
+                if !self.match_token(&[Token::Comma]) {
+                    return Err(self.error("Expected ',' after key in STORESET"));
+                }
+                Statement::StoreSet {
+                    key,
+                    value: self.parse_expression()?,
+                }
+            },
+            Some(Token::ClipSet) => {
+                self.advance();
+                Statement::ClipSet {
+                    text: self.parse_expression()?,
+                }
+            },
+            Some(Token::Option) => {
+                self.advance();
+                match self.advance() {
+                    Some(Token::Identifier(name)) if name == "EXPLICIT" => Statement::OptionExplicit,
+                    _ => return Err(self.error_at_previous("Expected EXPLICIT after OPTION")),
+                }
+            },
+            Some(Token::SaveImage) => {
+                self.advance();
+                Statement::SaveImage {
+                    path: self.parse_expression()?,
+                }
+            },
+            Some(Token::Kill) => {
+                self.advance();
+                Statement::Kill {
+                    path: self.parse_expression()?,
+                }
+            },
+            Some(Token::Chain) => {
+                self.advance();
+                Statement::Chain {
+                    path: self.parse_expression()?,
+                }
+            },
+            Some(Token::Common) => {
+                self.advance();
+                let mut variables = Vec::new();
+                loop {
+                    match self.advance() {
+                        Some(Token::Identifier(name)) => variables.push(name.clone()),
+                        _ => return Err(self.error_at_previous("Expected variable name in COMMON")),
+                    }
+                    if !self.match_token(&[Token::Comma]) {
+                        break;
+                    }
+                }
+                Statement::Common { variables }
+            },
+            Some(Token::Name) => {
+                self.advance();
+                let from = self.parse_expression()?;
+                if !self.match_token(&[Token::As]) {
+                    return Err(self.error("Expected AS in NAME"));
+                }
+                Statement::Name {
+                    from,
+                    to: self.parse_expression()?,
+                }
+            },
+            Some(Token::Files) => {
+                self.advance();
+                let pattern = match self.peek() {
+                    Some(Token::EOL) | Some(Token::Else) | None => None,
+                    _ => Some(self.parse_expression()?),
+                };
+                Statement::Files { pattern }
+            },
+            Some(Token::Identifier(name)) => {
+                self.advance();
+                let position = self.previous_position();
+                // Check for function call
+                if let Some(Token::LParen) = self.peek() {
+                    self.advance(); // consume (
+                    let mut args = Vec::new();
+                    loop {
+                        if let Some(Token::RParen) = self.peek() {
+                            self.advance();
+                            break;
+                        }
+                        args.push(self.parse_expression()?);
+                        if let Some(Token::Comma) = self.peek() {
+                            self.advance();
+                        } else if let Some(Token::RParen) = self.peek() {
+                            self.advance();
+                            break;
+                        } else {
+                            return Err(self.error("Expected ',' or ')' in function call"));
+                        }
+                    }
+                    Statement::Let {
+                        variable: name.clone(),
+                        cache: VarCache::default(),
+                        expression: Expression::FunctionCall {
+                            name,
+                            arguments: args,
+                            position,
+                        },
+                    }
+                } else if let Some(Token::Equals) = self.peek() {
+                    self.advance();
+                    Statement::Let {
+                        variable: name,
+                        cache: VarCache::default(),
+                        expression: self.parse_expression()?,
+                    }
+                } else {
+                    return Err(self.error("Expected = after variable name; a line that starts with a name is an assignment like `X = 5`, so check the keyword's spelling"))
+                }
+            },
+            Some(token) => return Err(self.error(format!("Unexpected token in statement: {:?}", token))),
+            None => return Err(self.error("Unexpected end of input")),
+        })
+    }
+
+    fn parse_let(&mut self) -> Result<Statement, LangError> {
+        let var_name = match self.advance() {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => return Err(self.error_at_previous("Expected variable name after LET")),
+        };
+
+        if !self.match_token(&[Token::Equals]) {
+            return Err(self.error("Expected '=' after variable name in LET"));
+        }
+
+        let expr = self.parse_expression()?;
+        Ok(Statement::Let {
+            variable: var_name,
+            cache: VarCache::default(),
+            expression: expr,
+        })
+    }
+
+    fn parse_print(&mut self) -> Result<Statement, LangError> {
+        let mut expressions = Vec::new();
+        let mut semicolon = false;
+
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Semicolon => {
+                    semicolon = true;
+                    self.advance();
+                    break;
+                }
+                Token::EOL | Token::Else => break,
+                _ => {
+                    expressions.push(self.parse_expression()?);
+                    if let Some(Token::Comma) = self.peek() {
+                        self.advance();
+                    }
+                }
+            }
+        }
+
+        Ok(Statement::Print {
+            expressions,
+            semicolon,
+        })
+    }
+
+    fn parse_expression(&mut self) -> Result<Expression, LangError> {
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expression, LangError> {
+        let mut expr = self.parse_additive()?;
+        
+        while let Some(token) = self.peek() {
+            match token {
+                Token::LessThan | Token::GreaterThan | Token::Equals | 
+                Token::LessOrEqual | Token::GreaterOrEqual | Token::NotEqual => {
+                    let operator = self.advance().unwrap().clone();
+                    let position = self.previous_position();
+                    let right = self.parse_additive()?;
+                    expr = Expression::Binary {
+                        left: Box::new(expr),
+                        operator,
+                        right: Box::new(right),
+                        position,
+                    };
+                }
+                _ => break,
+            }
+        }
+        
+        Ok(expr)
+    }
+
+    fn parse_additive(&mut self) -> Result<Expression, LangError> {
+        let mut expr = self.parse_multiplicative()?;
+
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Plus | Token::Minus => {
+                    let op = self.advance().unwrap().clone();
+                    let position = self.previous_position();
+                    let right = self.parse_multiplicative()?;
+                    expr = Expression::Binary {
+                        left: Box::new(expr),
+                        operator: op,
+                        right: Box::new(right),
+                        position,
+                    };
+                }
+                _ => break,
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expression, LangError> {
+        let mut expr = self.parse_power()?;
+
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Multiply | Token::Divide => {
+                    let op = self.advance().unwrap().clone();
+                    let position = self.previous_position();
+                    let right = self.parse_power()?;
+                    expr = Expression::Binary {
+                        left: Box::new(expr),
+                        operator: op,
+                        right: Box::new(right),
+                        position,
+                    };
+                }
+                _ => break,
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_power(&mut self) -> Result<Expression, LangError> {
+        let mut expr = self.parse_primary()?;
+
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Power => {
+                    let operator = self.advance().unwrap().clone();
+                    let position = self.previous_position();
+                    let right = self.parse_primary()?;
+                    expr = Expression::Binary {
+                        left: Box::new(expr),
+                        operator,
+                        right: Box::new(right),
+                        position,
+                    };
+                }
+                _ => break,
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, LangError> {
+        Ok(match self.advance().cloned() {
+            Some(Token::Number(n)) => Expression::Number(n),
+            Some(Token::String(s)) => Expression::String(s),
+            Some(Token::Identifier(name)) => {
+                let position = self.previous_position();
+                // Check for function call
+                if let Some(Token::LParen) = self.peek() {
+                    self.advance(); // consume (
+                    let mut args = Vec::new();
+                    loop {
+                        if let Some(Token::RParen) = self.peek() {
+                            self.advance();
+                            break;
+                        }
+                        args.push(self.parse_expression()?);
+                        if let Some(Token::Comma) = self.peek() {
+                            self.advance();
+                        } else if let Some(Token::RParen) = self.peek() {
+                            self.advance();
+                            break;
+                        } else {
+                            return Err(self.error("Expected ',' or ')' in function call"));
+                        }
+                    }
+                    Expression::FunctionCall {
+                        name,
+                        arguments: args,
+                        position,
+                    }
+                } else if matches!(name.as_str(), "COMMAND$" | "RC" | "ARGC") {
+                    // COMMAND$ on its own is the whole argument list; RC and ARGC never take arguments
+                    Expression::FunctionCall {
+                        name,
+                        arguments: Vec::new(),
+                        position,
+                    }
+                } else {
+                    Expression::Variable(name, VarCache::default(), position)
+                }
+            },
+            Some(Token::LParen) => {
+                let expr = self.parse_expression()?;
+                if !self.match_token(&[Token::RParen]) {
+                    return Err(self.error("Expected closing parenthesis"));
+                }
+                expr
+            },
+            // Unary minus takes in a power, so -2^2 is -4 as in GW-BASIC. A negative number
+            // stays a number and anything else becomes 0 - x.
+            Some(Token::Minus) => {
+                let position = self.previous_position();
+                match self.parse_power()? {
+                    Expression::Number(n) => Expression::Number(-n),
+                    operand => Expression::Binary {
+                        left: Box::new(Expression::Number(0.0)),
+                        operator: Token::Minus,
+                        right: Box::new(operand),
+                        position,
+                    },
+                }
+            },
+            Some(token) => return Err(self.error_at_previous(format!("Unexpected token in expression: {:?}", token))),
+            None => return Err(self.error("Unexpected end of input")),
+        })
+    }
+
+    fn parse_if(&mut self) -> Result<Statement, LangError> {
+        let condition = self.parse_expression()?;
+        
+        if !self.match_token(&[Token::Then]) {
+            return Err(self.error("Expected THEN after IF condition"));
+        }
+
+        let then_stmt = Box::new(self.parse_branch()?);
+        let else_stmt = if self.match_token(&[Token::Else]) {
+            Some(Box::new(self.parse_branch()?))
+        } else {
+            None
+        };
+
+        Ok(Statement::If {
+            condition,
+            then_branch: then_stmt,
+            else_branch: else_stmt,
+        })
+    }
+
+    // SPRITE LOAD n, row$, row$...  SPRITE MOVE n, row, col  SPRITE HIDE n
+    fn parse_sprite(&mut self) -> Result<Statement, LangError> {
+        let command = match self.advance() {
+            Some(Token::Identifier(command)) => command.clone(),
+            _ => return Err(self.error_at_previous("Expected LOAD, MOVE or HIDE after SPRITE")),
+        };
+        let number = self.parse_expression()?;
+        let mut arguments = Vec::new();
+        while self.match_token(&[Token::Comma]) {
+            arguments.push(self.parse_expression()?);
+        }
+        Ok(match (command.as_str(), arguments.len()) {
+            ("LOAD", 1..) => Statement::SpriteLoad { number, rows: arguments },
+            ("LOAD", _) => return Err(self.error("Expected at least one row of the shape in SPRITE LOAD")),
+            ("MOVE", 2) => {
+                let col = arguments.pop().unwrap();
+                let row = arguments.pop().unwrap();
+                Statement::SpriteMove { number, row, col }
+            },
+            ("MOVE", _) => return Err(self.error("Expected row and column in SPRITE MOVE")),
+            ("HIDE", 0) => Statement::SpriteHide { number },
+            ("HIDE", _) => return Err(self.error("SPRITE HIDE takes only a sprite number")),
+            _ => return Err(self.error("Expected LOAD, MOVE or HIDE after SPRITE")),
+        })
+    }
+
+    // DEFINT A-F, X: single letters or ranges of them
+    fn parse_def_type(&mut self, var_type: VarType) -> Result<Statement, LangError> {
+        let mut letters = Vec::new();
+        loop {
+            let first = self.parse_letter()?;
+            let last = if self.match_token(&[Token::Minus]) {
+                self.parse_letter()?
+            } else {
+                first
+            };
+            if last < first {
+                return Err(self.error_at_previous(format!("Letter range {}-{} is backwards", first, last)));
+            }
+            letters.push((first, last));
+            if !self.match_token(&[Token::Comma]) {
+                break;
+            }
+        }
+        Ok(Statement::DefType { var_type, letters })
+    }
+
+    fn parse_letter(&mut self) -> Result<char, LangError> {
+        match self.advance() {
+            Some(Token::Identifier(name)) if name.len() == 1 && name.as_bytes()[0].is_ascii_alphabetic() => {
+                Ok(name.as_bytes()[0] as char)
+            },
+            _ => Err(self.error_at_previous("Expected a letter in DEF type statement")),
+        }
+    }
+
+    fn parse_def(&mut self) -> Result<Statement, LangError> {
+        // Both DEF FNA(X) and DEF FN A(X) are accepted
+        let name = match self.advance().cloned() {
+            Some(Token::Identifier(name)) if name == "FN" => match self.advance() {
+                Some(Token::Identifier(rest)) => format!("FN{}", rest),
+                _ => return Err(self.error_at_previous("Expected function name after DEF FN")),
+            },
+            Some(Token::Identifier(name)) if name.starts_with("FN") => name,
+            _ => return Err(self.error_at_previous("Expected FN name after DEF")),
+        };
+
+        let mut parameters = Vec::new();
+        if self.match_token(&[Token::LParen]) {
+            loop {
+                match self.advance() {
+                    Some(Token::Identifier(param)) => parameters.push(param.clone()),
+                    _ => return Err(self.error_at_previous("Expected parameter name in DEF FN")),
+                }
+                if self.match_token(&[Token::RParen]) {
+                    break;
+                }
+                if !self.match_token(&[Token::Comma]) {
+                    return Err(self.error("Expected ',' or ')' in DEF FN parameters"));
+                }
+            }
+        }
+
+        if !self.match_token(&[Token::Equals]) {
+            return Err(self.error("Expected '=' in DEF FN"));
+        }
+
+        Ok(Statement::DefFn {
+            name,
+            parameters,
+            body: self.parse_expression()?,
+        })
+    }
+
+    // Arguments like LOCATE [row][, col] where either side may be left out
+    fn parse_optional_pair(&mut self) -> Result<(Option<Expression>, Option<Expression>), LangError> {
+        let at_end = |token: Option<&Token>| matches!(token, Some(Token::EOL) | Some(Token::Else) | None);
+        let first = if at_end(self.peek()) || self.peek() == Some(&Token::Comma) {
+            None
+        } else {
+            Some(self.parse_expression()?)
+        };
+        let second = if self.match_token(&[Token::Comma]) && !at_end(self.peek()) {
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        Ok((first, second))
+    }
+
+    // Graphics coordinates are written (x, y)
+    fn parse_point(&mut self) -> Result<Point, LangError> {
+        if !self.match_token(&[Token::LParen]) {
+            return Err(self.error("Expected '(' before point"));
+        }
+        let x = self.parse_expression()?;
+        if !self.match_token(&[Token::Comma]) {
+            return Err(self.error("Expected ',' in point"));
+        }
+        let y = self.parse_expression()?;
+        if !self.match_token(&[Token::RParen]) {
+            return Err(self.error("Expected ')' after point"));
+        }
+        Ok((x, y))
+    }
+
+    fn parse_optional_argument(&mut self) -> Result<Option<Expression>, LangError> {
+        if self.match_token(&[Token::Comma]) {
+            Ok(Some(self.parse_expression()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // LINE [(x1, y1)]-(x2, y2)[, [color][, B|BF]]; without a start point the line begins at the last point drawn
+    fn parse_line(&mut self) -> Result<Statement, LangError> {
+        let from = if self.peek() == Some(&Token::LParen) {
+            Some(self.parse_point()?)
+        } else {
+            None
+        };
+        if !self.match_token(&[Token::Minus]) {
+            return Err(self.error("Expected '-' between points in LINE"));
+        }
+        let to = self.parse_point()?;
+
+        let mut color = None;
+        let mut shape = graphics::LineShape::Line;
+        if self.match_token(&[Token::Comma]) {
+            if !matches!(self.peek(), Some(Token::Comma) | Some(Token::EOL) | Some(Token::Else) | None) {
+                color = Some(self.parse_expression()?);
+            }
+            if self.match_token(&[Token::Comma]) {
+                shape = match self.advance() {
+                    Some(Token::Identifier(style)) if style == "B" => graphics::LineShape::Box,
+                    Some(Token::Identifier(style)) if style == "BF" => graphics::LineShape::FilledBox,
+                    _ => return Err(self.error_at_previous("Expected B or BF in LINE")),
+                };
+            }
+        }
+        Ok(Statement::DrawLine { from, to, color, shape })
+    }
+
+    fn parse_file_number(&mut self) -> Result<Expression, LangError> {
+        self.match_token(&[Token::Hash]);
+        self.parse_expression()
+    }
+
+    fn parse_open(&mut self) -> Result<Statement, LangError> {
+        let filename = self.parse_expression()?;
+
+        if !self.match_token(&[Token::For]) {
+            return Err(self.error("Expected FOR after file name in OPEN"));
+        }
+        let mode = match self.advance() {
+            Some(Token::Identifier(mode)) if mode == "RANDOM" => FileMode::Random,
+            _ => return Err(self.error_at_previous("Expected RANDOM file mode in OPEN")),
+        };
+
+        if !self.match_token(&[Token::As]) {
+            return Err(self.error("Expected AS in OPEN"));
+        }
+        let file_number = self.parse_file_number()?;
+
+        let record_length = match self.peek() {
+            Some(Token::Identifier(name)) if name == "LEN" => {
+                self.advance();
+                if !self.match_token(&[Token::Equals]) {
+                    return Err(self.error("Expected '=' after LEN in OPEN"));
+                }
+                Some(self.parse_expression()?)
+            },
+            _ => None,
+        };
+
+        Ok(Statement::Open {
+            filename,
+            mode,
+            file_number,
+            record_length,
+        })
+    }
+
+    fn parse_field(&mut self) -> Result<Statement, LangError> {
+        let file_number = self.parse_file_number()?;
+        let mut fields = Vec::new();
+        while self.match_token(&[Token::Comma]) {
+            let width = self.parse_expression()?;
+            if !self.match_token(&[Token::As]) {
+                return Err(self.error("Expected AS in FIELD"));
+            }
+            match self.advance() {
+                Some(Token::Identifier(name)) if name.ends_with('$') => fields.push((width, name.clone())),
+                _ => return Err(self.error_at_previous("Expected string variable in FIELD")),
+            }
+        }
+        Ok(Statement::Field { file_number, fields })
+    }
+
+    // THEN and ELSE accept a bare line number as shorthand for GOTO
+    fn parse_branch(&mut self) -> Result<Statement, LangError> {
+        if let Some(&Token::Number(n)) = self.peek() {
+            self.advance();
+            Ok(Statement::Goto(n as u32))
+        } else {
+            self.parse_statement()
+        }
+    }
+
+    fn parse_for(&mut self) -> Result<Statement, LangError> {
+        let var_name = match self.advance() {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => return Err(self.error_at_previous("Expected variable name after FOR")),
+        };
+
+        if !self.match_token(&[Token::Equals]) {
+            return Err(self.error("Expected '=' after variable name in FOR statement"));
+        }
+
+        let start = self.parse_expression()?;
+
+        if !self.match_token(&[Token::To]) {
+            return Err(self.error("Expected TO in FOR statement"));
+        }
+
+        let end = self.parse_expression()?;
+
+        let step = if self.match_token(&[Token::Step]) {
+            self.parse_expression()?
+        } else {
+            Expression::Number(1.0)
+        };
+
+        Ok(Statement::For {
+            loop_data: ForLoop {
+                variable: var_name,
+                cache: VarCache::default(),
+                start,
+                end,
+                step,
+            },
+        })
+    }
+}
+
+// The DEF FN for `name` in a statement, which may sit in an IF branch
+fn find_def_fn(statement: &Statement, name: &str) -> Option<(Vec<String>, Expression)> {
+    match statement {
+        Statement::DefFn { name: defined, parameters, body } if defined == name => Some((parameters.clone(), body.clone())),
+        Statement::If { then_branch, else_branch, .. } => find_def_fn(then_branch, name)
+            .or_else(|| else_branch.as_deref().and_then(|branch| find_def_fn(branch, name))),
+        _ => None,
+    }
+}
+
+struct UserFunction {
+    parameters: Vec<String>,
+    body: Expression,
+    // The line whose DEF FN made it, for checkpoints
+    line: usize,
+}
+
+#[derive(Default)]
+struct FunctionStats {
+    calls: usize,
+    hits: usize,
+}
+
+#[derive(Default, Clone)]
+struct LineStats {
+    runs: usize,
+    time: std::time::Duration,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum ArgKey {
+    Number(u64),
+    String(String),
+}
+
+impl ArgKey {
+    fn bytes(&self) -> usize {
+        match self {
+            ArgKey::String(s) => s.len(),
+            ArgKey::Number(_) => 8,
+        }
+    }
+}
+
+const MAX_FN_DEPTH: usize = 100;
+// The most the memoize cache holds, or max_memory if that is less; it starts again empty when full
+const MAX_FN_CACHE_BYTES: usize = 16 * 1024 * 1024;
+// How long RUN$ lets a command run when no timeout is given, and the longest it can be given
+const RUN_TIMEOUT_SECONDS: f64 = 30.0;
+const MAX_RUN_TIMEOUT_SECONDS: f64 = 86_400.0;
+
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+// Variables live in stable slots; a generation number identifies this store so
+// cached slots from another interpreter are never trusted. Loading a program gives each of its
+// variables a slot up front, empty until the program assigns it.
+struct Variables {
+    slots: Vec<Option<Value>>,
+    index: HashMap<String, usize>,
+    generation: u64,
+    // What the string variables hold between them, for max_memory
+    string_bytes: usize,
+}
+
+impl Variables {
+    fn new() -> Self {
+        Variables {
+            slots: Vec::new(),
+            index: HashMap::new(),
+            generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
+            string_bytes: 0,
+        }
+    }
+
+    fn slot(&mut self, name: &str) -> usize {
+        if let Some(&slot) = self.index.get(name) {
+            return slot;
+        }
+        self.index.insert(name.to_string(), self.slots.len());
+        self.slots.push(None);
+        self.slots.len() - 1
+    }
+
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.index.get(name).and_then(|&slot| self.slots[slot].as_ref())
+    }
+
+    fn get_cached(&self, name: &str, cache: &VarCache) -> Option<&Value> {
+        if let Some((generation, slot)) = cache.get() {
+            if generation == self.generation {
+                return self.slots[slot].as_ref();
+            }
+        }
+        let slot = *self.index.get(name)?;
+        cache.set(self.generation, slot);
+        self.slots[slot].as_ref()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.index.iter().filter_map(|(name, &slot)| Some((name, self.slots[slot].as_ref()?)))
+    }
+
+    fn insert(&mut self, name: &str, value: Value) {
+        let slot = self.slot(name);
+        self.store(slot, value);
+    }
+
+    fn insert_cached(&mut self, name: &str, cache: &VarCache, value: Value) {
+        let slot = match cache.get() {
+            Some((generation, slot)) if generation == self.generation => slot,
+            _ => {
+                let slot = self.slot(name);
+                cache.set(self.generation, slot);
+                slot
+            },
+        };
+        self.store(slot, value);
+    }
+
+    // X = X + value where X is stored, for --vm-opt; false when X and value aren't two numbers
+    // or two strings
+    fn add_cached(&mut self, name: &str, cache: &VarCache, value: Value) -> bool {
+        let slot = match cache.get() {
+            Some((generation, slot)) if generation == self.generation => slot,
+            _ => match self.index.get(name) {
+                Some(&slot) => slot,
+                None => return false,
+            },
+        };
+        match (&mut self.slots[slot], value) {
+            (Some(Value::Number(n)), Value::Number(value)) => *n += value,
+            (Some(Value::String(text)), Value::String(value)) => {
+                self.string_bytes += value.len();
+                text.push_str(&value);
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    fn store(&mut self, slot: usize, value: Value) {
+        if let Value::String(text) = &value {
+            self.string_bytes += text.len();
+        }
+        if let Some(Value::String(old)) = self.slots[slot].replace(value) {
+            self.string_bytes -= old.len();
+        }
+    }
+}
+
+struct MathDiagnostic {
+    line: u32,
+    expression: String,
+    problem: &'static str,
+    count: usize,
+}
+
+// A Rust function BASIC can call, added with Interpreter::register_fn
+type HostFunction = Box<dyn Fn(&[Value]) -> Result<Value, String> + Send>;
+
+pub struct Interpreter {
+    variables: Variables,
+    files: HashMap<u32, files::RandomFile>,
+    hardware: hardware::Hardware,
+    clipboard: clipboard::Clipboard,
+    // RC: the exit status of the last RUN$
+    exit_code: Cell<i32>,
+    graphics: Option<graphics::Framebuffer>,
+    pub show_window: bool,
+    window: Option<window::Window>,
+    pub audio: Box<dyn audio::Audio>,
+    music: mml::Music,
+    pen: draw::Pen,
+    sprites: sprites::Sprites,
+    scheduler: tasks::Scheduler,
+    blocked: bool,
+    pub sandbox: bool,
+    // Variable types by first letter, from DEFINT and friends
+    var_types: [VarType; 26],
+    // The program file followed by its arguments, for COMMAND$
+    pub arguments: Vec<String>,
+    functions: HashMap<String, UserFunction>,
+    host_functions: HashMap<String, HostFunction>,
+    function_purity: HashMap<String, analyzer::Purity>,
+    locals: RefCell<Vec<HashMap<String, Value>>>,
+    pub memoize: bool,
+    fn_cache: RefCell<HashMap<(String, Vec<ArgKey>), Value>>,
+    fn_cache_bytes: Cell<usize>,
+    pub profile: bool,
+    fn_stats: RefCell<HashMap<String, FunctionStats>>,
+    // With --profile or --coverage, how often each line ran, by index into the program's lines.
+    // --profile adds how long they took and the time spent under each set of open FOR loops for
+    // the flamegraph.
+    pub coverage: bool,
+    line_stats: Vec<LineStats>,
+    stack_times: HashMap<Vec<usize>, std::time::Duration>,
+    loops: Vec<ForLoop>,
+    loop_stack: Vec<usize>,
+    current_line: usize,
+    next_line: Option<usize>,
+    running: bool,
+    program: Arc<Program>,
+    // Where each line number is in the program, so GOTO doesn't search the lines
+    line_index: HashMap<u32, usize>,
+    // --vm-opt: the quicker forms vm_opt.rs plans for some lines, by line index, used in their place
+    pub vm_opt: bool,
+    fast: Arc<Vec<Option<vm_opt::Fast>>>,
+    pub max_steps: Option<usize>,
+    // How long a run may take, and how many bytes its string variables may hold between them
+    pub timeout: Option<std::time::Duration>,
+    pub max_memory: Option<usize>,
+    started: Instant,
+    steps: usize,
+    // Where RND's numbers come from, the same ones every run after `seed`
+    rng: RefCell<StdRng>,
+    // Under run_async, where SLEEP leaves its time for the runner to await instead of blocking
+    #[cfg(feature = "async")]
+    async_sleep: Option<Option<std::time::Duration>>,
+    // Where the expression behind the current statement's error is
+    error_position: Cell<Option<Position>>,
+    // Line indexes of the last few statements run, for crash reports
+    recent_lines: VecDeque<usize>,
+    pub io: Box<dyn io::IoBackend>,
+    // Where CHECKPOINT and a checkpoint signal write the snapshot
+    pub checkpoint_file: String,
+    // Why the last run stopped
+    exit: output::ExitReason,
+    // The status given to END, for the process to exit with
+    end_status: i32,
+    // Lines for INPUT when the program is run with step rather than reading from io, which never
+    // blocks: without one, step says it needs input
+    queued_input: Option<VecDeque<String>>,
+    // The INPUT that asked for a line has shown its prompt and is waiting for provide_input
+    awaiting_input: bool,
+    // --jit: compile numeric programs to machine code instead of interpreting them
+    pub jit: bool,
+    // Why the last run with `jit` set was interpreted anyway
+    jit_fallback: Option<String>,
+    math_diagnostics: Option<RefCell<Vec<MathDiagnostic>>>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter {
+            variables: Variables::new(),
+            files: HashMap::new(),
+            hardware: hardware::Hardware::new(),
+            clipboard: clipboard::Clipboard::new(),
+            exit_code: Cell::new(0),
+            graphics: None,
+            show_window: false,
+            window: None,
+            audio: Box::new(audio::Bell),
+            music: mml::Music::new(),
+            pen: draw::Pen::new(),
+            sprites: sprites::Sprites::default(),
+            scheduler: tasks::Scheduler::default(),
+            blocked: false,
+            sandbox: false,
+            var_types: [VarType::Double; 26],
+            arguments: vec!["code.bs".to_string()],
+            functions: HashMap::new(),
+            host_functions: HashMap::new(),
+            function_purity: HashMap::new(),
+            locals: RefCell::new(Vec::new()),
+            memoize: false,
+            fn_cache: RefCell::new(HashMap::new()),
+            fn_cache_bytes: Cell::new(0),
+            profile: false,
+            fn_stats: RefCell::new(HashMap::new()),
+            coverage: false,
+            line_stats: Vec::new(),
+            stack_times: HashMap::new(),
+            loops: Vec::new(),
+            loop_stack: Vec::new(),
+            current_line: 0,
+            next_line: None,
+            running: true,
+            program: Arc::new(Program::new()),
+            line_index: HashMap::new(),
+            vm_opt: false,
+            fast: Arc::new(Vec::new()),
+            max_steps: None,
+            timeout: None,
+            max_memory: None,
+            started: Instant::now(),
+            rng: RefCell::new(StdRng::from_entropy()),
+            #[cfg(feature = "async")]
+            async_sleep: None,
+            steps: 0,
+            error_position: Cell::new(None),
+            recent_lines: VecDeque::new(),
+            io: Box::new(io::StdIo),
+            checkpoint_file: checkpoint::DEFAULT_FILE.to_string(),
+            exit: output::ExitReason::Finished,
+            end_status: 0,
+            queued_input: None,
+            awaiting_input: false,
+            jit: false,
+            jit_fallback: None,
+            math_diagnostics: None,
+        }
+    }
+
+    pub fn run(&mut self, program: &Program) -> Result<(), LangError> {
+        if !self.jit {
+            return self.execute_program(program.clone());
+        }
+        self.load_program(program.clone());
+        self.exit = output::ExitReason::Finished;
+        self.end_status = 0;
+        self.jit_fallback = None;
+        match jit::run(self)? {
+            jit::Outcome::Done => self.wind_up(),
+            jit::Outcome::Continue(line) => self.execute_from(line),
+            jit::Outcome::Unsupported(reason) => {
+                self.jit_fallback = Some(reason);
+                self.execute_from(0)
+            },
+        }
+    }
+
+    // --seed: RND gives the same numbers on every run with the same seed
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = RefCell::new(StdRng::seed_from_u64(seed));
+    }
+
+    // Why the last run was interpreted even though `jit` was set, like "line 30 calls RND"
+    pub fn jit_fallback(&self) -> Option<&str> {
+        self.jit_fallback.as_deref()
+    }
+
+    // --checked-math: collect NaN, overflow and precision problems for print_math_report
+    pub fn check_math_problems(&mut self) {
+        self.math_diagnostics = Some(RefCell::new(Vec::new()));
+    }
+
+    // Carries on a run saved by CHECKPOINT or a checkpoint signal
+    pub fn resume(&mut self, program: &Program, path: &str) -> Result<(), LangError> {
+        self.load_program(program.clone());
+        let snapshot = checkpoint::Snapshot::load(path).map_err(|e| self.runtime_error(e))?;
+        self.restore(snapshot).map_err(|e| self.runtime_error(e))?;
+        self.execute_from(self.current_line)
+    }
+
+    // Gets the program ready for step, at its first line, instead of running it all at once
+    pub fn load(&mut self, program: &Program) {
+        self.load_program(program.clone());
+        self.start_at(0);
+        self.queued_input = Some(VecDeque::new());
+    }
+
+    // Runs one statement of the program given to load. INPUT never waits for stdin: when it has
+    // no line, step returns NeedsInput without running it, and runs it once provide_input has
+    // given it one.
+    pub fn step(&mut self) -> StepResult {
+        if !self.running {
+            return StepResult::Finished;
+        }
+        let steps = self.steps;
+        match self.advance() {
+            Ok(true) => StepResult::Ran,
+            Ok(false) => StepResult::Finished,
+            Err(_) if self.awaiting_input => {
+                // The statement didn't happen, so it doesn't count
+                self.steps = steps;
+                self.recent_lines.pop_back();
+                StepResult::NeedsInput
+            },
+            Err(e) => {
+                self.running = false;
+                StepResult::Error(e)
+            },
+        }
+    }
+
+    // A line for INPUT to read, without its newline, when step has said it needs one
+    pub fn provide_input(&mut self, line: &str) {
+        self.queued_input.get_or_insert_with(VecDeque::new).push_back(format!("{}\n", line));
+    }
+
+    // The number of the line step runs next, or None once it is past the last one
+    pub fn line_number(&self) -> Option<u32> {
+        self.program.lines.get(self.current_line).map(|line| line.number)
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<Value> {
+        self.variables.get(&name.to_uppercase()).cloned()
+    }
+
+    // Gives a variable a value, before a run or between steps, for the program to read. The
+    // value has to suit the name the way LET checks: a string for A$ and a number otherwise,
+    // rounded for an integer variable.
+    pub fn set_var(&mut self, name: &str, value: impl Into<Value>) -> Result<(), String> {
+        self.assign(&name.to_uppercase(), value.into()).map_err(|e| e.message)
+    }
+
+    // Makes NAME(...) in BASIC call `function` with the values of its arguments. The name is
+    // uppercased like every BASIC name, one ending in $ has to return a string, and an Err
+    // stops the program with that message as a runtime error on the calling line. A
+    // registered name is used in place of a built-in function of the same name.
+    pub fn register_fn<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + Send + 'static,
+    {
+        self.host_functions.insert(name.to_uppercase(), Box::new(function));
+    }
+
+    // Every variable that has a value, by name
+    pub fn variables(&self) -> Vec<(String, Value)> {
+        let mut variables: Vec<(String, Value)> = self.variables.iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        variables
+    }
+
+    // The FOR loops that are open, outermost first: the number of each one's line and its variable
+    pub fn for_loops(&self) -> Vec<(u32, String)> {
+        self.loop_stack.iter().zip(&self.loops)
+            .map(|(&index, loop_data)| (self.program.lines[index].number, loop_data.variable.clone()))
+            .collect()
+    }
+
+    // What `END code` gave the last run, or 0
+    pub fn end_status(&self) -> i32 {
+        self.end_status
+    }
+
+    // True when the last run stopped to write a checkpoint rather than finishing
+    pub fn checkpointed(&self) -> bool {
+        self.exit == output::ExitReason::Checkpoint
+    }
+
+    // Runs with `input` as the lines INPUT reads and returns what happened instead of printing it
+    pub fn run_captured(&mut self, program: &Program, input: Vec<String>) -> output::RunOutput {
+        let capture = output::CaptureIo::new(input);
+        let segments = capture.segments();
+        let previous = std::mem::replace(&mut self.io, Box::new(capture));
+        let start = Instant::now();
+        let result = self.run(program);
+        let elapsed = start.elapsed();
+        self.io = previous;
+        let exit = match (&result, self.exit) {
+            (Err(_), output::ExitReason::Finished | output::ExitReason::End) => output::ExitReason::Error,
+            (_, exit) => exit,
+        };
+        let variables = self.variables().into_iter().collect();
+        let segments = std::mem::take(&mut *segments.lock().unwrap());
+        output::RunOutput { segments, error: result.err(), exit, steps: self.steps, elapsed, variables }
+    }
+
+    fn execute_program(&mut self, program: Program) -> Result<(), LangError> {
+        self.load_program(program);
+        self.execute_from(0)
+    }
+
+    fn load_program(&mut self, program: Program) {
+        self.function_purity = analyzer::function_purity(&program);
+        self.line_index = program.line_index();
+        // Resolve every variable the program names to a slot now, rather than one by one as they're assigned
+        let mut names: Vec<String> = stats::variable_names(&program).into_iter().collect();
+        names.sort();
+        for name in names {
+            self.variables.slot(&name);
+        }
+        self.line_stats = vec![LineStats::default(); program.lines.len()];
+        self.stack_times.clear();
+        // Counting and timing lines needs every line to run as written
+        self.fast = if self.vm_opt && !self.profile && !self.coverage {
+            Arc::new(vm_opt::plan(&program, &self.line_index))
+        } else {
+            Arc::new(Vec::new())
+        };
+        self.program = Arc::new(program);
+    }
+
+    fn find_line(&self, number: u32) -> Result<usize, Error> {
+        self.line_index.get(&number).copied().ok_or_else(|| Error::new(Code::UndefinedLine, format!("Undefined line number {}", number)))
+    }
+
+    fn execute_from(&mut self, line: usize) -> Result<(), LangError> {
+        self.start_at(line);
+        while self.advance()? {}
+        Ok(())
+    }
+
+    fn start_at(&mut self, line: usize) {
+        self.current_line = line;
+        self.running = true;
+        self.exit = output::ExitReason::Finished;
+        self.end_status = 0;
+        self.started = Instant::now();
+        self.queued_input = None;
+        self.awaiting_input = false;
+    }
+
+    // Runs the statement at current_line and moves on to the next one. False once the program
+    // has stopped, by then wound up.
+    fn advance(&mut self) -> Result<bool, LangError> {
+        // Running off the end finishes the current task; the program ends with the last one
+        if self.running && self.current_line >= self.program.lines.len() && !self.finish_task() {
+            self.running = false;
+        }
+        if self.running {
+            self.before_statement().map_err(|e| self.runtime_error(e))?;
+        }
+        if self.running {
+            // The program is shared rather than borrowed from self, so the statement can be run in place
+            let program = Arc::clone(&self.program);
+            let fast = Arc::clone(&self.fast);
+            let line = self.current_line;
+            // A line that fails still ran
+            if self.profile || self.coverage {
+                self.line_stats[line].runs += 1;
+            }
+            let started = self.profile.then(Instant::now);
+            let result = match fast.get(line) {
+                Some(Some(fast)) => self.execute_fast(fast),
+                _ => self.execute_statement(&program.lines[line].statement),
+            };
+            result.map_err(|e| self.runtime_error(e))?;
+            // A CHAIN has swapped in another program, and its lines with it
+            if let (Some(started), true) = (started, Arc::ptr_eq(&program, &self.program)) {
+                self.record_time(line, started.elapsed());
+            }
+            self.after_statement().map_err(|e| self.runtime_error(e))?;
+        }
+        if !self.running {
+            self.wind_up()?;
+        }
+        Ok(self.running)
+    }
+
+    // However the program stopped: the window stays up, music finishes and the drawing is saved
+    fn wind_up(&mut self) -> Result<(), LangError> {
+        if let (Some(window), Some(screen)) = (&mut self.window, &self.graphics) {
+            window.hold(screen);
+        }
+        self.audio.finish().map_err(|e| self.runtime_error(e))?;
+
+        // Anything drawn since the last SAVEIMAGE is written out when the program ends, unless
+        // sandboxed programs can't write files
+        match &mut self.graphics {
+            Some(screen) if screen.unsaved() && !self.sandbox => screen.save_png(graphics::DEFAULT_IMAGE).map_err(|e| self.runtime_error(e)),
+            _ => Ok(()),
+        }
+    }
+
+    // Writes a snapshot that resumes at line `resume_at` and stops the program
+    fn save_checkpoint(&mut self, path: &str, resume_at: usize) -> Result<(), Error> {
+        if self.scheduler.active() {
+            return Err("Can't checkpoint while tasks are running".into());
+        }
+        if !self.files.is_empty() {
+            return Err("Can't checkpoint with files open".into());
+        }
+        let mut variables: Vec<(String, Value)> = self.variables.iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut functions: Vec<(String, usize)> = self.functions.iter()
+            .map(|(name, function)| (name.clone(), function.line))
+            .collect();
+        functions.sort();
+        let snapshot = checkpoint::Snapshot {
+            fingerprint: checkpoint::fingerprint(self.program.lines.iter().map(|line| line.number)),
+            line: resume_at,
+            steps: self.steps,
+            var_types: self.var_types,
+            variables,
+            loops: self.loop_stack.clone(),
+            functions,
+        };
+        snapshot.save(path)?;
+        self.exit = output::ExitReason::Checkpoint;
+        self.running = false;
+        Ok(())
+    }
+
+    fn restore(&mut self, snapshot: checkpoint::Snapshot) -> Result<(), Error> {
+        let fingerprint = checkpoint::fingerprint(self.program.lines.iter().map(|line| line.number));
+        if snapshot.fingerprint != fingerprint {
+            return Err("The checkpoint was saved from a different program".into());
+        }
+        for (name, value) in snapshot.variables {
+            self.variables.insert(&name, value);
+        }
+        for line in snapshot.loops {
+            match self.program.lines.get(line).map(|line| &line.statement) {
+                Some(Statement::For { loop_data }) => {
+                    self.loops.push(loop_data.clone());
+                    self.loop_stack.push(line);
+                },
+                _ => return Err(format!("The checkpoint has a FOR loop at line index {} that isn't there", line).into()),
+            }
+        }
+        for (name, line) in snapshot.functions {
+            let statement = self.program.lines.get(line).map(|line| &line.statement);
+            let (parameters, body) = statement.and_then(|statement| find_def_fn(statement, &name))
+                .ok_or_else(|| format!("The checkpoint has {} defined at line index {} where it isn't", name, line))?;
+            self.functions.insert(name, UserFunction { parameters, body, line });
+        }
+        self.var_types = snapshot.var_types;
+        self.steps = snapshot.steps;
+        self.current_line = snapshot.line;
+        Ok(())
+    }
+
+    // Fills in where the program was when a crash happened while it ran
+    pub fn add_crash_context(&self, report: &mut crash::CrashReport, source: &str) {
+        let lines: Vec<&Line> = self.recent_lines.iter().filter_map(|&index| self.program.lines.get(index)).collect();
+        report.recent = lines.iter().map(|line| crash::source_line(source, line.position.0)).collect();
+        report.line = lines.last().map(|line| line.number);
+    }
+
+    // Errors after the program ran off the end belong to its last line
+    fn runtime_error(&self, error: impl Into<Error>) -> LangError {
+        let Error { code, message } = error.into();
+        let line = self.program.lines.get(self.current_line).or(self.program.lines.last());
+        let (source_line, column) = match (self.error_position.take(), line) {
+            (Some(position), _) => position,
+            (None, Some(line)) => line.position,
+            (None, None) => (0, 1),
+        };
+        // A loop open several times over in a row is one frame
+        let mut frames: Vec<(usize, usize)> = Vec::new();
+        for &index in self.loop_stack.iter().rev() {
+            match frames.last_mut() {
+                Some((last, times)) if *last == index => *times += 1,
+                _ => frames.push((index, 1)),
+            }
+        }
+        let stack = frames.into_iter()
+            .filter_map(|(index, times)| self.program.lines.get(index).map(|line| (line.number, unparse::statement(&line.statement), times)))
+            .collect();
+        LangError::RuntimeError { line: line.map_or(0, |line| line.number), source_line, column, code, message, stack }
+    }
+
+    fn write_output(&mut self, text: &str) -> Result<(), Error> {
+        self.io.write(text)
+    }
+
+    fn read_input(&mut self) -> Result<String, Error> {
+        let Some(queue) = &mut self.queued_input else {
+            return self.io.read_line();
+        };
+        match queue.pop_front() {
+            Some(line) => {
+                self.awaiting_input = false;
+                Ok(line)
+            },
+            None => {
+                self.awaiting_input = true;
+                Err("INPUT needs a line from provide_input".into())
+            },
+        }
+    }
+
+    // Runs before every statement of the main loop: a checkpoint asked for by a signal, the
+    // step and time limits, and what a crash report or runtime error needs to know
+    fn before_statement(&mut self) -> Result<(), Error> {
+        if checkpoint::requested() {
+            let path = self.checkpoint_file.clone();
+            return self.save_checkpoint(&path, self.current_line);
+        }
+        if let Some(max_steps) = self.max_steps {
+            if self.steps >= max_steps {
+                self.exit = output::ExitReason::StepLimit;
+                return Err(Error::new(Code::Limit, format!("Step limit of {} exceeded", max_steps)));
+            }
+        }
+        if let Some(timeout) = self.timeout {
+            if self.started.elapsed() >= timeout {
+                self.exit = output::ExitReason::TimeLimit;
+                return Err(Error::new(Code::Limit, format!("Time limit of {} seconds exceeded", timeout.as_secs_f64())));
+            }
+        }
+        self.steps += 1;
+        self.error_position.set(None);
+        if self.recent_lines.len() == crash::RECENT_STATEMENTS {
+            self.recent_lines.pop_front();
+        }
+        self.recent_lines.push_back(self.current_line);
+        Ok(())
+    }
+
+    // Runs after every statement that succeeded: moving to the next line, keeping the window
+    // open and letting other tasks have a turn
+    fn after_statement(&mut self) -> Result<(), Error> {
+        self.current_line = self.next_line.take().unwrap_or(self.current_line + 1);
+
+        // Closing the window stops the program
+        if let (Some(window), Some(screen)) = (&mut self.window, &self.graphics) {
+            if !window.refresh(screen) {
+                self.exit = output::ExitReason::WindowClosed;
+                self.running = false;
+            }
+        }
+
+        if self.running && (self.scheduler.active() || self.blocked) {
+            let blocked = std::mem::take(&mut self.blocked);
+            self.switch_task(blocked)?;
+        }
+        Ok(())
+    }
+
+    // Picks the handler in statements.rs; one-line statements are done right here
+    fn execute_statement(&mut self, statement: &Statement) -> Result<(), Error> {
+        match statement {
+            Statement::Print { expressions, semicolon } => self.execute_print(expressions, *semicolon),
+            Statement::Let { variable, cache, expression } => self.execute_let(variable, cache, expression),
+            Statement::If { condition, then_branch, else_branch } => self.execute_if(condition, then_branch, else_branch.as_deref()),
+            Statement::Input { variable } => self.execute_input(variable),
+            Statement::For { loop_data } => self.execute_for(loop_data),
+            Statement::Next { variable } => self.execute_next(variable),
+            Statement::End { code } => self.execute_end(code.as_ref()),
+            Statement::Goto(target) => self.execute_goto(*target),
+            Statement::Open { filename, mode: FileMode::Random, file_number, record_length } => {
+                self.execute_open(filename, file_number, record_length.as_ref())
+            },
+            Statement::Close { file_numbers } => self.execute_close(file_numbers),
+            Statement::Field { file_number, fields } => self.execute_field(file_number, fields),
+            Statement::Lset { variable, expression } => self.justify_variable(variable, expression, false),
+            Statement::Rset { variable, expression } => self.justify_variable(variable, expression, true),
+            Statement::Get { file_number, record } => self.execute_get(file_number, record.as_ref()),
+            Statement::Put { file_number, record } => self.execute_put(file_number, record.as_ref()),
+            Statement::Kill { path } => self.execute_kill(path),
+            Statement::Name { from, to } => self.execute_name(from, to),
+            Statement::Files { pattern } => self.execute_files(pattern.as_ref()),
+            Statement::Chain { path } => self.execute_chain(path),
+            // Only read when a CHAIN leaves the program
+            Statement::Common { .. } => Ok(()),
+            Statement::DefFn { name, parameters, body } => self.execute_def_fn(name, parameters, body),
+            Statement::Checkpoint => self.execute_checkpoint(),
+            Statement::Cls => self.write_output(screen::CLEAR),
+            Statement::Locate { row, col } => self.execute_locate(row.as_ref(), col.as_ref()),
+            Statement::Color { foreground, background } => self.execute_color(foreground.as_ref(), background.as_ref()),
+            Statement::Screen { width, height } => self.execute_screen(width, height),
+            Statement::Pset { point, color } => self.execute_pset(point, color.as_ref()),
+            Statement::DrawLine { from, to, color, shape } => self.execute_draw_line(from.as_ref(), to, color.as_ref(), shape),
+            Statement::Circle { center, radius, color } => self.execute_circle(center, radius, color.as_ref()),
+            Statement::Paint { point, color, border } => self.execute_paint(point, color.as_ref(), border.as_ref()),
+            Statement::Sleep { seconds } => self.execute_sleep(seconds.as_ref()),
+            Statement::Beep => Ok(self.audio.beep(self.io.as_mut())?),
+            Statement::Sound { frequency, duration } => self.execute_sound(frequency, duration),
+            Statement::Play { music } => self.execute_play(music),
+            Statement::Draw { commands } => self.execute_draw(commands),
+            Statement::SpriteLoad { number, rows } => self.execute_sprite_load(number, rows),
+            Statement::SpriteMove { number, row, col } => self.execute_sprite_move(number, row, col),
+            Statement::SpriteHide { number } => self.execute_sprite_hide(number),
+            // Checked before the program runs
+            Statement::OptionExplicit => Ok(()),
+            Statement::ClipSet { text } => self.execute_clip_set(text),
+            Statement::StoreSet { key, value } => self.execute_store_set(key, value),
+            Statement::DefType { var_type, letters } => self.execute_def_type(*var_type, letters),
+            Statement::Task { target, id_variable } => self.execute_task(*target, id_variable.as_deref()),
+            Statement::EndTask => self.execute_end_task(),
+            Statement::Await { task } => self.execute_await(task.as_ref()),
+            Statement::Shell { command } => self.execute_shell(command),
+            Statement::OpenSerial { device, baud, file_number } => self.execute_open_serial(device, baud, file_number),
+            Statement::SerialOut { file_number, value } => self.execute_serial_out(file_number, value),
+            Statement::SerialIn { file_number, variable } => self.execute_serial_in(file_number, variable),
+            Statement::OutPin { pin, value } => self.execute_out_pin(pin, value),
+            Statement::Send { channel, value } => self.execute_send(channel, value),
+            Statement::Receive { channel, variable } => self.execute_receive(channel, variable),
+            Statement::SaveImage { path } => self.execute_save_image(path),
+            _ => Err("Statement not implemented yet".into()),
+        }
+    }
+
+    fn call_function(&self, name: &str, values: Vec<Value>) -> Result<Value, Error> {
+        let function = self.functions.get(name)
+            .ok_or_else(|| Error::new(Code::UnknownFunction, format!("Undefined function: {}", name)))?;
+        if values.len() != function.parameters.len() {
+            return Err(Error::new(Code::IllegalFunctionCall, format!("{} expects {} arguments, got {}", name, function.parameters.len(), values.len())));
+        }
+        if self.locals.borrow().len() >= MAX_FN_DEPTH {
+            return Err(Error::new(Code::TooDeep, format!("{} nested too deeply", name)));
+        }
+
+        let pure = self.function_purity.get(name) == Some(&analyzer::Purity::Pure);
+        let key = (self.memoize && pure).then(|| {
+            let args = values.iter()
+                .map(|v| match v {
+                    Value::Number(n) => ArgKey::Number(n.to_bits()),
+                    Value::String(s) => ArgKey::String(s.clone()),
+                })
+                .collect();
+            (name.to_string(), args)
+        });
+        if self.profile {
+            self.fn_stats.borrow_mut().entry(name.to_string()).or_default().calls += 1;
+        }
+        if let Some(cached) = key.as_ref().and_then(|key| self.fn_cache.borrow().get(key).cloned()) {
+            if self.profile {
+                self.fn_stats.borrow_mut().entry(name.to_string()).or_default().hits += 1;
+            }
+            return Ok(cached);
+        }
+
+        let mut frame = HashMap::new();
+        for (parameter, value) in function.parameters.iter().zip(values) {
+            if (self.var_type(parameter) == VarType::String) != matches!(value, Value::String(_)) {
+                return Err(Error::new(Code::TypeMismatch, format!("Type mismatch for {} parameter {}", name, parameter)));
+            }
+            frame.insert(parameter.clone(), value);
+        }
+        self.locals.borrow_mut().push(frame);
+        let result = self.evaluate_expression(&function.body);
+        self.locals.borrow_mut().pop();
+        let result = result?;
+
+        if let Some(key) = key {
+            self.cache_result(key, &result);
+        }
+        Ok(result)
+    }
+
+    fn cache_result(&self, key: (String, Vec<ArgKey>), result: &Value) {
+        let bytes = key.0.len() + key.1.iter().map(ArgKey::bytes).sum::<usize>() + result.bytes();
+        let limit = self.max_memory.unwrap_or(MAX_FN_CACHE_BYTES).min(MAX_FN_CACHE_BYTES);
+        if bytes > limit {
+            return;
+        }
+        let mut cache = self.fn_cache.borrow_mut();
+        if self.fn_cache_bytes.get() + bytes > limit {
+            cache.clear();
+            self.fn_cache_bytes.set(0);
+        }
+        cache.insert(key, result.clone());
+        self.fn_cache_bytes.set(self.fn_cache_bytes.get() + bytes);
+    }
+
+    fn record_time(&mut self, line: usize, time: std::time::Duration) {
+        self.line_stats[line].time += time;
+        let mut stack = self.loop_stack.clone();
+        // A FOR's own line is already on the stack once its loop is open
+        if stack.last() != Some(&line) {
+            stack.push(line);
+        }
+        *self.stack_times.entry(stack).or_default() += time;
+    }
+
+    pub fn print_profile(&self) {
+        if !self.profile {
+            return;
+        }
+        eprintln!("Profile:");
+        // Slowest line first
+        let mut lines: Vec<(usize, &LineStats)> = self.line_stats.iter().enumerate().filter(|(_, s)| s.runs > 0).collect();
+        lines.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(a.0.cmp(&b.0)));
+        let total: std::time::Duration = lines.iter().map(|(_, s)| s.time).sum();
+        for (index, s) in lines {
+            let line = &self.program.lines[index];
+            let share = if total.is_zero() { 0.0 } else { 100.0 * s.time.as_secs_f64() / total.as_secs_f64() };
+            let runs = if s.runs == 1 { "1 run".to_string() } else { format!("{} runs", s.runs) };
+            eprintln!("  line {}: {}, {:.3} ms ({:.1}%)  {}",
+                line.number, runs, s.time.as_secs_f64() * 1000.0, share, unparse::statement(&line.statement));
+        }
+        let stats = self.fn_stats.borrow();
+        let mut names: Vec<&String> = stats.keys().collect();
+        names.sort();
+        for name in names {
+            let s = &stats[name];
+            let memo = match self.function_purity.get(name) {
+                Some(analyzer::Purity::Pure) if self.memoize => {
+                    format!("{} cache hits, {} misses", s.hits, s.calls - s.hits)
+                },
+                Some(analyzer::Purity::Pure) => "pure, memoize with --memoize".to_string(),
+                Some(analyzer::Purity::Impure(reason)) => format!("not memoized: {}", reason),
+                None => "not memoized".to_string(),
+            };
+            eprintln!("  {}: {} calls ({})", name, s.calls, memo);
+        }
+    }
+
+    // The --profile times in the folded format flamegraph.pl and inferno read: one line per
+    // stack of open FOR loops, outermost first, with the nanoseconds spent there
+    pub fn folded_profile(&self) -> String {
+        let frame = |index: usize| {
+            let line = &self.program.lines[index];
+            format!("line {} {}", line.number, unparse::statement(&line.statement)).replace(';', ",")
+        };
+        let mut stacks: Vec<String> = self.stack_times.iter()
+            .map(|(stack, time)| {
+                let frames: Vec<String> = stack.iter().map(|&index| frame(index)).collect();
+                format!("{} {}", frames.join(";"), time.as_nanos())
+            })
+            .collect();
+        stacks.sort();
+        stacks.iter().map(|stack| format!("{}\n", stack)).collect()
+    }
+
+    fn assign(&mut self, variable: &str, value: Value) -> Result<(), Error> {
+        let value = self.coerce(variable, value)?;
+        self.variables.insert(variable, value);
+        self.check_memory()
+    }
+
+    // Like assign, through the slot the statement's cache resolved to
+    fn assign_cached(&mut self, variable: &str, cache: &VarCache, value: Value) -> Result<(), Error> {
+        let value = self.coerce(variable, value)?;
+        self.variables.insert_cached(variable, cache, value);
+        self.check_memory()
+    }
+
+    fn check_memory(&mut self) -> Result<(), Error> {
+        match self.max_memory {
+            Some(max_memory) if self.variables.string_bytes + self.scheduler.queued_bytes > max_memory => {
+                self.exit = output::ExitReason::MemoryLimit;
+                Err(Error::new(Code::Limit, format!("Memory limit of {} bytes exceeded by the string variables and SEND queues", max_memory)))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    // The value as the variable's type stores it, or an error when it can't hold it
+    fn coerce(&self, variable: &str, value: Value) -> Result<Value, Error> {
+        Ok(match (self.var_type(variable), value) {
+            // Every number is a double here, so DEFSNG only documents intent
+            (VarType::String, value @ Value::String(_))
+            | (VarType::Single | VarType::Double, value @ Value::Number(_)) => value,
+            (VarType::Integer, Value::Number(n)) => {
+                let n = n.round();
+                if !(-32768.0..=32767.0).contains(&n) {
+                    return Err(Error::new(Code::Overflow, format!("Overflow assigning {} to integer {}", n, variable)));
+                }
+                Value::Number(n)
+            },
+            _ => return Err(Error::new(Code::TypeMismatch, format!("Type mismatch assigning to {}", variable))),
+        })
+    }
+
+    // A $ suffix always means a string; otherwise the first letter's DEF type decides
+    fn var_type(&self, variable: &str) -> VarType {
+        if variable.ends_with('$') {
+            return VarType::String;
+        }
+        match variable.as_bytes().first() {
+            Some(c) if c.is_ascii_uppercase() => self.var_types[(c - b'A') as usize],
+            _ => VarType::Double,
+        }
+    }
+
+    // LSET/RSET write through to a FIELD buffer, or pad within the variable's current length
+    fn justify_variable(&mut self, variable: &str, expression: &Expression, right: bool) -> Result<(), Error> {
+        let value = match self.evaluate_expression(expression)? {
+            Value::String(s) => s,
+            Value::Number(_) => return Err(Error::new(Code::TypeMismatch, "LSET/RSET requires a string")),
+        };
+        let field = self.files.values_mut().find(|f| f.has_field(variable));
+        let justified = match field {
+            Some(file) => file.write_field(variable, &value, right).unwrap_or_default(),
+            None => {
+                let width = match self.variables.get(variable) {
+                    Some(Value::String(current)) => current.len(),
+                    _ => 0,
+                };
+                String::from_utf8_lossy(&files::justify(&value, width, right)).into_owned()
+            },
+        };
+        self.assign(variable, Value::String(justified))
+    }
+
+    fn evaluate_number(&self, expr: &Expression) -> Result<f64, Error> {
+        match self.evaluate_expression(expr)? {
+            Value::Number(n) => Ok(n),
+            Value::String(_) => Err(Error::new(Code::TypeMismatch, "Expected a number")),
+        }
+    }
+
+    fn evaluate_optional_number(&self, expr: Option<&Expression>) -> Result<Option<f64>, Error> {
+        match expr {
+            Some(expr) => Ok(Some(self.evaluate_number(expr)?)),
+            None => Ok(None),
+        }
+    }
+
+    // --sandbox turns off everything that reaches outside the program: running commands,
+    // reading the environment, changing files, the key-value store, the clipboard and hardware
+    fn check_sandbox(&self, what: &str) -> Result<(), Error> {
+        if self.sandbox {
+            return Err(Error::new(Code::Sandbox, format!("{} is disabled in sandbox mode", what)));
+        }
+        Ok(())
+    }
+
+    fn evaluate_sprite_number(&self, expr: &Expression) -> Result<u32, Error> {
+        let number = self.evaluate_integer(expr)?;
+        if number < 0.0 {
+            return Err(Error::new(Code::OutOfRange, format!("Sprite {} out of range", number)));
+        }
+        Ok(number as u32)
+    }
+
+    // A sprite may hang off the top or left edge, so positions go as far below 1 as LOCATE's go above it
+    fn evaluate_sprite_position(&self, position: &Expression) -> Result<i64, Error> {
+        let position = self.evaluate_number(position)?.round();
+        let max = screen::MAX_POSITION as f64;
+        if !(-max..=max).contains(&position) {
+            return Err(Error::new(Code::OutOfRange, format!("SPRITE MOVE position {} out of range -{} to {}", position, max, max)));
+        }
+        Ok(position as i64)
+    }
+
+    fn evaluate_pin(&self, expr: &Expression) -> Result<u32, Error> {
+        let pin = self.evaluate_integer(expr)?;
+        if pin < 0.0 {
+            return Err(Error::new(Code::OutOfRange, format!("Pin {} out of range", pin)));
+        }
+        Ok(pin as u32)
+    }
+
+    fn evaluate_point(&self, (x, y): &Point) -> Result<(f64, f64), Error> {
+        Ok((self.evaluate_number(x)?, self.evaluate_number(y)?))
+    }
+
+    fn evaluate_color(&self, expr: Option<&Expression>) -> Result<u8, Error> {
+        match expr {
+            Some(expr) => graphics::color_index(self.evaluate_number(expr)?),
+            None => Ok(graphics::DEFAULT_COLOR),
+        }
+    }
+
+    fn screen(&mut self) -> Result<&mut graphics::Framebuffer, Error> {
+        self.graphics.as_mut().ok_or_else(|| Error::from(graphics::NO_SCREEN))
+    }
+
+    fn evaluate_string(&self, expr: &Expression) -> Result<String, Error> {
+        match self.evaluate_expression(expr)? {
+            Value::String(s) => Ok(s),
+            Value::Number(_) => Err(Error::new(Code::TypeMismatch, "Expected a string")),
+        }
+    }
+
+    fn evaluate_file_number(&self, expr: &Expression) -> Result<u32, Error> {
+        file_number(self.evaluate_number(expr)?)
+    }
+
+    fn evaluate_integer(&self, expr: &Expression) -> Result<f64, Error> {
+        let n = self.evaluate_number(expr)?;
+        self.check_integer(expr, n);
+        Ok(n)
+    }
+
+    // A failure notes the innermost expression that failed, so the error can give its column
+    fn evaluate_expression(&self, expr: &Expression) -> Result<Value, Error> {
+        let result = self.evaluate_node(expr);
+        if result.is_err() && self.error_position.get().is_none() {
+            self.error_position.set(expr.position());
+        }
+        result
+    }
+
+    fn evaluate_node(&self, expr: &Expression) -> Result<Value, Error> {
+        match expr {
+            Expression::Number(n) => Ok(Value::Number(*n)),
+            Expression::String(s) => Ok(Value::String(s.clone())),
+            Expression::Variable(name, cache, _) => {
+                if let Some(value) = self.locals.borrow().last().and_then(|frame| frame.get(name)) {
+                    return Ok(value.clone());
+                }
+                self.variables.get_cached(name, cache)
+                    .cloned()
+                    .ok_or_else(|| Error::new(Code::UndefinedVariable, format!("Undefined variable: {}", name)))
+            },
+            Expression::Binary { left, operator, right, .. } => {
+                let left_val = self.evaluate_expression(left)?;
+                let right_val = self.evaluate_expression(right)?;
+                let inputs: Vec<f64> = [&left_val, &right_val].iter()
+                    .filter_map(|v| match v {
+                        Value::Number(n) => Some(*n),
+                        Value::String(_) => None,
+                    })
+                    .collect();
+
+                let result = match (left_val, operator, right_val) {
+                    (Value::Number(l), Token::Plus, Value::Number(r)) => Ok(Value::Number(l + r)),
+                    (Value::Number(l), Token::Minus, Value::Number(r)) => Ok(Value::Number(l - r)),
+                    (Value::Number(l), Token::Multiply, Value::Number(r)) => Ok(Value::Number(l * r)),
+                    (Value::Number(l), Token::Divide, Value::Number(r)) => {
+                        if r == 0.0 {
+                            Err(Error::new(Code::DivisionByZero, "Division by zero"))
+                        } else {
+                            Ok(Value::Number(l / r))
+                        }
+                    },
+                    (Value::Number(l), Token::Power, Value::Number(r)) => Ok(Value::Number(l.powf(r))),
+                    (Value::Number(l), Token::LessThan, Value::Number(r)) => Ok(Value::Number(if l < r { 1.0 } else { 0.0 })),
+                    (Value::Number(l), Token::GreaterThan, Value::Number(r)) => Ok(Value::Number(if l > r { 1.0 } else { 0.0 })),
+                    (Value::Number(l), Token::Equals, Value::Number(r)) => Ok(Value::Number(if l == r { 1.0 } else { 0.0 })),
+                    (Value::Number(l), Token::LessOrEqual, Value::Number(r)) => Ok(Value::Number(if l <= r { 1.0 } else { 0.0 })),
+                    (Value::Number(l), Token::GreaterOrEqual, Value::Number(r)) => Ok(Value::Number(if l >= r { 1.0 } else { 0.0 })),
+                    (Value::Number(l), Token::NotEqual, Value::Number(r)) => Ok(Value::Number(if l != r { 1.0 } else { 0.0 })),
+                    (Value::String(l), Token::Plus, Value::String(r)) => Ok(Value::String(l + &r)),
+                    (Value::String(l), Token::Equals, Value::String(r)) => Ok(Value::Number(if l == r { 1.0 } else { 0.0 })),
+                    (Value::String(l), Token::NotEqual, Value::String(r)) => Ok(Value::Number(if l != r { 1.0 } else { 0.0 })),
+                    _ => Err(Error::new(Code::TypeMismatch, "Invalid operation or type mismatch")),
+                }?;
+                self.check_math(expr, &inputs, &result);
+                Ok(result)
+            },
+            Expression::FunctionCall { name, arguments, .. } => {
+                let values = arguments.iter()
+                    .map(|arg| self.evaluate_expression(arg))
+                    .collect::<Result<Vec<Value>, Error>>()?;
+                let inputs: Vec<f64> = values.iter()
+                    .filter_map(|v| match v {
+                        Value::Number(n) => Some(*n),
+                        Value::String(_) => None,
+                    })
+                    .collect();
+
+                if let Some(function) = self.host_functions.get(name) {
+                    return match (function(&values)?, name.ends_with('$')) {
+                        (Value::Number(_), true) => Err(Error::new(Code::TypeMismatch, format!("Type mismatch: {} returned a number", name))),
+                        (Value::String(_), false) => Err(Error::new(Code::TypeMismatch, format!("Type mismatch: {} returned a string", name))),
+                        (value, _) => Ok(value),
+                    };
+                }
+                if name.starts_with("FN") {
+                    return self.call_function(name, values);
+                }
+
+                let result = match name.as_str() {
+                    "ABS" => {
+                        let arg = first_argument(name, &values)?;
+                        match arg {
+                            Value::Number(n) => Ok(Value::Number(n.abs())),
+                            _ => Err(Error::new(Code::TypeMismatch, "ABS requires a number argument")),
+                        }
+                    },
+                    "SQR" => {
+                        let arg = first_argument(name, &values)?;
+                        match arg {
+                            Value::Number(n) => {
+                                if n < 0.0 {
+                                    Err(Error::new(Code::IllegalFunctionCall, "Cannot take square root of negative number"))
+                                } else {
+                                    Ok(Value::Number(n.sqrt()))
+                                }
+                            },
+                            _ => Err(Error::new(Code::TypeMismatch, "SQR requires a number argument")),
+                        }
+                    },
+                    "SIN" => {
+                        let arg = first_argument(name, &values)?;
+                        match arg {
+                            Value::Number(n) => Ok(Value::Number(n.sin())),
+                            _ => Err(Error::new(Code::TypeMismatch, "SIN requires a number argument")),
+                        }
+                    },
+                    "COS" => {
+                        let arg = first_argument(name, &values)?;
+                        match arg {
+                            Value::Number(n) => Ok(Value::Number(n.cos())),
+                            _ => Err(Error::new(Code::TypeMismatch, "COS requires a number argument")),
+                        }
+                    },
+                    "TAN" => {
+                        let arg = first_argument(name, &values)?;
+                        match arg {
+                            Value::Number(n) => Ok(Value::Number(n.tan())),
+                            _ => Err(Error::new(Code::TypeMismatch, "TAN requires a number argument")),
+                        }
+                    },
+                    "RND" => Ok(Value::Number(self.rng.borrow_mut().gen())),
+                    "INT" => {
+                        let arg = first_argument(name, &values)?;
+                        match arg {
+                            Value::Number(n) => Ok(Value::Number(n.floor())),
+                            _ => Err(Error::new(Code::TypeMismatch, "INT requires a number argument")),
+                        }
+                    },
+                    "SHELL$" => {
+                        self.check_sandbox("SHELL$")?;
+                        match first_argument(name, &values)? {
+                            Value::String(command) => Ok(Value::String(system::shell_output(&command)?)),
+                            Value::Number(_) => Err(Error::new(Code::TypeMismatch, "SHELL$ requires a command string")),
+                        }
+                    },
+                    "RUN$" => {
+                        self.check_sandbox("RUN$")?;
+                        let command = match first_argument(name, &values)? {
+                            Value::String(command) => command,
+                            Value::Number(_) => return Err(Error::new(Code::TypeMismatch, "RUN$ requires a command string")),
+                        };
+                        let seconds = match values.get(1) {
+                            None => RUN_TIMEOUT_SECONDS,
+                            Some(Value::Number(n)) if *n > 0.0 && *n <= MAX_RUN_TIMEOUT_SECONDS => *n,
+                            Some(Value::Number(n)) => {
+                                return Err(Error::new(Code::OutOfRange, format!("RUN$ timeout {} out of range 0-{}", n, MAX_RUN_TIMEOUT_SECONDS)));
+                            },
+                            Some(_) => return Err(Error::new(Code::TypeMismatch, "RUN$ timeout must be a positive number of seconds")),
+                        };
+                        let (output, status) = system::run_output(&command, std::time::Duration::from_secs_f64(seconds))?;
+                        self.exit_code.set(status);
+                        Ok(Value::String(output))
+                    },
+                    "RC" => Ok(Value::Number(self.exit_code.get() as f64)),
+                    "ENVIRON$" => {
+                        self.check_sandbox("ENVIRON$")?;
+                        match first_argument(name, &values)? {
+                            Value::String(variable) => Ok(Value::String(system::environ(&variable))),
+                            Value::Number(n) if n >= 1.0 => Ok(Value::String(system::environ_entry(n as usize))),
+                            Value::Number(n) => Err(Error::new(Code::OutOfRange, format!("ENVIRON$ entry {} out of range", n))),
+                        }
+                    },
+                    "COMMAND$" => match values.first() {
+                        None => Ok(Value::String(self.arguments.get(1..).unwrap_or_default().join(" "))),
+                        Some(Value::Number(n)) if *n >= 0.0 => {
+                            Ok(Value::String(self.arguments.get(*n as usize).cloned().unwrap_or_default()))
+                        },
+                        Some(_) => Err(Error::new(Code::IllegalFunctionCall, "COMMAND$ requires an argument number")),
+                    },
+                    // The arguments as an array, ARGS$(1) to ARGS$(ARGC), which unlike COMMAND$ stops at
+                    // the end rather than giving ""
+                    "ARGC" => Ok(Value::Number(self.arguments.len().saturating_sub(1) as f64)),
+                    "ARGS$" => match first_argument(name, &values)? {
+                        Value::Number(n) if n >= 1.0 && (n as usize) < self.arguments.len() => {
+                            Ok(Value::String(self.arguments[n as usize].clone()))
+                        },
+                        Value::Number(n) => Err(Error::new(Code::OutOfRange, format!(
+                            "Subscript out of range: ARGS$({}) with {} arguments", n, self.arguments.len().saturating_sub(1),
+                        ))),
+                        Value::String(_) => Err(Error::new(Code::TypeMismatch, "ARGS$ requires an argument number")),
+                    },
+                    "CLIPGET$" => {
+                        self.check_sandbox("CLIPGET$")?;
+                        Ok(Value::String(self.clipboard.get()?))
+                    },
+                    "STOREGET" | "STOREGET$" => {
+                        self.check_sandbox(name)?;
+                        let key = match first_argument(name, &values)? {
+                            Value::String(key) => key,
+                            Value::Number(_) => return Err(Error::new(Code::TypeMismatch, format!("{} requires a key string", name))),
+                        };
+                        // A missing key reads as 0 or ""
+                        match (name.as_str(), store::get(&key)?) {
+                            ("STOREGET", None) => Ok(Value::Number(0.0)),
+                            ("STOREGET$", None) => Ok(Value::String(String::new())),
+                            ("STOREGET", Some(value @ Value::Number(_))) | ("STOREGET$", Some(value @ Value::String(_))) => Ok(value),
+                            _ => Err(Error::new(Code::TypeMismatch, format!("Type mismatch reading {} with {}", key, name))),
+                        }
+                    },
+                    "COLLISION" => match values.as_slice() {
+                        [Value::Number(a), Value::Number(b)] if *a >= 0.0 && *b >= 0.0 => {
+                            Ok(Value::Number(if self.sprites.collision(*a as u32, *b as u32)? { 1.0 } else { 0.0 }))
+                        },
+                        _ => Err(Error::new(Code::IllegalFunctionCall, "COLLISION requires two sprite numbers")),
+                    },
+                    "INPIN" => {
+                        self.check_sandbox("INPIN")?;
+                        let pin = match first_argument(name, &values)? {
+                            Value::Number(n) if n >= 0.0 => n as u32,
+                            _ => return Err(Error::new(Code::IllegalFunctionCall, "INPIN requires a pin number")),
+                        };
+                        Ok(Value::Number(if self.hardware.input_pin(pin)? { 1.0 } else { 0.0 }))
+                    },
+                    "MKI$" | "MKS$" | "MKD$" => {
+                        let n = match first_argument(name, &values)? {
+                            Value::Number(n) => n,
+                            Value::String(_) => return Err(Error::new(Code::TypeMismatch, format!("{} requires a number", name))),
+                        };
+                        let packed = match name.as_str() {
+                            "MKI$" => binary::mki(n).map_err(|e| Error::new(Code::Overflow, e))?,
+                            "MKS$" => binary::mks(n)?,
+                            _ => binary::mkd(n)?,
+                        };
+                        Ok(Value::String(packed))
+                    },
+                    "CVI" | "CVS" | "CVD" => {
+                        let text = match first_argument(name, &values)? {
+                            Value::String(text) => text,
+                            Value::Number(_) => return Err(Error::new(Code::TypeMismatch, format!("{} requires a string", name))),
+                        };
+                        let n = match name.as_str() {
+                            "CVI" => binary::cvi(&text),
+                            "CVS" => binary::cvs(&text),
+                            _ => binary::cvd(&text),
+                        };
+                        Ok(Value::Number(n.map_err(|e| Error::new(Code::IllegalFunctionCall, e))?))
+                    },
+                    "LOF" | "LOC" => {
+                        let file_number = match first_argument(name, &values)? {
+                            Value::Number(n) => file_number(n)?,
+                            Value::String(_) => return Err(Error::new(Code::TypeMismatch, format!("{} requires a file number", name))),
+                        };
+                        let file = self.files.get(&file_number)
+                            .ok_or_else(|| Error::new(Code::BadFileNumber, format!("File #{} not open", file_number)))?;
+                        if name == "LOF" {
+                            Ok(Value::Number(file.length()? as f64))
+                        } else {
+                            Ok(Value::Number(file.location() as f64))
+                        }
+                    },
+                    _ => Err(Error::new(Code::UnknownFunction, format!("Unknown function: {}", name))),
+                }?;
+                self.check_math(expr, &inputs, &result);
+                Ok(result)
+            },
+        }
+    }
+
+    // --checked-math: note NaN and infinity where they first appear, and integers past 2^53
+    fn check_math(&self, expr: &Expression, inputs: &[f64], result: &Value) {
+        let (Some(_), Value::Number(n)) = (&self.math_diagnostics, result) else {
+            return;
+        };
+        if n.is_nan() && inputs.iter().all(|x| !x.is_nan()) {
+            self.report_math(expr, "NaN produced");
+        } else if n.is_infinite() && inputs.iter().all(|x| x.is_finite()) {
+            self.report_math(expr, "overflow to infinity");
+        } else if let (Expression::Binary { operator, .. }, [l, r]) = (expr, inputs) {
+            let integer_op = matches!(operator, Token::Plus | Token::Minus | Token::Multiply | Token::Power);
+            if integer_op && n.abs() > MAX_EXACT_INTEGER && l.fract() == 0.0 && r.fract() == 0.0
+                && exact_integer_result(operator, *l, *r) != Some(*n as i128) {
+                self.report_math(expr, "integer result beyond 2^53 loses precision");
+            }
+        }
+    }
+
+    fn check_integer(&self, expr: &Expression, n: f64) {
+        if self.math_diagnostics.is_some() && n.fract() != 0.0 {
+            self.report_math(expr, "fractional value truncated to integer");
+        }
+    }
+
+    fn report_math(&self, expr: &Expression, problem: &'static str) {
+        let Some(diagnostics) = &self.math_diagnostics else {
+            return;
+        };
+        let line = self.program.lines.get(self.current_line).map(|l| l.number).unwrap_or(0);
+        let expression = expr.to_string();
+        let mut diagnostics = diagnostics.borrow_mut();
+        match diagnostics.iter_mut().find(|d| d.line == line && d.expression == expression && d.problem == problem) {
+            Some(diagnostic) => diagnostic.count += 1,
+            None => diagnostics.push(MathDiagnostic { line, expression, problem, count: 1 }),
+        }
+    }
+
+    pub fn print_math_report(&self) {
+        let Some(diagnostics) = &self.math_diagnostics else {
+            return;
+        };
+        let diagnostics = diagnostics.borrow();
+        if diagnostics.is_empty() {
+            eprintln!("Checked math: no numeric problems found");
+            return;
+        }
+        eprintln!("Checked math diagnostics:");
+        for d in diagnostics.iter() {
+            eprintln!("  line {}: {} in {} ({} times)", d.line, d.problem, d.expression, d.count);
+        }
+    }
+}
+
+const MAX_EXACT_INTEGER: f64 = 9007199254740992.0;
+
+// What an integer BASIC would have computed, if it fits in an i128
+fn exact_integer_result(operator: &Token, l: f64, r: f64) -> Option<i128> {
+    let (l, r) = (l as i128, r as i128);
+    match operator {
+        Token::Plus => l.checked_add(r),
+        Token::Minus => l.checked_sub(r),
+        Token::Multiply => l.checked_mul(r),
+        _ => u32::try_from(r).ok().and_then(|r| l.checked_pow(r)),
+    }
+}
+
+fn first_argument(name: &str, values: &[Value]) -> Result<Value, Error> {
+    values.first().cloned().ok_or_else(|| Error::new(Code::IllegalFunctionCall, format!("{} requires an argument", name)))
+}
+
+fn file_number(n: f64) -> Result<u32, Error> {
+    if n < 1.0 || n.fract() != 0.0 {
+        return Err(Error::new(Code::BadFileNumber, format!("Bad file number {}", n)));
+    }
+    Ok(n as u32)
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Number(f64),
+    String(String),
+}
+
+impl Value {
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::String(_) => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            Value::Number(_) => None,
+        }
+    }
+
+    // What holding it costs, for max_memory: a string's text, or the eight bytes of a number
+    pub(crate) fn bytes(&self) -> usize {
+        match self {
+            Value::String(s) => s.len(),
+            Value::Number(_) => 8,
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+// What one call to Interpreter::step did
+#[derive(Debug)]
+pub enum StepResult {
+    // A statement ran and there is more to run
+    Ran,
+    // The next statement is an INPUT with no line to read; give it one with provide_input
+    NeedsInput,
+    Finished,
+    Error(LangError),
+}
+
+// Hosts run interpreters on threads of their own and share parsed programs between them. A
+// window has to stay on the thread that opened it, so a build with one can't move interpreters.
+#[cfg(not(feature = "window"))]
+const _: () = {
+    const fn send<T: Send>() {}
+    const fn sync<T: Sync>() {}
+    send::<Interpreter>();
+    send::<Program>();
+    sync::<Program>();
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_of(source: &str) -> String {
+        let program = parse(source).unwrap();
+        Interpreter::new().run_captured(&program, Vec::new()).error.unwrap().message().to_string()
+    }
+
+    #[test]
+    fn run_timeout_has_to_fit_in_a_day() {
+        assert_eq!(error_of("PRINT RUN$(\"true\", 10^30)"), "RUN$ timeout 1000000000000000000000000000000 out of range 0-86400");
+        assert_eq!(error_of("PRINT RUN$(\"true\", 0)"), "RUN$ timeout 0 out of range 0-86400");
+    }
+
+    #[test]
+    fn line_index_finds_the_first_line_with_a_number() {
+        let program = parse("PRINT 1\nPRINT 2\n10 PRINT 3\n20 GOTO 10").unwrap();
+        let index = program.line_index();
+        assert_eq!(index[&0], 0);
+        assert_eq!(index[&10], 2);
+        assert_eq!(index[&20], 3);
+    }
+
+    #[test]
+    fn locate_stays_on_the_screen() {
+        assert_eq!(error_of("LOCATE 10^20, 1"), "LOCATE position 100000000000000000000 out of range 1-255");
+        assert_eq!(error_of("LOCATE 1, 0"), "LOCATE position 0 out of range 1-255");
+    }
+
+    #[test]
+    fn sprites_move_only_near_the_screen() {
+        let load = "10 SPRITE LOAD 1, \"##\"\n20 SPRITE MOVE 1, ";
+        assert_eq!(error_of(&format!("{}1, 10^20", load)), "SPRITE MOVE position 100000000000000000000 out of range -255 to 255");
+        assert_eq!(error_of(&format!("{}-300, 1", load)), "SPRITE MOVE position -300 out of range -255 to 255");
+        let program = parse(&format!("{}-1, 255", load)).unwrap();
+        let run = Interpreter::new().run_captured(&program, Vec::new());
+        assert!(run.error.is_none());
+    }
+
+    #[test]
+    fn vm_opt_runs_programs_the_same() {
+        let programs = [
+            "10 I = 0\n20 GOTO 40\n30 GOTO 50\n40 GOTO 30\n50 I = I + 1\n60 IF I < 5 THEN 20 ELSE 70\n70 PRINT I",
+            "10 DEFINT N\n20 N = 1.2\n30 N = N + 2.4\n40 A$ = \"a\"\n50 A$ = A$ + \"b\"\n60 PRINT N\n70 PRINT A$",
+            "10 X = 1\n20 X = X + \"a\"",
+            "10 X = X + 1",
+            "10 IF \"a\" THEN 10",
+        ];
+        for source in programs {
+            let program = parse(source).unwrap();
+            let plain = Interpreter::new().run_captured(&program, Vec::new());
+            let mut interpreter = Interpreter::new();
+            interpreter.vm_opt = true;
+            let optimized = interpreter.run_captured(&program, Vec::new());
+            assert_eq!(optimized.stdout(), plain.stdout(), "{}", source);
+            assert_eq!(optimized.error.map(|e| e.to_string()), plain.error.map(|e| e.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn send_queues_count_against_max_memory() {
+        let program = parse("10 SEND \"c\", \"0123456789\"\n20 GOTO 10").unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.max_memory = Some(1000);
+        let error = interpreter.run_captured(&program, Vec::new()).error.unwrap();
+        assert_eq!(error.message(), "Memory limit of 1000 bytes exceeded by the string variables and SEND queues");
+    }
+
+    #[test]
+    fn memoize_cache_stays_under_max_memory() {
+        let program = parse("10 DEF FNSQ(X) = X * X\n20 FOR I = 1 TO 1000\n30 Y = FNSQ(I)\n40 NEXT I").unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.memoize = true;
+        interpreter.max_memory = Some(1000);
+        interpreter.run(&program).unwrap();
+        assert!(interpreter.fn_cache_bytes.get() <= 1000);
+        assert!(!interpreter.fn_cache.borrow().is_empty());
+    }
+}
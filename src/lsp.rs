@@ -0,0 +1,306 @@
+// `lang lsp`: a language server for editors, speaking JSON-RPC over stdin and stdout. It reports
+// syntax errors, semantic errors and warnings as the program is typed, goes from the line
+// number after GOTO, THEN, ELSE or TASK to that line and from a variable to where it's first
+// assigned, describes built-in functions on hover and formats the document as `lang fmt` does.
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::Token;
+
+const FUNCTION_DOCS: &[(&str, &str)] = &[
+    ("ABS", "ABS(x): x without its sign"),
+    ("SQR", "SQR(x): the square root of x"),
+    ("SIN", "SIN(x): the sine of x radians"),
+    ("COS", "COS(x): the cosine of x radians"),
+    ("TAN", "TAN(x): the tangent of x radians"),
+    ("INT", "INT(x): the largest whole number not above x"),
+    ("RND", "RND(): a random number from 0 up to but not including 1"),
+    ("SHELL$", "SHELL$(command$): what a command run through the system shell prints"),
+    ("RUN$", "RUN$(command$[, seconds]): what a command prints, killing it after 30 seconds or the given number"),
+    ("RC", "RC: the exit status of the last RUN$, or -1 when it was killed"),
+    ("ENVIRON$", "ENVIRON$(name$): an environment variable; ENVIRON$(n) is the nth NAME=value entry"),
+    ("COMMAND$", "COMMAND$: the program's arguments joined by spaces; COMMAND$(n) is the nth and COMMAND$(0) the program file"),
+    ("ARGS$", "ARGS$(n): the nth of the program's arguments, from 1 to ARGC"),
+    ("ARGC", "ARGC: how many arguments the program was given after --"),
+    ("CLIPGET$", "CLIPGET$(): the text on the clipboard"),
+    ("STOREGET", "STOREGET(key$): the number kept in the key-value store under key$"),
+    ("STOREGET$", "STOREGET$(key$): the string kept in the key-value store under key$"),
+    ("COLLISION", "COLLISION(a, b): 1 when the visible parts of sprites a and b overlap, otherwise 0"),
+    ("INPIN", "INPIN(pin): a GPIO pin read as 0 or 1"),
+    ("MKI$", "MKI$(x): x packed into a 2 byte integer string, for a record field"),
+    ("MKS$", "MKS$(x): x packed into a 4 byte single precision string, for a record field"),
+    ("MKD$", "MKD$(x): x packed into an 8 byte double precision string, for a record field"),
+    ("CVI", "CVI(s$): the number in a 2 byte string made by MKI$"),
+    ("CVS", "CVS(s$): the number in a 4 byte string made by MKS$"),
+    ("CVD", "CVD(s$): the number in an 8 byte string made by MKD$"),
+    ("LOF", "LOF(n): the length in bytes of open file n"),
+    ("LOC", "LOC(n): the number of the last record read from or written to file n"),
+];
+
+const ERROR: u32 = 1;
+const WARNING: u32 = 2;
+
+pub fn run() -> Result<(), String> {
+    let mut input = std::io::stdin().lock();
+    let mut documents: HashMap<String, String> = HashMap::new();
+    let mut shutting_down = false;
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "documentFormattingProvider": true,
+                },
+                "serverInfo": { "name": "lang", "version": env!("CARGO_PKG_VERSION") },
+            }),
+            "shutdown" => {
+                shutting_down = true;
+                Value::Null
+            },
+            "exit" if shutting_down => return Ok(()),
+            "exit" => return Err("The editor stopped the language server without shutting it down".to_string()),
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                // The whole text each time, as asked for in initialize
+                let text = match method {
+                    "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+                    _ => params["contentChanges"].as_array().and_then(|changes| changes.last()?["text"].as_str()),
+                };
+                if let Some(text) = text {
+                    publish(&uri, &diagnostics(text))?;
+                    documents.insert(uri, text.to_string());
+                }
+                continue;
+            },
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+                publish(&uri, &[])?;
+                continue;
+            },
+            "textDocument/definition" | "textDocument/hover" | "textDocument/formatting" => {
+                let Some(text) = documents.get(&uri) else {
+                    respond_error(&message["id"], -32602, &format!("{} isn't open", uri))?;
+                    continue;
+                };
+                let position = &params["position"];
+                let at = source_position(
+                    text,
+                    position["line"].as_u64().unwrap_or_default() as usize,
+                    position["character"].as_u64().unwrap_or_default() as usize,
+                );
+                match method {
+                    "textDocument/definition" => definition(text, at).map_or(Value::Null, |(line, column, length)| {
+                        json!({ "uri": uri, "range": range(text, line, column, length) })
+                    }),
+                    "textDocument/hover" => hover(text, at).map_or(Value::Null, |docs| {
+                        json!({ "contents": { "kind": "markdown", "value": docs } })
+                    }),
+                    _ => match crate::format(text) {
+                        Ok(formatted) => json!([{ "range": whole(text), "newText": formatted }]),
+                        Err(_) => Value::Null,
+                    },
+                }
+            },
+            // Notifications that need nothing from us
+            _ if message.get("id").is_none() => continue,
+            _ => {
+                respond_error(&message["id"], -32601, &format!("{} isn't supported", method))?;
+                continue;
+            },
+        };
+        send(&json!({ "jsonrpc": "2.0", "id": message["id"], "result": result }))?;
+    }
+    Ok(())
+}
+
+// A message is a Content-Length header, a blank line and that many bytes of JSON; None at the end of input
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>, String> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).map_err(|e| format!("Failed to read from the editor: {}", e))? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or("A message from the editor has no Content-Length")?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).map_err(|e| format!("Failed to read from the editor: {}", e))?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| format!("Bad message from the editor: {}", e))
+}
+
+fn send(message: &Value) -> Result<(), String> {
+    let body = message.to_string();
+    let mut output = std::io::stdout().lock();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| output.flush())
+        .map_err(|e| format!("Failed to write to the editor: {}", e))
+}
+
+fn respond_error(id: &Value, code: i32, message: &str) -> Result<(), String> {
+    send(&json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }))
+}
+
+fn publish(uri: &str, diagnostics: &[Value]) -> Result<(), String> {
+    send(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    }))
+}
+
+// What `lang` would say before running the program: syntax errors, or else semantic errors and warnings
+fn diagnostics(source: &str) -> Vec<Value> {
+    let diagnostic = |(line, column): crate::Position, message: &str, severity: u32| json!({
+        "range": range(source, line, column, word_length(source, line, column)),
+        "severity": severity,
+        "source": "lang",
+        "message": message,
+    });
+    // Errors carry their code from the catalog in errors.rs; warnings have none
+    let coded = |position: crate::Position, message: &str, code: crate::errors::Code| {
+        let mut found = diagnostic(position, message, ERROR);
+        found["code"] = json!(code.entry().code);
+        found
+    };
+    let program = match crate::parse(source) {
+        Ok(program) => program,
+        Err(error) => {
+            let errors = match error {
+                crate::LangError::Multiple(errors) => errors,
+                error => vec![error],
+            };
+            return errors.iter().filter_map(|error| match error {
+                crate::LangError::LexError { line, column, code, message }
+                | crate::LangError::ParseError { line, column, code, message } => Some(coded((*line, *column), message, *code)),
+                _ => None,
+            }).collect();
+        },
+    };
+    let mut found: Vec<Value> = crate::analyzer::semantic_errors(&program).into_iter()
+        .map(|(index, position, code, message)| coded(position.unwrap_or(program.lines[index].position), &message, code))
+        .collect();
+    let line_index = program.line_index();
+    for warning in crate::diagnostics::warnings(&program) {
+        if let Some(line) = line_index.get(&warning.line).map(|&index| &program.lines[index]) {
+            found.push(diagnostic((line.position.0, warning.column), &warning.message, WARNING));
+        }
+    }
+    found
+}
+
+// The line, column and length of what the token at `at` refers to
+fn definition(source: &str, at: crate::Position) -> Option<(usize, usize, usize)> {
+    let lexed = crate::tokenize(source, false);
+    let i = token_at(&lexed, source, at)?;
+    match (&lexed.tokens[i], i.checked_sub(1).map(|i| &lexed.tokens[i])) {
+        (&Token::Number(target), Some(Token::Goto | Token::Then | Token::Else | Token::Task)) => {
+            let j = lexed.tokens.iter().position(|token| *token == Token::LineNumber(target as u32))?;
+            let (line, column) = lexed.positions[j];
+            Some((line, column, target.to_string().len()))
+        },
+        (Token::Identifier(name), _) => {
+            let j = assignment(&lexed.tokens, name)?;
+            let (line, column) = lexed.positions[j];
+            Some((line, column, word_length(source, line, column)))
+        },
+        _ => None,
+    }
+}
+
+// Where a variable is first given a value by LET, INPUT or FOR, or a function by DEF, falling
+// back to wherever it first appears
+fn assignment(tokens: &[Token], name: &str) -> Option<usize> {
+    let is_name = |i: usize| matches!(tokens.get(i), Some(Token::Identifier(n)) if n == name);
+    let mut statement_start = true;
+    for (i, token) in tokens.iter().enumerate() {
+        if statement_start {
+            match token {
+                Token::Identifier(_) if is_name(i) && tokens.get(i + 1) == Some(&Token::Equals) => return Some(i),
+                Token::Let | Token::Input | Token::For if is_name(i + 1) => return Some(i + 1),
+                Token::Def if is_name(i + 1) => return Some(i + 1),
+                // DEF FN A(X) as well as DEF FNA(X)
+                Token::Def if matches!(tokens.get(i + 1), Some(Token::Identifier(fn_word)) if fn_word == "FN")
+                    && matches!(tokens.get(i + 2), Some(Token::Identifier(rest)) if format!("FN{}", rest) == name) => {
+                    return Some(i + 1);
+                },
+                _ => {},
+            }
+        }
+        statement_start = matches!(token, Token::EOL | Token::LineNumber(_) | Token::Then | Token::Else);
+    }
+    tokens.iter().position(|token| matches!(token, Token::Identifier(n) if n == name))
+}
+
+// What a built-in function does, or the DEF of a user's function
+fn hover(source: &str, at: crate::Position) -> Option<String> {
+    let lexed = crate::tokenize(source, false);
+    let Token::Identifier(name) = &lexed.tokens[token_at(&lexed, source, at)?] else {
+        return None;
+    };
+    if let Some((_, docs)) = FUNCTION_DOCS.iter().find(|(function, _)| function == name) {
+        let (usage, description) = docs.split_once(": ").unwrap_or((docs, ""));
+        return Some(format!("```\n{}\n```\n{}", usage, description));
+    }
+    let program = crate::parse(source).ok()?;
+    program.lines.iter().find_map(|line| match &line.statement {
+        statement @ crate::Statement::DefFn { name: function, .. } if function == name => {
+            Some(format!("```\n{}\n```", crate::unparse::statement(statement)))
+        },
+        _ => None,
+    })
+}
+
+// The token the cursor is on or just after
+fn token_at(lexed: &crate::Lexed, source: &str, (line, column): crate::Position) -> Option<usize> {
+    let i = lexed.positions.iter()
+        .rposition(|&(token_line, token_column)| token_line == line && token_column <= column)?;
+    let (_, start) = lexed.positions[i];
+    (column <= start + word_length(source, line, start)).then_some(i)
+}
+
+// How many characters the name or number starting at a line and column takes up, at least 1
+fn word_length(source: &str, line: usize, column: usize) -> usize {
+    let text = source.lines().nth(line - 1).unwrap_or_default();
+    text.chars().skip(column - 1)
+        .take_while(|c| c.is_alphanumeric() || matches!(c, '$' | '_' | '.'))
+        .count()
+        .max(1)
+}
+
+// Editors count lines from 0 and columns in UTF-16 code units; LANG counts both from 1 in characters
+fn source_position(source: &str, line: usize, character: usize) -> crate::Position {
+    let text = source.lines().nth(line).unwrap_or_default();
+    let mut units = 0;
+    let column = text.chars().take_while(|c| {
+        units += c.len_utf16();
+        units <= character
+    }).count();
+    (line + 1, column + 1)
+}
+
+fn lsp_position(source: &str, line: usize, column: usize) -> Value {
+    let text = source.lines().nth(line.saturating_sub(1)).unwrap_or_default();
+    let character: usize = text.chars().take(column.saturating_sub(1)).map(char::len_utf16).sum();
+    json!({ "line": line.saturating_sub(1), "character": character })
+}
+
+fn range(source: &str, line: usize, column: usize, length: usize) -> Value {
+    json!({ "start": lsp_position(source, line, column), "end": lsp_position(source, line, column + length) })
+}
+
+fn whole(source: &str) -> Value {
+    json!({ "start": { "line": 0, "character": 0 }, "end": { "line": source.lines().count() + 1, "character": 0 } })
+}
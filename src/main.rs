@@ -1,982 +1,766 @@
-use std::collections::HashMap;
-use std::io::Write;
-use rand::Rng;
-
-mod compiler;
-use compiler::Compiler;
-
-#[allow(dead_code)]
-#[derive(Debug, Clone, PartialEq)]
-enum Token {
-    // Numbers and Identifiers
-    Number(f64),
-    Identifier(String),
-    
-    // Operators
-    Plus,
-    Minus,
-    Multiply,
-    Divide,
-    Power,
-    Equals,
-    LessThan,
-    GreaterThan,
-    LessOrEqual,
-    GreaterOrEqual,
-    NotEqual,
-    
-    // Brackets and Separators
-    LParen,
-    RParen,
-    Comma,
-    Semicolon,
-    Colon,
-    
-    // Keywords
-    Let,
-    Print,
-    Input,
-    If,
-    Then,
-    Else,
-    For,
-    To,
-    Step,
-    Next,
-    Goto,
-    Gosub,
-    Return,
-    Rem,
-    End,
-    Stop,
-    Dim,
-    Read,
-    Data,
-    Restore,
-    
-    // Built-in Functions
-    Abs,
-    Rnd,
-    Int,
-    Sqr,
-    Sin,
-    Cos,
-    Tan,
-    Log,
-    Exp,
-    Len,
-    Mid,
-    Left,
-    Right,
-    
-    // Special
-    LineNumber(u32),
-    String(String),
-    EOL,
-    EOF,
-}
-
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-enum Expression {
-    Number(f64),
-    String(String),
-    Variable(String),
-    Binary {
-        left: Box<Expression>,
-        operator: Token,
-        right: Box<Expression>,
-    },
-    FunctionCall {
-        name: String,
-        arguments: Vec<Expression>,
-    },
-}
-
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-struct ForLoop {
-    variable: String,
-    start: Expression,
-    end: Expression,
-    step: Expression,
-}
-
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-enum Statement {
-    Let {
-        variable: String,
-        expression: Expression,
-    },
-    Print {
-        expressions: Vec<Expression>,
-        semicolon: bool,
-    },
-    If {
-        condition: Expression,
-        then_branch: Box<Statement>,
-        else_branch: Option<Box<Statement>>,
-    },
-    Input {
-        variable: String,
-    },
-    For {
-        loop_data: ForLoop,
-    },
-    Next {
-        variable: String,
-    },
-    End,
-    Goto(u32),
-    Rem(String),
-}
-
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-struct Line {
-    number: u32,
-    statement: Statement,
-}
-
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-struct Program {
-    lines: Vec<Line>,
-}
-
-impl Program {
-    fn new() -> Self {
-        Program {
-            lines: Vec::new(),
-        }
-    }
+// The command line: run, compile or check a program, and the tools for formatting, fuzzing,
+// minimizing and analyzing programs, each a subcommand
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Args, CommandFactory, Parser, Subcommand};
+
+use lang::ccompiler::CCompiler;
+use lang::compiler::{Compiler, Target};
+use lang::config::Config;
+use lang::crash::{self, CrashReport};
+use lang::diagnostics::{self, Level};
+use lang::{audio, build, checkpoint, coverage, debugger, errors, fuzz, golden, golf, io, lsp, midi, minimize, renum, repl, serve, stats, Interpreter, Program};
+
+#[derive(Parser)]
+#[command(name = "lang", version, about = "LANG, a BASIC inspired by MS BASIC")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 }
 
-fn tokenize(input: &str) -> Vec<Token> {
-    let mut tokens = Vec::new();
-    let mut chars = input.chars().peekable();
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Run a program in the interpreter; `lang prog.bs` does the same")]
+    Run(RunArgs),
+    #[command(about = "Compile a program to an executable, or only to Rust or C")]
+    Compile(CompileArgs),
+    #[command(about = "Run a program a statement at a time, with breakpoints")]
+    Debug(DebugArgs),
+    #[command(about = "Parse a program and check it for errors without running it")]
+    Check(CheckArgs),
+    #[command(about = "Run every program in a directory and compare what it prints with its .expected file")]
+    Test(TestArgs),
+    #[command(about = "Rewrite programs in the standard layout")]
+    Fmt(FmtArgs),
+    #[command(about = "Type in and run a program line by line")]
+    Repl(Settings),
+    #[command(about = "Print the parsed program as JSON")]
+    Ast(SourceArgs),
+    #[command(about = "Number a program's lines again, fixing every jump to them")]
+    Renum(RenumArgs),
+    #[command(about = "List a program in color, for a terminal or as HTML")]
+    Highlight(HighlightArgs),
+    #[command(about = "Run the language server for editors on stdin and stdout")]
+    Lsp,
+    #[command(about = "Run programs sent over HTTP, streaming back what they print")]
+    Serve(ServeArgs),
+    #[command(about = "Count statements, jumps and complexity in a program")]
+    Stats(StatsArgs),
+    #[command(about = "Spell out the keywords of a --golf program")]
+    Expand(FileArgs),
+    #[command(about = "Cut a failing program down to the lines that make it fail")]
+    Minimize(MinimizeArgs),
+    #[command(about = "Run generated programs until the interpreter crashes")]
+    Fuzz(FuzzArgs),
+    #[command(about = "Explain an error code, like E001, or list them all")]
+    Explain(ExplainArgs),
+}
 
-    while let Some(&c) = chars.peek() {
-        match c {
-            ' ' | '\t' | '\r' => {
-                chars.next();
-            }
-            '\n' => {
-                tokens.push(Token::EOL);
-                chars.next();
-            }
-            '0'..='9' => {
-                let mut number = String::new();
-                while let Some(&c) = chars.peek() {
-                    if c.is_digit(10) || c == '.' {
-                        number.push(c);
-                        chars.next();
-                    } else {
-                        break;
-                    }
-                }
-                if let Ok(n) = number.parse::<f64>() {
-                    tokens.push(Token::Number(n));
-                }
-            }
-            'A'..='Z' | 'a'..='z' | '_' => {
-                let mut ident = String::new();
-                while let Some(&c) = chars.peek() {
-                    if c.is_alphanumeric() || c == '_' {
-                        ident.push(c.to_ascii_uppercase());
-                        chars.next();
-                    } else {
-                        break;
-                    }
-                }
-                match ident.as_str() {
-                    "LET" => tokens.push(Token::Let),
-                    "PRINT" => tokens.push(Token::Print),
-                    "IF" => tokens.push(Token::If),
-                    "THEN" => tokens.push(Token::Then),
-                    "ELSE" => tokens.push(Token::Else),
-                    "FOR" => tokens.push(Token::For),
-                    "TO" => tokens.push(Token::To),
-                    "STEP" => tokens.push(Token::Step),
-                    "NEXT" => tokens.push(Token::Next),
-                    "END" => tokens.push(Token::End),
-                    "INPUT" => tokens.push(Token::Input),
-                    _ => tokens.push(Token::Identifier(ident)),
-                }
-            }
-            '"' => {
-                chars.next();
-                let mut string = String::new();
-                while let Some(&c) = chars.peek() {
-                    if c == '"' {
-                        chars.next();
-                        break;
-                    }
-                    string.push(c);
-                    chars.next();
-                }
-                tokens.push(Token::String(string));
-            }
-            '+' => {
-                tokens.push(Token::Plus);
-                chars.next();
-            }
-            '-' => {
-                tokens.push(Token::Minus);
-                chars.next();
-            }
-            '*' => {
-                tokens.push(Token::Multiply);
-                chars.next();
-            }
-            '/' => {
-                tokens.push(Token::Divide);
-                chars.next();
-            }
-            '^' => {
-                tokens.push(Token::Power);
-                chars.next();
-            }
-            '=' => {
-                tokens.push(Token::Equals);
-                chars.next();
-            }
-            '<' => {
-                chars.next();
-                if let Some(&'=') = chars.peek() {
-                    tokens.push(Token::LessOrEqual);
-                    chars.next();
-                } else if let Some(&'>') = chars.peek() {
-                    tokens.push(Token::NotEqual);
-                    chars.next();
-                } else {
-                    tokens.push(Token::LessThan);
-                }
-            }
-            '>' => {
-                chars.next();
-                if let Some(&'=') = chars.peek() {
-                    tokens.push(Token::GreaterOrEqual);
-                    chars.next();
-                } else {
-                    tokens.push(Token::GreaterThan);
-                }
-            }
-            '(' => {
-                tokens.push(Token::LParen);
-                chars.next();
-            }
-            ')' => {
-                tokens.push(Token::RParen);
-                chars.next();
-            }
-            ',' => {
-                tokens.push(Token::Comma);
-                chars.next();
-            }
-            ';' => {
-                tokens.push(Token::Semicolon);
-                chars.next();
-            }
-            ':' => {
-                tokens.push(Token::Colon);
-                chars.next();
-            }
-            _ => {
-                chars.next();
-            }
-        }
-    }
+// Where the program comes from
+#[derive(Args)]
+struct SourceArgs {
+    #[arg(help = "The program's file, or - for stdin; a program piped in is read without one")]
+    file: Option<String>,
+    #[arg(short, long, value_name = "STATEMENT", help = "Run this line instead of a file; give it again for more lines")]
+    eval: Vec<String>,
+    #[arg(long, help = "Read the program in the golf dialect")]
+    golf: bool,
+    #[arg(long, value_name = "FILE", help = "Load the program from an AST saved by `lang ast`")]
+    load_ast: Option<String>,
+}
+
+// The settings ~/.langrc can also hold; these win over the file
+#[derive(Args)]
+struct Settings {
+    #[arg(long, help = "Ignore ~/.langrc")]
+    no_config: bool,
+    #[arg(long, help = "Turn off the shell, files, the network and the hardware")]
+    sandbox: bool,
+    #[arg(long, help = "Require every variable to be assigned before it is read")]
+    strict: bool,
+    #[arg(long, help = "Report NaN, overflow and lost precision")]
+    checked_math: bool,
+    #[arg(long, help = "Remember the results of pure DEF FN functions")]
+    memoize: bool,
+    #[arg(long, help = "Print how often each line ran and how long it took")]
+    profile: bool,
+    #[arg(long, help = "Show graphics in a window")]
+    window: bool,
+    #[arg(long, help = "Compile FOR loops through the variables map rather than as Rust for loops")]
+    no_native_loops: bool,
+    #[arg(long, help = "Run numeric programs as machine code")]
+    jit: bool,
+    #[arg(long, help = "Thread GOTO chains and fuse IF-GOTO and X = X + e when the program loads")]
+    vm_opt: bool,
+    #[arg(long, value_name = "N", help = "Stop the program after N statements")]
+    max_steps: Option<usize>,
+    #[arg(long, value_name = "SECONDS", value_parser = lang::config::parse_seconds, help = "Stop the program after this long")]
+    timeout: Option<Duration>,
+    #[arg(long, value_name = "BYTES", help = "Stop the program when its string variables hold more than this")]
+    max_memory: Option<usize>,
+    #[arg(long, value_name = "N", help = "Make RND give the same numbers on every run")]
+    seed: Option<u64>,
+    #[arg(long, value_name = "LEVEL", value_parser = Level::parse, help = "allow, warn or deny")]
+    warnings: Option<Level>,
+    #[arg(long, value_name = "FILE", help = "Also write a crash report to FILE if LANG crashes")]
+    crash_report: Option<String>,
+}
 
-    // If the last token isn't EOL, add one
-    if !tokens.is_empty() && !matches!(tokens.last(), Some(Token::EOL)) {
-        tokens.push(Token::EOL);
+impl Settings {
+    fn config(&self) -> Result<Config, String> {
+        let mut config = if self.no_config { Config::default() } else { Config::load()? };
+        config.sandbox |= self.sandbox;
+        config.strict |= self.strict;
+        config.checked_math |= self.checked_math;
+        config.memoize |= self.memoize;
+        config.profile |= self.profile;
+        config.window |= self.window;
+        config.native_loops &= !self.no_native_loops;
+        config.jit |= self.jit;
+        config.vm_opt |= self.vm_opt;
+        config.max_steps = self.max_steps.or(config.max_steps);
+        config.timeout = self.timeout.or(config.timeout);
+        config.max_memory = self.max_memory.or(config.max_memory);
+        config.seed = self.seed.or(config.seed);
+        config.warnings = self.warnings.unwrap_or(config.warnings);
+        Ok(config)
     }
-    tokens.push(Token::EOF);
-    tokens
 }
 
-struct Parser {
-    tokens: Vec<Token>,
-    current: usize,
+#[derive(Args)]
+struct RunArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+    #[command(flatten)]
+    settings: Settings,
+    #[arg(short, long, help = "Say what is being read and when the program has finished")]
+    verbose: bool,
+    #[arg(long, value_name = "FILE", help = "Record SOUND and PLAY to a MIDI file")]
+    midi: Option<String>,
+    #[arg(long, value_name = "FILE", help = "Where CHECKPOINT and signals save the program's state")]
+    checkpoint: Option<String>,
+    #[arg(long, value_name = "FILE", help = "Carry on from a checkpoint")]
+    resume: Option<String>,
+    #[arg(long, value_name = "FILE", help = "Send what the program prints to FILE")]
+    stdout: Option<String>,
+    #[arg(long, value_name = "FILE", help = "Read INPUT's lines from FILE")]
+    stdin: Option<String>,
+    #[arg(long, value_name = "FILE", help = "Profile the run and write the times to FILE for flamegraph.pl or inferno")]
+    flamegraph: Option<String>,
+    #[arg(long, help = "Show which lines ran and how often when the program stops")]
+    coverage: bool,
+    #[arg(long, value_name = "FILE", help = "Write which lines ran to FILE as an lcov tracefile")]
+    lcov: Option<String>,
+    #[arg(last = true, help = "Arguments for the program, after --, for COMMAND$")]
+    arguments: Vec<String>,
 }
 
-impl Parser {
-    fn new(tokens: Vec<Token>) -> Self {
-        Parser {
-            tokens,
-            current: 0,
-        }
-    }
+#[derive(Args)]
+struct CompileArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+    #[command(flatten)]
+    settings: Settings,
+    #[arg(short, long, help = "Say what is being read, compiled and built")]
+    verbose: bool,
+    #[arg(long, value_name = "KIND", value_parser = Emit::parse, help = "c, rust, or rust=FILE to only write the Rust")]
+    emit: Option<Emit>,
+    #[arg(short, long, value_name = "FILE", help = "The program to build, code by default")]
+    out: Option<String>,
+    #[arg(long, value_name = "TARGET", value_parser = Target::parse, help = "native, wasm or a target triple")]
+    target: Option<Target>,
+    #[arg(long, value_name = "DIR", help = "Where the Cargo project is built")]
+    target_dir: Option<PathBuf>,
+    #[arg(short = 'O', value_name = "LEVEL", value_parser = ["0", "1", "2", "3", "s", "z"], help = "Optimization level")]
+    opt_level: Option<String>,
+    #[arg(long = "rustc-arg", value_name = "FLAG", allow_hyphen_values = true, help = "Pass a flag to rustc")]
+    rustc_args: Vec<String>,
+    #[arg(long, help = "Comment the Rust with the BASIC lines and keep the BASIC names")]
+    readable: bool,
+    #[arg(long, help = "Say where each variable went and which lines were left out")]
+    explain_codegen: bool,
+}
 
-    fn peek(&self) -> Option<&Token> {
-        self.tokens.get(self.current)
-    }
+#[derive(Args)]
+struct DebugArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+    #[command(flatten)]
+    settings: Settings,
+    #[arg(last = true, help = "Arguments for the program, after --, for COMMAND$")]
+    arguments: Vec<String>,
+}
 
-    fn advance(&mut self) -> Option<&Token> {
-        if self.current < self.tokens.len() {
-            self.current += 1;
-        }
-        self.tokens.get(self.current - 1)
-    }
+#[derive(Args)]
+struct CheckArgs {
+    #[arg(help = "The programs, or - for stdin; a program piped in is read without one")]
+    files: Vec<String>,
+    #[arg(short, long, value_name = "STATEMENT", help = "Check this line instead of a file; give it again for more lines")]
+    eval: Vec<String>,
+    #[arg(long, help = "Read the programs in the golf dialect")]
+    golf: bool,
+    #[arg(short, long, help = "Say which files are fine too")]
+    verbose: bool,
+    #[command(flatten)]
+    settings: Settings,
+}
 
-    fn match_token(&mut self, expected: &[Token]) -> bool {
-        if let Some(token) = self.peek() {
-            if expected.contains(token) {
-                self.advance();
-                return true;
-            }
-        }
-        false
-    }
+#[derive(Args)]
+struct ServeArgs {
+    #[arg(long, default_value = "127.0.0.1:7070", value_name = "ADDRESS", help = "The address and port to listen on")]
+    listen: String,
+    #[arg(long, value_name = "ORIGIN", help = "Let pages from this origin, or * for any, call the server from a browser")]
+    allow_origin: Option<String>,
+    #[command(flatten)]
+    settings: Settings,
+}
 
-    fn parse_program(&mut self) -> Program {
-        let mut program = Program::new();
-        let mut line_number = 0;
-
-        while let Some(token) = self.peek() {
-            match token {
-                Token::EOL => {
-                    self.advance();
-                },
-                Token::EOF => {
-                    break;
-                },
-                _ => {
-                    program.lines.push(Line {
-                        number: line_number,
-                        statement: self.parse_statement(),
-                    });
-                    line_number += 1;
-
-                    // Consume any EOL after the statement
-                    if let Some(Token::EOL) = self.peek() {
-                        self.advance();
-                    }
-                }
-            }
-        }
+#[derive(Args)]
+struct TestArgs {
+    #[arg(default_value = ".", help = "The directory of .bs programs, with a .expected file and optionally a .input file for each")]
+    dir: String,
+    #[arg(long, help = "Write each program's output to its .expected file instead of comparing")]
+    bless: bool,
+    #[command(flatten)]
+    settings: Settings,
+}
 
-        program
-    }
+#[derive(Args)]
+struct FmtArgs {
+    #[arg(long, help = "Change nothing, and fail if any file isn't formatted")]
+    check: bool,
+    #[arg(required = true, help = "The files, or - to format stdin to stdout")]
+    files: Vec<String>,
+}
 
-    fn parse_statement(&mut self) -> Statement {
-        let token = self.peek().cloned();
-        match token {
-            Some(Token::Let) => {
-                self.advance();
-                self.parse_let()
-            },
-            Some(Token::Print) => {
-                self.advance();
-                self.parse_print()
-            },
-            Some(Token::If) => {
-                self.advance();
-                self.parse_if()
-            },
-            Some(Token::For) => {
-                self.advance();
-                self.parse_for()
-            },
-            Some(Token::Input) => {
-                self.advance();
-                if let Some(Token::Identifier(name)) = self.advance().cloned() {
-                    Statement::Input {
-                        variable: name,
-                    }
-                } else {
-                    panic!("Expected variable name after INPUT")
-                }
-            },
-            Some(Token::Next) => {
-                self.advance();
-                if let Some(Token::Identifier(name)) = self.advance().cloned() {
-                    Statement::Next {
-                        variable: name,
-                    }
-                } else {
-                    panic!("Expected variable name after NEXT")
-                }
-            },
-            Some(Token::End) => {
-                self.advance();
-                Statement::End
-            },
-            Some(Token::Identifier(name)) => {
-                self.advance();
-                // Check for function call
-                if let Some(Token::LParen) = self.peek() {
-                    self.advance(); // consume (
-                    let mut args = Vec::new();
-                    loop {
-                        if let Some(Token::RParen) = self.peek() {
-                            self.advance();
-                            break;
-                        }
-                        args.push(self.parse_expression());
-                        if let Some(Token::Comma) = self.peek() {
-                            self.advance();
-                        } else if let Some(Token::RParen) = self.peek() {
-                            self.advance();
-                            break;
-                        } else {
-                            panic!("Expected ',' or ')' in function call");
-                        }
-                    }
-                    Statement::Let {
-                        variable: name.clone(),
-                        expression: Expression::FunctionCall {
-                            name,
-                            arguments: args,
-                        },
-                    }
-                } else if let Some(Token::Equals) = self.peek() {
-                    self.advance();
-                    Statement::Let {
-                        variable: name,
-                        expression: self.parse_expression(),
-                    }
-                } else {
-                    panic!("Expected = after variable name")
-                }
-            },
-            Some(token) => panic!("Unexpected token in statement: {:?}", token),
-            None => panic!("Unexpected end of input"),
-        }
-    }
+#[derive(Args)]
+struct RenumArgs {
+    file: String,
+    #[arg(default_value_t = 10, help = "The first line's new number")]
+    new_start: u32,
+    #[arg(default_value_t = 10, help = "How far apart the new numbers are")]
+    increment: u32,
+}
 
-    fn parse_let(&mut self) -> Statement {
-        let var_name = match self.advance() {
-            Some(Token::Identifier(name)) => name.clone(),
-            _ => panic!("Expected variable name after LET"),
-        };
+#[derive(Args)]
+struct HighlightArgs {
+    file: String,
+    #[arg(long, help = "Make a web page instead of a terminal listing")]
+    html: bool,
+    #[arg(short, long, value_name = "FILE", help = "Write to FILE instead of stdout")]
+    out: Option<String>,
+}
 
-        if !self.match_token(&[Token::Equals]) {
-            panic!("Expected '=' after variable name in LET");
-        }
+#[derive(Args)]
+struct StatsArgs {
+    file: String,
+    #[arg(long, help = "Print the report as JSON")]
+    json: bool,
+}
 
-        let expr = self.parse_expression();
-        Statement::Let {
-            variable: var_name,
-            expression: expr,
+#[derive(Args)]
+struct FileArgs {
+    file: String,
+}
+
+#[derive(Args)]
+struct MinimizeArgs {
+    file: String,
+    #[arg(long, value_name = "CHECK", help = "crashes, errors, or text the output or error must contain")]
+    check: String,
+    #[arg(long, value_name = "FILE", help = "Lines to give INPUT")]
+    input: Option<String>,
+}
+
+#[derive(Args)]
+struct ExplainArgs {
+    #[arg(help = "The code from error[E001]; without one every code is listed")]
+    code: Option<String>,
+}
+
+#[derive(Args)]
+struct FuzzArgs {
+    #[arg(default_value_t = 10_000)]
+    iterations: usize,
+    seed: Option<u64>,
+}
+
+// Before there were subcommands, `lang prog.bs` ran a program and `lang --compile prog.bs`,
+// `--ast` and `--fuzz` did the rest, so those still work
+fn with_subcommand(mut args: Vec<String>) -> Vec<String> {
+    // `echo '10 PRINT 1' | lang` runs what was piped in
+    let Some(first) = args.get(1) else {
+        if !std::io::stdin().is_terminal() {
+            args.push("run".to_string());
         }
+        return args;
+    };
+    let command = Cli::command();
+    if command.find_subcommand(first).is_some() || matches!(first.as_str(), "help" | "-h" | "--help" | "-V" | "--version") {
+        return args;
     }
+    let end = args.iter().position(|a| a == "--").unwrap_or(args.len());
+    let subcommand = if let Some(i) = args[..end].iter().position(|a| a == "--compile") {
+        args.remove(i);
+        "compile"
+    } else if args[..end].iter().any(|a| a == "--emit" || a.starts_with("--emit=")) {
+        "compile"
+    } else if let Some(i) = args[..end].iter().position(|a| a == "--ast" || a == "--fuzz" || a == "--explain") {
+        match args.remove(i).as_str() {
+            "--ast" => "ast",
+            "--fuzz" => "fuzz",
+            _ => "explain",
+        }
+    } else {
+        "run"
+    };
+    args.insert(1, subcommand.to_string());
+    args
+}
+
+fn run_minimize(args: MinimizeArgs) -> Result<(), String> {
+    let inputs = match &args.input {
+        Some(input_path) => std::fs::read_to_string(input_path)
+            .map_err(|e| format!("Error reading input file: {}", e))?
+            .lines().map(|l| l.to_string()).collect(),
+        None => Vec::new(),
+    };
+    let contents = std::fs::read_to_string(&args.file)
+        .map_err(|e| format!("Error reading file: {}", e))?;
 
-    fn parse_print(&mut self) -> Statement {
-        let mut expressions = Vec::new();
-        let mut semicolon = false;
-
-        while let Some(token) = self.peek() {
-            match token {
-                Token::Semicolon => {
-                    semicolon = true;
-                    self.advance();
-                    break;
-                }
-                Token::EOL => break,
-                _ => {
-                    expressions.push(self.parse_expression());
-                    if let Some(Token::Comma) = self.peek() {
-                        self.advance();
-                    }
-                }
+    let mut minimizer = minimize::Minimizer::new(minimize::Check::parse(&args.check), inputs);
+    let minimized = minimizer.minimize(&contents)?;
+    eprintln!("Reduced {} lines to {} lines in {} runs",
+        contents.lines().filter(|l| !l.trim().is_empty()).count(),
+        minimized.lines().count(),
+        minimizer.runs());
+    println!("{}", minimized);
+    Ok(())
+}
+
+// Rewrites each file in its canonical form, or with --check only says which ones aren't, and fails
+// if any aren't; - formats stdin to stdout
+fn run_fmt(args: FmtArgs) -> Result<(), String> {
+    let color = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let mut unformatted = 0;
+    for path in &args.files {
+        let (contents, file_name) = match path.as_str() {
+            "-" => (std::io::read_to_string(std::io::stdin()).map_err(|e| format!("Error reading stdin: {}", e))?, "<stdin>"),
+            path => (std::fs::read_to_string(path).map_err(|e| format!("Error reading {}: {}", path, e))?, path),
+        };
+        let formatted = lang::format(&contents).map_err(|e| e.render(&contents, file_name, color).trim_end().to_string())?;
+        if args.check {
+            if formatted != contents {
+                println!("{} isn't formatted", file_name);
+                unformatted += 1;
             }
+        } else if path == "-" {
+            print!("{}", formatted);
+        } else if formatted != contents {
+            std::fs::write(path, formatted).map_err(|e| format!("Error writing {}: {}", path, e))?;
         }
+    }
+    match unformatted {
+        0 => Ok(()),
+        1 => Err("1 file needs formatting; run lang fmt on it".to_string()),
+        n => Err(format!("{} files need formatting; run lang fmt on them", n)),
+    }
+}
 
-        Statement::Print {
-            expressions,
-            semicolon,
-        }
+// A colored listing for the terminal, or with --html a web page, to stdout or the file after -o
+fn run_highlight(args: HighlightArgs) -> Result<(), String> {
+    let contents = std::fs::read_to_string(&args.file)
+        .map_err(|e| format!("Error reading {}: {}", args.file, e))?;
+    let listing = if args.html {
+        lang::highlight::html(&contents, &args.file)
+    } else {
+        lang::highlight::ansi(&contents)
+    };
+    match &args.out {
+        Some(output) => std::fs::write(output, listing).map_err(|e| format!("Error writing {}: {}", output, e)),
+        None => {
+            print!("{}", listing);
+            Ok(())
+        },
     }
+}
 
-    fn parse_expression(&mut self) -> Expression {
-        self.parse_comparison()
+// Rewrites the file in place, as RENUM does to the program in the REPL
+fn run_renum(args: RenumArgs) -> Result<(), String> {
+    let contents = std::fs::read_to_string(&args.file)
+        .map_err(|e| format!("Error reading file: {}", e))?;
+    let renumbered = renum::renumber(&contents, args.new_start, args.increment)?;
+    std::fs::write(&args.file, renumbered).map_err(|e| format!("Error writing {}: {}", args.file, e))
+}
+
+fn run_stats(args: StatsArgs) -> Result<(), String> {
+    let contents = std::fs::read_to_string(&args.file)
+        .map_err(|e| format!("Error reading file: {}", e))?;
+    let program = lang::parse(&contents).map_err(|e| e.to_string())?;
+    let stats = stats::Stats::collect(&program);
+    if args.json {
+        println!("{}", stats.to_json());
+    } else {
+        print!("{}", stats.report());
     }
+    Ok(())
+}
 
-    fn parse_comparison(&mut self) -> Expression {
-        let mut expr = self.parse_additive();
-        
-        while let Some(token) = self.peek() {
-            match token {
-                Token::LessThan | Token::GreaterThan | Token::Equals | 
-                Token::LessOrEqual | Token::GreaterOrEqual | Token::NotEqual => {
-                    let operator = self.advance().unwrap().clone();
-                    let right = self.parse_additive();
-                    expr = Expression::Binary {
-                        left: Box::new(expr),
-                        operator,
-                        right: Box::new(right),
-                    };
-                }
-                _ => break,
-            }
+// What compiling produces: an executable built from Rust or from C, or only the Rust, written
+// to stdout or to a file the way rustc's --emit kind=PATH names one
+#[derive(Clone, PartialEq)]
+enum Emit {
+    Executable,
+    C,
+    Rust(Option<String>),
+}
+
+impl Emit {
+    fn parse(text: &str) -> Result<Emit, String> {
+        match text.split_once('=') {
+            None if text == "c" => Ok(Emit::C),
+            None if text == "rust" => Ok(Emit::Rust(None)),
+            Some(("rust", "-")) => Ok(Emit::Rust(None)),
+            Some(("rust", path)) if !path.is_empty() => Ok(Emit::Rust(Some(path.to_string()))),
+            _ => Err(format!("--emit must be c, rust or rust=<file>, not {}", text)),
         }
-        
-        expr
     }
+}
 
-    fn parse_additive(&mut self) -> Expression {
-        let mut expr = self.parse_multiplicative();
-
-        while let Some(token) = self.peek() {
-            match token {
-                Token::Plus | Token::Minus => {
-                    let op = self.advance().unwrap().clone();
-                    let right = self.parse_multiplicative();
-                    expr = Expression::Binary {
-                        left: Box::new(expr),
-                        operator: op,
-                        right: Box::new(right),
-                    };
-                }
-                _ => break,
-            }
+// A crash ends the run like an error, with the report as its message
+fn crashed(report: &CrashReport, path: Option<&String>) -> String {
+    if let Some(path) = path {
+        if let Err(e) = report.write(path) {
+            eprintln!("{}", e);
         }
-
-        expr
     }
+    report.render().trim_end().to_string()
+}
 
-    fn parse_multiplicative(&mut self) -> Expression {
-        let mut expr = self.parse_power();
-
-        while let Some(token) = self.peek() {
-            match token {
-                Token::Multiply | Token::Divide => {
-                    let op = self.advance().unwrap().clone();
-                    let right = self.parse_power();
-                    expr = Expression::Binary {
-                        left: Box::new(expr),
-                        operator: op,
-                        right: Box::new(right),
-                    };
-                }
-                _ => break,
-            }
-        }
+// A program read, parsed and checked, with its source kept to show errors against
+struct Loaded {
+    contents: String,
+    file_name: String,
+    program: Program,
+    color: bool,
+}
 
-        expr
-    }
+// The program's source and the name to show in errors, from its file, stdin or -e
+fn read(source: &SourceArgs, status: &dyn Fn(&str)) -> Result<(String, String), String> {
+    // Without a file, a program piped in is read from stdin, as with `lang -`
+    let path = source.file.clone().or_else(|| (!std::io::stdin().is_terminal()).then(|| "-".to_string()));
+    let file_name = match path.as_deref() {
+        _ if !source.eval.is_empty() => "-e".to_string(),
+        Some("-") => "<stdin>".to_string(),
+        path => path.unwrap_or_default().to_string(),
+    };
+    // A program loaded from an AST has no source to point errors at
+    let contents = match (&source.load_ast, path.as_deref()) {
+        (Some(_), _) => String::new(),
+        _ if !source.eval.is_empty() => source.eval.join("\n") + "\n",
+        (None, Some("-")) => std::io::read_to_string(std::io::stdin()).map_err(|e| format!("Error reading stdin: {}", e))?,
+        (None, Some(path)) => {
+            let contents = std::fs::read_to_string(path).map_err(|e| format!("Error reading {}: {}", path, e))?;
+            status(&format!("Reading BASIC code from {}...", file_name));
+            contents
+        },
+        (None, None) => return Err(Cli::command().render_usage().to_string()),
+    };
+    Ok((contents, file_name))
+}
 
-    fn parse_power(&mut self) -> Expression {
-        let mut expr = self.parse_primary();
-
-        while let Some(token) = self.peek() {
-            match token {
-                Token::Power => {
-                    let operator = self.advance().unwrap().clone();
-                    let right = self.parse_primary();
-                    expr = Expression::Binary {
-                        left: Box::new(expr),
-                        operator,
-                        right: Box::new(right),
-                    };
-                }
-                _ => break,
+// Reads the program, or loads its saved AST, parses it, runs the checks and prints the warnings
+fn load(source: &SourceArgs, config: &Config, crash_report: Option<&String>, status: &dyn Fn(&str)) -> Result<Loaded, String> {
+    let (contents, file_name) = read(source, status)?;
+    let color = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let render = |e: lang::LangError| e.render(&contents, &file_name, color).trim_end().to_string();
+
+    let program = match &source.load_ast {
+        Some(path) => {
+            status(&format!("Reading AST from {}...", path));
+            let json = std::fs::read_to_string(path).map_err(|e| format!("Error reading file: {}", e))?;
+            Program::from_json(&json)?
+        },
+        None if source.golf => crash::catch("parse", &contents, || lang::parse_golf(&contents))
+            .map_err(|report| crashed(&report, crash_report))?
+            .map_err(render)?,
+        None => crash::catch("parse", &contents, || lang::parse(&contents))
+            .map_err(|report| crashed(&report, crash_report))?
+            .map_err(render)?,
+    };
+    lang::check_declarations(&program, config.strict)?;
+    lang::check_program(&program).map_err(render)?;
+    let warnings = diagnostics::warnings(&program);
+    match config.warnings {
+        Level::Deny if !warnings.is_empty() => {
+            let warnings: String = warnings.iter().map(|w| w.render(&contents, &file_name, color) + "\n").collect();
+            return Err(format!("{}Warnings are errors with --warnings=deny", warnings));
+        },
+        Level::Allow => {},
+        _ => {
+            for warning in &warnings {
+                eprintln!("{}", warning.render(&contents, &file_name, color).trim_end());
             }
-        }
-
-        expr
+        },
     }
+    Ok(Loaded { contents, file_name, program, color })
+}
 
-    fn parse_primary(&mut self) -> Expression {
-        match self.advance().cloned() {
-            Some(Token::Number(n)) => Expression::Number(n),
-            Some(Token::String(s)) => Expression::String(s),
-            Some(Token::Identifier(name)) => {
-                // Check for function call
-                if let Some(Token::LParen) = self.peek() {
-                    self.advance(); // consume (
-                    let mut args = Vec::new();
-                    loop {
-                        if let Some(Token::RParen) = self.peek() {
-                            self.advance();
-                            break;
-                        }
-                        args.push(self.parse_expression());
-                        if let Some(Token::Comma) = self.peek() {
-                            self.advance();
-                        } else if let Some(Token::RParen) = self.peek() {
-                            self.advance();
-                            break;
-                        } else {
-                            panic!("Expected ',' or ')' in function call");
-                        }
-                    }
-                    Expression::FunctionCall {
-                        name,
-                        arguments: args,
-                    }
-                } else {
-                    Expression::Variable(name)
-                }
-            },
-            Some(Token::LParen) => {
-                let expr = self.parse_expression();
-                if !self.match_token(&[Token::RParen]) {
-                    panic!("Expected closing parenthesis");
-                }
-                expr
-            },
-            Some(token) => panic!("Unexpected token in expression: {:?}", token),
-            None => panic!("Unexpected end of input"),
+// Only the program writes to stdout unless --verbose is given, and what it returns is the
+// status the process exits with: 0, or the one END gave
+fn run_program(args: RunArgs) -> Result<i32, String> {
+    let config = args.settings.config()?;
+    let crash_report = args.settings.crash_report.as_ref();
+    let status = |message: &str| {
+        if args.verbose {
+            println!("{}", message);
         }
+    };
+    let loaded = load(&args.source, &config, crash_report, &status)?;
+    let (contents, program) = (&loaded.contents, &loaded.program);
+
+    let mut interpreter = Interpreter::new();
+    config.configure(&mut interpreter);
+    interpreter.profile |= args.flamegraph.is_some();
+    interpreter.coverage = args.coverage || args.lcov.is_some();
+    if !loaded.file_name.is_empty() {
+        interpreter.arguments[0] = loaded.file_name.clone();
+    }
+    interpreter.arguments.extend(args.arguments);
+    interpreter.audio = match &args.midi {
+        Some(path) => Box::new(midi::Recorder::new(path)),
+        None => audio::default_backend(),
+    };
+    if args.stdout.is_some() || args.stdin.is_some() {
+        interpreter.io = Box::new(io::FileIo::new(args.stdout.as_deref(), args.stdin.as_deref())?);
+    }
+    if let Some(path) = &args.checkpoint {
+        interpreter.checkpoint_file = path.clone();
+        checkpoint::watch_signals()?;
+    }
+    let run = || match &args.resume {
+        Some(path) => interpreter.resume(program, path),
+        None => interpreter.run(program),
+    };
+    let result = match crash::catch("run", contents, run) {
+        Ok(result) => result,
+        Err(mut report) => {
+            interpreter.add_crash_context(&mut report, contents);
+            return Err(crashed(&report, crash_report));
+        },
+    };
+    interpreter.print_math_report();
+    interpreter.print_profile();
+    // After a CHAIN, errors and coverage are for the program it chained to
+    let chained = (!loaded.file_name.is_empty() && interpreter.arguments[0] != loaded.file_name)
+        .then(|| interpreter.arguments[0].clone());
+    let chained = chained.and_then(|path| Some((std::fs::read_to_string(&path).ok()?, path)));
+    let (contents, file_name) = match &chained {
+        Some((source, path)) => (source.as_str(), path.as_str()),
+        None => (contents.as_str(), loaded.file_name.as_str()),
+    };
+    if let Some(path) = &args.flamegraph {
+        std::fs::write(path, interpreter.folded_profile()).map_err(|e| format!("Error writing {}: {}", path, e))?;
+    }
+    if args.coverage {
+        eprint!("{}", coverage::listing(&interpreter, contents));
+    }
+    if let Some(path) = &args.lcov {
+        let lcov = coverage::lcov(&interpreter, file_name);
+        std::fs::write(path, lcov).map_err(|e| format!("Error writing {}: {}", path, e))?;
+    }
+    if let Some(reason) = interpreter.jit_fallback() {
+        eprintln!("--jit: ran in the interpreter because {}", reason);
     }
+    // Errors show the source line they point at, in color on a terminal
+    result.map_err(|e| e.render(contents, file_name, loaded.color).trim_end().to_string())?;
+    if interpreter.checkpointed() {
+        eprintln!("Checkpoint written to {}; continue with --resume {}",
+            interpreter.checkpoint_file, interpreter.checkpoint_file);
+    } else {
+        status("\nProgram execution completed.");
+    }
+    Ok(interpreter.end_status())
+}
 
-    fn parse_if(&mut self) -> Statement {
-        let condition = self.parse_expression();
-        
-        if !self.match_token(&[Token::Then]) {
-            panic!("Expected THEN after IF condition");
+fn compile(args: CompileArgs) -> Result<(), String> {
+    let config = args.settings.config()?;
+    let crash_report = args.settings.crash_report.as_ref();
+    let emit = args.emit.unwrap_or(Emit::Executable);
+    let target = args.target.unwrap_or(Target::Native);
+    let target_dir = args.target_dir.unwrap_or_else(build::default_dir);
+    let build_options = build::Options { opt_level: args.opt_level, rustc_args: args.rustc_args };
+    // Progress goes to stderr when stdout is the generated code
+    let code_on_stdout = emit == Emit::Rust(None);
+    let status = |message: &str| {
+        if !args.verbose {
+            return;
         }
-
-        let then_stmt = Box::new(self.parse_statement());
-        let else_stmt = if self.match_token(&[Token::Else]) {
-            Some(Box::new(self.parse_statement()))
+        if code_on_stdout {
+            eprintln!("{}", message);
         } else {
-            None
-        };
-
-        Statement::If {
-            condition,
-            then_branch: then_stmt,
-            else_branch: else_stmt,
+            println!("{}", message);
         }
-    }
+    };
+    let loaded = load(&args.source, &config, crash_report, &status)?;
+    let (contents, program) = (&loaded.contents, &loaded.program);
 
-    fn parse_for(&mut self) -> Statement {
-        let var_name = match self.advance() {
-            Some(Token::Identifier(name)) => name.clone(),
-            _ => panic!("Expected variable name after FOR"),
-        };
-
-        if !self.match_token(&[Token::Equals]) {
-            panic!("Expected '=' after variable name in FOR statement");
+    if emit == Emit::C {
+        if target != Target::Native {
+            return Err("--target needs the Rust code generator, not --emit c".to_string());
         }
-
-        let start = self.parse_expression();
-
-        if !self.match_token(&[Token::To]) {
-            panic!("Expected TO in FOR statement");
+        if !build_options.rustc_args.is_empty() {
+            return Err("--rustc-arg needs the Rust code generator, not --emit c".to_string());
         }
+        status("Compiling to C code...");
+        let c_code = crash::catch("compile", contents, || CCompiler::new().compile_program(program))
+            .map_err(|report| crashed(&report, crash_report))??;
+        status("Compiling to executable...");
+        let output_file = args.out.unwrap_or_else(|| Target::Native.default_output());
+        build::c(&c_code, &target_dir, &output_file, &build_options)?;
+
+        status(&format!("Successfully compiled to {}!", output_file));
+        return Ok(());
+    }
 
-        let end = self.parse_expression();
-
-        let step = if self.match_token(&[Token::Step]) {
-            self.parse_expression()
+    status("Compiling to Rust code...");
+    let mut compiler = Compiler::new();
+    compiler.native_loops = config.native_loops;
+    compiler.target = target.clone();
+    compiler.readable = args.readable;
+    compiler.source = contents.clone();
+    let rust_code = crash::catch("compile", contents, || compiler.compile_program(program))
+        .map_err(|report| crashed(&report, crash_report))??;
+    if args.explain_codegen {
+        if code_on_stdout {
+            eprint!("{}", compiler.explain());
         } else {
-            Expression::Number(1.0)
-        };
-
-        Statement::For {
-            loop_data: ForLoop {
-                variable: var_name,
-                start,
-                end,
-                step,
-            },
+            print!("{}", compiler.explain());
         }
     }
+    match &emit {
+        Emit::Rust(None) => {
+            print!("{}", rust_code);
+            return Ok(());
+        },
+        Emit::Rust(Some(path)) => {
+            std::fs::write(path, rust_code).map_err(|e| format!("Error writing {}: {}", path, e))?;
+            status(&format!("Wrote the Rust code to {}", path));
+            return Ok(());
+        },
+        _ => {},
+    }
+
+    status("Compiling to executable...");
+    let output_file = args.out.unwrap_or_else(|| target.default_output());
+    build::rust(&rust_code, &compiler.cargo_manifest(), &target, &target_dir, &output_file, &build_options)?;
+
+    status(&format!("Successfully compiled to {}!", output_file));
+    Ok(())
 }
 
-struct Interpreter {
-    variables: HashMap<String, f64>,
-    loops: Vec<ForLoop>,
-    loop_stack: Vec<usize>,
-    current_line: usize,
-    running: bool,
-    program: Program,
+fn debug(args: DebugArgs) -> Result<(), String> {
+    let config = args.settings.config()?;
+    let loaded = load(&args.source, &config, args.settings.crash_report.as_ref(), &|_| {})?;
+    let mut interpreter = Interpreter::new();
+    config.configure(&mut interpreter);
+    interpreter.arguments[0] = loaded.file_name.clone();
+    interpreter.arguments.extend(args.arguments);
+    debugger::run(&mut interpreter, &loaded.program, &loaded.contents, &loaded.file_name)
 }
 
-impl Interpreter {
-    fn new() -> Self {
-        Interpreter {
-            variables: HashMap::new(),
-            loops: Vec::new(),
-            loop_stack: Vec::new(),
-            current_line: 0,
-            running: true,
-            program: Program::new(),
+// Everything `run` does before the program starts, and nothing after, for each file in turn so
+// one with errors doesn't hide the others'
+fn check(args: CheckArgs) -> Result<(), String> {
+    let config = args.settings.config()?;
+    let files = match args.files.len() {
+        0 => vec![None],
+        _ => args.files.iter().cloned().map(Some).collect(),
+    };
+    let mut failed = 0;
+    for file in &files {
+        let source = SourceArgs { file: file.clone(), eval: args.eval.clone(), golf: args.golf, load_ast: None };
+        match load(&source, &config, args.settings.crash_report.as_ref(), &|_| {}) {
+            Ok(loaded) if args.verbose => println!("{}: ok", loaded.file_name),
+            Ok(_) => {},
+            Err(e) if files.len() == 1 => return Err(e),
+            Err(e) => {
+                eprintln!("{}", e);
+                failed += 1;
+            },
         }
     }
+    match failed {
+        0 => Ok(()),
+        1 => Err(format!("1 of {} files has errors", files.len())),
+        failed => Err(format!("{} of {} files have errors", failed, files.len())),
+    }
+}
 
-    fn execute_program(&mut self, program: Program) -> Result<(), String> {
-        self.program = program;
-        self.current_line = 0;
-        self.running = true;
-        
-        while self.running && self.current_line < self.program.lines.len() {
-            let line = &self.program.lines[self.current_line].clone();
-            match self.execute_statement(line.statement.clone()) {
-                Ok(_) => {
-                    self.current_line += 1;
-                },
-                Err(e) => return Err(format!("Error at line {}: {}", self.current_line, e)),
+fn explain(args: ExplainArgs) -> Result<(), String> {
+    match &args.code {
+        Some(code) => print!("{}", errors::explain(code)?),
+        None => {
+            for entry in errors::CODES {
+                println!("{}  {}", entry.code, entry.title);
             }
-        }
-        Ok(())
+        },
     }
+    Ok(())
+}
 
-    fn execute_statement(&mut self, statement: Statement) -> Result<(), String> {
-        match statement {
-            Statement::Print { expressions, semicolon } => {
-                for (i, expr) in expressions.iter().enumerate() {
-                    if i > 0 {
-                        print!(" ");
-                    }
-                    match self.evaluate_expression(expr)? {
-                        Value::Number(n) => print!("{}", n),
-                        Value::String(s) => print!("{}", s),
-                    }
-                }
-                if !semicolon {
-                    println!();
-                }
-                std::io::stdout().flush().unwrap();
-                Ok(())
-            },
-            Statement::Let { variable, expression } => {
-                let value = self.evaluate_expression(&expression)?;
-                match value {
-                    Value::Number(n) => {
-                        self.variables.insert(variable, n);
-                        Ok(())
-                    },
-                    Value::String(_) => Err("Can only store numbers in variables".to_string()),
-                }
-            },
-            Statement::If { condition, then_branch, else_branch } => {
-                let value = self.evaluate_expression(&condition)?;
-                match value {
-                    Value::Number(n) => {
-                        if n != 0.0 {
-                            self.execute_statement(*then_branch)
-                        } else if let Some(else_stmt) = else_branch {
-                            self.execute_statement(*else_stmt)
-                        } else {
-                            Ok(())
-                        }
-                    },
-                    Value::String(_) => Err("Condition must evaluate to a number".to_string()),
-                }
-            },
-            Statement::Input { variable } => {
-                print!("Enter {}: ", variable);
-                std::io::stdout().flush().unwrap();
-                let mut input = String::new();
-                match std::io::stdin().read_line(&mut input) {
-                    Ok(_) => {
-                        match input.trim().parse::<f64>() {
-                            Ok(n) => {
-                                self.variables.insert(variable, n);
-                                Ok(())
-                            },
-                            Err(_) => Err("Invalid number input".to_string()),
-                        }
-                    },
-                    Err(e) => Err(format!("Failed to read input: {}", e)),
-                }
-            },
-            Statement::For { loop_data } => {
-                let start = self.evaluate_expression(&loop_data.start)?;
-                let end = self.evaluate_expression(&loop_data.end)?;
-                let step = self.evaluate_expression(&loop_data.step)?;
-                
-                match (start, end, step) {
-                    (Value::Number(start), Value::Number(end), Value::Number(step)) => {
-                        self.variables.insert(loop_data.variable.clone(), start);
-                        self.loops.push(loop_data);
-                        self.loop_stack.push(self.current_line);
-                        Ok(())
-                    },
-                    _ => Err("Loop bounds must be numbers".to_string()),
-                }
-            },
-            Statement::Next { variable } => {
-                if let Some(loop_data) = self.loops.last() {
-                    if loop_data.variable != variable {
-                        return Err(format!("NEXT {} doesn't match FOR {}", variable, loop_data.variable));
-                    }
-                    
-                    let current = *self.variables.get(&variable).unwrap();
-                    let step = match self.evaluate_expression(&loop_data.step)? {
-                        Value::Number(n) => n,
-                        _ => return Err("Step must be a number".to_string()),
-                    };
-                    let next_val = current + step;
-                    
-                    let end = match self.evaluate_expression(&loop_data.end)? {
-                        Value::Number(n) => n,
-                        _ => return Err("End must be a number".to_string()),
-                    };
-                    
-                    if (step > 0.0 && next_val <= end) || (step < 0.0 && next_val >= end) {
-                        self.variables.insert(variable.clone(), next_val);
-                        if let Some(&loop_start) = self.loop_stack.last() {
-                            self.current_line = loop_start;
-                            Ok(())
-                        } else {
-                            Err("Loop start not found".to_string())
-                        }
-                    } else {
-                        self.loops.pop();
-                        self.loop_stack.pop();
-                        Ok(())
-                    }
-                } else {
-                    Err("NEXT without FOR".to_string())
-                }
-            },
-            Statement::End => {
-                self.running = false;
-                Ok(())
-            },
-            _ => Err("Statement not implemented yet".to_string()),
-        }
-    }
+// Only the AST of the program, as JSON for other tools
+fn ast(source: SourceArgs) -> Result<(), String> {
+    let (contents, _) = read(&source, &|_| {})?;
+    let program = lang::parse(&contents).map_err(|e| e.to_string())?;
+    println!("{}", program.to_json());
+    Ok(())
+}
 
-    fn evaluate_expression(&self, expr: &Expression) -> Result<Value, String> {
-        match expr {
-            Expression::Number(n) => Ok(Value::Number(*n)),
-            Expression::String(s) => Ok(Value::String(s.clone())),
-            Expression::Variable(name) => {
-                self.variables.get(name)
-                    .map(|&n| Value::Number(n))
-                    .ok_or_else(|| format!("Undefined variable: {}", name))
-            },
-            Expression::Binary { left, operator, right } => {
-                let left_val = self.evaluate_expression(left)?;
-                let right_val = self.evaluate_expression(right)?;
-                
-                match (left_val, operator, right_val) {
-                    (Value::Number(l), Token::Plus, Value::Number(r)) => Ok(Value::Number(l + r)),
-                    (Value::Number(l), Token::Minus, Value::Number(r)) => Ok(Value::Number(l - r)),
-                    (Value::Number(l), Token::Multiply, Value::Number(r)) => Ok(Value::Number(l * r)),
-                    (Value::Number(l), Token::Divide, Value::Number(r)) => {
-                        if r == 0.0 {
-                            Err("Division by zero".to_string())
-                        } else {
-                            Ok(Value::Number(l / r))
-                        }
-                    },
-                    (Value::Number(l), Token::Power, Value::Number(r)) => Ok(Value::Number(l.powf(r))),
-                    (Value::Number(l), Token::LessThan, Value::Number(r)) => Ok(Value::Number(if l < r { 1.0 } else { 0.0 })),
-                    (Value::Number(l), Token::GreaterThan, Value::Number(r)) => Ok(Value::Number(if l > r { 1.0 } else { 0.0 })),
-                    (Value::Number(l), Token::Equals, Value::Number(r)) => Ok(Value::Number(if l == r { 1.0 } else { 0.0 })),
-                    (Value::Number(l), Token::LessOrEqual, Value::Number(r)) => Ok(Value::Number(if l <= r { 1.0 } else { 0.0 })),
-                    (Value::Number(l), Token::GreaterOrEqual, Value::Number(r)) => Ok(Value::Number(if l >= r { 1.0 } else { 0.0 })),
-                    (Value::Number(l), Token::NotEqual, Value::Number(r)) => Ok(Value::Number(if l != r { 1.0 } else { 0.0 })),
-                    _ => Err("Invalid operation or type mismatch".to_string()),
-                }
-            },
-            Expression::FunctionCall { name, arguments } => {
-                match name.as_str() {
-                    "ABS" => {
-                        let arg = self.evaluate_expression(&arguments[0])?;
-                        match arg {
-                            Value::Number(n) => Ok(Value::Number(n.abs())),
-                            _ => Err("ABS requires a number argument".to_string()),
-                        }
-                    },
-                    "SQR" => {
-                        let arg = self.evaluate_expression(&arguments[0])?;
-                        match arg {
-                            Value::Number(n) => {
-                                if n < 0.0 {
-                                    Err("Cannot take square root of negative number".to_string())
-                                } else {
-                                    Ok(Value::Number(n.sqrt()))
-                                }
-                            },
-                            _ => Err("SQR requires a number argument".to_string()),
-                        }
-                    },
-                    "SIN" => {
-                        let arg = self.evaluate_expression(&arguments[0])?;
-                        match arg {
-                            Value::Number(n) => Ok(Value::Number(n.sin())),
-                            _ => Err("SIN requires a number argument".to_string()),
-                        }
-                    },
-                    "COS" => {
-                        let arg = self.evaluate_expression(&arguments[0])?;
-                        match arg {
-                            Value::Number(n) => Ok(Value::Number(n.cos())),
-                            _ => Err("COS requires a number argument".to_string()),
-                        }
-                    },
-                    "TAN" => {
-                        let arg = self.evaluate_expression(&arguments[0])?;
-                        match arg {
-                            Value::Number(n) => Ok(Value::Number(n.tan())),
-                            _ => Err("TAN requires a number argument".to_string()),
-                        }
-                    },
-                    "RND" => Ok(Value::Number(rand::thread_rng().gen())),
-                    "INT" => {
-                        let arg = self.evaluate_expression(&arguments[0])?;
-                        match arg {
-                            Value::Number(n) => Ok(Value::Number(n.floor())),
-                            _ => Err("INT requires a number argument".to_string()),
-                        }
-                    },
-                    _ => Err(format!("Unknown function: {}", name)),
-                }
-            },
-        }
+// Errors are printed as they are, not through Debug the way returning them from main would
+fn main() {
+    let cli = Cli::parse_from(with_subcommand(std::env::args().collect()));
+    match run(cli.command) {
+        Ok(0) => {},
+        Ok(status) => std::process::exit(status),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
     }
 }
 
-#[derive(Debug, Clone)]
-enum Value {
-    Number(f64),
-    String(String),
+// The status to exit with, which only a program run can make anything but 0
+fn run(command: Command) -> Result<i32, String> {
+    let finished = match command {
+        Command::Run(args) => return run_program(args),
+        Command::Compile(args) => compile(args),
+        Command::Debug(args) => debug(args),
+        Command::Check(args) => check(args),
+        Command::Test(args) => golden::run(&args.dir, &args.settings.config()?, args.bless),
+        Command::Fmt(args) => run_fmt(args),
+        Command::Repl(settings) => repl::run(&settings.config()?),
+        Command::Ast(source) => ast(source),
+        Command::Renum(args) => run_renum(args),
+        Command::Highlight(args) => run_highlight(args),
+        Command::Lsp => lsp::run(),
+        Command::Serve(args) => serve::run(&args.listen, args.allow_origin, args.settings.config()?),
+        Command::Stats(args) => run_stats(args),
+        Command::Expand(args) => {
+            let contents = std::fs::read_to_string(&args.file)
+                .map_err(|e| format!("Error reading file: {}", e))?;
+            print!("{}", golf::expand(&contents));
+            Ok(())
+        },
+        Command::Minimize(args) => run_minimize(args),
+        Command::Fuzz(args) => {
+            let seed = args.seed.unwrap_or_else(rand::random);
+            println!("Fuzzing interpreter with seed {}...", seed);
+            fuzz::Fuzzer::new(seed).run(args.iterations)
+        },
+        Command::Explain(args) => explain(args),
+    };
+    finished.map(|()| 0)
 }
-
-fn main() -> Result<(), String> {
-    let args: Vec<String> = std::env::args().collect();
-    let should_compile = args.len() > 1 && args[1] == "--compile";
-
-    println!("Reading BASIC code from code.bs...");
-    let contents = std::fs::read_to_string("code.bs")
-        .map_err(|e| format!("Error reading file: {}", e))?;
-
-    let mut tokens = tokenize(&contents);
-    let mut parser = Parser::new(tokens);
-    let program = parser.parse_program();
-
-    if should_compile {
-        println!("Compiling to Rust code...");
-        let mut compiler = Compiler::new();
-        let rust_code = compiler.compile_program(&program);
-        
-        // Write Rust code to a temporary file
-        std::fs::write("temp.rs", rust_code)
-            .map_err(|e| format!("Error writing Rust code: {}", e))?;
-        
-        // Compile the Rust code
-        println!("Compiling to executable...");
-        let output = std::process::Command::new("rustc")
-            .args(&["temp.rs", "-o", "code.exe"])
-            .output()
-            .map_err(|e| format!("Failed to run rustc: {}", e))?;
-        
-        if !output.status.success() {
-            return Err(format!("Compilation failed: {}", 
-                String::from_utf8_lossy(&output.stderr)));
-        }
-        
-        // Clean up temporary file
-        std::fs::remove_file("temp.rs")
-            .map_err(|e| format!("Error removing temporary file: {}", e))?;
-        
-        println!("Successfully compiled to code.exe!");
-    } else {
-        let mut interpreter = Interpreter::new();
-        interpreter.execute_program(program)?;
-        println!("\nProgram execution completed.");
-    }
-    
-    Ok(())
-}
\ No newline at end of file
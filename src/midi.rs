@@ -0,0 +1,76 @@
+// Headless audio for --midi: BEEP, SOUND and PLAY are recorded to a standard MIDI file
+// instead of being played, and nothing waits for the notes to finish
+use crate::audio::Audio;
+
+// 480 ticks per quarter note at the default 120 beats per minute
+const TICKS_PER_QUARTER: u16 = 480;
+const TICKS_PER_SECOND: f64 = TICKS_PER_QUARTER as f64 * 2.0;
+const VELOCITY: u8 = 96;
+
+pub struct Recorder {
+    path: String,
+    track: Vec<u8>,
+    // Ticks since the last event, from rests and articulation gaps
+    delay: u32,
+}
+
+impl Recorder {
+    pub fn new(path: &str) -> Self {
+        Recorder {
+            path: path.to_string(),
+            track: Vec::new(),
+            delay: 0,
+        }
+    }
+
+    fn event(&mut self, bytes: &[u8]) {
+        write_variable_length(&mut self.track, std::mem::take(&mut self.delay));
+        self.track.extend_from_slice(bytes);
+    }
+}
+
+impl Audio for Recorder {
+    fn tone(&mut self, frequency: f64, seconds: f64) -> Result<(), String> {
+        let note = (69.0 + 12.0 * (frequency / 440.0).log2()).round().clamp(0.0, 127.0) as u8;
+        self.event(&[0x90, note, VELOCITY]);
+        self.delay = ticks(seconds);
+        self.event(&[0x80, note, 0]);
+        Ok(())
+    }
+
+    fn rest(&mut self, seconds: f64) -> Result<(), String> {
+        self.delay += ticks(seconds);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        self.event(&[0xFF, 0x2F, 0x00]);
+        let mut file = Vec::new();
+        file.extend_from_slice(b"MThd");
+        file.extend_from_slice(&6u32.to_be_bytes());
+        // Format 0: a single track
+        file.extend_from_slice(&0u16.to_be_bytes());
+        file.extend_from_slice(&1u16.to_be_bytes());
+        file.extend_from_slice(&TICKS_PER_QUARTER.to_be_bytes());
+        file.extend_from_slice(b"MTrk");
+        file.extend_from_slice(&(self.track.len() as u32).to_be_bytes());
+        file.extend_from_slice(&self.track);
+        std::fs::write(&self.path, file)
+            .map_err(|e| format!("Error writing {}: {}", self.path, e))
+    }
+}
+
+fn ticks(seconds: f64) -> u32 {
+    (seconds * TICKS_PER_SECOND).round() as u32
+}
+
+// Delta times are big-endian, seven bits a byte, with the high bit set on all but the last
+fn write_variable_length(out: &mut Vec<u8>, mut value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
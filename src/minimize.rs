@@ -0,0 +1,107 @@
+use std::panic;
+use crate::fuzz::{run_guarded, Outcome};
+
+pub enum Check {
+    Crashes,
+    Errors,
+    Contains(String),
+}
+
+impl Check {
+    pub fn parse(text: &str) -> Check {
+        match text {
+            "crashes" => Check::Crashes,
+            "errors" => Check::Errors,
+            _ => Check::Contains(text.to_string()),
+        }
+    }
+
+    fn matches(&self, outcome: &Outcome) -> bool {
+        match (self, outcome) {
+            (Check::Crashes, Outcome::Crash(_)) => true,
+            (Check::Errors, Outcome::Crash(_) | Outcome::Error(_)) => true,
+            (Check::Contains(text), Outcome::Crash(message) | Outcome::Error(message)) => message.contains(text.as_str()),
+            (Check::Contains(text), Outcome::Finished(output)) => output.contains(text.as_str()),
+            _ => false,
+        }
+    }
+}
+
+pub struct Minimizer {
+    check: Check,
+    inputs: Vec<String>,
+    runs: usize,
+}
+
+impl Minimizer {
+    pub fn new(check: Check, inputs: Vec<String>) -> Self {
+        Minimizer {
+            check,
+            inputs,
+            runs: 0,
+        }
+    }
+
+    pub fn minimize(&mut self, source: &str) -> Result<String, String> {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+
+        let lines: Vec<String> = source.lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| l.to_string())
+            .collect();
+        let result = if self.fails(&lines) {
+            Ok(self.ddmin(lines).join("\n"))
+        } else {
+            Err("The program does not fail the check, nothing to minimize".to_string())
+        };
+
+        panic::set_hook(default_hook);
+        result
+    }
+
+    pub fn runs(&self) -> usize {
+        self.runs
+    }
+
+    fn fails(&mut self, lines: &[String]) -> bool {
+        self.runs += 1;
+        let outcome = run_guarded(&lines.join("\n"), self.inputs.clone());
+        self.check.matches(&outcome)
+    }
+
+    // Zeller's ddmin: remove ever smaller chunks of lines while the failure persists
+    fn ddmin(&mut self, mut lines: Vec<String>) -> Vec<String> {
+        let mut granularity = 2;
+        while lines.len() >= 2 {
+            let chunk_size = lines.len().div_ceil(granularity);
+            let mut reduced = false;
+
+            for start in (0..lines.len()).step_by(chunk_size) {
+                let end = (start + chunk_size).min(lines.len());
+                let complement: Vec<String> = lines[..start].iter()
+                    .chain(lines[end..].iter())
+                    .cloned()
+                    .collect();
+                if self.fails(&complement) {
+                    lines = complement;
+                    granularity = (granularity - 1).max(2);
+                    reduced = true;
+                    break;
+                }
+            }
+
+            if !reduced {
+                if granularity >= lines.len() {
+                    break;
+                }
+                granularity = (granularity * 2).min(lines.len());
+            }
+        }
+
+        if lines.len() == 1 && self.fails(&[]) {
+            lines.clear();
+        }
+        lines
+    }
+}
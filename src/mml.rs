@@ -0,0 +1,152 @@
+// The GW-BASIC music macro language used by PLAY, e.g. "T160 O4 L8 C D E F G4"
+use crate::errors::{Code, Error};
+
+pub struct Note {
+    // None for a rest
+    pub frequency: Option<f64>,
+    pub seconds: f64,
+    // Silence after the note, from MN/ML/MS articulation
+    pub gap: f64,
+}
+
+// Settings carry over from one PLAY statement to the next, as in GW-BASIC
+pub struct Music {
+    octave: i32,
+    length: f64,
+    tempo: f64,
+    articulation: f64,
+}
+
+impl Music {
+    pub fn new() -> Self {
+        Music {
+            octave: 4,
+            length: 4.0,
+            tempo: 120.0,
+            articulation: 7.0 / 8.0,
+        }
+    }
+
+    pub fn parse(&mut self, source: &str) -> Result<Vec<Note>, Error> {
+        let chars: Vec<char> = source.to_uppercase().chars().filter(|c| !c.is_whitespace()).collect();
+        let mut notes = Vec::new();
+        let mut pos = 0;
+
+        while pos < chars.len() {
+            let command = chars[pos];
+            pos += 1;
+            match command {
+                'A'..='G' => {
+                    let mut semitone = match command {
+                        'C' => 0,
+                        'D' => 2,
+                        'E' => 4,
+                        'F' => 5,
+                        'G' => 7,
+                        'A' => 9,
+                        _ => 11,
+                    };
+                    match chars.get(pos) {
+                        Some('#') | Some('+') => {
+                            semitone += 1;
+                            pos += 1;
+                        },
+                        Some('-') => {
+                            semitone -= 1;
+                            pos += 1;
+                        },
+                        _ => {},
+                    }
+                    let length = match number(&chars, &mut pos) {
+                        Some(n) => check(n, 1, 64, "note length")? as f64,
+                        None => self.length,
+                    };
+                    let dots = dots(&chars, &mut pos);
+                    notes.push(self.note(Some(self.octave * 12 + semitone + 1), length, dots));
+                },
+                'N' => {
+                    let n = number(&chars, &mut pos).ok_or("Expected a number after N")?;
+                    let n = check(n, 0, 84, "note number")?;
+                    let dots = dots(&chars, &mut pos);
+                    notes.push(self.note((n > 0).then_some(n as i32), self.length, dots));
+                },
+                'P' | 'R' => {
+                    let n = number(&chars, &mut pos).ok_or("Expected a length after P")?;
+                    let length = check(n, 1, 64, "pause length")? as f64;
+                    let dots = dots(&chars, &mut pos);
+                    notes.push(self.note(None, length, dots));
+                },
+                'O' => {
+                    let n = number(&chars, &mut pos).ok_or("Expected a number after O")?;
+                    self.octave = check(n, 0, 6, "octave")? as i32;
+                },
+                '>' => self.octave = (self.octave + 1).min(6),
+                '<' => self.octave = (self.octave - 1).max(0),
+                'L' => {
+                    let n = number(&chars, &mut pos).ok_or("Expected a number after L")?;
+                    self.length = check(n, 1, 64, "length")? as f64;
+                },
+                'T' => {
+                    let n = number(&chars, &mut pos).ok_or("Expected a number after T")?;
+                    self.tempo = check(n, 32, 255, "tempo")? as f64;
+                },
+                'M' => {
+                    let mode = chars.get(pos).copied();
+                    pos += 1;
+                    match mode {
+                        Some('N') => self.articulation = 7.0 / 8.0,
+                        Some('L') => self.articulation = 1.0,
+                        Some('S') => self.articulation = 3.0 / 4.0,
+                        // Foreground and background play are the same here
+                        Some('F') | Some('B') => {},
+                        _ => return Err("Expected MN, ML, MS, MF or MB".into()),
+                    }
+                },
+                other => return Err(format!("Unknown PLAY command '{}'", other).into()),
+            }
+        }
+        Ok(notes)
+    }
+
+    // Note numbers count semitones from C in octave 0 as 1; N46 is A440
+    fn note(&self, number: Option<i32>, length: f64, dots: u32) -> Note {
+        let dotted = (0..=dots).map(|d| 0.5f64.powi(d as i32)).sum::<f64>();
+        let total = 60.0 / self.tempo * 4.0 / length * dotted;
+        match number {
+            Some(n) => Note {
+                frequency: Some(440.0 * 2f64.powf((n - 46) as f64 / 12.0)),
+                seconds: total * self.articulation,
+                gap: total * (1.0 - self.articulation),
+            },
+            None => Note {
+                frequency: None,
+                seconds: total,
+                gap: 0.0,
+            },
+        }
+    }
+}
+
+fn number(chars: &[char], pos: &mut usize) -> Option<u32> {
+    let start = *pos;
+    while *pos < chars.len() && chars[*pos].is_ascii_digit() {
+        *pos += 1;
+    }
+    chars[start..*pos].iter().collect::<String>().parse().ok()
+}
+
+fn dots(chars: &[char], pos: &mut usize) -> u32 {
+    let mut count = 0;
+    while chars.get(*pos) == Some(&'.') {
+        count += 1;
+        *pos += 1;
+    }
+    count
+}
+
+fn check(n: u32, min: u32, max: u32, what: &str) -> Result<u32, Error> {
+    if n < min || n > max {
+        return Err(Error::new(Code::OutOfRange, format!("PLAY {} {} out of range {}-{}", what, n, min, max)));
+    }
+    Ok(n)
+}
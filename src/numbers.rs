@@ -0,0 +1,20 @@
+// How PRINT writes numbers. The same code runs in the interpreter and is pasted into compiled
+// programs, so both print identically: whole numbers have no decimal point, and numbers that
+// are not negative get the classic leading space where the sign would go.
+shared! {
+    pub fn format_number(n: f64) -> String {
+        if n == 0.0 {
+            // Also catches -0
+            " 0".to_string()
+        } else if n < 0.0 {
+            n.to_string()
+        } else {
+            format!(" {}", n)
+        }
+    }
+}
+
+// Helper emitted into compiled programs that PRINT numbers
+pub fn rust_helpers() -> String {
+    format!("\n{}\n", RUST_SOURCE)
+}
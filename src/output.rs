@@ -0,0 +1,124 @@
+// Everything a run produced, for host applications that show or grade it rather than print it.
+// `Interpreter::run_captured` fills one in with its own backend, so nothing reaches the terminal.
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::errors::Error;
+use crate::io::IoBackend;
+use crate::{LangError, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    // PRINT, and SHELL's output
+    Print,
+    // The text INPUT shows before it reads
+    Prompt,
+    // A line INPUT read, without its newline
+    Input,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    // Ran off the end of the program
+    Finished,
+    // Stopped at an END statement
+    End,
+    // Stopped to write a checkpoint
+    Checkpoint,
+    // The graphics window was closed
+    WindowClosed,
+    StepLimit,
+    TimeLimit,
+    MemoryLimit,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct RunOutput {
+    // In the order they happened; text of the same kind written in a row is one segment
+    pub segments: Vec<Segment>,
+    // The error that stopped the program, with its line and column
+    pub error: Option<LangError>,
+    pub exit: ExitReason,
+    // Statements run
+    pub steps: usize,
+    pub elapsed: Duration,
+    // Every variable's value when the run stopped, by name
+    pub variables: BTreeMap<String, Value>,
+}
+
+impl RunOutput {
+    // A variable's value when the run stopped; the name is uppercased like BASIC names
+    pub fn var(&self, name: &str) -> Option<&Value> {
+        self.variables.get(&name.to_uppercase())
+    }
+
+    // What PRINT wrote, as it would appear on the terminal without the prompts and input
+    pub fn stdout(&self) -> String {
+        self.segments.iter()
+            .filter(|segment| segment.kind == SegmentKind::Print)
+            .map(|segment| segment.text.as_str())
+            .collect()
+    }
+
+    // The whole conversation as it would look on a terminal, with the input echoed
+    pub fn transcript(&self) -> String {
+        self.segments.iter().map(|segment| match segment.kind {
+            SegmentKind::Input => format!("{}\n", segment.text),
+            _ => segment.text.clone(),
+        }).collect()
+    }
+}
+
+// Like BufferIo, but keeping what kind of text each piece is
+pub(crate) struct CaptureIo {
+    input: VecDeque<String>,
+    segments: Arc<Mutex<Vec<Segment>>>,
+}
+
+impl CaptureIo {
+    pub fn new(input: Vec<String>) -> Self {
+        CaptureIo { input: input.into(), segments: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    pub fn segments(&self) -> Arc<Mutex<Vec<Segment>>> {
+        Arc::clone(&self.segments)
+    }
+
+    fn push(&mut self, kind: SegmentKind, text: &str) {
+        let mut segments = self.segments.lock().unwrap();
+        match segments.last_mut() {
+            Some(last) if last.kind == kind && kind != SegmentKind::Input => last.text.push_str(text),
+            _ => segments.push(Segment { kind, text: text.to_string() }),
+        }
+    }
+}
+
+impl IoBackend for CaptureIo {
+    fn write(&mut self, text: &str) -> Result<(), Error> {
+        self.push(SegmentKind::Print, text);
+        Ok(())
+    }
+
+    fn prompt(&mut self, text: &str) -> Result<(), Error> {
+        self.push(SegmentKind::Prompt, text);
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<String, Error> {
+        let line = self.input.pop_front().ok_or_else(|| Error::from("No input available"))?;
+        self.push(SegmentKind::Input, line.trim_end_matches(['\r', '\n']));
+        Ok(line)
+    }
+
+    fn interactive(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,136 @@
+// A Python module, for driving programs from notebooks and test harnesses. Built with
+// `--features python`, usually by `maturin develop` or `pip install .`, which read
+// pyproject.toml. Runs collect their output and return it instead of printing, and errors are
+// raised as lang.LangError with the message the command line would print.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyTypeError};
+use pyo3::prelude::*;
+
+use crate::io::BufferIo;
+use crate::{Interpreter, Program, StepResult, Value};
+
+create_exception!(lang, LangError, PyException);
+
+fn raise(error: crate::LangError) -> PyErr {
+    LangError::new_err(error.to_string())
+}
+
+fn to_python(py: Python<'_>, value: Value) -> PyResult<Py<PyAny>> {
+    Ok(match value {
+        Value::Number(number) => number.into_pyobject(py)?.into_any().unbind(),
+        Value::String(text) => text.into_pyobject(py)?.into_any().unbind(),
+    })
+}
+
+// A parsed and checked program, which any number of interpreters can run
+#[pyclass(name = "Program", frozen)]
+struct PyProgram(Arc<Program>);
+
+#[pyfunction]
+fn parse(source: &str) -> PyResult<PyProgram> {
+    let program = crate::parse(source).map_err(raise)?;
+    crate::check_program(&program).map_err(raise)?;
+    Ok(PyProgram(Arc::new(program)))
+}
+
+// Interpreters keep RefCells, so each stays on the Python thread that made it. Stepping
+// collects what the program prints in `output`, since a notebook doesn't show the process's
+// stdout.
+#[pyclass(name = "Interpreter", unsendable)]
+struct PyInterpreter {
+    interpreter: Interpreter,
+    output: Arc<Mutex<String>>,
+}
+
+#[pymethods]
+impl PyInterpreter {
+    #[new]
+    #[pyo3(signature = (sandbox = false, max_steps = None, timeout = None, seed = None))]
+    fn new(sandbox: bool, max_steps: Option<usize>, timeout: Option<f64>, seed: Option<u64>) -> PyResult<Self> {
+        let buffer = BufferIo::new(Vec::new());
+        let output = buffer.output();
+        let mut interpreter = Interpreter::new();
+        interpreter.io = Box::new(buffer);
+        interpreter.sandbox = sandbox;
+        interpreter.max_steps = max_steps;
+        interpreter.timeout = timeout.map(Duration::try_from_secs_f64).transpose()
+            .map_err(|_| PyTypeError::new_err("timeout must be a number of seconds"))?;
+        if let Some(seed) = seed {
+            interpreter.seed(seed);
+        }
+        Ok(PyInterpreter { interpreter, output })
+    }
+
+    // Runs the whole program with `input` as the lines INPUT reads, and returns what it printed
+    #[pyo3(signature = (program, input = Vec::new()))]
+    fn run(&mut self, program: &PyProgram, input: Vec<String>) -> PyResult<String> {
+        let output = self.interpreter.run_captured(&program.0, input.into_iter().map(|line| format!("{}\n", line)).collect());
+        match output.error {
+            Some(error) => Err(raise(error)),
+            None => Ok(output.stdout()),
+        }
+    }
+
+    // Gets the program ready for step, at its first line
+    fn load(&mut self, program: &PyProgram) {
+        self.interpreter.load(&program.0);
+    }
+
+    // Runs one statement: "ran", "finished", or "input" when INPUT needs provide_input first
+    fn step(&mut self) -> PyResult<&'static str> {
+        match self.interpreter.step() {
+            StepResult::Ran => Ok("ran"),
+            StepResult::NeedsInput => Ok("input"),
+            StepResult::Finished => Ok("finished"),
+            StepResult::Error(error) => Err(raise(error)),
+        }
+    }
+
+    fn provide_input(&mut self, line: &str) {
+        self.interpreter.provide_input(line);
+    }
+
+    // What the program has printed while stepping since the last call
+    fn take_output(&self) -> String {
+        std::mem::take(&mut *self.output.lock().unwrap())
+    }
+
+    // The number of the line step runs next, or None at the end
+    #[getter]
+    fn line(&self) -> Option<u32> {
+        self.interpreter.line_number()
+    }
+
+    // A float for a number, a str for a string, or None when the variable has no value
+    fn get_var(&self, py: Python<'_>, name: &str) -> PyResult<Option<Py<PyAny>>> {
+        self.interpreter.get_var(name).map(|value| to_python(py, value)).transpose()
+    }
+
+    fn set_var(&mut self, name: &str, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        let value = match value.extract::<String>() {
+            Ok(text) => Value::String(text),
+            Err(_) => Value::Number(value.extract::<f64>().map_err(|_| PyTypeError::new_err("a variable holds a float or a str"))?),
+        };
+        self.interpreter.set_var(name, value).map_err(LangError::new_err)
+    }
+
+    // Every variable that has a value, as a dict
+    fn variables(&self, py: Python<'_>) -> PyResult<BTreeMap<String, Py<PyAny>>> {
+        self.interpreter.variables().into_iter()
+            .map(|(name, value)| Ok((name, to_python(py, value)?)))
+            .collect()
+    }
+}
+
+#[pymodule]
+fn lang(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(parse, module)?)?;
+    module.add_class::<PyProgram>()?;
+    module.add_class::<PyInterpreter>()?;
+    module.add("LangError", module.py().get_type::<LangError>())?;
+    Ok(())
+}
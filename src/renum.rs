@@ -0,0 +1,127 @@
+// RENUM: numbers the lines from `start` in steps of `increment`, in the order they run, and
+// points every GOTO, THEN, ELSE and TASK at its line's new number. The numbers are changed in
+// the text itself, so the rest of each line keeps its spacing and case.
+use std::collections::HashMap;
+
+use crate::Token;
+
+// Where a number goes: in place of the digits at a line and column, or before a line that had none
+struct Edit {
+    line: usize,
+    column: usize,
+    replaces: bool,
+    number: u32,
+}
+
+pub fn renumber(source: &str, start: u32, increment: u32) -> Result<String, String> {
+    if increment == 0 {
+        return Err("RENUM needs an increment of at least 1".to_string());
+    }
+    crate::parse(source).map_err(|e| e.to_string())?;
+    let lexed = crate::tokenize(source, false);
+
+    // The number each line has now, which without one is its place in the program as it is for
+    // the parser, and the number it gets
+    let mut numbers = HashMap::new();
+    let mut edits = Vec::new();
+    let mut next = Some(start);
+    let mut index = 0;
+    for (i, token) in lexed.tokens.iter().enumerate() {
+        let line_start = i == 0 || matches!(lexed.tokens[i - 1], Token::EOL);
+        if !line_start || matches!(token, Token::EOL | Token::EOF) {
+            continue;
+        }
+        let number = next.ok_or_else(|| format!("Line numbers would go past {}", u32::MAX))?;
+        let (line, column) = lexed.positions[i];
+        let old = match token {
+            &Token::LineNumber(n) => n,
+            _ => index,
+        };
+        edits.push(Edit { line, column, replaces: matches!(token, Token::LineNumber(_)), number });
+        numbers.entry(old).or_insert(number);
+        next = number.checked_add(increment);
+        index += 1;
+    }
+
+    let mut current = 0;
+    for (i, token) in lexed.tokens.iter().enumerate() {
+        if let &Token::LineNumber(n) = token {
+            current = n;
+        }
+        let (Token::Number(target), Some(Token::Goto | Token::Then | Token::Else | Token::Task)) =
+            (token, i.checked_sub(1).map(|i| &lexed.tokens[i]))
+        else {
+            continue;
+        };
+        let number = *numbers.get(&(*target as u32))
+            .ok_or_else(|| format!("Undefined line number {} in line {}", target, current))?;
+        let (line, column) = lexed.positions[i];
+        edits.push(Edit { line, column, replaces: true, number });
+    }
+
+    let mut renumbered = String::new();
+    for (i, text) in source.split_inclusive('\n').enumerate() {
+        let mut text = text.to_string();
+        let mut line_edits: Vec<&Edit> = edits.iter().filter(|edit| edit.line == i + 1).collect();
+        // From the right, so each edit leaves the columns of the ones still to come alone
+        line_edits.sort_by_key(|edit| std::cmp::Reverse(edit.column));
+        for edit in line_edits {
+            let offset = text.char_indices().nth(edit.column - 1).map_or(text.len(), |(offset, _)| offset);
+            if edit.replaces {
+                let digits = text[offset..].find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                    .unwrap_or(text.len() - offset);
+                text.replace_range(offset..offset + digits, &edit.number.to_string());
+            } else {
+                text.insert_str(offset, &format!("{} ", edit.number));
+            }
+        }
+        renumbered.push_str(&text);
+    }
+    Ok(renumbered)
+}
+
+// RENUM's arguments, `new_start, increment`, either of which can be left out for 10
+pub fn arguments(text: &str) -> Result<(u32, u32), String> {
+    let (start, increment) = text.split_once(',').unwrap_or((text, ""));
+    let number = |text: &str| match text.trim() {
+        "" => Ok(10),
+        digits => digits.parse::<u32>().map_err(|_| format!("{} isn't a line number", digits)),
+    };
+    Ok((number(start)?, number(increment)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jumps_follow_their_lines() {
+        let source = "5 LET A = 1\n7 IF A > 3 THEN 30 ELSE 12\n12 LET A = A + 1\n  15 goto   7\n30 END\n";
+        assert_eq!(
+            renumber(source, 100, 5),
+            Ok("100 LET A = 1\n105 IF A > 3 THEN 120 ELSE 110\n110 LET A = A + 1\n  115 goto   105\n120 END\n".to_string()),
+        );
+    }
+
+    #[test]
+    fn lines_without_numbers_get_one() {
+        assert_eq!(renumber("PRINT 1\nGOTO 0\n", 10, 10), Ok("10 PRINT 1\n20 GOTO 10\n".to_string()));
+    }
+
+    #[test]
+    fn bad_programs_are_left_alone() {
+        assert_eq!(renumber("10 GOTO 50\n", 10, 10), Err("Undefined line number 50 in line 10".to_string()));
+        assert_eq!(renumber("10 END\n", 10, 0), Err("RENUM needs an increment of at least 1".to_string()));
+        assert!(renumber("10 PRINT (\n", 10, 10).is_err());
+        assert_eq!(renumber("10 END\n20 END\n", u32::MAX, 1), Err(format!("Line numbers would go past {}", u32::MAX)));
+    }
+
+    #[test]
+    fn arguments_default_to_ten() {
+        assert_eq!(arguments(""), Ok((10, 10)));
+        assert_eq!(arguments("100"), Ok((100, 10)));
+        assert_eq!(arguments(" 100 , 5 "), Ok((100, 5)));
+        assert_eq!(arguments(",5"), Ok((10, 5)));
+        assert_eq!(arguments("A"), Err("A isn't a line number".to_string()));
+    }
+}
@@ -0,0 +1,218 @@
+// `lang repl`: type numbered lines to build a program, then RUN it. A line number on its own
+// deletes that line. LIST, DELETE, NEW, SAVE, LOAD and RENUM are the classic direct commands, and UNDO
+// and REDO step back and forward through the edits. Input goes through rustyline, so the arrow
+// keys walk the history kept in ~/.lang_history, Ctrl-R searches it and Tab completes keywords,
+// functions and the variables of the last RUN.
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+
+use crate::config::Config;
+use crate::editor::Editor;
+use crate::Interpreter;
+
+const COMMANDS: &[&str] = &["LIST", "DELETE", "NEW", "SAVE", "LOAD", "RENUM", "UNDO", "REDO", "RUN", "QUIT", "EXIT", "SYSTEM"];
+
+const KEYWORDS: &[&str] = &[
+    "LET", "PRINT", "IF", "THEN", "ELSE", "FOR", "TO", "STEP", "NEXT", "END", "INPUT", "GOTO",
+    "OPEN", "CLOSE", "FIELD", "GET", "PUT", "LSET", "RSET", "AS", "KILL", "NAME", "FILES", "CHAIN", "COMMON", "DEF",
+    "DEFINT", "DEFSNG", "DEFDBL", "DEFSTR", "CHECKPOINT", "CLS", "LOCATE", "COLOR", "SCREEN",
+    "PSET", "LINE", "CIRCLE", "PAINT", "SAVEIMAGE", "SLEEP", "BEEP", "SOUND", "PLAY", "DRAW",
+    "SPRITE", "OPTION", "STORESET", "CLIPSET", "TASK", "AWAIT", "SEND", "RECEIVE", "SHELL",
+    "OPENSERIAL", "SERIALOUT", "SERIALIN", "OUTPIN",
+];
+
+const FUNCTIONS: &[&str] = &[
+    "ABS", "SQR", "SIN", "COS", "TAN", "RND", "INT", "SHELL$", "RUN$", "RC", "ENVIRON$",
+    "COMMAND$", "ARGS$", "ARGC", "CLIPGET$", "STOREGET", "STOREGET$", "COLLISION", "INPIN", "MKI$", "MKS$",
+    "MKD$", "CVI", "CVS", "CVD", "LOF", "LOC",
+];
+
+// Completes the word before the cursor from the keywords, the functions and `names`, which are
+// the variables and DEF FN functions the last RUN left behind
+#[derive(Default)]
+struct Helper {
+    names: Vec<String>,
+}
+
+impl Completer for Helper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '$' || c == '_'))
+            .map_or(0, |i| i + 1);
+        let word = line[start..pos].to_ascii_uppercase();
+        if word.is_empty() || word.starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok((start, Vec::new()));
+        }
+        let mut candidates: Vec<String> = COMMANDS.iter().chain(KEYWORDS).chain(FUNCTIONS)
+            .map(|name| name.to_string())
+            .chain(self.names.iter().cloned())
+            .filter(|name| name.starts_with(&word))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for Helper {
+    type Hint = String;
+}
+
+impl Highlighter for Helper {}
+
+impl Validator for Helper {}
+
+impl rustyline::Helper for Helper {}
+
+fn history_path() -> Option<std::path::PathBuf> {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(std::path::Path::new(&home).join(".lang_history"))
+}
+
+pub fn run(config: &Config) -> Result<(), String> {
+    let mut editor = Editor::new();
+    // Like a shell: Tab fills in as much as the candidates share, and a second Tab lists them
+    let settings = rustyline::Config::builder().completion_type(rustyline::CompletionType::List).build();
+    let mut reader = rustyline::Editor::<Helper, DefaultHistory>::with_config(settings)
+        .map_err(|e| format!("Failed to start the line editor: {}", e))?;
+    reader.set_helper(Some(Helper::default()));
+    let history = history_path();
+    if let Some(history) = &history {
+        // There's no history yet the first time
+        let _ = reader.load_history(history);
+    }
+    println!(
+        "LANG {}. Type numbered lines, then RUN. LIST, DELETE, NEW, SAVE, LOAD, RENUM, UNDO, REDO and QUIT also work.",
+        env!("CARGO_PKG_VERSION")
+    );
+    let result = read_commands(&mut reader, &mut editor, config);
+    if let Some(history) = &history {
+        if let Err(e) = reader.save_history(history) {
+            eprintln!("Warning: couldn't save the history to {}: {}", history.display(), e);
+        }
+    }
+    result
+}
+
+fn read_commands(
+    reader: &mut rustyline::Editor<Helper, DefaultHistory>,
+    editor: &mut Editor,
+    config: &Config,
+) -> Result<(), String> {
+    loop {
+        let input = match reader.readline("> ") {
+            Ok(input) => input,
+            // Ctrl-C drops what was typed, as in a shell; Ctrl-D leaves
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(format!("Failed to read input: {}", e)),
+        };
+        let input = input.trim();
+        if input.is_empty() {
+            continue;
+        }
+        let _ = reader.add_history_entry(input);
+
+        let digits = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+        if digits > 0 {
+            let number = input[..digits].parse().map_err(|_| format!("Line number {} is too big", &input[..digits]));
+            match number {
+                Ok(number) if input[digits..].trim().is_empty() => editor.delete_line(number),
+                Ok(number) => editor.set_line(number, input[digits..].trim()),
+                Err(e) => println!("{}", e),
+            }
+            continue;
+        }
+
+        let (command, argument) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+        let argument = argument.trim();
+        let result = match command.to_uppercase().as_str() {
+            "UNDO" => {
+                println!("{}", editor.undo().map_or("Nothing to undo".to_string(), |s| format!("Undid: {}", s)));
+                Ok(())
+            },
+            "REDO" => {
+                println!("{}", editor.redo().map_or("Nothing to redo".to_string(), |s| format!("Redid: {}", s)));
+                Ok(())
+            },
+            "LIST" => line_range(argument).map(|(first, last)| print!("{}", editor.listing(first, last))),
+            "DELETE" if argument.is_empty() => Err("Usage: DELETE 10, DELETE 10-50, DELETE 10- or DELETE -50".to_string()),
+            "DELETE" => line_range(argument).map(|(first, last)| editor.delete_lines(first, last)),
+            "NEW" => {
+                editor.delete_lines(0, u32::MAX);
+                Ok(())
+            },
+            "SAVE" => file_name(argument).and_then(|path| {
+                std::fs::write(&path, editor.source()).map_err(|e| format!("Error writing {}: {}", path, e))?;
+                println!("Saved {}", path);
+                Ok(())
+            }),
+            "LOAD" => file_name(argument).and_then(|path| {
+                let source = std::fs::read_to_string(&path).map_err(|e| format!("Error reading {}: {}", path, e))?;
+                editor.load(&source)?;
+                println!("Loaded {}", path);
+                Ok(())
+            }),
+            "RENUM" => crate::renum::arguments(argument).and_then(|(start, increment)| {
+                let renumbered = crate::renum::renumber(&editor.source(), start, increment)?;
+                editor.load(&renumbered)
+            }),
+            "RUN" => run_program(&editor.source(), config).map(|names| {
+                if let Some(helper) = reader.helper_mut() {
+                    helper.names = names;
+                }
+            }),
+            "QUIT" | "EXIT" | "SYSTEM" => return Ok(()),
+            _ => Err(format!("Unknown command {}; lines of a program start with a number", input)),
+        };
+        if let Err(e) = result {
+            println!("{}", e);
+        }
+    }
+}
+
+// LIST and DELETE take a line, first-last, first- or -last, and LIST takes nothing for every line
+fn line_range(text: &str) -> Result<(u32, u32), String> {
+    let number = |text: &str, default: u32| match text.trim() {
+        "" => Ok(default),
+        digits => digits.parse::<u32>().map_err(|_| format!("{} isn't a line number", digits)),
+    };
+    match text.split_once('-') {
+        Some((first, last)) => Ok((number(first, 0)?, number(last, u32::MAX)?)),
+        None if text.is_empty() => Ok((0, u32::MAX)),
+        None => number(text, 0).map(|line| (line, line)),
+    }
+}
+
+// SAVE and LOAD take a file name, in quotes or not, and add .bs when it has no extension
+fn file_name(text: &str) -> Result<String, String> {
+    let name = text.trim_matches('"');
+    if name.is_empty() {
+        return Err("Usage: SAVE \"name\" or LOAD \"name\"".to_string());
+    }
+    if std::path::Path::new(name).extension().is_some() {
+        Ok(name.to_string())
+    } else {
+        Ok(format!("{}.bs", name))
+    }
+}
+
+// Each RUN starts from a fresh interpreter, like running a file. What it leaves is the names of its
+// variables and functions, for Tab to complete.
+fn run_program(source: &str, config: &Config) -> Result<Vec<String>, String> {
+    let program = crate::parse(source).map_err(|e| e.to_string())?;
+    crate::check_declarations(&program, config.strict)?;
+    crate::check_program(&program).map_err(|e| e.to_string())?;
+    let mut interpreter = Interpreter::new();
+    config.configure(&mut interpreter);
+    interpreter.run(&program).map_err(|e| e.to_string())?;
+    Ok(interpreter.variables.iter().map(|(name, _)| name.clone())
+        .chain(interpreter.functions.keys().cloned())
+        .collect())
+}
@@ -0,0 +1,63 @@
+// Terminal control for CLS, LOCATE and COLOR using ANSI escape sequences
+use crate::errors::{Code, Error};
+
+
+// GW-BASIC colors 0-7 (black, blue, green, cyan, red, magenta, brown, white) as ANSI color numbers;
+// 8-15 are the bright versions
+pub const ANSI_COLORS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+pub const CLEAR: &str = "\x1b[2J\x1b[H";
+
+// The furthest row or column LOCATE moves to, past any real terminal
+pub const MAX_POSITION: u32 = 255;
+
+pub fn locate(row: Option<f64>, col: Option<f64>) -> Result<String, Error> {
+    for n in [row, col].into_iter().flatten() {
+        if !(1.0..=MAX_POSITION as f64).contains(&n) {
+            return Err(Error::new(Code::OutOfRange, format!("LOCATE position {} out of range 1-{}", n, MAX_POSITION)));
+        }
+    }
+    Ok(match (row, col) {
+        (Some(row), Some(col)) => format!("\x1b[{};{}H", row as u32, col as u32),
+        (Some(row), None) => format!("\x1b[{}d", row as u32),
+        (None, Some(col)) => format!("\x1b[{}G", col as u32),
+        (None, None) => String::new(),
+    })
+}
+
+pub fn color(fg: Option<f64>, bg: Option<f64>) -> Result<String, Error> {
+    let mut codes = Vec::new();
+    if let Some(fg) = fg {
+        codes.push(color_code(fg, 30)?.to_string());
+    }
+    if let Some(bg) = bg {
+        codes.push(color_code(bg, 40)?.to_string());
+    }
+    if codes.is_empty() {
+        return Ok(String::new());
+    }
+    Ok(format!("\x1b[{}m", codes.join(";")))
+}
+
+fn color_code(color: f64, base: u8) -> Result<u8, Error> {
+    if !(0.0..16.0).contains(&color) {
+        return Err(Error::new(Code::OutOfRange, format!("Color {} out of range 0-15", color)));
+    }
+    let color = color as usize;
+    let bright = if color >= 8 { 60 } else { 0 };
+    Ok(base + ANSI_COLORS[color % 8] + bright)
+}
+
+// Helper emitted into compiled programs that use COLOR, sharing the palette above
+pub fn rust_helpers() -> String {
+    format!(
+        r#"
+fn basic_color(color: f64, base: u8) -> u8 {{
+    const ANSI_COLORS: [u8; 8] = {:?};
+    let color = (color as usize).min(15);
+    base + ANSI_COLORS[color % 8] + if color >= 8 {{ 60 }} else {{ 0 }}
+}}
+"#,
+        ANSI_COLORS
+    )
+}
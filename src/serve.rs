@@ -0,0 +1,222 @@
+// `lang serve`: runs programs sent over HTTP, the backend for a hosted playground. POST /run
+// takes {"source": "...", "input": ["line", ...]} and streams back newline-delimited JSON as the
+// program runs: an "output" event for each piece of text it prints, an "error" event if it
+// stops with one, and a "done" event with its exit status last. Programs always run sandboxed
+// and with time, memory and output limits, each on a thread of its own.
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::errors::{Code, Error};
+use crate::io::IoBackend;
+use crate::Interpreter;
+
+// Limits for the ones --timeout and --max-memory don't set
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_MAX_MEMORY: usize = 16 << 20;
+const MAX_OUTPUT: usize = 1 << 20;
+const MAX_REQUEST: usize = 1 << 20;
+// Connections past this many get a 503 rather than a thread, and a client that sends or reads
+// nothing for this long is dropped
+const MAX_CONNECTIONS: usize = 64;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Server {
+    config: Config,
+    // Sent as Access-Control-Allow-Origin so pages from that origin can call the server
+    allow_origin: Option<String>,
+    connections: AtomicUsize,
+}
+
+// Counts a connection for as long as its thread runs
+struct Connection<'a>(&'a AtomicUsize);
+
+impl Drop for Connection<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub fn run(address: &str, allow_origin: Option<String>, mut config: Config) -> Result<(), String> {
+    config.sandbox = true;
+    config.window = false;
+    config.timeout = config.timeout.or(Some(DEFAULT_TIMEOUT));
+    config.max_memory = config.max_memory.or(Some(DEFAULT_MAX_MEMORY));
+    let server = Arc::new(Server { config, allow_origin, connections: AtomicUsize::new(0) });
+    let listener = TcpListener::bind(address).map_err(|e| format!("Error listening on {}: {}", address, e))?;
+    eprintln!("Listening on http://{}", listener.local_addr().map_err(|e| e.to_string())?);
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else { continue };
+        let _ = stream.set_read_timeout(Some(IDLE_TIMEOUT));
+        let _ = stream.set_write_timeout(Some(IDLE_TIMEOUT));
+        if server.connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            server.connections.fetch_sub(1, Ordering::SeqCst);
+            let _ = respond(&mut stream, &server, "503 Service Unavailable", "Too many programs are running, try again later");
+            continue;
+        }
+        let server = Arc::clone(&server);
+        std::thread::spawn(move || {
+            let _connection = Connection(&server.connections);
+            // A client that goes away halfway only ends its own connection
+            let _ = handle(stream, &server);
+        });
+    }
+    Ok(())
+}
+
+fn handle(stream: TcpStream, server: &Server) -> Result<(), String> {
+    let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+    let mut writer = stream;
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).map_err(|e| e.to_string())?;
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).map_err(|e| e.to_string())? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    let mut words = request_line.split_whitespace();
+    match (words.next().unwrap_or_default(), words.next().unwrap_or_default()) {
+        // Browsers ask before posting JSON to another origin
+        ("OPTIONS", _) => respond(&mut writer, server, "204 No Content", ""),
+        ("POST", "/run") if length > MAX_REQUEST => respond(&mut writer, server, "413 Payload Too Large", "The program is too big"),
+        ("POST", "/run") => {
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+            match request(&body) {
+                Ok((source, input)) => run_program(writer, server, &source, input),
+                Err(e) => respond(&mut writer, server, "400 Bad Request", &e),
+            }
+        },
+        ("GET", "/") => respond(&mut writer, server, "200 OK", "POST a program to /run as {\"source\": \"10 PRINT 1\"}"),
+        _ => respond(&mut writer, server, "404 Not Found", "POST programs to /run"),
+    }
+}
+
+// The source and INPUT lines from a request's JSON
+fn request(body: &[u8]) -> Result<(String, VecDeque<String>), String> {
+    let request: Value = serde_json::from_slice(body).map_err(|e| format!("The request isn't JSON: {}", e))?;
+    let source = request["source"].as_str().ok_or("The request has no \"source\" string")?;
+    let input = match &request["input"] {
+        Value::Null => VecDeque::new(),
+        Value::Array(lines) => lines.iter()
+            .map(|line| line.as_str().map(|line| format!("{}\n", line)).ok_or("Every \"input\" line has to be a string"))
+            .collect::<Result<_, _>>()?,
+        _ => return Err("\"input\" has to be an array of strings".to_string()),
+    };
+    Ok((source.to_string(), input))
+}
+
+fn respond(writer: &mut TcpStream, server: &Server, status: &str, message: &str) -> Result<(), String> {
+    let body = if message.is_empty() { String::new() } else { json!({ "error": message }).to_string() };
+    write!(
+        writer,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        status, body.len(), cors(server), body,
+    ).map_err(|e| e.to_string())
+}
+
+// No headers at all without --allow-origin, so browsers keep other sites' pages out
+fn cors(server: &Server) -> String {
+    match &server.allow_origin {
+        Some(origin) => format!(
+            "Access-Control-Allow-Origin: {}\r\nAccess-Control-Allow-Methods: POST, GET, OPTIONS\r\nAccess-Control-Allow-Headers: Content-Type\r\n",
+            origin,
+        ),
+        None => String::new(),
+    }
+}
+
+fn run_program(mut writer: TcpStream, server: &Server, source: &str, input: VecDeque<String>) -> Result<(), String> {
+    write!(
+        writer,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nTransfer-Encoding: chunked\r\n{}Connection: close\r\n\r\n",
+        cors(server),
+    ).map_err(|e| e.to_string())?;
+    let mut events = Events(writer.try_clone().map_err(|e| e.to_string())?);
+
+    let io = StreamIo { events: Events(writer.try_clone().map_err(|e| e.to_string())?), input, written: 0 };
+
+    // A panic in LANG ends this program with an error, like ffi.rs, instead of leaving the
+    // response without its done event
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        let result = crate::parse(source).and_then(|program| crate::check_program(&program).map(|_| program));
+        match result {
+            Err(e) => (Some(e), 1, 0),
+            Ok(program) => {
+                let mut interpreter = Interpreter::new();
+                server.config.configure(&mut interpreter);
+                interpreter.io = Box::new(io);
+                match interpreter.run(&program) {
+                    Ok(()) => (None, interpreter.end_status(), interpreter.steps),
+                    Err(e) => (Some(e), 1, interpreter.steps),
+                }
+            },
+        }
+    }));
+    let (status, steps) = match outcome {
+        Ok((error, status, steps)) => {
+            if let Some(error) = error {
+                events.send(json!({ "type": "error", "code": error.code(), "message": error.to_string() }))?;
+            }
+            (status, steps)
+        },
+        Err(payload) => {
+            let message = format!("LANG crashed: {}", crate::crash::panic_message(payload.as_ref()));
+            events.send(json!({ "type": "error", "message": message }))?;
+            (1, 0)
+        },
+    };
+    events.send(json!({ "type": "done", "status": status, "steps": steps }))?;
+    writer.write_all(b"0\r\n\r\n").map_err(|e| e.to_string())
+}
+
+// One JSON object a line, each in a chunk of its own so it reaches the client straight away
+struct Events(TcpStream);
+
+impl Events {
+    fn send(&mut self, event: Value) -> Result<(), String> {
+        let line = format!("{}\n", event);
+        write!(self.0, "{:x}\r\n{}\r\n", line.len(), line).map_err(|_| "The client disconnected".to_string())
+    }
+}
+
+// PRINT becomes output events, and INPUT reads the request's lines
+struct StreamIo {
+    events: Events,
+    input: VecDeque<String>,
+    written: usize,
+}
+
+impl IoBackend for StreamIo {
+    fn write(&mut self, text: &str) -> Result<(), Error> {
+        self.written += text.len();
+        if self.written > MAX_OUTPUT {
+            return Err(Error::new(Code::Limit, "Output limit exceeded"));
+        }
+        Ok(self.events.send(json!({ "type": "output", "text": text }))?)
+    }
+
+    fn read_line(&mut self) -> Result<String, Error> {
+        self.input.pop_front().ok_or_else(|| Error::from("No input available"))
+    }
+
+    // Nobody is watching, so SLEEP doesn't hold the thread
+    fn interactive(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,93 @@
+// Text-mode sprites for SPRITE LOAD/MOVE/HIDE and COLLISION. Sprites sit on top of the
+// terminal; after every change the frame is composited again and only the cells that changed
+// are rewritten, so moving sprites do not flicker. Spaces in a shape are transparent.
+use std::collections::{BTreeMap, HashMap};
+
+type Cell = (i64, i64);
+
+// The parts of a sprite past the screen LOCATE can reach aren't drawn
+fn on_screen(position: i64) -> bool {
+    (1..=crate::screen::MAX_POSITION as i64).contains(&position)
+}
+
+struct Sprite {
+    rows: Vec<Vec<char>>,
+    // Row and column of the top-left corner, counting from 1 like LOCATE; None while hidden
+    position: Option<Cell>,
+}
+
+#[derive(Default)]
+pub struct Sprites {
+    // Higher numbers are drawn on top
+    sprites: BTreeMap<u32, Sprite>,
+    // What is on screen now
+    frame: HashMap<Cell, char>,
+}
+
+impl Sprites {
+    // Loading over a visible sprite redraws it with the new shape in place
+    pub fn load(&mut self, number: u32, rows: Vec<String>) -> String {
+        let position = self.sprites.get(&number).and_then(|sprite| sprite.position);
+        let rows = rows.iter().map(|row| row.chars().collect()).collect();
+        self.sprites.insert(number, Sprite { rows, position });
+        self.composite()
+    }
+
+    pub fn move_to(&mut self, number: u32, row: i64, col: i64) -> Result<String, String> {
+        self.sprite_mut(number)?.position = Some((row, col));
+        Ok(self.composite())
+    }
+
+    pub fn hide(&mut self, number: u32) -> Result<String, String> {
+        self.sprite_mut(number)?.position = None;
+        Ok(self.composite())
+    }
+
+    // Two sprites collide when a visible cell of one lands on a visible cell of the other
+    pub fn collision(&self, a: u32, b: u32) -> Result<bool, String> {
+        let a: Vec<Cell> = self.cells(self.sprite(a)?).map(|(cell, _)| cell).collect();
+        Ok(self.cells(self.sprite(b)?).any(|(cell, _)| a.contains(&cell)))
+    }
+
+    fn sprite(&self, number: u32) -> Result<&Sprite, String> {
+        self.sprites.get(&number).ok_or_else(|| format!("Sprite {} not loaded", number))
+    }
+
+    fn sprite_mut(&mut self, number: u32) -> Result<&mut Sprite, String> {
+        self.sprites.get_mut(&number).ok_or_else(|| format!("Sprite {} not loaded", number))
+    }
+
+    fn cells<'a>(&self, sprite: &'a Sprite) -> impl Iterator<Item = (Cell, char)> + 'a {
+        let (top, left) = sprite.position.unwrap_or_default();
+        let visible = sprite.position.is_some();
+        sprite.rows.iter().enumerate()
+            .flat_map(move |(y, row)| row.iter().enumerate().map(move |(x, &c)| ((top + y as i64, left + x as i64), c)))
+            .filter(move |&((row, col), c)| visible && c != ' ' && on_screen(row) && on_screen(col))
+    }
+
+    // The escape codes that turn the last frame into the new one, leaving the cursor where it was
+    fn composite(&mut self) -> String {
+        let mut frame = HashMap::new();
+        for sprite in self.sprites.values() {
+            frame.extend(self.cells(sprite));
+        }
+
+        let mut changes: Vec<(Cell, char)> = self.frame.keys()
+            .filter(|cell| !frame.contains_key(*cell))
+            .map(|&cell| (cell, ' '))
+            .collect();
+        changes.extend(frame.iter().filter(|(cell, c)| self.frame.get(*cell) != Some(*c)).map(|(&cell, &c)| (cell, c)));
+        changes.sort();
+        self.frame = frame;
+
+        if changes.is_empty() {
+            return String::new();
+        }
+        let mut output = String::from("\x1b7");
+        for ((row, col), c) in changes {
+            output.push_str(&format!("\x1b[{};{}H{}", row, col, c));
+        }
+        output.push_str("\x1b8");
+        output
+    }
+}
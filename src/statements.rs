@@ -0,0 +1,721 @@
+// What each statement does. Interpreter::execute_statement in lib.rs only picks the handler
+// here; the work every statement shares, like counting steps and switching tasks, happens in
+// its before_statement and after_statement hooks instead of in each handler.
+use crate::{audio, files, graphics, numbers, output, screen, store, system, vm_opt, window};
+use crate::errors::{Code, Error};
+use crate::{Expression, ForLoop, Interpreter, Point, Statement, UserFunction, Value, VarCache, VarType, Variables};
+
+impl Interpreter {
+    // Variables, flow and the terminal
+    pub(crate) fn execute_print(&mut self, expressions: &[Expression], semicolon: bool) -> Result<(), Error> {
+        for (i, expr) in expressions.iter().enumerate() {
+            if i > 0 {
+                self.write_output(" ")?;
+            }
+            let text = match self.evaluate_expression(expr)? {
+                Value::Number(n) => numbers::format_number(n),
+                Value::String(s) => s,
+            };
+            self.write_output(&text)?;
+        }
+        if !semicolon {
+            self.write_output("\n")?;
+        }
+        self.io.flush()?;
+        Ok(())
+    }
+
+    pub(crate) fn execute_let(&mut self, variable: &str, cache: &VarCache, expression: &Expression) -> Result<(), Error> {
+        let value = self.evaluate_expression(expression)?;
+        self.assign_cached(variable, cache, value)
+    }
+
+    // What --vm-opt runs in place of a line's own statement
+    pub(crate) fn execute_fast(&mut self, fast: &vm_opt::Fast) -> Result<(), Error> {
+        match fast {
+            vm_opt::Fast::Jump(line) => {
+                self.next_line = Some(*line);
+                Ok(())
+            },
+            vm_opt::Fast::JumpIf { condition, then, otherwise } => match self.evaluate_expression(condition)? {
+                Value::Number(n) => {
+                    if n != 0.0 {
+                        self.next_line = Some(*then);
+                    } else if let Some(otherwise) = otherwise {
+                        self.next_line = Some(*otherwise);
+                    }
+                    Ok(())
+                },
+                Value::String(_) => Err(Error::new(Code::TypeMismatch, "Condition must evaluate to a number")),
+            },
+            vm_opt::Fast::AddTo { variable, cache, addend, sum, position } => {
+                // An integer has to be rounded and range checked, and --checked-math looks at
+                // every sum, so those add the way LET does
+                let stored = self.variables.get_cached(variable, cache).is_some();
+                if !stored || self.var_type(variable) == VarType::Integer || self.math_diagnostics.is_some() {
+                    return self.execute_let(variable, cache, sum);
+                }
+                let addend = self.evaluate_expression(addend)?;
+                if !self.variables.add_cached(variable, cache, addend) {
+                    self.error_position.set(Some(*position));
+                    return Err(Error::new(Code::TypeMismatch, "Invalid operation or type mismatch"));
+                }
+                self.check_memory()
+            },
+        }
+    }
+
+    pub(crate) fn execute_if(
+        &mut self,
+        condition: &Expression,
+        then_branch: &Statement,
+        else_branch: Option<&Statement>,
+    ) -> Result<(), Error> {
+        let value = self.evaluate_expression(condition)?;
+        match value {
+            Value::Number(n) => {
+                if n != 0.0 {
+                    self.execute_statement(then_branch)
+                } else if let Some(else_stmt) = else_branch {
+                    self.execute_statement(else_stmt)
+                } else {
+                    Ok(())
+                }
+            },
+            Value::String(_) => Err(Error::new(Code::TypeMismatch, "Condition must evaluate to a number")),
+        }
+    }
+
+    pub(crate) fn execute_input(&mut self, variable: &str) -> Result<(), Error> {
+        // Shown already if this INPUT ran before and had to wait for a line
+        if !self.awaiting_input {
+            self.io.prompt(&format!("Enter {}: ", variable))?;
+            self.io.flush()?;
+        }
+        let input = self.read_input()?;
+        if self.var_type(variable) == VarType::String {
+            let text = input.trim_end_matches(['\r', '\n']).to_string();
+            return self.assign(variable, Value::String(text));
+        }
+        match input.trim().parse::<f64>() {
+            Ok(n) => self.assign(variable, Value::Number(n)),
+            Err(_) => Err(Error::new(Code::TypeMismatch, "Invalid number input")),
+        }
+    }
+
+    pub(crate) fn execute_for(&mut self, loop_data: &ForLoop) -> Result<(), Error> {
+        // Tasks share variables, so two of them looping on the same one would trample each other
+        if let Some(owner) = self.scheduler.loop_owner(&loop_data.variable) {
+            return Err(format!("FOR {} is already running in task {}", loop_data.variable, owner).into());
+        }
+        let start = self.evaluate_expression(&loop_data.start)?;
+        let end = self.evaluate_expression(&loop_data.end)?;
+        let step = self.evaluate_expression(&loop_data.step)?;
+        
+        match (start, end, step) {
+            (Value::Number(start), Value::Number(_), Value::Number(_)) => {
+                self.assign_cached(&loop_data.variable, &loop_data.cache, Value::Number(start))?;
+                // As in GW-BASIC, a loop already open on the variable is closed, along with the
+                // ones inside it, so a GOTO back to a FOR doesn't pile loops up
+                if let Some(open) = self.loops.iter().rposition(|open| open.variable == loop_data.variable) {
+                    self.loops.truncate(open);
+                    self.loop_stack.truncate(open);
+                }
+                self.loops.push(loop_data.clone());
+                self.loop_stack.push(self.current_line);
+                Ok(())
+            },
+            _ => Err(Error::new(Code::TypeMismatch, "Loop bounds must be numbers")),
+        }
+    }
+
+    pub(crate) fn execute_next(&mut self, variable: &str) -> Result<(), Error> {
+        if let Some(loop_data) = self.loops.last() {
+            if loop_data.variable != variable {
+                return Err(Error::new(Code::NextMismatch, format!("NEXT {} doesn't match FOR {}", variable, loop_data.variable)));
+            }
+            
+            let cache = loop_data.cache.clone();
+            let current = match self.variables.get_cached(variable, &cache) {
+                Some(Value::Number(n)) => *n,
+                _ => return Err(Error::new(Code::TypeMismatch, format!("Loop variable {} is not a number", variable))),
+            };
+            let step = match self.evaluate_expression(&loop_data.step)? {
+                Value::Number(n) => n,
+                _ => return Err(Error::new(Code::TypeMismatch, "Step must be a number")),
+            };
+            let next_val = current + step;
+            
+            let end = match self.evaluate_expression(&loop_data.end)? {
+                Value::Number(n) => n,
+                _ => return Err(Error::new(Code::TypeMismatch, "End must be a number")),
+            };
+            
+            if (step > 0.0 && next_val <= end) || (step < 0.0 && next_val >= end) {
+                self.assign_cached(variable, &cache, Value::Number(next_val))?;
+                if let Some(&loop_start) = self.loop_stack.last() {
+                    self.current_line = loop_start;
+                    Ok(())
+                } else {
+                    Err("Loop start not found".into())
+                }
+            } else {
+                self.loops.pop();
+                self.loop_stack.pop();
+                Ok(())
+            }
+        } else {
+            Err(Error::new(Code::NextWithoutFor, "NEXT without FOR"))
+        }
+    }
+
+    pub(crate) fn execute_end(&mut self, code: Option<&Expression>) -> Result<(), Error> {
+        if let Some(code) = code {
+            self.end_status = match self.evaluate_expression(code)? {
+                Value::Number(n) if n.fract() == 0.0 && (0.0..=255.0).contains(&n) => n as i32,
+                Value::Number(n) => return Err(Error::new(Code::BadExitStatus, format!("END needs an exit status from 0 to 255, not {}", n))),
+                Value::String(_) => return Err(Error::new(Code::TypeMismatch, "END needs a number")),
+            };
+        }
+        self.exit = output::ExitReason::End;
+        self.running = false;
+        Ok(())
+    }
+
+    pub(crate) fn execute_goto(&mut self, target: u32) -> Result<(), Error> {
+        self.next_line = Some(self.find_line(target)?);
+        Ok(())
+    }
+
+    // Runs another program from its first line in place of this one. Only the variables named
+    // in this program's COMMON statements keep their values; open files stay open. A relative
+    // path is found from the running program's directory.
+    pub(crate) fn execute_chain(&mut self, path: &Expression) -> Result<(), Error> {
+        self.check_sandbox("CHAIN")?;
+        if self.scheduler.active() {
+            return Err("Can't CHAIN while tasks are running".into());
+        }
+        let path = std::path::PathBuf::from(self.evaluate_string(path)?);
+        // A host can leave the arguments empty, and then the path is taken as it is
+        let directory = self.arguments.first().and_then(|program| std::path::Path::new(program).parent());
+        let path = match directory {
+            Some(directory) if path.is_relative() => directory.join(path),
+            _ => path,
+        };
+        let source = files::read_program(&path)?;
+        let program = crate::parse(&source)
+            .and_then(|program| crate::check_program(&program).map(|_| program))
+            .map_err(|e| Error::new(e.kind(), format!("Can't CHAIN to {}: {}", path.display(), e)))?;
+
+        let common: Vec<(String, Value)> = self.program.lines.iter()
+            .filter_map(|line| match &line.statement {
+                Statement::Common { variables } => Some(variables),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|name| Some((name.clone(), self.variables.get(name)?.clone())))
+            .collect();
+        self.variables = Variables::new();
+        self.functions.clear();
+        self.fn_cache.borrow_mut().clear();
+        self.fn_cache_bytes.set(0);
+        self.loops.clear();
+        self.loop_stack.clear();
+        self.var_types = [VarType::Double; 26];
+        self.load_program(program);
+        for (name, value) in common {
+            self.variables.insert(&name, value);
+        }
+        match self.arguments.first_mut() {
+            Some(program) => *program = path.display().to_string(),
+            None => self.arguments.push(path.display().to_string()),
+        }
+        self.next_line = Some(0);
+        Ok(())
+    }
+
+    pub(crate) fn execute_def_fn(
+        &mut self,
+        name: &str,
+        parameters: &[String],
+        body: &Expression,
+    ) -> Result<(), Error> {
+        let function = UserFunction { parameters: parameters.to_vec(), body: body.clone(), line: self.current_line };
+        self.functions.insert(name.to_string(), function);
+        Ok(())
+    }
+
+    pub(crate) fn execute_def_type(&mut self, var_type: VarType, letters: &[(char, char)]) -> Result<(), Error> {
+        for &(first, last) in letters {
+            for letter in first..=last {
+                self.var_types[(letter as u8 - b'A') as usize] = var_type;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn execute_checkpoint(&mut self) -> Result<(), Error> {
+        self.check_sandbox("CHECKPOINT")?;
+        let path = self.checkpoint_file.clone();
+        self.save_checkpoint(&path, self.current_line + 1)
+    }
+
+    // Random-access files and the file system
+    pub(crate) fn execute_open(
+        &mut self,
+        filename: &Expression,
+        file_number: &Expression,
+        record_length: Option<&Expression>,
+    ) -> Result<(), Error> {
+        self.check_sandbox("OPEN")?;
+        let filename = self.evaluate_string(filename)?;
+        let file_number = self.evaluate_file_number(file_number)?;
+        if self.files.contains_key(&file_number) || self.hardware.is_open(file_number) {
+            return Err(Error::new(Code::BadFileNumber, format!("File #{} already open", file_number)));
+        }
+        let record_length = match record_length {
+            Some(expr) => self.evaluate_integer(expr)? as usize,
+            None => files::DEFAULT_RECORD_LENGTH,
+        };
+        let file = files::RandomFile::open(&filename, record_length)?;
+        self.files.insert(file_number, file);
+        Ok(())
+    }
+
+    pub(crate) fn execute_close(&mut self, file_numbers: &[Expression]) -> Result<(), Error> {
+        if file_numbers.is_empty() {
+            self.files.clear();
+            self.hardware.close_all();
+        }
+        for expr in file_numbers {
+            let file_number = self.evaluate_file_number(expr)?;
+            self.files.remove(&file_number);
+            self.hardware.close(file_number);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn execute_field(
+        &mut self,
+        file_number: &Expression,
+        fields: &[(Expression, String)],
+    ) -> Result<(), Error> {
+        let file_number = self.evaluate_file_number(file_number)?;
+        let record_length = self.files.get(&file_number)
+            .ok_or_else(|| Error::new(Code::BadFileNumber, format!("File #{} not open", file_number)))?
+            .record_length();
+        let mut widths = Vec::new();
+        for (width, variable) in fields {
+            let width = self.evaluate_integer(width)?;
+            if !(0.0..=record_length as f64).contains(&width) {
+                return Err(Error::new(Code::OutOfRange, format!("FIELD width {} out of range 0-{}", width, record_length)));
+            }
+            widths.push((width as usize, variable.clone()));
+        }
+        let file = self.files.get_mut(&file_number)
+            .ok_or_else(|| Error::new(Code::BadFileNumber, format!("File #{} not open", file_number)))?;
+        file.set_fields(widths)?;
+        for (variable, value) in file.field_values() {
+            self.variables.insert(&variable, Value::String(value));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn execute_get(&mut self, file_number: &Expression, record: Option<&Expression>) -> Result<(), Error> {
+        let file_number = self.evaluate_file_number(file_number)?;
+        let record = match record {
+            Some(expr) => Some(self.evaluate_integer(expr)?),
+            None => None,
+        };
+        let file = self.files.get_mut(&file_number)
+            .ok_or_else(|| Error::new(Code::BadFileNumber, format!("File #{} not open", file_number)))?;
+        file.get(record)?;
+        for (variable, value) in file.field_values() {
+            self.variables.insert(&variable, Value::String(value));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn execute_put(&mut self, file_number: &Expression, record: Option<&Expression>) -> Result<(), Error> {
+        let file_number = self.evaluate_file_number(file_number)?;
+        let record = match record {
+            Some(expr) => Some(self.evaluate_integer(expr)?),
+            None => None,
+        };
+        let file = self.files.get_mut(&file_number)
+            .ok_or_else(|| Error::new(Code::BadFileNumber, format!("File #{} not open", file_number)))?;
+        file.put(record)
+    }
+
+    pub(crate) fn execute_kill(&mut self, path: &Expression) -> Result<(), Error> {
+        self.check_sandbox("KILL")?;
+        let path = self.evaluate_string(path)?;
+        files::kill(&path)
+    }
+
+    pub(crate) fn execute_name(&mut self, from: &Expression, to: &Expression) -> Result<(), Error> {
+        self.check_sandbox("NAME")?;
+        let from = self.evaluate_string(from)?;
+        let to = self.evaluate_string(to)?;
+        files::rename(&from, &to)
+    }
+
+    pub(crate) fn execute_files(&mut self, pattern: Option<&Expression>) -> Result<(), Error> {
+        self.check_sandbox("FILES")?;
+        let pattern = match pattern {
+            Some(expr) => Some(self.evaluate_string(expr)?),
+            None => None,
+        };
+        for name in files::list(pattern.as_deref())? {
+            self.write_output(&format!("{}\n", name))?;
+        }
+        Ok(())
+    }
+
+    // Text screen and graphics
+    pub(crate) fn execute_locate(&mut self, row: Option<&Expression>, col: Option<&Expression>) -> Result<(), Error> {
+        let row = self.evaluate_optional_number(row)?;
+        let col = self.evaluate_optional_number(col)?;
+        self.write_output(&screen::locate(row, col)?)
+    }
+
+    pub(crate) fn execute_color(
+        &mut self,
+        foreground: Option<&Expression>,
+        background: Option<&Expression>,
+    ) -> Result<(), Error> {
+        let foreground = self.evaluate_optional_number(foreground)?;
+        let background = self.evaluate_optional_number(background)?;
+        self.write_output(&screen::color(foreground, background)?)
+    }
+
+    pub(crate) fn execute_screen(&mut self, width: &Expression, height: &Expression) -> Result<(), Error> {
+        let width = self.evaluate_number(width)?;
+        let height = self.evaluate_number(height)?;
+        let screen = graphics::Framebuffer::new(width, height)?;
+        if self.show_window {
+            self.window = Some(window::Window::open(&screen)?);
+        }
+        self.graphics = Some(screen);
+        Ok(())
+    }
+
+    pub(crate) fn execute_pset(&mut self, point: &Point, color: Option<&Expression>) -> Result<(), Error> {
+        let point = self.evaluate_point(point)?;
+        let color = self.evaluate_color(color)?;
+        self.screen()?.pset(point, color);
+        Ok(())
+    }
+
+    pub(crate) fn execute_draw_line(
+        &mut self,
+        from: Option<&Point>,
+        to: &Point,
+        color: Option<&Expression>,
+        shape: &graphics::LineShape,
+    ) -> Result<(), Error> {
+        let from = match from {
+            Some(from) => self.evaluate_point(from)?,
+            None => self.screen()?.last_point(),
+        };
+        let to = self.evaluate_point(to)?;
+        let color = self.evaluate_color(color)?;
+        self.screen()?.line(from, to, color, shape.clone());
+        Ok(())
+    }
+
+    pub(crate) fn execute_circle(
+        &mut self,
+        center: &Point,
+        radius: &Expression,
+        color: Option<&Expression>,
+    ) -> Result<(), Error> {
+        let center = self.evaluate_point(center)?;
+        let radius = self.evaluate_number(radius)?;
+        let color = self.evaluate_color(color)?;
+        self.screen()?.circle(center, radius, color);
+        Ok(())
+    }
+
+    pub(crate) fn execute_paint(
+        &mut self,
+        point: &Point,
+        color: Option<&Expression>,
+        border: Option<&Expression>,
+    ) -> Result<(), Error> {
+        let point = self.evaluate_point(point)?;
+        let color = self.evaluate_color(color)?;
+        // Without a border color, PAINT fills up to pixels of its own color
+        let border = match border {
+            Some(border) => graphics::color_index(self.evaluate_number(border)?)?,
+            None => color,
+        };
+        self.screen()?.paint(point, color, border);
+        Ok(())
+    }
+
+    pub(crate) fn execute_save_image(&mut self, path: &Expression) -> Result<(), Error> {
+        self.check_sandbox("SAVEIMAGE")?;
+        let path = self.evaluate_string(path)?;
+        self.screen()?.save_png(&path)
+    }
+
+    pub(crate) fn execute_draw(&mut self, commands: &Expression) -> Result<(), Error> {
+        let commands = self.evaluate_string(commands)?;
+        let screen = self.graphics.as_mut().ok_or_else(|| Error::from(graphics::NO_SCREEN))?;
+        self.pen.draw(screen, &commands)
+    }
+
+    // Sound and timing
+    pub(crate) fn execute_sleep(&mut self, seconds: Option<&Expression>) -> Result<(), Error> {
+        let seconds = self.evaluate_optional_number(seconds)?;
+        // A negative time doesn't wait, and one past what a Duration holds, some 585 billion
+        // years, is an error
+        let out_of_range = |seconds: f64| Error::new(Code::OutOfRange, format!("SLEEP {} is out of range", numbers::format_number(seconds).trim()));
+        let duration = seconds
+            .map(|seconds| std::time::Duration::try_from_secs_f64(seconds.max(0.0)).map_err(|_| out_of_range(seconds)))
+            .transpose()?;
+        #[cfg(feature = "async")]
+        if let (Some(pending), Some(duration)) = (&mut self.async_sleep, duration) {
+            *pending = Some(duration);
+            return Ok(());
+        }
+        // Fuzzing and minimizing run with buffered I/O and should never wait
+        if !self.io.interactive() {
+            return Ok(());
+        }
+        // With tasks running, SLEEP only suspends the current task
+        if let (true, Some(duration), Some(seconds)) = (self.scheduler.active(), duration, seconds) {
+            let wake_at = crate::Instant::now().checked_add(duration).ok_or_else(|| out_of_range(seconds))?;
+            self.scheduler.wake_at = Some(wake_at);
+            return Ok(());
+        }
+        // SLEEP 0 waits for a key, like SLEEP on its own
+        let duration = duration.filter(|duration| !duration.is_zero());
+        match (&mut self.window, &self.graphics, duration) {
+            (Some(window), Some(screen), _) => {
+                if !window.wait(screen, duration) {
+                    self.exit = output::ExitReason::WindowClosed;
+                    self.running = false;
+                }
+            },
+            (_, _, Some(duration)) => std::thread::sleep(duration),
+            // Without a time SLEEP waits for a key, which on a terminal means Enter
+            _ => {
+                self.read_input()?;
+            },
+        }
+        Ok(())
+    }
+
+    pub(crate) fn execute_sound(&mut self, frequency: &Expression, duration: &Expression) -> Result<(), Error> {
+        let frequency = self.evaluate_number(frequency)?;
+        let seconds = audio::check_sound(frequency, self.evaluate_number(duration)?)?;
+        if seconds > 0.0 {
+            self.audio.tone(frequency, seconds)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn execute_play(&mut self, music: &Expression) -> Result<(), Error> {
+        let music = self.evaluate_string(music)?;
+        for note in self.music.parse(&music)? {
+            match note.frequency {
+                Some(frequency) => self.audio.tone(frequency, note.seconds)?,
+                None => self.audio.rest(note.seconds)?,
+            }
+            if note.gap > 0.0 {
+                self.audio.rest(note.gap)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Sprites
+    pub(crate) fn execute_sprite_load(&mut self, number: &Expression, rows: &[Expression]) -> Result<(), Error> {
+        let number = self.evaluate_sprite_number(number)?;
+        let mut shape = Vec::new();
+        for row in rows {
+            shape.push(self.evaluate_string(row)?);
+        }
+        let update = self.sprites.load(number, shape);
+        self.write_output(&update)
+    }
+
+    pub(crate) fn execute_sprite_move(
+        &mut self,
+        number: &Expression,
+        row: &Expression,
+        col: &Expression,
+    ) -> Result<(), Error> {
+        let number = self.evaluate_sprite_number(number)?;
+        let row = self.evaluate_sprite_position(row)?;
+        let col = self.evaluate_sprite_position(col)?;
+        let update = self.sprites.move_to(number, row, col)?;
+        self.write_output(&update)
+    }
+
+    pub(crate) fn execute_sprite_hide(&mut self, number: &Expression) -> Result<(), Error> {
+        let number = self.evaluate_sprite_number(number)?;
+        let update = self.sprites.hide(number)?;
+        self.write_output(&update)
+    }
+
+    // Tasks and channels
+    pub(crate) fn execute_task(&mut self, target: u32, id_variable: Option<&str>) -> Result<(), Error> {
+        let line = self.find_line(target)?;
+        let id = self.spawn_task(line);
+        match id_variable {
+            Some(variable) => self.assign(variable, Value::Number(id as f64)),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn execute_end_task(&mut self) -> Result<(), Error> {
+        self.next_line = Some(self.program.lines.len());
+        Ok(())
+    }
+
+    pub(crate) fn execute_await(&mut self, task: Option<&Expression>) -> Result<(), Error> {
+        let done = match task {
+            Some(task) => {
+                let id = self.evaluate_integer(task)?;
+                if id < 1.0 || !self.scheduler.spawned(id as u32) {
+                    return Err(format!("No task {}", id).into());
+                }
+                if id as u32 == self.scheduler.current {
+                    return Err("A task cannot AWAIT itself".into());
+                }
+                self.scheduler.finished(id as u32)
+            },
+            None => self.scheduler.all_others_finished(),
+        };
+        // Not done yet: stay on this line and let the other tasks run
+        if !done {
+            self.blocked = true;
+            self.next_line = Some(self.current_line);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn execute_send(&mut self, channel: &Expression, value: &Expression) -> Result<(), Error> {
+        let channel = self.evaluate_string(channel)?;
+        let value = self.evaluate_expression(value)?;
+        self.scheduler.send(channel, value);
+        self.check_memory()
+    }
+
+    pub(crate) fn execute_receive(&mut self, channel: &Expression, variable: &str) -> Result<(), Error> {
+        let channel = self.evaluate_string(channel)?;
+        match self.scheduler.receive(&channel) {
+            Some(value) => self.assign(variable, value),
+            // Nothing sent yet: wait on this line like AWAIT
+            None => {
+                self.blocked = true;
+                self.next_line = Some(self.current_line);
+                Ok(())
+            },
+        }
+    }
+
+    // The host system and hardware
+    pub(crate) fn execute_clip_set(&mut self, text: &Expression) -> Result<(), Error> {
+        self.check_sandbox("CLIPSET")?;
+        let text = self.evaluate_string(text)?;
+        Ok(self.clipboard.set(&text)?)
+    }
+
+    pub(crate) fn execute_store_set(&mut self, key: &Expression, value: &Expression) -> Result<(), Error> {
+        self.check_sandbox("STORESET")?;
+        let key = self.evaluate_string(key)?;
+        let value = self.evaluate_expression(value)?;
+        if let Value::Number(n) = value {
+            if !n.is_finite() {
+                return Err(format!("Can't store {} for {}", n, key).into());
+            }
+        }
+        store::set(&key, value)
+    }
+
+    pub(crate) fn execute_shell(&mut self, command: &Expression) -> Result<(), Error> {
+        self.check_sandbox("SHELL")?;
+        let command = self.evaluate_string(command)?;
+        // Under buffered I/O the command's output has to be captured like PRINT
+        if !self.io.interactive() {
+            let output = system::shell_output(&command)?;
+            return self.write_output(&format!("{}\n", output));
+        }
+        Ok(system::shell(&command)?)
+    }
+
+    pub(crate) fn execute_open_serial(
+        &mut self,
+        device: &Expression,
+        baud: &Expression,
+        file_number: &Expression,
+    ) -> Result<(), Error> {
+        self.check_sandbox("OPENSERIAL")?;
+        let device = self.evaluate_string(device)?;
+        let baud = self.evaluate_integer(baud)?;
+        let file_number = self.evaluate_file_number(file_number)?;
+        if self.files.contains_key(&file_number) || self.hardware.is_open(file_number) {
+            return Err(Error::new(Code::BadFileNumber, format!("File #{} already open", file_number)));
+        }
+        if baud <= 0.0 {
+            return Err(Error::new(Code::OutOfRange, format!("Baud rate {} out of range", baud)));
+        }
+        self.hardware.open_serial(file_number, &device, baud as u32)
+    }
+
+    pub(crate) fn execute_serial_out(&mut self, file_number: &Expression, value: &Expression) -> Result<(), Error> {
+        let file_number = self.evaluate_file_number(file_number)?;
+        let text = match self.evaluate_expression(value)? {
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => s,
+        };
+        self.hardware.serial_write(file_number, &text)
+    }
+
+    pub(crate) fn execute_serial_in(&mut self, file_number: &Expression, variable: &str) -> Result<(), Error> {
+        let file_number = self.evaluate_file_number(file_number)?;
+        let text = self.hardware.serial_read(file_number)?;
+        self.assign(variable, Value::String(text))
+    }
+
+    pub(crate) fn execute_out_pin(&mut self, pin: &Expression, value: &Expression) -> Result<(), Error> {
+        self.check_sandbox("OUTPIN")?;
+        let pin = self.evaluate_pin(pin)?;
+        let high = self.evaluate_number(value)? != 0.0;
+        Ok(self.hardware.output_pin(pin, high)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Interpreter, LangError};
+
+    fn loops_at_error(source: &str) -> Vec<u32> {
+        let program = crate::parse(source).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.max_steps = Some(1000);
+        match interpreter.run_captured(&program, Vec::new()).error {
+            Some(LangError::RuntimeError { stack, .. }) => stack.iter().map(|(number, ..)| *number).collect(),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn for_on_an_open_variable_replaces_its_loop() {
+        assert_eq!(loops_at_error("10 FOR I = 1 TO 2\n20 GOTO 10"), [10]);
+        // The loops inside the one replaced close with it
+        assert_eq!(loops_at_error("10 FOR I = 1 TO 2\n20 FOR J = 1 TO 2\n30 FOR I = 1 TO 2\n40 PRINT 1 / 0"), [30]);
+        assert_eq!(loops_at_error("10 FOR I = 1 TO 2\n20 FOR J = 1 TO 2\n30 FOR J = 1 TO 2\n40 PRINT 1 / 0"), [30, 10]);
+    }
+
+    #[test]
+    fn sleep_too_long_for_a_duration_is_an_error() {
+        let program = crate::parse("10 SLEEP 10^400").unwrap();
+        let error = Interpreter::new().run_captured(&program, Vec::new()).error.unwrap();
+        assert_eq!(error.message(), "SLEEP inf is out of range");
+    }
+}
@@ -0,0 +1,430 @@
+use std::collections::{BTreeMap, HashSet};
+
+pub struct Stats {
+    pub lines: usize,
+    pub statements: BTreeMap<&'static str, usize>,
+    pub variables: usize,
+    pub cyclomatic_complexity: usize,
+    pub forward_jumps: usize,
+    pub backward_jumps: usize,
+    pub crossing_jumps: usize,
+    pub spaghetti_score: f64,
+}
+
+impl Stats {
+    pub fn collect(program: &crate::Program) -> Self {
+        let mut statements = BTreeMap::new();
+        let mut variables = HashSet::new();
+        let mut decisions = 0;
+        let mut jumps = Vec::new();
+        let line_index = program.line_index();
+
+        for (index, line) in program.lines.iter().enumerate() {
+            let mut targets = Vec::new();
+            visit_statement(&line.statement, &mut statements, &mut variables, &mut decisions, &mut targets);
+            for target in targets {
+                if let Some(&to) = line_index.get(&target) {
+                    jumps.push((index, to));
+                }
+            }
+        }
+
+        let backward_jumps = jumps.iter().filter(|(from, to)| to <= from).count();
+        let forward_jumps = jumps.len() - backward_jumps;
+
+        // Two jumps cross when exactly one end of one lies inside the other's span
+        let spans: Vec<(usize, usize)> = jumps.iter()
+            .map(|&(from, to)| (from.min(to), from.max(to)))
+            .collect();
+        let mut crossing_jumps = 0;
+        for (i, a) in spans.iter().enumerate() {
+            for b in &spans[i + 1..] {
+                if (a.0 < b.0 && b.0 < a.1 && a.1 < b.1) || (b.0 < a.0 && a.0 < b.1 && b.1 < a.1) {
+                    crossing_jumps += 1;
+                }
+            }
+        }
+
+        // Jumps weighted by how hard they are to follow, per 10 lines
+        let weighted = forward_jumps + 2 * backward_jumps + 3 * crossing_jumps;
+        let spaghetti_score = if program.lines.is_empty() {
+            0.0
+        } else {
+            let per_ten_lines = weighted as f64 * 10.0 / program.lines.len() as f64;
+            (per_ten_lines * 10.0).round() / 10.0
+        };
+
+        Stats {
+            lines: program.lines.len(),
+            statements,
+            variables: variables.len(),
+            cyclomatic_complexity: decisions + 1,
+            forward_jumps,
+            backward_jumps,
+            crossing_jumps,
+            spaghetti_score,
+        }
+    }
+
+    pub fn report(&self) -> String {
+        let statements: Vec<String> = self.statements.iter()
+            .map(|(kind, count)| format!("{} {}", kind, count))
+            .collect();
+        format!(
+            "Lines: {}\nStatements: {}\nVariables: {}\nCyclomatic complexity: {}\nForward jumps: {}\nBackward jumps: {}\nCrossing jumps: {}\nSpaghetti score: {}\n",
+            self.lines,
+            statements.join(", "),
+            self.variables,
+            self.cyclomatic_complexity,
+            self.forward_jumps,
+            self.backward_jumps,
+            self.crossing_jumps,
+            self.spaghetti_score
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        let statements: Vec<String> = self.statements.iter()
+            .map(|(kind, count)| format!("\"{}\": {}", kind, count))
+            .collect();
+        format!(
+            "{{\"lines\": {}, \"statements\": {{{}}}, \"variables\": {}, \"cyclomatic_complexity\": {}, \"forward_jumps\": {}, \"backward_jumps\": {}, \"crossing_jumps\": {}, \"spaghetti_score\": {}}}",
+            self.lines,
+            statements.join(", "),
+            self.variables,
+            self.cyclomatic_complexity,
+            self.forward_jumps,
+            self.backward_jumps,
+            self.crossing_jumps,
+            self.spaghetti_score
+        )
+    }
+}
+
+// Every variable name the program mentions
+pub(crate) fn variable_names(program: &crate::Program) -> HashSet<String> {
+    let mut statements = BTreeMap::new();
+    let mut variables = HashSet::new();
+    let mut decisions = 0;
+    let mut targets = Vec::new();
+    for line in &program.lines {
+        visit_statement(&line.statement, &mut statements, &mut variables, &mut decisions, &mut targets);
+    }
+    variables
+}
+
+// The variable names one statement mentions, read or written
+pub(crate) fn statement_variables(statement: &crate::Statement) -> HashSet<String> {
+    let mut statements = BTreeMap::new();
+    let mut variables = HashSet::new();
+    let mut decisions = 0;
+    let mut targets = Vec::new();
+    visit_statement(statement, &mut statements, &mut variables, &mut decisions, &mut targets);
+    variables
+}
+
+// The keyword a statement starts with, like "PRINT"
+pub(crate) fn keyword(statement: &crate::Statement) -> &'static str {
+    if let crate::Statement::If { .. } = statement {
+        return "IF";
+    }
+    let mut statements = BTreeMap::new();
+    visit_statement(statement, &mut statements, &mut HashSet::new(), &mut 0, &mut Vec::new());
+    statements.into_keys().next().unwrap_or_default()
+}
+
+fn visit_statement(
+    statement: &crate::Statement,
+    statements: &mut BTreeMap<&'static str, usize>,
+    variables: &mut HashSet<String>,
+    decisions: &mut usize,
+    targets: &mut Vec<u32>,
+) {
+    let kind = match statement {
+        crate::Statement::Let { variable, expression, .. } => {
+            variables.insert(variable.clone());
+            visit_expression(expression, variables);
+            "LET"
+        },
+        crate::Statement::Print { expressions, .. } => {
+            for expr in expressions {
+                visit_expression(expr, variables);
+            }
+            "PRINT"
+        },
+        crate::Statement::If { condition, then_branch, else_branch } => {
+            *decisions += 1;
+            visit_expression(condition, variables);
+            visit_statement(then_branch, statements, variables, decisions, targets);
+            if let Some(else_branch) = else_branch {
+                visit_statement(else_branch, statements, variables, decisions, targets);
+            }
+            "IF"
+        },
+        crate::Statement::Input { variable } => {
+            variables.insert(variable.clone());
+            "INPUT"
+        },
+        crate::Statement::For { loop_data } => {
+            *decisions += 1;
+            variables.insert(loop_data.variable.clone());
+            visit_expression(&loop_data.start, variables);
+            visit_expression(&loop_data.end, variables);
+            visit_expression(&loop_data.step, variables);
+            "FOR"
+        },
+        crate::Statement::Next { .. } => "NEXT",
+        crate::Statement::End { code } => {
+            if let Some(code) = code {
+                visit_expression(code, variables);
+            }
+            "END"
+        },
+        crate::Statement::Goto(target) => {
+            targets.push(*target);
+            "GOTO"
+        },
+        crate::Statement::Rem(_) => "REM",
+        crate::Statement::Open { filename, file_number, record_length, .. } => {
+            visit_expression(filename, variables);
+            visit_expression(file_number, variables);
+            if let Some(record_length) = record_length {
+                visit_expression(record_length, variables);
+            }
+            "OPEN"
+        },
+        crate::Statement::Close { file_numbers } => {
+            for expr in file_numbers {
+                visit_expression(expr, variables);
+            }
+            "CLOSE"
+        },
+        crate::Statement::Field { file_number, fields } => {
+            visit_expression(file_number, variables);
+            for (width, variable) in fields {
+                visit_expression(width, variables);
+                variables.insert(variable.clone());
+            }
+            "FIELD"
+        },
+        crate::Statement::Lset { variable, expression } | crate::Statement::Rset { variable, expression } => {
+            variables.insert(variable.clone());
+            visit_expression(expression, variables);
+            if matches!(statement, crate::Statement::Lset { .. }) { "LSET" } else { "RSET" }
+        },
+        crate::Statement::Get { file_number, record } | crate::Statement::Put { file_number, record } => {
+            visit_expression(file_number, variables);
+            if let Some(record) = record {
+                visit_expression(record, variables);
+            }
+            if matches!(statement, crate::Statement::Get { .. }) { "GET" } else { "PUT" }
+        },
+        crate::Statement::Kill { path } => {
+            visit_expression(path, variables);
+            "KILL"
+        },
+        crate::Statement::Name { from, to } => {
+            visit_expression(from, variables);
+            visit_expression(to, variables);
+            "NAME"
+        },
+        crate::Statement::Files { pattern } => {
+            if let Some(pattern) = pattern {
+                visit_expression(pattern, variables);
+            }
+            "FILES"
+        },
+        crate::Statement::Chain { path } => {
+            visit_expression(path, variables);
+            "CHAIN"
+        },
+        crate::Statement::Common { variables: names } => {
+            variables.extend(names.iter().cloned());
+            "COMMON"
+        },
+        crate::Statement::DefFn { body, .. } => {
+            visit_expression(body, variables);
+            "DEF"
+        },
+        crate::Statement::Checkpoint => "CHECKPOINT",
+        crate::Statement::Cls => "CLS",
+        crate::Statement::Locate { row, col } => {
+            for expr in [row, col].into_iter().flatten() {
+                visit_expression(expr, variables);
+            }
+            "LOCATE"
+        },
+        crate::Statement::Color { foreground, background } => {
+            for expr in [foreground, background].into_iter().flatten() {
+                visit_expression(expr, variables);
+            }
+            "COLOR"
+        },
+        crate::Statement::Screen { width, height } => {
+            visit_expression(width, variables);
+            visit_expression(height, variables);
+            "SCREEN"
+        },
+        crate::Statement::Pset { point, color } => {
+            visit_point(point, variables);
+            if let Some(color) = color {
+                visit_expression(color, variables);
+            }
+            "PSET"
+        },
+        crate::Statement::DrawLine { from, to, color, .. } => {
+            for point in from.iter().chain([to]) {
+                visit_point(point, variables);
+            }
+            if let Some(color) = color {
+                visit_expression(color, variables);
+            }
+            "LINE"
+        },
+        crate::Statement::Circle { center, radius, color } => {
+            visit_point(center, variables);
+            visit_expression(radius, variables);
+            if let Some(color) = color {
+                visit_expression(color, variables);
+            }
+            "CIRCLE"
+        },
+        crate::Statement::Paint { point, color, border } => {
+            visit_point(point, variables);
+            for expr in [color, border].into_iter().flatten() {
+                visit_expression(expr, variables);
+            }
+            "PAINT"
+        },
+        crate::Statement::Sleep { seconds } => {
+            if let Some(seconds) = seconds {
+                visit_expression(seconds, variables);
+            }
+            "SLEEP"
+        },
+        crate::Statement::Beep => "BEEP",
+        crate::Statement::Sound { frequency, duration } => {
+            visit_expression(frequency, variables);
+            visit_expression(duration, variables);
+            "SOUND"
+        },
+        crate::Statement::Play { music } => {
+            visit_expression(music, variables);
+            "PLAY"
+        },
+        crate::Statement::OptionExplicit => "OPTION",
+        crate::Statement::ClipSet { text } => {
+            visit_expression(text, variables);
+            "CLIPSET"
+        },
+        crate::Statement::StoreSet { key, value } => {
+            visit_expression(key, variables);
+            visit_expression(value, variables);
+            "STORESET"
+        },
+        crate::Statement::DefType { var_type, .. } => match var_type {
+            crate::VarType::Integer => "DEFINT",
+            crate::VarType::Single => "DEFSNG",
+            crate::VarType::Double => "DEFDBL",
+            crate::VarType::String => "DEFSTR",
+        },
+        crate::Statement::SpriteLoad { number, rows } => {
+            visit_expression(number, variables);
+            for row in rows {
+                visit_expression(row, variables);
+            }
+            "SPRITE"
+        },
+        crate::Statement::SpriteMove { number, row, col } => {
+            visit_expression(number, variables);
+            visit_expression(row, variables);
+            visit_expression(col, variables);
+            "SPRITE"
+        },
+        crate::Statement::SpriteHide { number } => {
+            visit_expression(number, variables);
+            "SPRITE"
+        },
+        crate::Statement::Draw { commands } => {
+            visit_expression(commands, variables);
+            "DRAW"
+        },
+        crate::Statement::Task { target, id_variable } => {
+            targets.push(*target);
+            if let Some(variable) = id_variable {
+                variables.insert(variable.clone());
+            }
+            "TASK"
+        },
+        crate::Statement::EndTask => "END TASK",
+        crate::Statement::Await { task } => {
+            if let Some(task) = task {
+                visit_expression(task, variables);
+            }
+            "AWAIT"
+        },
+        crate::Statement::Send { channel, value } => {
+            visit_expression(channel, variables);
+            visit_expression(value, variables);
+            "SEND"
+        },
+        crate::Statement::Receive { channel, variable } => {
+            visit_expression(channel, variables);
+            variables.insert(variable.clone());
+            "RECEIVE"
+        },
+        crate::Statement::Shell { command } => {
+            visit_expression(command, variables);
+            "SHELL"
+        },
+        crate::Statement::OpenSerial { device, baud, file_number } => {
+            visit_expression(device, variables);
+            visit_expression(baud, variables);
+            visit_expression(file_number, variables);
+            "OPENSERIAL"
+        },
+        crate::Statement::SerialOut { file_number, value } => {
+            visit_expression(file_number, variables);
+            visit_expression(value, variables);
+            "SERIALOUT"
+        },
+        crate::Statement::SerialIn { file_number, variable } => {
+            visit_expression(file_number, variables);
+            variables.insert(variable.clone());
+            "SERIALIN"
+        },
+        crate::Statement::OutPin { pin, value } => {
+            visit_expression(pin, variables);
+            visit_expression(value, variables);
+            "OUTPIN"
+        },
+        crate::Statement::SaveImage { path } => {
+            visit_expression(path, variables);
+            "SAVEIMAGE"
+        },
+    };
+    *statements.entry(kind).or_insert(0) += 1;
+}
+
+fn visit_point((x, y): &crate::Point, variables: &mut HashSet<String>) {
+    visit_expression(x, variables);
+    visit_expression(y, variables);
+}
+
+fn visit_expression(expr: &crate::Expression, variables: &mut HashSet<String>) {
+    match expr {
+        crate::Expression::Variable(name, ..) => {
+            variables.insert(name.clone());
+        },
+        crate::Expression::Binary { left, right, .. } => {
+            visit_expression(left, variables);
+            visit_expression(right, variables);
+        },
+        crate::Expression::FunctionCall { arguments, .. } => {
+            for arg in arguments {
+                visit_expression(arg, variables);
+            }
+        },
+        crate::Expression::Number(_) | crate::Expression::String(_) => {},
+    }
+}
@@ -0,0 +1,141 @@
+// A small persistent key-value store for STORESET, STOREGET and STOREGET$, kept as a flat
+// JSON object of strings and numbers so it is easy to inspect or edit by hand
+use std::collections::BTreeMap;
+
+use crate::Value;
+use crate::errors::{Code, Error};
+
+pub const STORE_FILE: &str = "store.json";
+
+pub fn get(key: &str) -> Result<Option<Value>, Error> {
+    Ok(load()?.remove(key))
+}
+
+// The whole file is rewritten on every set, through a temporary file so a crash can't leave it half written
+pub fn set(key: &str, value: Value) -> Result<(), Error> {
+    let mut store = load()?;
+    store.insert(key.to_string(), value);
+    let temporary = format!("{}.tmp", STORE_FILE);
+    std::fs::write(&temporary, encode(&store))
+        .and_then(|_| std::fs::rename(&temporary, STORE_FILE))
+        .map_err(|e| Error::new(Code::FileError, format!("Error writing {}: {}", STORE_FILE, e)))
+}
+
+fn load() -> Result<BTreeMap<String, Value>, Error> {
+    match std::fs::read_to_string(STORE_FILE) {
+        Ok(text) => decode(&text).map_err(|e| Error::new(Code::FileError, format!("Error reading {}: {}", STORE_FILE, e))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(Error::new(Code::FileError, format!("Error reading {}: {}", STORE_FILE, e))),
+    }
+}
+
+fn encode(store: &BTreeMap<String, Value>) -> String {
+    let entries: Vec<String> = store.iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(s) => quote(s),
+                Value::Number(n) => n.to_string(),
+            };
+            format!("  {}: {}", quote(key), value)
+        })
+        .collect();
+    if entries.is_empty() {
+        return "{}\n".to_string();
+    }
+    format!("{{\n{}\n}}\n", entries.join(",\n"))
+}
+
+pub(crate) fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Only what encode writes: one object whose values are strings or numbers
+fn decode(text: &str) -> Result<BTreeMap<String, Value>, String> {
+    let mut chars = text.chars().peekable();
+    let mut store = BTreeMap::new();
+    skip_whitespace(&mut chars);
+    expect(&mut chars, '{')?;
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+        return Ok(store);
+    }
+    loop {
+        skip_whitespace(&mut chars);
+        let key = string(&mut chars)?;
+        skip_whitespace(&mut chars);
+        expect(&mut chars, ':')?;
+        skip_whitespace(&mut chars);
+        let value = if chars.peek() == Some(&'"') {
+            Value::String(string(&mut chars)?)
+        } else {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E') {
+                    number.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            Value::Number(number.parse().map_err(|_| format!("bad value for {}", key))?)
+        };
+        store.insert(key, value);
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            Some(',') => continue,
+            Some('}') => return Ok(store),
+            _ => return Err("expected ',' or '}'".to_string()),
+        }
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn skip_whitespace(chars: &mut Chars) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn expect(chars: &mut Chars, expected: char) -> Result<(), String> {
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        _ => Err(format!("expected '{}'", expected)),
+    }
+}
+
+pub(crate) fn string(chars: &mut Chars) -> Result<String, String> {
+    expect(chars, '"')?;
+    let mut s = String::new();
+    loop {
+        match chars.next().ok_or("unterminated string")? {
+            '"' => return Ok(s),
+            '\\' => match chars.next().ok_or("unterminated string")? {
+                'n' => s.push('\n'),
+                'r' => s.push('\r'),
+                't' => s.push('\t'),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&hex, 16).map_err(|_| "bad \\u escape")?;
+                    s.push(char::from_u32(code).ok_or("bad \\u escape")?);
+                },
+                c => s.push(c),
+            },
+            c => s.push(c),
+        }
+    }
+}
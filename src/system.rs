@@ -0,0 +1,99 @@
+// Access to the host operating system: SHELL, SHELL$, RUN$ and ENVIRON$
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+fn shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    }
+}
+
+// SHELL: the command shares the terminal; a failing command is not a BASIC error
+pub fn shell(command: &str) -> Result<(), String> {
+    shell_command(command)
+        .status()
+        .map(|_| ())
+        .map_err(|e| format!("Error running {}: {}", command, e))
+}
+
+// SHELL$: stdout of the command, without the trailing newline
+pub fn shell_output(command: &str) -> Result<String, String> {
+    let output = shell_command(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("Error running {}: {}", command, e))?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    while text.ends_with(['\n', '\r']) {
+        text.pop();
+    }
+    Ok(text)
+}
+
+// RUN$: stdout of the command like SHELL$, plus its exit status for RC. A command still running
+// after the timeout is killed, keeps what it printed so far and gets status -1, as does one
+// ended by a signal.
+pub fn run_output(command: &str, timeout: Duration) -> Result<(String, i32), String> {
+    let mut child = shell_command(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| format!("Error running {}: {}", command, e))?;
+    let deadline = Instant::now() + timeout;
+
+    // Read on another thread so a command that never closes stdout can't get past the deadline
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        while let Ok(n @ 1..) = stdout.read(&mut buffer) {
+            if sender.send(buffer[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    let mut bytes = Vec::new();
+    let mut status = None;
+    while let Ok(chunk) = receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        bytes.extend(chunk);
+    }
+    while Instant::now() < deadline {
+        if let Some(exited) = child.try_wait().map_err(|e| format!("Error running {}: {}", command, e))? {
+            status = exited.code();
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    if status.is_none() {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
+    let mut text = String::from_utf8_lossy(&bytes).into_owned();
+    while text.ends_with(['\n', '\r']) {
+        text.pop();
+    }
+    Ok((text, status.unwrap_or(-1)))
+}
+
+// ENVIRON$("NAME") is the variable's value, or "" when it is not set
+pub fn environ(name: &str) -> String {
+    std::env::var(name).unwrap_or_default()
+}
+
+// ENVIRON$(n) is the nth "NAME=value" entry, counting from 1
+pub fn environ_entry(n: usize) -> String {
+    std::env::vars()
+        .nth(n.wrapping_sub(1))
+        .map(|(name, value)| format!("{}={}", name, value))
+        .unwrap_or_default()
+}
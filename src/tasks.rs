@@ -0,0 +1,154 @@
+// Experimental TASK/AWAIT dialect: cooperative tasks that take turns one statement at a time.
+// Variables are shared by every task; each task has its own position and FOR stack.
+use std::collections::{HashMap, VecDeque};
+use crate::Instant;
+
+pub struct Task {
+    id: u32,
+    line: usize,
+    loops: Vec<crate::ForLoop>,
+    loop_stack: Vec<usize>,
+    wake_at: Option<Instant>,
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+    // Every task except the one running now
+    waiting: VecDeque<Task>,
+    pub current: u32,
+    next_id: u32,
+    // Consecutive steps that made no progress, for deadlock detection
+    stalled: usize,
+    pub wake_at: Option<Instant>,
+    // Named channels for SEND/RECEIVE; sends never block, receives wait for a value
+    channels: HashMap<String, VecDeque<crate::Value>>,
+    // What the channels hold between them, counted against max_memory
+    pub queued_bytes: usize,
+}
+
+impl Scheduler {
+    pub fn active(&self) -> bool {
+        !self.waiting.is_empty() || self.current != 0
+    }
+
+    pub fn finished(&self, id: u32) -> bool {
+        id != self.current && self.waiting.iter().all(|task| task.id != id)
+    }
+
+    pub fn all_others_finished(&self) -> bool {
+        self.waiting.is_empty()
+    }
+
+    pub fn spawned(&self, id: u32) -> bool {
+        id <= self.next_id
+    }
+
+    pub fn send(&mut self, channel: String, value: crate::Value) {
+        self.queued_bytes += value.bytes();
+        self.channels.entry(channel).or_default().push_back(value);
+    }
+
+    pub fn receive(&mut self, channel: &str) -> Option<crate::Value> {
+        let value = self.channels.get_mut(channel)?.pop_front()?;
+        self.queued_bytes -= value.bytes();
+        Some(value)
+    }
+
+    // The task, if any, that is already looping on a FOR variable
+    pub fn loop_owner(&self, variable: &str) -> Option<u32> {
+        self.waiting.iter()
+            .find(|task| task.loops.iter().any(|l| l.variable == variable))
+            .map(|task| task.id)
+    }
+}
+
+impl crate::Interpreter {
+    pub(crate) fn spawn_task(&mut self, line: usize) -> u32 {
+        self.scheduler.next_id += 1;
+        let id = self.scheduler.next_id;
+        self.scheduler.waiting.push_back(Task {
+            id,
+            line,
+            loops: Vec::new(),
+            loop_stack: Vec::new(),
+            wake_at: None,
+        });
+        id
+    }
+
+    // Hands over to the next task after every statement. `blocked` means the statement
+    // only waited (AWAIT, RECEIVE) and will run again on the task's next turn.
+    pub(crate) fn switch_task(&mut self, blocked: bool) -> Result<(), String> {
+        if self.scheduler.waiting.is_empty() {
+            if let Some(wake) = self.scheduler.wake_at.take() {
+                std::thread::sleep(wake.saturating_duration_since(Instant::now()));
+            } else if blocked {
+                return Err(format!("Deadlock: task {} at line {} is waiting with no other task running", self.scheduler.current, self.current_line));
+            }
+            return Ok(());
+        }
+
+        self.scheduler.stalled = if blocked { self.scheduler.stalled + 1 } else { 0 };
+        let now = Instant::now();
+        let sleeping = self.scheduler.waiting.iter().any(|task| task.wake_at.is_some_and(|wake| wake > now));
+        if self.scheduler.stalled > self.scheduler.waiting.len() && !sleeping {
+            let mut lines = vec![format!("task {} at line {}", self.scheduler.current, self.current_line)];
+            lines.extend(self.scheduler.waiting.iter().map(|task| format!("task {} at line {}", task.id, task.line)));
+            return Err(format!("Deadlock: {}", lines.join(", ")));
+        }
+
+        let task = self.save_task();
+        self.scheduler.waiting.push_back(task);
+        self.resume_task(blocked);
+        Ok(())
+    }
+
+    // The current task is done; returns false when no tasks are left
+    pub(crate) fn finish_task(&mut self) -> bool {
+        self.loops.clear();
+        self.loop_stack.clear();
+        if self.scheduler.waiting.is_empty() {
+            return false;
+        }
+        self.resume_task(false);
+        true
+    }
+
+    fn save_task(&mut self) -> Task {
+        Task {
+            id: self.scheduler.current,
+            line: self.current_line,
+            loops: std::mem::take(&mut self.loops),
+            loop_stack: std::mem::take(&mut self.loop_stack),
+            wake_at: self.scheduler.wake_at.take(),
+        }
+    }
+
+    // Runs the first task that is not asleep, sleeping until one wakes if they all are.
+    // A task that just blocked only polls, so it never gets the next turn while others can run.
+    fn resume_task(&mut self, blocked: bool) {
+        loop {
+            let now = Instant::now();
+            let last = self.scheduler.waiting.len() - 1;
+            let ready = self.scheduler.waiting.iter().enumerate()
+                .position(|(i, task)| task.wake_at.is_none_or(|wake| wake <= now) && !(blocked && i == last));
+            let index = match ready {
+                Some(index) => index,
+                None => match self.scheduler.waiting.iter().filter_map(|task| task.wake_at).min() {
+                    Some(earliest) => {
+                        std::thread::sleep(earliest.saturating_duration_since(now));
+                        continue;
+                    },
+                    None => last,
+                },
+            };
+            let task = self.scheduler.waiting.remove(index).unwrap();
+            self.scheduler.current = task.id;
+            self.current_line = task.line;
+            self.loops = task.loops;
+            self.loop_stack = task.loop_stack;
+            self.scheduler.wake_at = None;
+            return;
+        }
+    }
+}
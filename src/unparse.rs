@@ -0,0 +1,238 @@
+// The parsed program written back out as source, in one canonical form: keywords in capitals,
+// a space either side of each operator, line numbers lined up on the right and the body of each
+// FOR indented. Parsing what comes out gives the same program back.
+use crate::{Expression, FileMode, Statement, VarType};
+use crate::graphics::LineShape;
+
+pub fn program(program: &crate::Program) -> String {
+    // A program typed without line numbers is numbered 0, 1, 2... by the parser, so leaving the
+    // numbers out again changes nothing
+    let numbered = program.lines.iter().enumerate().any(|(i, line)| line.number as usize != i);
+    let width = program.lines.iter().map(|line| line.number.to_string().len()).max().unwrap_or(0);
+    let mut source = String::new();
+    let mut depth: usize = 0;
+    for line in &program.lines {
+        if let Statement::Next { .. } = line.statement {
+            depth = depth.saturating_sub(1);
+        }
+        if numbered {
+            source.push_str(&format!("{:>width$} ", line.number, width = width));
+        }
+        source.push_str(&"    ".repeat(depth));
+        source.push_str(&statement(&line.statement));
+        source.push('\n');
+        if let Statement::For { .. } = line.statement {
+            depth += 1;
+        }
+    }
+    source
+}
+
+pub fn statement(statement: &Statement) -> String {
+    match statement {
+        // A statement that is only a call, like FOO(1), parses as assigning the call to FOO
+        Statement::Let { variable, expression: call @ Expression::FunctionCall { name, .. }, .. } if name == variable => {
+            call.to_string()
+        },
+        Statement::Let { variable, expression, .. } => format!("LET {} = {}", variable, expression),
+        Statement::Print { expressions, semicolon } => {
+            let mut text = String::from("PRINT");
+            if !expressions.is_empty() {
+                text.push(' ');
+                text.push_str(&list(expressions));
+            }
+            if *semicolon {
+                text.push(';');
+            }
+            text
+        },
+        Statement::If { condition, then_branch, else_branch } => {
+            let mut text = format!("IF {} THEN {}", condition, branch(then_branch));
+            if let Some(else_branch) = else_branch {
+                text.push_str(&format!(" ELSE {}", branch(else_branch)));
+            }
+            text
+        },
+        Statement::Input { variable } => format!("INPUT {}", variable),
+        Statement::For { loop_data } => {
+            let mut text = format!("FOR {} = {} TO {}", loop_data.variable, loop_data.start, loop_data.end);
+            if !matches!(loop_data.step, Expression::Number(step) if step == 1.0) {
+                text.push_str(&format!(" STEP {}", loop_data.step));
+            }
+            text
+        },
+        Statement::Next { variable } => format!("NEXT {}", variable),
+        Statement::End { code: Some(code) } => format!("END {}", code),
+        Statement::End { code: None } => "END".to_string(),
+        Statement::Goto(target) => format!("GOTO {}", target),
+        Statement::Rem(text) => format!("REM {}", text),
+        Statement::Open { filename, mode, file_number, record_length } => {
+            let mode = match mode {
+                FileMode::Random => "RANDOM",
+            };
+            let mut text = format!("OPEN {} FOR {} AS #{}", filename, mode, file_number);
+            if let Some(length) = record_length {
+                text.push_str(&format!(" LEN = {}", length));
+            }
+            text
+        },
+        Statement::Close { file_numbers } => {
+            let numbers: Vec<String> = file_numbers.iter().map(|n| format!("#{}", n)).collect();
+            format!("CLOSE {}", numbers.join(", ")).trim_end().to_string()
+        },
+        Statement::Field { file_number, fields } => {
+            let mut text = format!("FIELD #{}", file_number);
+            for (width, variable) in fields {
+                text.push_str(&format!(", {} AS {}", width, variable));
+            }
+            text
+        },
+        Statement::Lset { variable, expression } => format!("LSET {} = {}", variable, expression),
+        Statement::Rset { variable, expression } => format!("RSET {} = {}", variable, expression),
+        Statement::Get { file_number, record } => format!("GET #{}{}", file_number, optional(record)),
+        Statement::Put { file_number, record } => format!("PUT #{}{}", file_number, optional(record)),
+        Statement::Kill { path } => format!("KILL {}", path),
+        Statement::Name { from, to } => format!("NAME {} AS {}", from, to),
+        Statement::Files { pattern: Some(pattern) } => format!("FILES {}", pattern),
+        Statement::Files { pattern: None } => "FILES".to_string(),
+        Statement::Chain { path } => format!("CHAIN {}", path),
+        Statement::Common { variables } => format!("COMMON {}", variables.join(", ")),
+        Statement::DefFn { name, parameters, body } if parameters.is_empty() => format!("DEF {} = {}", name, body),
+        Statement::DefFn { name, parameters, body } => format!("DEF {}({}) = {}", name, parameters.join(", "), body),
+        Statement::Checkpoint => "CHECKPOINT".to_string(),
+        Statement::Cls => "CLS".to_string(),
+        Statement::Locate { row, col } => format!("LOCATE{}", pair(row, col)),
+        Statement::Color { foreground, background } => format!("COLOR{}", pair(foreground, background)),
+        Statement::Screen { width, height } => format!("SCREEN {}, {}", width, height),
+        Statement::Pset { point, color } => format!("PSET {}{}", self::point(point), optional(color)),
+        Statement::DrawLine { from, to, color, shape } => {
+            let mut text = format!("LINE {}-{}", from.as_ref().map(self::point).unwrap_or_default(), self::point(to));
+            let shape = match shape {
+                LineShape::Line => None,
+                LineShape::Box => Some("B"),
+                LineShape::FilledBox => Some("BF"),
+            };
+            match (color, shape) {
+                (None, None) => {},
+                (Some(color), None) => text.push_str(&format!(", {}", color)),
+                (color, Some(shape)) => {
+                    text.push_str(&format!(", {}, {}", color.as_ref().map(|c| c.to_string()).unwrap_or_default(), shape));
+                },
+            }
+            text
+        },
+        Statement::Circle { center, radius, color } => format!("CIRCLE {}, {}{}", point(center), radius, optional(color)),
+        Statement::Paint { point, color, border } => {
+            let mut text = format!("PAINT {}", self::point(point));
+            if color.is_some() || border.is_some() {
+                text.push(',');
+                text.push_str(&pair(color, border));
+            }
+            text
+        },
+        Statement::SaveImage { path } => format!("SAVEIMAGE {}", path),
+        Statement::Sleep { seconds: Some(seconds) } => format!("SLEEP {}", seconds),
+        Statement::Sleep { seconds: None } => "SLEEP".to_string(),
+        Statement::Beep => "BEEP".to_string(),
+        Statement::Sound { frequency, duration } => format!("SOUND {}, {}", frequency, duration),
+        Statement::Play { music } => format!("PLAY {}", music),
+        Statement::Draw { commands } => format!("DRAW {}", commands),
+        Statement::SpriteLoad { number, rows } => format!("SPRITE LOAD {}, {}", number, list(rows)),
+        Statement::SpriteMove { number, row, col } => format!("SPRITE MOVE {}, {}, {}", number, row, col),
+        Statement::SpriteHide { number } => format!("SPRITE HIDE {}", number),
+        Statement::Task { target, id_variable: Some(id) } => format!("TASK {}, {}", target, id),
+        Statement::Task { target, id_variable: None } => format!("TASK {}", target),
+        Statement::EndTask => "END TASK".to_string(),
+        Statement::Await { task: Some(task) } => format!("AWAIT {}", task),
+        Statement::Await { task: None } => "AWAIT".to_string(),
+        Statement::Send { channel, value } => format!("SEND {}, {}", channel, value),
+        Statement::Receive { channel, variable } => format!("RECEIVE {}, {}", channel, variable),
+        Statement::Shell { command } => format!("SHELL {}", command),
+        Statement::OpenSerial { device, baud, file_number } => format!("OPENSERIAL {}, {} AS #{}", device, baud, file_number),
+        Statement::SerialOut { file_number, value } => format!("SERIALOUT #{}, {}", file_number, value),
+        Statement::SerialIn { file_number, variable } => format!("SERIALIN #{}, {}", file_number, variable),
+        Statement::OutPin { pin, value } => format!("OUTPIN {}, {}", pin, value),
+        Statement::DefType { var_type, letters } => {
+            let keyword = match var_type {
+                VarType::Integer => "DEFINT",
+                VarType::Single => "DEFSNG",
+                VarType::Double => "DEFDBL",
+                VarType::String => "DEFSTR",
+            };
+            let letters: Vec<String> = letters.iter()
+                .map(|&(first, last)| if first == last { first.to_string() } else { format!("{}-{}", first, last) })
+                .collect();
+            format!("{} {}", keyword, letters.join(", "))
+        },
+        Statement::OptionExplicit => "OPTION EXPLICIT".to_string(),
+        Statement::StoreSet { key, value } => format!("STORESET {}, {}", key, value),
+        Statement::ClipSet { text } => format!("CLIPSET {}", text),
+    }
+}
+
+// THEN 100 rather than THEN GOTO 100, as most programs write it
+fn branch(statement: &Statement) -> String {
+    match statement {
+        Statement::Goto(target) => target.to_string(),
+        statement => self::statement(statement),
+    }
+}
+
+fn list(expressions: &[Expression]) -> String {
+    expressions.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn point((x, y): &crate::Point) -> String {
+    format!("({}, {})", x, y)
+}
+
+fn optional(argument: &Option<Expression>) -> String {
+    argument.as_ref().map(|a| format!(", {}", a)).unwrap_or_default()
+}
+
+// LOCATE row, col with either left out: LOCATE row, LOCATE , col
+fn pair(first: &Option<Expression>, second: &Option<Expression>) -> String {
+    match (first, second) {
+        (Some(first), Some(second)) => format!(" {}, {}", first, second),
+        (Some(first), None) => format!(" {}", first),
+        (None, Some(second)) => format!(" , {}", second),
+        (None, None) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Formats `source` and checks that formatting the result changes nothing
+    fn format(source: &str) -> String {
+        let formatted = crate::format(source).unwrap();
+        assert_eq!(crate::format(&formatted).unwrap(), formatted);
+        formatted
+    }
+
+    #[test]
+    fn layout_is_canonical() {
+        assert_eq!(
+            format("10 for i=1 to 3\n20 if i>1 then print -i else print \"x\";\n30 next i\n40 goto 60\n60 end\n"),
+            "10 FOR I = 1 TO 3\n20     IF I > 1 THEN PRINT -I ELSE PRINT \"x\";\n30 NEXT I\n40 GOTO 60\n60 END\n",
+        );
+        assert_eq!(format("1 PRINT 1\n100 PRINT 2"), "  1 PRINT 1\n100 PRINT 2\n");
+    }
+
+    #[test]
+    fn unnumbered_programs_stay_unnumbered() {
+        assert_eq!(format("x=1\nprint x*-2"), "LET X = 1\nPRINT X * (-2)\n");
+    }
+
+    #[test]
+    fn minus_keeps_its_meaning() {
+        assert_eq!(
+            format("print 2^-1, -(a+b), (-2)^2, -2^2, 3--1, 0-5, -a*b, -(-a)"),
+            "PRINT 2 ^ (-1), -(A + B), (-2) ^ 2, -2 ^ 2, 3 - -1, 0 - 5, -A * B, -(-A)\n",
+        );
+        for (source, value) in [("-2^2", -4.0), ("(-2)^2", 4.0), ("2^-1", 0.5), ("3--1", 4.0), ("-(1+2)*2", -6.0)] {
+            let program = crate::parse(&format(&format!("PRINT {}", source))).unwrap();
+            let output = crate::Interpreter::new().run_captured(&program, Vec::new());
+            assert_eq!(output.stdout().trim().parse::<f64>(), Ok(value), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+// --vm-opt: a pass over the program when it loads that gives some statements a quicker form to
+// run in place of their own. The statements are the interpreter's instructions, so this is
+// where a bytecode VM would thread its jumps and fuse its instructions:
+//
+// - a GOTO to a line that is only another GOTO goes straight to the end of the chain
+// - an IF whose branches are GOTOs jumps to the line index without running a GOTO
+// - LET X = X + e adds to X where it is stored, rather than reading X out, adding and
+//   storing the sum back
+//
+// The program itself is left as it is, so errors, checkpoints and CHAIN still see the lines
+// as written. A GOTO that is jumped past doesn't count as a step.
+use std::collections::{HashMap, HashSet};
+
+use crate::{Expression, Position, Program, Statement, Token, VarCache};
+
+pub(crate) enum Fast {
+    // GOTO, as the index of the line its chain ends at
+    Jump(usize),
+    // IF c THEN GOTO, with the index to go to when c is true and, given an ELSE GOTO, when it is false
+    JumpIf {
+        condition: Expression,
+        then: usize,
+        otherwise: Option<usize>,
+    },
+    // LET X = X + e, keeping the whole of X + e for when X isn't a number or string to add to
+    AddTo {
+        variable: String,
+        cache: VarCache,
+        addend: Expression,
+        sum: Expression,
+        position: Position,
+    },
+}
+
+// The quicker form of each line, by index, for the lines that have one
+pub(crate) fn plan(program: &Program, line_index: &HashMap<u32, usize>) -> Vec<Option<Fast>> {
+    program.lines.iter()
+        .map(|line| match &line.statement {
+            Statement::Goto(target) => Some(Fast::Jump(thread(program, line_index, *target)?)),
+            Statement::If { condition, then_branch, else_branch } => {
+                let Statement::Goto(then) = **then_branch else {
+                    return None;
+                };
+                let otherwise = match else_branch.as_deref() {
+                    None => None,
+                    Some(Statement::Goto(target)) => Some(thread(program, line_index, *target)?),
+                    Some(_) => return None,
+                };
+                Some(Fast::JumpIf { condition: condition.clone(), then: thread(program, line_index, then)?, otherwise })
+            },
+            Statement::Let { variable, cache, expression } => match expression {
+                Expression::Binary { left, operator: Token::Plus, right, position }
+                    if matches!(&**left, Expression::Variable(name, ..) if name == variable) =>
+                {
+                    Some(Fast::AddTo {
+                        variable: variable.clone(),
+                        cache: cache.clone(),
+                        addend: (**right).clone(),
+                        sum: expression.clone(),
+                        position: *position,
+                    })
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+// The index of the line a GOTO to `target` ends up running, following GOTOs until a line does
+// something else. None for a missing line, so the GOTO reports it when it runs; a loop of GOTOs
+// stops where it comes back round.
+fn thread(program: &Program, line_index: &HashMap<u32, usize>, target: u32) -> Option<usize> {
+    let mut index = *line_index.get(&target)?;
+    let mut seen = HashSet::from([index]);
+    while let Statement::Goto(next) = program.lines[index].statement {
+        match line_index.get(&next) {
+            Some(&next) if seen.insert(next) => index = next,
+            _ => break,
+        }
+    }
+    Some(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan_of(source: &str) -> Vec<Option<Fast>> {
+        let program = crate::parse(source).unwrap();
+        plan(&program, &program.line_index())
+    }
+
+    #[test]
+    fn goto_chains_are_followed_to_their_end() {
+        let plan = plan_of("10 GOTO 20\n20 GOTO 30\n30 GOTO 40\n40 PRINT 1\n50 GOTO 50\n60 GOTO 99");
+        assert!(matches!(plan[0], Some(Fast::Jump(3))));
+        assert!(plan[3].is_none());
+        assert!(matches!(plan[4], Some(Fast::Jump(4))));
+        assert!(plan[5].is_none());
+    }
+
+    #[test]
+    fn if_goto_becomes_a_conditional_jump() {
+        let plan = plan_of("10 IF X THEN 30 ELSE 40\n20 IF X THEN PRINT 1\n30 GOTO 40\n40 END");
+        assert!(matches!(plan[0], Some(Fast::JumpIf { then: 3, otherwise: Some(3), .. })));
+        assert!(plan[1].is_none());
+    }
+
+    #[test]
+    fn only_adding_to_the_same_variable_is_fused() {
+        let plan = plan_of("10 X = X + 1\n20 X = Y + 1\n30 X = 1 + X\n40 A$ = A$ + \"!\"");
+        assert!(matches!(&plan[0], Some(Fast::AddTo { variable, .. }) if variable == "X"));
+        assert!(plan[1].is_none());
+        assert!(plan[2].is_none());
+        assert!(plan[3].is_some());
+    }
+}
@@ -0,0 +1,107 @@
+// LANG in a web page, for a playground where programs run without installing anything. Built
+// with `--features wasm --crate-type cdylib` for wasm32-unknown-unknown, then wasm-bindgen.
+// The page gives a Playground the program with compile and calls step until it says the
+// program has finished. step never blocks: it hands back events for the page to act on, so
+// SLEEP becomes a pause the page waits out and INPUT a request for a line. Programs always
+// run sandboxed, and the browser build has no files, building or REPL.
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::io::BufferIo;
+use crate::{Interpreter, StepResult};
+
+// What step saw happen, as JSON objects with a "type"
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Event {
+    Output { text: String },
+    // Call step again once this many milliseconds have passed
+    Sleep { milliseconds: f64 },
+    // Give INPUT its line with provideInput, then call step again
+    Input,
+    Error { code: &'static str, message: String },
+    Finished { status: i32 },
+}
+
+#[wasm_bindgen]
+pub struct Playground {
+    interpreter: Interpreter,
+    output: Arc<Mutex<String>>,
+    source: String,
+}
+
+impl Default for Playground {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl Playground {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Playground {
+        let buffer = BufferIo::new(Vec::new());
+        let output = buffer.output();
+        let mut interpreter = Interpreter::new();
+        interpreter.io = Box::new(buffer);
+        interpreter.sandbox = true;
+        interpreter.async_sleep = Some(None);
+        Playground { interpreter, output, source: String::new() }
+    }
+
+    // Parses and checks the program and gets it ready to step from its first line, with no
+    // variables set. A program with errors throws them, shown against the source.
+    pub fn compile(&mut self, source: &str) -> Result<(), JsValue> {
+        let program = crate::parse(source)
+            .and_then(|program| crate::check_program(&program).map(|_| program))
+            .map_err(|e| JsValue::from_str(&e.render(source, "playground.bs", false)))?;
+        *self = Playground::new();
+        self.source = source.to_string();
+        self.interpreter.load(&program);
+        Ok(())
+    }
+
+    // Runs up to `budget` statements and returns a JSON array of events. It stops early at a
+    // SLEEP, an INPUT with no line, an error or the end of the program, which is always the
+    // last event.
+    pub fn step(&mut self, budget: u32) -> String {
+        let mut events = Vec::new();
+        for _ in 0..budget {
+            let step = self.interpreter.step();
+            let text = std::mem::take(&mut *self.output.lock().unwrap());
+            if !text.is_empty() {
+                events.push(Event::Output { text });
+            }
+            let sleep = self.interpreter.async_sleep.as_mut().and_then(Option::take);
+            match step {
+                StepResult::Ran => {},
+                StepResult::NeedsInput => events.push(Event::Input),
+                StepResult::Finished => events.push(Event::Finished { status: self.interpreter.end_status() }),
+                StepResult::Error(e) => events.push(Event::Error {
+                    code: e.code(),
+                    message: e.render(&self.source, "playground.bs", false),
+                }),
+            }
+            if let Some(duration) = sleep {
+                events.push(Event::Sleep { milliseconds: duration.as_secs_f64() * 1000.0 });
+            }
+            if !matches!(events.last(), None | Some(Event::Output { .. })) {
+                break;
+            }
+        }
+        serde_json::to_string(&events).unwrap_or_default()
+    }
+
+    // A line for INPUT, without its newline, after step has sent an input event
+    #[wasm_bindgen(js_name = provideInput)]
+    pub fn provide_input(&mut self, line: &str) {
+        self.interpreter.provide_input(line);
+    }
+
+    // The number of the line step runs next, for highlighting it in an editor
+    pub fn line(&self) -> Option<u32> {
+        self.interpreter.line_number()
+    }
+}
@@ -0,0 +1,101 @@
+// Live display of the graphics screen, available when built with `--features window`
+use std::time::Duration;
+#[cfg(feature = "window")]
+use std::time::Instant;
+
+use crate::graphics::Framebuffer;
+
+#[cfg(feature = "window")]
+const FRAME: Duration = Duration::from_millis(16);
+
+#[cfg(feature = "window")]
+pub struct Window {
+    window: minifb::Window,
+    buffer: Vec<u32>,
+    last_frame: Instant,
+}
+
+#[cfg(feature = "window")]
+impl Window {
+    pub fn open(screen: &Framebuffer) -> Result<Self, String> {
+        let options = minifb::WindowOptions {
+            scale: minifb::Scale::FitScreen,
+            ..minifb::WindowOptions::default()
+        };
+        let mut window = minifb::Window::new("LANG", screen.width(), screen.height(), options)
+            .map_err(|e| format!("Error opening window: {}", e))?;
+        // Frames are paced by refresh, so minifb should never sleep on its own
+        window.set_target_fps(0);
+        let mut window = Window {
+            window,
+            buffer: Vec::new(),
+            last_frame: Instant::now(),
+        };
+        window.present(screen);
+        Ok(window)
+    }
+
+    // Called after every statement; redraws at most once a frame and returns false once the window is closed
+    pub fn refresh(&mut self, screen: &Framebuffer) -> bool {
+        if self.last_frame.elapsed() >= FRAME {
+            self.present(screen);
+        }
+        self.window.is_open()
+    }
+
+    // SLEEP: keep the window live for a number of seconds, or until a key is pressed
+    pub fn wait(&mut self, screen: &Framebuffer, duration: Option<Duration>) -> bool {
+        let start = Instant::now();
+        while self.window.is_open() {
+            self.present(screen);
+            let done = match duration {
+                Some(duration) => start.elapsed() >= duration,
+                None => !self.window.get_keys_pressed(minifb::KeyRepeat::No).is_empty(),
+            };
+            if done {
+                return true;
+            }
+            std::thread::sleep(FRAME);
+        }
+        false
+    }
+
+    // After END the last frame stays up until the window is closed or Escape is pressed
+    pub fn hold(&mut self, screen: &Framebuffer) {
+        while self.window.is_open() && !self.window.is_key_down(minifb::Key::Escape) {
+            self.present(screen);
+            std::thread::sleep(FRAME);
+        }
+    }
+
+    fn present(&mut self, screen: &Framebuffer) {
+        self.buffer.clear();
+        self.buffer.extend(screen.pixels().iter().map(|&color| {
+            let (r, g, b) = crate::graphics::PALETTE[color as usize];
+            u32::from_be_bytes([0, r, g, b])
+        }));
+        // A failed update means the window went away, which is_open reports
+        let _ = self.window.update_with_buffer(&self.buffer, screen.width(), screen.height());
+        self.last_frame = Instant::now();
+    }
+}
+
+#[cfg(not(feature = "window"))]
+pub struct Window;
+
+#[cfg(not(feature = "window"))]
+impl Window {
+    pub fn open(_screen: &Framebuffer) -> Result<Self, String> {
+        Err("--window needs a build with `--features window`".to_string())
+    }
+
+    pub fn refresh(&mut self, _screen: &Framebuffer) -> bool {
+        true
+    }
+
+    pub fn wait(&mut self, _screen: &Framebuffer, _duration: Option<Duration>) -> bool {
+        true
+    }
+
+    pub fn hold(&mut self, _screen: &Framebuffer) {}
+}